@@ -19,6 +19,7 @@ fn make_dummy_events(n: usize) -> Vec<Event> {
                     cmdline: Some(format!("bench --id {}", i)),
                     uid: Some(1000),
                     started_at: Some(Utc::now().timestamp()),
+                    publisher: None,
                 }),
                 "bench",
             )
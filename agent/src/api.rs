@@ -0,0 +1,157 @@
+//! Optional HTTP API on 127.0.0.1 for monitoring tools and scripts that don't speak the control
+//! protocol: `GET /health` without authentication, and with `Authorization: Bearer <token>`
+//! `GET /status`, `/events`, and `/risk`. Answers come from the daemon through the same
+//! [`Handler`] as the control interface ([`crate::control`]).
+
+use crate::config::secrets::same_secret;
+use crate::config::ApiConfig;
+use crate::control::{Handler, Request};
+use crate::risk::RiskLevel;
+use crate::runtime::CancellationToken;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Response, StatusCode};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Token file under `data_dir` when `api.token` is unset
+pub const TOKEN_FILE: &str = "api_token";
+
+/// Results returned when the request sets no `limit`, and the most it may set
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// Token clients must present: `api.token`, else the one in `data_dir/api_token`, created
+/// (readable by the agent user only) on first use
+pub fn token(config: &ApiConfig, data_dir: &Path) -> Result<String, BoxError> {
+    if let Some(ref token) = config.token {
+        return Ok(token.clone());
+    }
+    let path = data_dir.join(TOKEN_FILE);
+    if let Ok(token) = std::fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    use base64::Engine;
+    let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(crate::storage::random_secret());
+    crate::storage::write_private(&path, token.as_bytes())?;
+    Ok(token)
+}
+
+/// Serve the API on 127.0.0.1:`api.port` until `cancel` is cancelled; requests in progress are
+/// answered first
+pub async fn serve(config: ApiConfig, token: String, handler: Arc<dyn Handler>, cancel: CancellationToken) {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, config.port));
+    let token = Arc::new(token);
+    let make = make_service_fn(move |_| {
+        let (handler, token) = (Arc::clone(&handler), Arc::clone(&token));
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let (handler, token) = (Arc::clone(&handler), Arc::clone(&token));
+                async move { Ok::<_, Infallible>(respond(req, &token, handler).await) }
+            }))
+        }
+    });
+    let server = match hyper::Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make),
+        Err(e) => {
+            tracing::warn!(%addr, error = %e, "HTTP API unavailable");
+            return;
+        }
+    };
+    tracing::info!(%addr, "HTTP API listening");
+    if let Err(e) = server.with_graceful_shutdown(async move { cancel.cancelled().await }).await {
+        tracing::warn!(%addr, error = %e, "HTTP API failed");
+    }
+}
+
+fn reply(status: StatusCode, body: &Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/json"));
+    response
+}
+
+fn error(status: StatusCode, message: impl std::fmt::Display) -> Response<Body> {
+    reply(status, &json!({ "error": message.to_string() }))
+}
+
+/// Query parameters of a request
+struct Params {
+    since: Option<i64>,
+    until: Option<i64>,
+    kind: Option<String>,
+    min_risk: Option<RiskLevel>,
+    limit: usize,
+}
+
+impl Params {
+    /// Parse `query`, which may only hold the parameters in `allowed`
+    fn parse(query: &str, allowed: &[&str]) -> Result<Self, String> {
+        let mut params = Params { since: None, until: None, kind: None, min_risk: None, limit: DEFAULT_LIMIT };
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            if !allowed.contains(&name.as_ref()) {
+                return Err(format!("unknown parameter {}", name));
+            }
+            let invalid = |e: String| format!("{}: {}", name, e);
+            match name.as_ref() {
+                "since" => params.since = Some(crate::cli::parse_time(&value).map_err(invalid)?),
+                "until" => params.until = Some(crate::cli::parse_time(&value).map_err(invalid)?),
+                "kind" => params.kind = Some(value.into_owned()),
+                "min_risk" => params.min_risk = Some(<RiskLevel as clap::ValueEnum>::from_str(&value, true).map_err(invalid)?),
+                "limit" => params.limit = value.parse::<usize>().map_err(|e| invalid(e.to_string()))?.min(MAX_LIMIT),
+                _ => {}
+            }
+        }
+        Ok(params)
+    }
+}
+
+async fn respond(req: hyper::Request<Body>, token: &str, handler: Arc<dyn Handler>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return error(StatusCode::METHOD_NOT_ALLOWED, "only GET is supported");
+    }
+    if req.uri().path() == "/health" {
+        return reply(StatusCode::OK, &json!({ "status": "ok" }));
+    }
+    let presented = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.as_bytes().strip_prefix(b"Bearer "));
+    if !presented.is_some_and(|given| same_secret(given, token.as_bytes())) {
+        let mut response = error(StatusCode::UNAUTHORIZED, "missing or wrong bearer token");
+        response.headers_mut().insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+        return response;
+    }
+    let query = req.uri().query().unwrap_or("");
+    let request = match req.uri().path() {
+        "/status" => Params::parse(query, &[]).map(|_| Request::Status),
+        "/events" => Params::parse(query, &["since", "until", "kind", "limit"]).map(|p| Request::Events {
+            since: p.since,
+            until: p.until,
+            kind: p.kind,
+            limit: Some(p.limit),
+        }),
+        "/risk" => Params::parse(query, &["since", "until", "min_risk", "limit"]).map(|p| Request::Query {
+            since: p.since,
+            until: p.until,
+            min_risk: p.min_risk,
+            limit: Some(p.limit),
+        }),
+        _ => return error(StatusCode::NOT_FOUND, "not found"),
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
+    match tokio::task::spawn_blocking(move || handler.handle(request)).await {
+        Ok(Ok(result)) => reply(StatusCode::OK, &result),
+        Ok(Err(e)) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
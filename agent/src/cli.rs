@@ -0,0 +1,395 @@
+//! Command line: flags overriding the config file and the `DADM_*` environment variables for one
+//! run, and the subcommands (`run` when none is given).
+
+use crate::config::{layers::merge, AgentConfig};
+use crate::risk::{RiskLevel, Verdict};
+use crate::storage::ExportFormat;
+use clap::{Args, Parser, Subcommand};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+#[derive(Debug, Default, Parser)]
+#[command(name = "dadm-agent", version, about = "DADM edge agent")]
+pub struct Cli {
+    /// Config file (default: `DADM_CONFIG_PATH`, else `config.json`)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Data directory (`data_dir`)
+    #[arg(long, global = true, value_name = "DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Run one collection cycle and exit
+    #[arg(long, global = true, conflicts_with = "interval")]
+    pub once: bool,
+    /// Run as a daemon, collecting every SECS seconds (`collectors.process.interval_secs`)
+    #[arg(long, global = true, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub interval: Option<u64>,
+    /// Log filter directives (`log.level`, and `RUST_LOG` for the run), e.g. `debug`
+    #[arg(long, global = true, value_name = "DIRECTIVES", value_parser = parse_level)]
+    pub log_level: Option<String>,
+    /// Don't contact the server (`uplink.enabled` false)
+    #[arg(long, global = true)]
+    pub no_uplink: bool,
+    /// Detect only: don't store results, report to the server, or act on them (`dry_run`)
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the agent: as a daemon with a collection interval, else one cycle (the default)
+    Run,
+    /// Run one collection cycle and exit
+    ScanOnce,
+    /// Print the state the daemon last wrote
+    Status,
+    /// Live dashboard of the running daemon (over the control interface)
+    Top {
+        /// Seconds between refreshes
+        #[arg(long, value_name = "SECS", default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..))]
+        refresh_secs: u64,
+    },
+    /// Print stored risk results, oldest first, one JSON object per line
+    Query(QueryArgs),
+    /// Stream stored events as JSONL, CSV, or Parquet
+    Export(ExportArgs),
+    /// Apply retention now, or delete everything older than `--before`
+    Prune {
+        /// Delete events, risk results, and feature vectors before this time
+        #[arg(long, value_name = "TIME", value_parser = parse_time)]
+        before: Option<i64>,
+    },
+    /// Config tooling (works without a valid config)
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Replay events against labels and print a model evaluation report
+    Evaluate(EvaluateArgs),
+    /// Feed recorded events through detection and print the score of each cycle
+    Replay(ReplayArgs),
+    /// Simulate suspicious activity harmlessly and check that detection raises it
+    Selftest {
+        /// Directory for the file sandbox (default: the system temp directory)
+        #[arg(long, value_name = "PATH")]
+        sandbox: Option<PathBuf>,
+    },
+    /// Verify the event hash chain; fails if tampering was detected
+    VerifyChain(StoreArgs),
+    /// Check every stored row; fails on corruption or tampering
+    Scrub {
+        /// Move corrupt rows aside (local store only)
+        #[arg(long)]
+        quarantine: bool,
+        #[command(flatten)]
+        store: StoreArgs,
+    },
+    /// Print the device's (or an entity's) risk history
+    RiskTrend(TrendArgs),
+    /// Record an analyst verdict on a risk result, or list allowlist candidates
+    Feedback(FeedbackArgs),
+    /// Print the applied server policies and the settings each changed
+    PolicyAudit(StoreArgs),
+    /// Print the server commands handled, with their status and results
+    CommandAudit {
+        /// Only commands handled since this time
+        #[arg(long, value_name = "TIME", value_parser = parse_time, default_value = "0")]
+        since: i64,
+        #[command(flatten)]
+        store: StoreArgs,
+    },
+    /// Print the response actions taken, with who asked for them, why, and their undo
+    ResponseAudit {
+        /// Only actions taken since this time
+        #[arg(long, value_name = "TIME", value_parser = parse_time, default_value = "0")]
+        since: i64,
+        #[command(flatten)]
+        store: StoreArgs,
+    },
+    /// Have the running daemon undo a response action (over the control interface)
+    Undo {
+        /// Id of the action, from `response-audit`
+        id: String,
+    },
+    /// Print, set, or reset the log level override of the running daemon
+    LogLevel {
+        /// Filter directives, e.g. `info,dadm_agent::uplink=trace`
+        #[arg(value_parser = parse_level, conflicts_with = "reset")]
+        directives: Option<String>,
+        /// Remove the override
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Verify the audit log; fails on a broken link, hash, or signature
+    VerifyAudit {
+        /// Audit log (default: `log.audit.file`)
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+        /// Base64 public key entries must be signed with (repeatable)
+        #[arg(long = "key", value_name = "BASE64")]
+        keys: Vec<String>,
+    },
+    /// Re-encrypt the store under a new storage secret
+    RotateKey,
+    /// Lift host isolation at the machine with the operator credential read from stdin (one
+    /// trailing newline removed)
+    ReleaseIsolation {
+        /// Print the `response.firewall.release_credential_hash` of the credential instead
+        #[arg(long)]
+        hash: bool,
+    },
+    /// Collect for an agent running unprivileged, over `helper.socket` (runs as root)
+    Helper,
+    /// Ask the user at this desktop to allow a response, for the agent running outside their
+    /// session (exit status 0 allows, 1 denies, 2 without an answer in time)
+    #[command(hide = true)]
+    Prompt {
+        /// Seconds the user has to answer
+        #[arg(long)]
+        secs: u64,
+        /// The question (base64)
+        text: String,
+    },
+    /// Print the launchd property list of the agent or the privileged helper (macOS)
+    Launchd {
+        #[arg(long, value_enum, default_value = "agent")]
+        role: crate::launchd::Role,
+        /// User the agent runs as
+        #[arg(long, default_value = "_dadm")]
+        user: String,
+        /// Agent binary (default: this one)
+        #[arg(long, value_name = "PATH")]
+        binary: Option<PathBuf>,
+    },
+    /// Secrets referenced from the config as `keystore://<name>`
+    #[command(subcommand)]
+    Secret(SecretCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the JSON Schema of the config file
+    Schema,
+    /// Print the merged config, the files read, and the layer each setting came from
+    Effective,
+    /// Check the config (files, signatures, secrets, settings); fails on a problem
+    Validate,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SecretCommand {
+    /// Store the value read from stdin (one trailing newline removed)
+    Set { name: String },
+    /// Delete a secret
+    Rm { name: String },
+}
+
+/// Store to read: the local one, or a copy opened read-only
+#[derive(Debug, Default, Args)]
+pub struct StoreArgs {
+    /// Copied store database to open read-only
+    #[arg(long, value_name = "PATH")]
+    pub db: Option<PathBuf>,
+    /// Storage secret of the `--db` copy (default: the key provider's)
+    #[arg(long, value_name = "PATH", requires = "db")]
+    pub secret_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct QueryArgs {
+    /// Only results from this time on (default: all)
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub since: Option<i64>,
+    /// Only results before this time
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub until: Option<i64>,
+    /// Only results at this level or above
+    #[arg(long, value_name = "LEVEL")]
+    pub min_risk: Option<RiskLevel>,
+    /// At most this many results
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+    #[command(flatten)]
+    pub store: StoreArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[arg(long, default_value = "jsonl")]
+    pub format: ExportFormat,
+    /// Only events from this time on
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub since: Option<i64>,
+    /// Only events before this time
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub until: Option<i64>,
+    /// Only events of this kind
+    #[arg(long)]
+    pub kind: Option<String>,
+    /// Only events scored at least this (0-1)
+    #[arg(long, value_name = "SCORE")]
+    pub min_risk: Option<f32>,
+    /// Only events whose blind-indexed field has this value
+    #[arg(long = "match", value_name = "FIELD=VALUE", value_parser = parse_match)]
+    pub field_equals: Option<(String, String)>,
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+    /// Apply `uplink.redaction` to the payloads
+    #[arg(long)]
+    pub redact: bool,
+    /// Output file (default: stdout)
+    #[arg(long, value_name = "PATH")]
+    pub out: Option<PathBuf>,
+    #[command(flatten)]
+    pub store: StoreArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct EvaluateArgs {
+    /// Labeled events (JSONL)
+    #[arg(long, value_name = "PATH", required_unless_present = "store")]
+    pub events: Option<PathBuf>,
+    /// Labels (CSV of event id, label)
+    #[arg(long, value_name = "PATH")]
+    pub labels: Option<PathBuf>,
+    /// Replay the stored events instead
+    #[arg(long, conflicts_with = "events")]
+    pub store: bool,
+    /// Only stored events from this time on
+    #[arg(long, value_name = "TIME", value_parser = parse_time, default_value = "0")]
+    pub since: i64,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// Recorded events (JSONL: events, or `export` lines)
+    #[arg(long, value_name = "PATH")]
+    pub input: PathBuf,
+    /// Pace: 1 replays at the recorded speed, 60 sixty times faster; 0 doesn't wait
+    #[arg(long, value_name = "FACTOR", default_value = "0", value_parser = parse_speed)]
+    pub speed: f64,
+    /// Length of a cycle (default: `collectors.process.interval_secs`, else 60)
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub cycle_secs: Option<u64>,
+    /// Only print cycles at this level or above
+    #[arg(long, value_name = "LEVEL")]
+    pub min_risk: Option<RiskLevel>,
+    /// Keep `risk.learning_period_days`, which holds results at low for the first days
+    #[arg(long)]
+    pub learning: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct TrendArgs {
+    /// Entity (`process:<name>`, `user:<uid>`, `device`) instead of the device's window scores
+    #[arg(long)]
+    pub entity: Option<String>,
+    /// Start of the range (default: 24 hours ago)
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub since: Option<i64>,
+    /// End of the range (default: now)
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub until: Option<i64>,
+    #[arg(long, value_name = "SECS")]
+    pub bucket_secs: Option<u64>,
+    /// Buckets averaged into the rolling mean
+    #[arg(long, value_name = "N")]
+    pub rolling: Option<usize>,
+    #[command(flatten)]
+    pub store: StoreArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct FeedbackArgs {
+    /// Event whose risk result the verdict is on
+    #[arg(required_unless_present = "candidates")]
+    pub event_id: Option<String>,
+    /// `fp` or `tp`
+    #[arg(required_unless_present = "candidates")]
+    pub verdict: Option<Verdict>,
+    #[arg(long)]
+    pub note: Option<String>,
+    /// Print allowlist entries suggested by repeated false positives instead
+    #[arg(long, conflicts_with_all = ["event_id", "verdict", "note"])]
+    pub candidates: bool,
+    /// False positives an entry needs to be suggested
+    #[arg(long, default_value_t = 2)]
+    pub min_count: usize,
+}
+
+fn parse_level(directives: &str) -> Result<String, String> {
+    tracing_subscriber::EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+    Ok(directives.to_string())
+}
+
+fn parse_speed(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor >= 0.0 => Ok(factor),
+        _ => Err("expected a factor of 0 or more".to_string()),
+    }
+}
+
+fn parse_match(text: &str) -> Result<(String, String), String> {
+    let (field, value) = text.split_once('=').ok_or("expected FIELD=VALUE")?;
+    Ok((field.to_string(), value.to_string()))
+}
+
+/// A time (ms): a Unix timestamp in ms, or a duration before now such as `90s`, `30m`, `1h`, `7d`
+pub fn parse_time(text: &str) -> Result<i64, String> {
+    if let Ok(ms) = text.parse::<i64>() {
+        return Ok(ms);
+    }
+    let unit_ms = match text.chars().last() {
+        Some('s') => 1_000,
+        Some('m') => 60_000,
+        Some('h') => 3_600_000,
+        Some('d') => 86_400_000,
+        _ => return Err("expected a timestamp in ms or a duration like 30m, 1h, 7d".to_string()),
+    };
+    let count: i64 = text[..text.len() - 1]
+        .parse()
+        .map_err(|_| format!("{}: expected a number before the unit", text))?;
+    Ok(chrono::Utc::now().timestamp_millis() - count.saturating_mul(unit_ms))
+}
+
+impl Cli {
+    /// Config file to load: `--config`, else `DADM_CONFIG_PATH`, else `config.json`
+    pub fn config_path(&self) -> PathBuf {
+        self.config
+            .clone()
+            .or_else(|| std::env::var("DADM_CONFIG_PATH").ok().map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from("config.json"))
+    }
+
+    /// The flags given, as a config fragment ([`crate::config::Layer::Cli`])
+    pub fn layer(&self) -> Value {
+        let mut cli = json!({});
+        if let Some(ref dir) = self.data_dir {
+            cli["data_dir"] = json!(dir);
+        }
+        if self.once || matches!(self.command, Some(Command::ScanOnce)) {
+            cli["collectors"]["process"]["interval_secs"] = json!(0);
+        }
+        if let Some(secs) = self.interval {
+            cli["collectors"]["process"]["interval_secs"] = json!(secs);
+        }
+        if let Some(ref level) = self.log_level {
+            cli["log"]["level"] = json!(level);
+        }
+        if self.no_uplink {
+            cli["uplink"]["enabled"] = json!(false);
+        }
+        if self.dry_run {
+            cli["dry_run"] = json!(true);
+        }
+        cli
+    }
+
+    /// Set the flags given into `config`
+    pub fn apply(&self, config: &mut AgentConfig) {
+        let mut value = serde_json::to_value(&*config).unwrap_or_default();
+        merge(&mut value, &self.layer(), "", &mut Vec::new());
+        if let Ok(merged) = serde_json::from_value(value) {
+            *config = merged;
+        }
+    }
+}
@@ -1,28 +1,38 @@
 //! File integrity hashes (scan paths, emit hash + metadata).
 
 use super::{Event, EventKind, FileIntegrityEvent, FileIntegrityChange};
+use crate::config::FileCollectorConfig;
+use crate::risk::rules::wildcard_match;
 use sha2::{Sha256, Digest};
 use std::path::Path;
 use std::sync::Mutex;
 use walkdir::WalkDir;
-use std::collections::HashSet;
-
-const MAX_FILES_PER_SNAPSHOT: usize = 500;
-const MAX_DEPTH: usize = 4;
+use std::collections::HashMap;
 
 pub struct FileIntegrityCollector {
     interval_secs: u64,
     /// Paths to watch (default: temp and home sample)
     watch_paths: Mutex<Vec<std::path::PathBuf>>,
-    last_hashes: Mutex<HashSet<String>>,
+    excludes: Vec<String>,
+    max_file_bytes: u64,
+    max_depth: usize,
+    max_files: usize,
+    /// Hash per path at the last scan (`None` before the first)
+    last_hashes: Mutex<Option<HashMap<String, String>>>,
 }
 
 impl FileIntegrityCollector {
-    pub fn new(interval_secs: u64) -> Self {
+    /// Collector for `collectors.file_integrity`; no `paths` watches the defaults
+    pub fn new(config: &FileCollectorConfig) -> Self {
+        let paths = if config.paths.is_empty() { Self::default_paths() } else { config.paths.clone() };
         Self {
-            interval_secs,
-            watch_paths: Mutex::new(Self::default_paths()),
-            last_hashes: Mutex::new(HashSet::new()),
+            interval_secs: config.interval_secs,
+            watch_paths: Mutex::new(paths),
+            excludes: config.excludes.clone(),
+            max_file_bytes: config.max_file_bytes,
+            max_depth: config.max_depth,
+            max_files: config.max_files,
+            last_hashes: Mutex::new(None),
         }
     }
 
@@ -38,12 +48,28 @@ fn default_paths() -> Vec<std::path::PathBuf> {
         p
     }
 
+    /// Paths currently watched
+    pub fn watch_paths(&self) -> Vec<std::path::PathBuf> {
+        self.watch_paths.lock().map(|p| p.clone()).unwrap_or_default()
+    }
+
     pub fn add_path(&self, path: std::path::PathBuf) {
         if let Ok(mut paths) = self.watch_paths.lock() {
             paths.push(path);
         }
     }
 
+    /// Whether `path` matches `excludes`
+    fn excluded(&self, path: &Path) -> bool {
+        let text = path.to_string_lossy();
+        self.excludes.iter().any(|p| wildcard_match(p, &text))
+    }
+
+    /// Whether `path` is above `max_file_bytes`
+    fn too_large(&self, path: &Path) -> bool {
+        self.max_file_bytes > 0 && std::fs::metadata(path).is_ok_and(|m| m.len() > self.max_file_bytes)
+    }
+
     fn hash_file(path: &Path) -> Option<String> {
         let data = std::fs::read(path).ok()?;
         let mut h = Sha256::new();
@@ -51,56 +77,86 @@ fn hash_file(path: &Path) -> Option<String> {
         Some(format!("{:x}", h.finalize()))
     }
 
+    /// Hash one file into an event (`None` if it can't be read)
+    fn file_event(path: &Path, change: FileIntegrityChange, source: &str) -> Option<Event> {
+        let hash = Self::hash_file(path)?;
+        let m = std::fs::metadata(path).ok();
+        let size = m.as_ref().map(|m| m.len()).unwrap_or(0);
+        let modified = m.and_then(|m| m.modified().ok()).and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok().map(|d| d.as_secs() as i64));
+        let ev = FileIntegrityEvent {
+            path: path.to_string_lossy().to_string(),
+            hash_sha256: hash,
+            size,
+            modified_ts: modified,
+            event: change,
+        };
+        Some(Event::new(EventKind::FileIntegrity(ev), source))
+    }
+
+    /// Deep scan: hash every file under `roots` (files or directories, not just the watch paths)
+    /// up to `max_files`, as `deep_scan` events. Doesn't touch the regular scan's state.
+    pub fn scan_paths(&self, roots: &[std::path::PathBuf], max_files: usize) -> Vec<Event> {
+        let mut events = Vec::new();
+        for root in roots {
+            for entry in WalkDir::new(root)
+                .max_depth(self.max_depth)
+                .follow_links(false)
+                .into_iter()
+                .filter_map(|e| e.ok())
+            {
+                if events.len() >= max_files {
+                    return events;
+                }
+                if entry.file_type().is_file() && !self.too_large(entry.path()) {
+                    events.extend(Self::file_event(entry.path(), FileIntegrityChange::Scanned, "deep_scan"));
+                }
+            }
+        }
+        events
+    }
+
+    /// Hash the watched files; against the previous scan, new files are `Created` and changed
+    /// ones `Modified` (the first scan reports everything as `Scanned`)
     pub fn snapshot(&self) -> Result<Vec<Event>, std::io::Error> {
         let paths = self.watch_paths.lock().map_err(|_| std::io::ErrorKind::Other)?;
         let mut last = self.last_hashes.lock().map_err(|_| std::io::ErrorKind::Other)?;
         let mut events = Vec::new();
-        let mut current_hashes = HashSet::new();
+        let mut current_hashes = HashMap::new();
 
         for root in paths.iter() {
             if !root.exists() {
                 continue;
             }
             for entry in WalkDir::new(root)
-                .max_depth(MAX_DEPTH)
+                .max_depth(self.max_depth)
                 .follow_links(false)
                 .into_iter()
+                .filter_entry(|e| !self.excluded(e.path()))
                 .filter_map(|e| e.ok())
             {
-                if events.len() >= MAX_FILES_PER_SNAPSHOT {
+                if events.len() >= self.max_files {
                     break;
                 }
                 let path = entry.path();
-                if path.is_dir() {
+                if path.is_dir() || self.too_large(path) {
                     continue;
                 }
                 let path_str = path.to_string_lossy().to_string();
-                let (hash, size, modified) = match Self::hash_file(path) {
-                    Some(h) => {
-                        let m = std::fs::metadata(path).ok();
-                        let size = m.as_ref().map(|m| m.len()).unwrap_or(0);
-                        let modified = m.and_then(|m| m.modified().ok()).and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok().map(|d| d.as_secs() as i64));
-                        (h, size, modified)
-                    }
-                    None => continue,
-                };
-                current_hashes.insert(path_str.clone());
-                let change = if last.contains(&path_str) {
-                    FileIntegrityChange::Scanned
-                } else {
-                    FileIntegrityChange::Scanned
-                };
-                let ev = FileIntegrityEvent {
-                    path: path_str,
-                    hash_sha256: hash,
-                    size,
-                    modified_ts: modified,
-                    event: change,
+                let Some(mut ev) = Self::file_event(path, FileIntegrityChange::Scanned, "file_integrity") else {
+                    continue;
                 };
-                events.push(Event::new(EventKind::FileIntegrity(ev), "file_integrity"));
+                if let EventKind::FileIntegrity(ref mut f) = ev.kind {
+                    f.event = match last.as_ref().map(|last| last.get(&path_str)) {
+                        Some(None) => FileIntegrityChange::Created,
+                        Some(Some(hash)) if *hash != f.hash_sha256 => FileIntegrityChange::Modified,
+                        _ => FileIntegrityChange::Scanned,
+                    };
+                    current_hashes.insert(path_str, f.hash_sha256.clone());
+                }
+                events.push(ev);
             }
         }
-        *last = current_hashes;
+        *last = Some(current_hashes);
         Ok(events)
     }
 }
@@ -8,12 +8,14 @@
 
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
 use uuid::Uuid;
 
-pub use process::ProcessCollector;
+pub use process::{ProcessCollector, ProcessDetails};
 pub use network::NetworkCollector;
 pub use file::FileIntegrityCollector;
-pub use privilege::PrivilegeCollector;
+pub use privilege::{parse_auth_line, PrivilegeCollector};
 
 /// Unified event from any collector
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,6 +46,9 @@ pub struct ProcessEvent {
     pub cmdline: Option<String>,
     pub uid: Option<u32>,
     pub started_at: Option<i64>,
+    /// Code-signing publisher, when the platform layer verified the executable's signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,6 +57,9 @@ pub struct NetworkEvent {
     pub local_port: Option<u16>,
     pub remote_addr: Option<String>,
     pub remote_port: Option<u16>,
+    /// DNS name of the remote end, when the platform layer resolved it (DNS cache, SNI)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_host: Option<String>,
     pub protocol: String,
     pub bytes_sent: u64,
     pub bytes_recv: u64,
@@ -85,6 +93,21 @@ pub struct PrivilegeEvent {
     pub method: String,
 }
 
+/// Collector names, in the order [`CollectorPipeline`] runs them (each one's event `type`)
+pub const COLLECTORS: [&str; 4] = ["process", "network", "file_integrity", "privilege"];
+
+impl EventKind {
+    /// Serialized `type` tag (`process`, `network`, `file_integrity`, `privilege`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Process(_) => "process",
+            EventKind::Network(_) => "network",
+            EventKind::FileIntegrity(_) => "file_integrity",
+            EventKind::Privilege(_) => "privilege",
+        }
+    }
+}
+
 impl Event {
     pub fn new(kind: EventKind, source: impl Into<String>) -> Self {
         Self {
@@ -97,39 +120,177 @@ pub fn new(kind: EventKind, source: impl Into<String>) -> Self {
     }
 }
 
+/// Temporary higher-fidelity collection requested on elevated risk (`RiskEngine::deep_scan`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeepScan {
+    /// Active until this time (ms)
+    pub until: i64,
+    /// Poll interval while active
+    pub interval_secs: u64,
+    /// Suspect processes whose loaded modules are hashed each cycle
+    pub pids: Vec<u32>,
+    /// Files and directories hashed in full each cycle
+    pub paths: Vec<std::path::PathBuf>,
+    /// Files hashed per cycle at most
+    pub max_files: usize,
+}
+
+impl DeepScan {
+    /// Fold a further request into this one: the later end, the shorter interval, and the union
+    /// of targets
+    fn merge(&mut self, other: DeepScan) {
+        self.until = self.until.max(other.until);
+        self.interval_secs = self.interval_secs.min(other.interval_secs);
+        self.max_files = self.max_files.max(other.max_files);
+        for pid in other.pids {
+            if !self.pids.contains(&pid) {
+                self.pids.push(pid);
+            }
+        }
+        for path in other.paths {
+            if !self.paths.contains(&path) {
+                self.paths.push(path);
+            }
+        }
+    }
+}
+
 /// Orchestrates all collectors and yields unified events (e.g. via channel)
 pub struct CollectorPipeline {
     pub process: ProcessCollector,
     pub network: NetworkCollector,
     pub file: FileIntegrityCollector,
     pub privilege: PrivilegeCollector,
+    /// Which collectors run (`collectors.process.enabled` etc.)
+    enabled: [bool; 4],
+    deep_scan: Mutex<Option<DeepScan>>,
+    /// Privileged helper collecting `helper.collectors` in this process's stead
+    helper: Option<crate::config::HelperConfig>,
+    /// Interval multiplier and file hashing deferral of the resource governor
+    stretch: AtomicU32,
+    defer_hashing: AtomicBool,
 }
 
 impl CollectorPipeline {
     pub fn new(config: &crate::config::CollectorsConfig) -> Self {
         Self {
-            process: ProcessCollector::new(config.process_interval_secs),
-            network: NetworkCollector::default(),
-            file: FileIntegrityCollector::new(config.file_interval_secs),
-            privilege: PrivilegeCollector::default(),
+            process: ProcessCollector::new(&config.process),
+            network: NetworkCollector::new(&config.network),
+            file: FileIntegrityCollector::new(&config.file_integrity),
+            privilege: PrivilegeCollector::new(&config.privilege),
+            enabled: [
+                config.process.enabled,
+                config.network.enabled,
+                config.file_integrity.enabled,
+                config.privilege.enabled,
+            ],
+            deep_scan: Mutex::new(None),
+            helper: None,
+            stretch: AtomicU32::new(1),
+            defer_hashing: AtomicBool::new(false),
         }
     }
 
+    /// Have the privileged helper collect `helper.collectors` when `helper.enabled`
+    pub fn with_helper(mut self, helper: &crate::config::HelperConfig) -> Self {
+        self.helper = helper.enabled.then(|| helper.clone());
+        self
+    }
+
+    /// Apply the resource governor's `throttle` from the next collection on
+    pub fn set_throttle(&self, throttle: crate::governor::Throttle) {
+        self.stretch.store(throttle.stretch.max(1), Ordering::Relaxed);
+        self.defer_hashing.store(throttle.defer_hashing, Ordering::Relaxed);
+    }
+
+    /// Start (or extend) a deep scan
+    pub fn escalate(&self, scan: DeepScan) {
+        let mut current = self.deep_scan.lock().unwrap();
+        match current.as_mut() {
+            Some(active) => active.merge(scan),
+            None => {
+                tracing::info!(pids = ?scan.pids, paths = scan.paths.len(), "deep scan started");
+                *current = Some(scan);
+            }
+        }
+    }
+
+    /// Deep scan active at `now` (ms); an expired one is ended
+    pub fn deep_scan(&self, now: i64) -> Option<DeepScan> {
+        let mut current = self.deep_scan.lock().unwrap();
+        if current.as_ref().is_some_and(|s| now >= s.until) {
+            tracing::info!("deep scan ended; collection back to normal");
+            *current = None;
+        }
+        current.clone()
+    }
+
+    /// Poll interval at `now`: the deep scan's while one is active, else `normal` stretched by the
+    /// resource governor
+    pub fn interval_secs(&self, normal: u64, now: i64) -> u64 {
+        let stretched = normal.saturating_mul(self.stretch.load(Ordering::Relaxed) as u64);
+        self.deep_scan(now).map_or(stretched, |s| s.interval_secs.min(normal))
+    }
+
     /// Collect current snapshot of events (polling). In production, would be driven by OS hooks.
+    /// With a helper, the kinds it collects are asked from it; when it can't be reached they are
+    /// missing from the snapshot. File integrity is skipped while the resource governor defers
+    /// hashing; a deep scan's files are hashed regardless.
     pub fn collect_snapshot(&self) -> Vec<Event> {
+        let scan = self.deep_scan(Utc::now().timestamp_millis());
+        let deferred = self.defer_hashing.load(Ordering::Relaxed);
+        let kinds: Vec<String> = COLLECTORS
+            .iter()
+            .zip(self.enabled)
+            .filter(|(kind, enabled)| *enabled && !(deferred && **kind == "file_integrity"))
+            .map(|(kind, _)| kind.to_string())
+            .collect();
+        let Some(ref helper) = self.helper else {
+            return self.collect_kinds(&kinds, scan.as_ref());
+        };
+        let (remote, local): (Vec<String>, Vec<String>) = kinds.into_iter().partition(|kind| helper.collectors.contains(kind));
+        // Files are hashed where file integrity is collected
+        let remote_scan = helper.collectors.iter().any(|k| k == "file_integrity");
+        let mut out = Vec::new();
+        if !remote.is_empty() || (remote_scan && scan.is_some()) {
+            match crate::helper::collect(&helper.socket, &remote, scan.clone().filter(|_| remote_scan)) {
+                Ok(events) => out.extend(events),
+                Err(e) => tracing::warn!(socket = %helper.socket.display(), error = %e, "privileged helper unavailable"),
+            }
+        }
+        out.extend(self.collect_kinds(&local, scan.as_ref().filter(|_| !remote_scan)));
+        out
+    }
+
+    /// Collect the enabled collectors among `kinds` here, and the deep scan's files with `scan`
+    pub fn collect_kinds(&self, kinds: &[String], scan: Option<&DeepScan>) -> Vec<Event> {
+        let [process, network, file, privilege] =
+            std::array::from_fn(|i| self.enabled[i] && kinds.iter().any(|k| k == COLLECTORS[i]));
         let mut out = Vec::new();
-        if let Ok(events) = self.process.snapshot() {
+        if let Some(Ok(events)) = process.then(|| self.process.snapshot()) {
             out.extend(events);
         }
-        if let Ok(events) = self.network.snapshot() {
+        if let Some(Ok(events)) = network.then(|| self.network.snapshot()) {
             out.extend(events);
         }
-        if let Ok(events) = self.file.snapshot() {
+        if let Some(Ok(events)) = file.then(|| self.file.snapshot()) {
             out.extend(events);
         }
-        if let Ok(events) = self.privilege.snapshot() {
+        if let Some(Ok(events)) = privilege.then(|| self.privilege.snapshot()) {
             out.extend(events);
         }
+        if let Some(scan) = scan {
+            out.extend(self.deep_scan_events(scan));
+        }
         out
     }
+
+    /// Deep scan collection: implicated paths and the modules of suspect processes, hashed in full
+    fn deep_scan_events(&self, scan: &DeepScan) -> Vec<Event> {
+        let mut roots = scan.paths.clone();
+        for pid in &scan.pids {
+            roots.extend(ProcessCollector::modules(*pid));
+        }
+        self.file.scan_paths(&roots, scan.max_files)
+    }
 }
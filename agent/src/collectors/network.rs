@@ -1,22 +1,116 @@
 //! Network flow summaries. Cross-platform best-effort (e.g. sysinfo connections).
+//! With `collectors.network.connections`, Linux also reports established TCP connections from
+//! `/proc/net/tcp{,6}`, attributed to their process when `resolve_pids` is set.
 
 use super::{Event, EventKind, NetworkEvent};
+use crate::config::NetworkCollectorConfig;
 use sysinfo::System;
 use std::sync::Mutex;
 
 pub struct NetworkCollector {
+    connections: bool,
+    resolve_pids: bool,
     sys: Mutex<System>,
 }
 
 impl Default for NetworkCollector {
     fn default() -> Self {
-        Self {
-            sys: Mutex::new(System::new_all()),
+        Self::new(&NetworkCollectorConfig::default())
+    }
+}
+
+/// One established TCP connection
+#[cfg(target_os = "linux")]
+struct Connection {
+    local: (std::net::IpAddr, u16),
+    remote: (std::net::IpAddr, u16),
+    inode: u64,
+}
+
+/// `0100007F:0050` (`/proc/net/tcp`, 32-bit words in host order) as an address
+#[cfg(target_os = "linux")]
+fn parse_endpoint(text: &str) -> Option<(std::net::IpAddr, u16)> {
+    let (addr, port) = text.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut words = Vec::new();
+    for i in (0..addr.len()).step_by(8) {
+        words.push(u32::from_str_radix(addr.get(i..i + 8)?, 16).ok()?);
+    }
+    let ip = match words.as_slice() {
+        [w] => std::net::IpAddr::from(w.to_ne_bytes()),
+        [a, b, c, d] => {
+            let mut bytes = [0u8; 16];
+            for (chunk, w) in bytes.chunks_mut(4).zip([a, b, c, d]) {
+                chunk.copy_from_slice(&w.to_ne_bytes());
+            }
+            std::net::IpAddr::from(bytes)
+        }
+        _ => return None,
+    };
+    Some((ip, port))
+}
+
+/// Established connections in a `/proc/net/tcp`-format table
+#[cfg(target_os = "linux")]
+fn established(table: &str) -> Vec<Connection> {
+    const ESTABLISHED: &str = "01";
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            // sl local_address rem_address st tx:rx tr:when retrnsmt uid timeout inode
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            if cols.get(3) != Some(&ESTABLISHED) {
+                return None;
+            }
+            Some(Connection {
+                local: parse_endpoint(cols.get(1)?)?,
+                remote: parse_endpoint(cols.get(2)?)?,
+                inode: cols.get(9)?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Socket inode to owning pid, from the `/proc/<pid>/fd` links readable to the agent
+#[cfg(target_os = "linux")]
+fn socket_owners() -> std::collections::HashMap<u64, u32> {
+    let mut owners = std::collections::HashMap::new();
+    let Ok(procs) = std::fs::read_dir("/proc") else {
+        return owners;
+    };
+    for entry in procs.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|n| n.parse::<u32>().ok()) else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let target = target.to_string_lossy();
+            if let Some(inode) = target.strip_prefix("socket:[").and_then(|t| t.strip_suffix(']')) {
+                if let Ok(inode) = inode.parse() {
+                    owners.insert(inode, pid);
+                }
+            }
         }
     }
+    owners
 }
 
 impl NetworkCollector {
+    /// Collector for `collectors.network`
+    pub fn new(config: &NetworkCollectorConfig) -> Self {
+        Self {
+            connections: config.connections,
+            resolve_pids: config.resolve_pids,
+            sys: Mutex::new(System::new_all()),
+        }
+    }
+
     /// Snapshot network connections as flow summary events
     pub fn snapshot(&self) -> Result<Vec<Event>, std::io::Error> {
         let mut sys = self.sys.lock().map_err(|_| std::io::ErrorKind::Other)?;
@@ -30,6 +124,7 @@ pub fn snapshot(&self) -> Result<Vec<Event>, std::io::Error> {
                 local_port: None,
                 remote_addr: None,
                 remote_port: None,
+                remote_host: None,
                 protocol: iface.name().to_string(),
                 bytes_sent: data.received(),
                 bytes_recv: data.transmitted(),
@@ -37,6 +132,49 @@ pub fn snapshot(&self) -> Result<Vec<Event>, std::io::Error> {
             };
             events.push(Event::new(EventKind::Network(event), "network"));
         }
+        if self.connections {
+            events.extend(self.connection_events());
+        }
         Ok(events)
     }
+
+    /// Established TCP connections (Linux; nothing elsewhere yet)
+    fn connection_events(&self) -> Vec<Event> {
+        #[cfg(target_os = "linux")]
+        {
+            let mut connections = Vec::new();
+            for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+                if let Ok(text) = std::fs::read_to_string(table) {
+                    connections.extend(established(&text));
+                }
+            }
+            let owners = if self.resolve_pids && !connections.is_empty() {
+                socket_owners()
+            } else {
+                Default::default()
+            };
+            connections
+                .into_iter()
+                .map(|c| {
+                    let event = NetworkEvent {
+                        local_addr: Some(c.local.0.to_string()),
+                        local_port: Some(c.local.1),
+                        remote_addr: Some(c.remote.0.to_string()),
+                        remote_port: Some(c.remote.1),
+                        remote_host: None,
+                        protocol: "tcp".to_string(),
+                        bytes_sent: 0,
+                        bytes_recv: 0,
+                        pid: owners.get(&c.inode).copied(),
+                    };
+                    Event::new(EventKind::Network(event), "network")
+                })
+                .collect()
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = self.resolve_pids;
+            Vec::new()
+        }
+    }
 }
@@ -1,25 +1,124 @@
 //! Privilege escalation attempt detection.
-//! Platform-specific: audit logs, setuid binaries, sudo. Stub for cross-platform compile.
+//! Platform-specific: audit logs, setuid binaries, sudo. Events come from platform hooks; with
+//! the `auth_log` backend (`collectors.privilege.backend`) sudo and su sessions and
+//! authentication failures are also read from the PAM lines of the system auth log.
 
 use super::{Event, EventKind, PrivilegeEvent};
+use crate::config::{PrivilegeBackend, PrivilegeCollectorConfig};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
 use std::sync::Mutex;
 use std::collections::VecDeque;
 
+/// Auth logs tried when `collectors.privilege.auth_log` is unset (Debian, Red Hat)
+const AUTH_LOGS: [&str; 2] = ["/var/log/auth.log", "/var/log/secure"];
+
 /// In production: parse audit logs (Linux), ETW (Windows), or similar.
 /// Here we use a stub that can be fed by platform-specific hooks.
 pub struct PrivilegeCollector {
     recent: Mutex<VecDeque<PrivilegeEvent>>,
+    auth_log: Option<Mutex<AuthLogTail>>,
 }
 
 impl Default for PrivilegeCollector {
     fn default() -> Self {
-        Self {
-            recent: Mutex::new(VecDeque::new()),
+        Self::new(&PrivilegeCollectorConfig::default())
+    }
+}
+
+/// Reads lines appended to the auth log since the last read
+struct AuthLogTail {
+    path: PathBuf,
+    offset: u64,
+}
+
+impl AuthLogTail {
+    /// Start at the end of `path`: earlier lines were logged before the agent ran
+    fn open(path: PathBuf) -> Self {
+        let offset = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        Self { path, offset }
+    }
+
+    /// Complete lines appended since the last call; a shorter file (rotated) is read from the start
+    fn read_new(&mut self) -> std::io::Result<String> {
+        let mut file = std::fs::File::open(&self.path)?;
+        if file.metadata()?.len() < self.offset {
+            self.offset = 0;
         }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        // A partial last line is read again next time
+        let end = buf.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        self.offset += end as u64;
+        Ok(String::from_utf8_lossy(&buf[..end]).into_owned())
     }
 }
 
+/// `uid=<n>` after `key` in `text` (`key` is e.g. `(uid=` or ` uid=`)
+fn uid_after(text: &str, key: &str) -> Option<u32> {
+    let rest = &text[text.find(key)? + key.len()..];
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+/// A sudo or su PAM line as an event: `sudo[1234]: pam_unix(sudo:session): session opened for
+/// user root(uid=0) by alice(uid=1000)` or `su[99]: pam_unix(su:auth): authentication failure;
+/// logname=alice uid=1000 ...`
+pub fn parse_auth_line(line: &str) -> Option<PrivilegeEvent> {
+    let start = line.find("pam_unix(")?;
+    let (method, _) = line[start + "pam_unix(".len()..].split_once(':')?;
+    if !matches!(method, "sudo" | "sudo-i" | "su" | "su-l") {
+        return None;
+    }
+    let pid = line[..start]
+        .rfind('[')
+        .and_then(|i| line[i + 1..start].split(']').next())
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(0);
+    let message = &line[start..];
+    let (from_uid, to_uid, success) = if let Some(i) = message.find("session opened for user ") {
+        let rest = &message[i..];
+        let (target, by) = rest.split_once(" by ").unwrap_or((rest, ""));
+        (uid_after(by, "(uid=")?, uid_after(target, "(uid="), true)
+    } else if message.contains("authentication failure") {
+        (uid_after(message, " uid=")?, None, false)
+    } else {
+        return None;
+    };
+    Some(PrivilegeEvent { pid, from_uid, to_uid, success, method: method.to_string() })
+}
+
 impl PrivilegeCollector {
+    /// Collector for `collectors.privilege`
+    pub fn new(config: &PrivilegeCollectorConfig) -> Self {
+        let auth_log = match config.backend {
+            PrivilegeBackend::Hooks => None,
+            PrivilegeBackend::AuthLog => {
+                let path = config
+                    .auth_log
+                    .clone()
+                    .or_else(|| AUTH_LOGS.iter().map(PathBuf::from).find(|p| p.exists()));
+                match path {
+                    Some(path) => Some(Mutex::new(AuthLogTail::open(path))),
+                    None => {
+                        tracing::warn!("no auth log found; privilege events come from hooks only");
+                        None
+                    }
+                }
+            }
+        };
+        Self {
+            recent: Mutex::new(VecDeque::new()),
+            auth_log,
+        }
+    }
+
+    /// Auth log read by the `auth_log` backend
+    pub fn auth_log(&self) -> Option<PathBuf> {
+        self.auth_log.as_ref().and_then(|t| t.lock().ok().map(|t| t.path.clone()))
+    }
+
     /// Record a privilege event (call from platform layer when e.g. setuid/sudo detected)
     pub fn record(&self, e: PrivilegeEvent) {
         if let Ok(mut q) = self.recent.lock() {
@@ -30,8 +129,23 @@ pub fn record(&self, e: PrivilegeEvent) {
         }
     }
 
+    /// Record the sudo and su events appended to the auth log
+    fn read_auth_log(&self, tail: &Mutex<AuthLogTail>) {
+        let mut tail = match tail.lock() {
+            Ok(tail) => tail,
+            Err(_) => return,
+        };
+        match tail.read_new() {
+            Ok(text) => text.lines().filter_map(parse_auth_line).for_each(|e| self.record(e)),
+            Err(e) => tracing::debug!(path = %tail.path.display(), error = %e, "auth log unreadable"),
+        }
+    }
+
     /// Snapshot: return recent privilege events as unified Events
     pub fn snapshot(&self) -> Result<Vec<Event>, std::io::Error> {
+        if let Some(ref tail) = self.auth_log {
+            self.read_auth_log(tail);
+        }
         let mut q = self.recent.lock().map_err(|_| std::io::ErrorKind::Other)?;
         let out: Vec<Event> = q.drain(..).map(|e| Event::new(EventKind::Privilege(e), "privilege")).collect();
         Ok(out)
@@ -1,18 +1,41 @@
 //! Process execution metadata collector (cross-platform via sysinfo).
 
 use super::{Event, EventKind, ProcessEvent};
-use sysinfo::System;
+use crate::config::ProcessCollectorConfig;
+use serde::Serialize;
+use sysinfo::{Pid, System};
 use std::sync::Mutex;
 
+/// Everything known about one running process, for a server `process_details` command
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessDetails {
+    pub pid: u32,
+    pub ppid: Option<u32>,
+    pub name: String,
+    pub exe: Option<String>,
+    /// Full command line, arguments included
+    pub cmdline: Vec<String>,
+    pub cwd: Option<String>,
+    pub status: String,
+    /// Start time (seconds since the epoch)
+    pub started_at: u64,
+    pub memory_bytes: u64,
+    /// Mapped executable and libraries (see [`ProcessCollector::modules`])
+    pub modules: Vec<String>,
+}
+
 pub struct ProcessCollector {
     interval_secs: u64,
+    include_kernel_threads: bool,
     sys: Mutex<System>,
 }
 
 impl ProcessCollector {
-    pub fn new(interval_secs: u64) -> Self {
+    /// Collector for `collectors.process`
+    pub fn new(config: &ProcessCollectorConfig) -> Self {
         Self {
-            interval_secs,
+            interval_secs: config.interval_secs,
+            include_kernel_threads: config.include_kernel_threads,
             sys: Mutex::new(System::new_all()),
         }
     }
@@ -25,6 +48,9 @@ pub fn snapshot(&self) -> Result<Vec<Event>, std::io::Error> {
 
         let mut events = Vec::new();
         for (pid, proc_) in sys.processes() {
+            if !self.include_kernel_threads && proc_.thread_kind() == Some(sysinfo::ThreadKind::Kernel) {
+                continue;
+            }
             let exe = proc_.exe().and_then(|p| p.to_str().map(String::from));
             let cmd = proc_.cmd().first().cloned().unwrap_or_else(|| proc_.name().to_string());
             let event = ProcessEvent {
@@ -35,9 +61,60 @@ pub fn snapshot(&self) -> Result<Vec<Event>, std::io::Error> {
                 cmdline: Some(cmd),
                 uid: None, // sysinfo doesn't provide; platform layer can fill
                 started_at: None,
+                publisher: None,
             };
             events.push(Event::new(EventKind::Process(event), "process"));
         }
         Ok(events)
     }
+
+    /// Details of process `pid`, if it is running
+    pub fn details(&self, pid: u32) -> Option<ProcessDetails> {
+        let mut sys = self.sys.lock().ok()?;
+        let pid_ = Pid::from_u32(pid);
+        if !sys.refresh_process(pid_) {
+            return None;
+        }
+        let proc_ = sys.process(pid_)?;
+        Some(ProcessDetails {
+            pid,
+            ppid: proc_.parent().map(|p| p.as_u32()),
+            name: proc_.name().to_string(),
+            exe: proc_.exe().map(|p| p.to_string_lossy().into_owned()),
+            cmdline: proc_.cmd().to_vec(),
+            cwd: proc_.cwd().map(|p| p.to_string_lossy().into_owned()),
+            status: proc_.status().to_string(),
+            started_at: proc_.start_time(),
+            memory_bytes: proc_.memory(),
+            modules: Self::modules(pid).iter().map(|p| p.to_string_lossy().into_owned()).collect(),
+        })
+    }
+
+    /// Files mapped into `pid` (executable and loaded libraries), for deep scans. Linux reads
+    /// `/proc/<pid>/maps`; other platforms have no module enumeration yet and return nothing.
+    pub fn modules(pid: u32) -> Vec<std::path::PathBuf> {
+        #[cfg(target_os = "linux")]
+        {
+            let Ok(maps) = std::fs::read_to_string(format!("/proc/{}/maps", pid)) else {
+                return Vec::new();
+            };
+            let mut out: Vec<std::path::PathBuf> = Vec::new();
+            for line in maps.lines() {
+                // address perms offset dev inode pathname; only the pathname contains '/'
+                let Some(start) = line.find('/') else {
+                    continue;
+                };
+                let path = std::path::PathBuf::from(line[start..].trim_end_matches(" (deleted)"));
+                if !out.contains(&path) {
+                    out.push(path);
+                }
+            }
+            out
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = pid;
+            Vec::new()
+        }
+    }
 }
@@ -0,0 +1,283 @@
+//! Server tasking (`commands`): the server asks the agent for an action, either polled from
+//! the uplink or pushed over the stream, and gets the result back. Actions:
+//! - `file_scan` `{"paths": [...], "max_files": n}`: hash every file under the paths (default: the
+//!   watch paths) and return path, size, and SHA-256 of each
+//! - `process_details` `{"pid": n}`: command line, working directory, memory, mapped modules
+//! - `set_interval` `{"process_interval_secs": n}`: change the collection interval until restart
+//! - `isolate` / `release`: isolate the host, see [`crate::response::isolation`], or with
+//!   `commands.isolate_command` / `release_command` set, run those; the server can't choose what
+//!   is run
+//! - `kill_process` / `suspend_process` `{"pid": n, "name": "..."}`: see [`crate::response`];
+//!   with `name`, a pid now belonging to another process is refused
+//! - `block_network` `{"ip": "...", "port": n}` or `{"pid": n, "name": "..."}`, with
+//!   `"duration_secs": n`: block the traffic in the host firewall until it expires, see
+//!   [`crate::response::firewall`]; `unblock_network` `{"id": "..."}` lifts a block early
+//! - `undo` `{"id": "..."}`: undo a response action kept in the response trail, see
+//!   [`crate::response::trail`]
+//!
+//! Response actions taken are kept in the response trail, and their result has its id
+//! (`response_id`).
+//!
+//! Only actions listed in `commands.actions` run. Every command, run or refused, is recorded in
+//! the store's audit trail ([`crate::SecureStore::record_command`]). In a dry run (`dry_run` or
+//! `response.dry_run`), `isolate`, `release`, and the process and network actions only report
+//! what they would have done.
+
+use crate::collectors::{CollectorPipeline, EventKind};
+use crate::config::CommandsConfig;
+use crate::response::{self, ProcessAction, ResponseEngine, ResponseOutcome, UndoActor};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Output of `isolate` / `release` kept in the result (bytes, from the end)
+const MAX_COMMAND_OUTPUT: usize = 4096;
+
+/// Action requested by the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Command {
+    /// Server-assigned id; a command is executed once even if delivered again
+    pub id: String,
+    pub action: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    /// Executed; `result` holds its output
+    Done,
+    /// Executed but failed; `result.error` says why
+    Failed,
+    /// Not executed: unknown or disallowed action, or bad arguments
+    Refused,
+}
+
+impl CommandStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandStatus::Done => "done",
+            CommandStatus::Failed => "failed",
+            CommandStatus::Refused => "refused",
+        }
+    }
+}
+
+/// Outcome of a command, reported to the server and kept in the audit trail
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandResult {
+    pub command_id: String,
+    pub action: String,
+    pub args: Value,
+    pub status: CommandStatus,
+    pub result: Value,
+    /// When it was handled (ms)
+    pub ts: i64,
+}
+
+/// What commands act on
+pub struct CommandContext<'a> {
+    pub config: &'a CommandsConfig,
+    pub collectors: &'a CollectorPipeline,
+    /// The daemon's collection interval (`set_interval`)
+    pub interval_secs: &'a mut u64,
+    /// Response actions; without it they are refused
+    pub response: Option<&'a ResponseEngine>,
+    /// Response actions are logged, not taken
+    pub dry_run: bool,
+}
+
+fn refused(reason: impl Into<String>) -> (CommandStatus, Value) {
+    (CommandStatus::Refused, json!({ "error": reason.into() }))
+}
+
+fn failed(reason: impl Into<String>) -> (CommandStatus, Value) {
+    (CommandStatus::Failed, json!({ "error": reason.into() }))
+}
+
+fn file_scan(ctx: &CommandContext, args: &Value) -> (CommandStatus, Value) {
+    let paths: Vec<std::path::PathBuf> = match args.get("paths") {
+        Some(paths) => match serde_json::from_value(paths.clone()) {
+            Ok(paths) => paths,
+            Err(e) => return refused(format!("paths: {}", e)),
+        },
+        None => ctx.collectors.file.watch_paths(),
+    };
+    let max_files = args
+        .get("max_files")
+        .and_then(Value::as_u64)
+        .map_or(ctx.config.max_scan_files, |n| (n as usize).min(ctx.config.max_scan_files));
+    let files: Vec<Value> = ctx
+        .collectors
+        .file
+        .scan_paths(&paths, max_files)
+        .into_iter()
+        .filter_map(|event| match event.kind {
+            EventKind::FileIntegrity(f) => Some(json!({ "path": f.path, "size": f.size, "sha256": f.hash_sha256 })),
+            _ => None,
+        })
+        .collect();
+    (CommandStatus::Done, json!({ "files": files.len(), "truncated": files.len() >= max_files, "hashes": files }))
+}
+
+/// Result of the response action taken for `command`, kept in the response trail
+fn taken(engine: &ResponseEngine, command: &Command, outcome: ResponseOutcome, now_ms: i64) -> (CommandStatus, Value) {
+    let reason = json!({ "command": command.action, "args": command.args });
+    let id = engine.keep("server", reason, outcome.before(), &outcome, now_ms);
+    let mut result = serde_json::to_value(&outcome).unwrap_or_default();
+    if let (Some(id), Some(fields)) = (id, result.as_object_mut()) {
+        fields.insert("response_id".into(), id.into());
+    }
+    (outcome.status(), result)
+}
+
+fn process_action(ctx: &CommandContext, command: &Command, action: ProcessAction, now_ms: i64) -> (CommandStatus, Value) {
+    let Some(engine) = ctx.response else {
+        return refused("response actions are not available");
+    };
+    let Some(pid) = command.args.get("pid").and_then(Value::as_u64).and_then(|pid| u32::try_from(pid).ok()) else {
+        return refused("pid missing");
+    };
+    let name = command.args.get("name").and_then(Value::as_str);
+    let trigger = format!("command:{}", command.id);
+    let outcome = response::act_on_process(engine.config(), action, pid, name, &trigger, ctx.dry_run);
+    taken(engine, command, ResponseOutcome::Process(outcome), now_ms)
+}
+
+fn block_network(ctx: &CommandContext, command: &Command, now_ms: i64) -> (CommandStatus, Value) {
+    let Some(engine) = ctx.response else {
+        return refused("network blocks are not available");
+    };
+    let firewall = engine.firewall();
+    let args = &command.args;
+    let trigger = format!("command:{}", command.id);
+    let duration_secs = args.get("duration_secs").and_then(Value::as_u64);
+    let outcome = match (args.get("ip"), args.get("pid")) {
+        (Some(ip), None) => {
+            let Some(ip) = ip.as_str().and_then(|ip| ip.parse().ok()) else {
+                return refused("ip must be an IPv4 or IPv6 address");
+            };
+            let port = match args.get("port") {
+                Some(port) => match port.as_u64().and_then(|p| u16::try_from(p).ok()).filter(|p| *p > 0) {
+                    Some(port) => Some(port),
+                    None => return refused("port must be 1-65535"),
+                },
+                None => None,
+            };
+            firewall.block_remote(ip, port, duration_secs, &trigger, now_ms)
+        }
+        (None, Some(pid)) => {
+            let Some(pid) = pid.as_u64().and_then(|pid| u32::try_from(pid).ok()) else {
+                return refused("pid must be a process id");
+            };
+            firewall.block_process(pid, args.get("name").and_then(Value::as_str), duration_secs, &trigger, now_ms)
+        }
+        _ => return refused("either ip or pid is required"),
+    };
+    taken(engine, command, ResponseOutcome::Network(outcome), now_ms)
+}
+
+/// `isolate` / `release`: the configured programs, else the built-in isolation
+fn isolation(ctx: &CommandContext, command: &Command, now_ms: i64) -> (CommandStatus, Value) {
+    let (program, isolate) = match command.action.as_str() {
+        "isolate" => (&ctx.config.isolate_command, true),
+        _ => (&ctx.config.release_command, false),
+    };
+    if !program.is_empty() {
+        return run_program(program, &command.action, ctx.dry_run || ctx.response.is_some_and(|r| r.config().dry_run));
+    }
+    let Some(engine) = ctx.response else {
+        return refused(format!("{} is not configured on this device", command.action));
+    };
+    let trigger = format!("command:{}", command.id);
+    if isolate {
+        taken(engine, command, ResponseOutcome::Network(engine.firewall().isolate(&trigger, now_ms)), now_ms)
+    } else {
+        let outcome = engine.firewall().release(&trigger);
+        (outcome.status, serde_json::to_value(&outcome).unwrap_or_default())
+    }
+}
+
+fn run_program(argv: &[String], action: &str, dry_run: bool) -> (CommandStatus, Value) {
+    let Some((program, args)) = argv.split_first() else {
+        return refused(format!("{} is not configured on this device", action));
+    };
+    if dry_run {
+        tracing::info!(action, command = ?argv, "dry run: would have run the command");
+        return (CommandStatus::Done, json!({ "dry_run": true, "would_run": argv }));
+    }
+    match std::process::Command::new(program).args(args).output() {
+        Ok(output) => {
+            let tail = |bytes: &[u8]| {
+                let text = String::from_utf8_lossy(&bytes[bytes.len().saturating_sub(MAX_COMMAND_OUTPUT)..]);
+                text.into_owned()
+            };
+            let result = json!({
+                "exit_code": output.status.code(),
+                "stdout": tail(&output.stdout),
+                "stderr": tail(&output.stderr),
+            });
+            let status = if output.status.success() { CommandStatus::Done } else { CommandStatus::Failed };
+            (status, result)
+        }
+        Err(e) => failed(format!("{}: {}", program, e)),
+    }
+}
+
+/// Execute `command`
+pub fn execute(command: &Command, ctx: &mut CommandContext, now_ms: i64) -> CommandResult {
+    let args = &command.args;
+    let (status, result) = if !ctx.config.actions.iter().any(|a| a == &command.action) {
+        refused(format!("action {} is not allowed", command.action))
+    } else {
+        match command.action.as_str() {
+            "file_scan" => file_scan(ctx, args),
+            "process_details" => match args.get("pid").and_then(Value::as_u64) {
+                Some(pid) => match ctx.collectors.process.details(pid as u32) {
+                    Some(details) => (CommandStatus::Done, serde_json::to_value(details).unwrap_or_default()),
+                    None => failed(format!("no process {}", pid)),
+                },
+                None => refused("pid missing"),
+            },
+            "set_interval" => match args.get("process_interval_secs").and_then(Value::as_u64) {
+                Some(secs) if secs > 0 => {
+                    let old = std::mem::replace(ctx.interval_secs, secs);
+                    (CommandStatus::Done, json!({ "process_interval_secs": secs, "previous": old }))
+                }
+                _ => refused("process_interval_secs must be a positive number"),
+            },
+            "isolate" | "release" => isolation(ctx, command, now_ms),
+            "kill_process" => process_action(ctx, command, ProcessAction::Kill, now_ms),
+            "suspend_process" => process_action(ctx, command, ProcessAction::Suspend, now_ms),
+            "block_network" => block_network(ctx, command, now_ms),
+            "unblock_network" => match (ctx.response, args.get("id").and_then(Value::as_str)) {
+                (None, _) => refused("network blocks are not available"),
+                (Some(_), None) => refused("id missing"),
+                (Some(engine), Some(id)) => {
+                    let outcome = engine.firewall().unblock(id, &format!("command:{}", command.id));
+                    (outcome.status, serde_json::to_value(&outcome).unwrap_or_default())
+                }
+            },
+            "undo" => match (ctx.response, args.get("id").and_then(Value::as_str)) {
+                (None, _) => refused("response actions are not available"),
+                (Some(_), None) => refused("id missing"),
+                (Some(engine), Some(id)) => match engine.undo(id, UndoActor::Server, &format!("command:{}", command.id), now_ms) {
+                    Ok(record) => {
+                        let status = record.undone.as_ref().map_or(CommandStatus::Failed, |u| u.status);
+                        (status, serde_json::to_value(&record).unwrap_or_default())
+                    }
+                    Err(e) => refused(e.to_string()),
+                },
+            },
+            other => refused(format!("unknown action {}", other)),
+        }
+    };
+    CommandResult {
+        command_id: command.id.clone(),
+        action: command.action.clone(),
+        args: args.clone(),
+        status,
+        result,
+        ts: now_ms,
+    }
+}
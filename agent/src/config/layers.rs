@@ -0,0 +1,204 @@
+//! Layered configuration: built-in defaults < system config file < local overrides
+//! (`config.local.json` next to it) < environment (`DADM_*`) < command-line flags < server
+//! policy. Each layer is a JSON fragment merged over the ones below, so a layer only names the
+//! settings it changes. Every setting remembers the layer that last set it, for
+//! `dadm-agent config effective`.
+
+use super::AgentConfig;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Source of a setting, lowest precedence first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layer {
+    Default,
+    System,
+    Local,
+    Env,
+    Cli,
+    Policy,
+}
+
+/// Local overrides of the config at `path`: `config.json` -> `config.local.json`
+pub fn local_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.local.{}", stem, ext.to_string_lossy()),
+        None => format!("{}.local", stem),
+    };
+    path.with_file_name(name)
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Merge `fragment` over `target` at `path`: objects key by key, anything else replaces the value.
+/// The dotted paths set are appended to `set`.
+pub fn merge(target: &mut Value, fragment: &Value, path: &str, set: &mut Vec<String>) {
+    match fragment {
+        Value::Object(fields) => {
+            if !target.is_object() {
+                *target = Value::Object(Map::new());
+            }
+            for (key, value) in fields {
+                let path = join(path, key);
+                let slot = target.as_object_mut().expect("object above").entry(key.clone()).or_insert(Value::Null);
+                merge(slot, value, &path, set);
+            }
+        }
+        // `"network": false` (flat layout) switches a collector section, keeping its settings
+        Value::Bool(on) if path.starts_with("collectors.") && target.get("enabled").is_some() => {
+            target["enabled"] = Value::Bool(*on);
+            set.push(join(path, "enabled"));
+        }
+        _ => {
+            *target = fragment.clone();
+            set.push(path.to_string());
+        }
+    }
+}
+
+/// Leaf paths of `value` (arrays and empty objects are leaves)
+fn leaves(value: &Value, path: &str, out: &mut Vec<String>) {
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            for (key, value) in fields {
+                leaves(value, &join(path, key), out);
+            }
+        }
+        _ => out.push(path.to_string()),
+    }
+}
+
+/// Check that `fragment`, the environment or command-line layer, only sets what may still change
+/// over a signed config: the log level, the enrollment code, or a single cycle (`--once`). The
+/// rest of a signed config, such as the model, the uplink, or `data_dir`, can't be overridden by
+/// whoever can edit the service's environment or command line.
+pub fn check_unsigned(layer: Layer, fragment: &Value) -> Result<(), BoxError> {
+    let mut paths = Vec::new();
+    leaves(fragment, "", &mut paths);
+    for path in paths {
+        let value = match path.as_str() {
+            "" => Some(fragment),
+            _ => fragment.pointer(&format!("/{}", path.replace('.', "/"))),
+        };
+        let allowed = match path.as_str() {
+            // An empty object sets nothing
+            _ if value.and_then(Value::as_object).is_some_and(Map::is_empty) => true,
+            "log.level" | "uplink.enrollment_code" => true,
+            "collectors.process.interval_secs" => value.and_then(Value::as_u64) == Some(0),
+            _ => false,
+        };
+        if !allowed {
+            let source = if layer == Layer::Env { "the environment" } else { "the command line" };
+            return Err(format!("{} can't be set from {} over a signed config", path, source).into());
+        }
+    }
+    Ok(())
+}
+
+/// Config built from layers, with the source of each setting
+#[derive(Debug, Clone)]
+pub struct LayeredConfig {
+    value: Value,
+    /// Paths set by a layer other than the defaults
+    sources: BTreeMap<String, Layer>,
+    /// Files read, by layer
+    files: Vec<(Layer, PathBuf)>,
+    /// Settings upgraded from an older layout, by file
+    warnings: Vec<String>,
+}
+
+impl Default for LayeredConfig {
+    fn default() -> Self {
+        Self {
+            value: serde_json::to_value(AgentConfig::default()).unwrap_or_default(),
+            sources: BTreeMap::new(),
+            files: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+impl LayeredConfig {
+    /// The built-in defaults
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Merge `fragment` over the layers so far
+    pub fn push(&mut self, layer: Layer, fragment: &Value) {
+        let mut fragment = fragment.clone();
+        // The flat collector settings go into their sections, so a layer above can still change them
+        if let Some(collectors) = fragment.get_mut("collectors").and_then(Value::as_object_mut) {
+            super::migrate::nest_collectors(collectors);
+        }
+        let mut set = Vec::new();
+        merge(&mut self.value, &fragment, "", &mut set);
+        for path in set {
+            let prefix = format!("{}.", path);
+            self.sources.retain(|p, _| !p.starts_with(&prefix));
+            self.sources.insert(path, layer);
+        }
+    }
+
+    /// Merge the fragment read from `file`, upgraded to the current layout
+    pub fn push_file(&mut self, layer: Layer, file: &Path, fragment: &Value) {
+        let mut fragment = fragment.clone();
+        self.warnings.extend(super::migrate::upgrade(&mut fragment, &file.display().to_string()));
+        self.push(layer, &fragment);
+        self.files.push((layer, file.to_path_buf()));
+    }
+
+    /// Settings of the files upgraded from an older layout
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Files merged, by layer
+    pub fn files(&self) -> &[(Layer, PathBuf)] {
+        &self.files
+    }
+
+    /// The merged config
+    pub fn config(&self) -> Result<AgentConfig, BoxError> {
+        Ok(serde_json::from_value(self.value.clone())?)
+    }
+
+    /// Layer the setting at dotted `path` (or the object containing it) was last set by
+    pub fn source(&self, path: &str) -> Layer {
+        let mut path = path;
+        loop {
+            if let Some(layer) = self.sources.get(path) {
+                return *layer;
+            }
+            match path.rfind('.') {
+                Some(i) => path = &path[..i],
+                None => return Layer::Default,
+            }
+        }
+    }
+
+    /// Every setting of the merged config with its source
+    pub fn sources(&self) -> Result<BTreeMap<String, Layer>, BoxError> {
+        let mut paths = Vec::new();
+        leaves(&serde_json::to_value(self.config()?)?, "", &mut paths);
+        Ok(paths
+            .into_iter()
+            .map(|p| {
+                let layer = self.source(&p);
+                (p, layer)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,91 @@
+//! Config layout versions. A config file states the layout it is written in with
+//! `config_version`; files of an older layout (no `config_version` is version 1) are upgraded
+//! step by step when read, with a warning for each setting moved, so a release that renames or
+//! restructures settings doesn't silently turn them back to their defaults.
+
+use serde_json::{Map, Value};
+
+/// Layout written by this release
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Upgrade from the version at the index + 1 to the next one; returns what it changed
+type Step = fn(&mut Map<String, Value>) -> Vec<String>;
+
+/// Steps, from version 1 on
+const STEPS: &[Step] = &[v1_collector_sections];
+
+/// Version 2 gave every collector a section: `"process": true` becomes `{"enabled": true}` and
+/// the flat settings move into their sections
+fn v1_collector_sections(config: &mut Map<String, Value>) -> Vec<String> {
+    let Some(collectors) = config.get_mut("collectors").and_then(Value::as_object_mut) else {
+        return Vec::new();
+    };
+    let mut changes = Vec::new();
+    for section in ["process", "network", "file_integrity", "privilege"] {
+        if let Some(Value::Bool(enabled)) = collectors.get(section).cloned() {
+            collectors.insert(section.to_string(), serde_json::json!({ "enabled": enabled }));
+            changes.push(format!("collectors.{} -> collectors.{}.enabled", section, section));
+        }
+    }
+    for (flat, section, key) in nest_collectors(collectors) {
+        changes.push(format!("collectors.{} -> collectors.{}.{}", flat, section, key));
+    }
+    changes
+}
+
+/// Move the flat collector settings of `collectors` into their sections; returns the
+/// (flat key, section, key) moved
+pub(super) fn nest_collectors(collectors: &mut Map<String, Value>) -> Vec<(&'static str, &'static str, &'static str)> {
+    let mut moved = Vec::new();
+    for (flat, section, key) in [
+        ("process_interval_secs", "process", "interval_secs"),
+        ("file_interval_secs", "file_integrity", "interval_secs"),
+        ("watch_paths", "file_integrity", "paths"),
+    ] {
+        let Some(value) = collectors.remove(flat) else {
+            continue;
+        };
+        let slot = collectors.entry(section).or_insert(Value::Object(Map::new()));
+        if let Value::Bool(enabled) = *slot {
+            *slot = serde_json::json!({ "enabled": enabled });
+        }
+        if let Value::Object(section) = slot {
+            section.insert(key.to_string(), value);
+        }
+        moved.push((flat, section, key));
+    }
+    moved
+}
+
+/// Upgrade the config file fragment `config` (read from `source`) to [`CONFIG_VERSION`]. Returns
+/// a warning per setting moved, or for a layout newer than this release, whose unknown settings
+/// are ignored.
+pub fn upgrade(config: &mut Value, source: &str) -> Vec<String> {
+    let Some(fields) = config.as_object_mut() else {
+        return Vec::new();
+    };
+    let version = match fields.get("config_version") {
+        None => 1,
+        // Not a number: left for parsing to reject
+        Some(v) => match v.as_u64() {
+            Some(v) => v.clamp(1, u32::MAX as u64) as u32,
+            None => return Vec::new(),
+        },
+    };
+    if version > CONFIG_VERSION {
+        return vec![format!(
+            "{}: config_version {} is newer than this agent's ({}); settings it doesn't know are ignored",
+            source, version, CONFIG_VERSION
+        )];
+    }
+    let mut warnings = Vec::new();
+    for (i, step) in STEPS.iter().enumerate().skip(version as usize - 1) {
+        for change in step(fields) {
+            warnings.push(format!("{}: config_version {}: {} (upgrade the file)", source, i + 1, change));
+        }
+    }
+    if fields.contains_key("config_version") {
+        fields.insert("config_version".to_string(), CONFIG_VERSION.into());
+    }
+    warnings
+}
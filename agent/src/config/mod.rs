@@ -0,0 +1,2097 @@
+//! Agent configuration. Uplink is server-controlled (Aiximius), not user.
+
+pub mod layers;
+pub mod migrate;
+pub mod secrets;
+
+pub use layers::{check_unsigned, local_path, Layer, LayeredConfig};
+pub use migrate::CONFIG_VERSION;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Ed25519 public keys (comma-separated base64) pinned at build time: when set, the config file
+/// must carry a detached signature by one of them ([`AgentConfig::load_signed`])
+const PINNED_CONFIG_KEYS: Option<&str> = option_env!("DADM_CONFIG_KEYS");
+
+/// Keys the config file must be signed with (empty: unsigned configs load)
+pub fn pinned_config_keys() -> Vec<String> {
+    PINNED_CONFIG_KEYS
+        .unwrap_or_default()
+        .split(',')
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty())
+        .collect()
+}
+
+/// JSON Schema (draft 7) of the config file, for validating rendered configs before deployment
+pub fn schema() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(AgentConfig)).unwrap_or_default()
+}
+
+/// Detached signature of the config at `path`: `<path>.sig`
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AgentConfig {
+    /// Layout the file is written in; older layouts are upgraded when read (see [`migrate`])
+    #[serde(default = "default_config_version")]
+    pub config_version: u32,
+    /// Data directory (encrypted store, model cache)
+    pub data_dir: PathBuf,
+    /// Detect-only: detection runs as usual, but results aren't stored, nothing is sent to the
+    /// server, and response actions aren't taken; each is logged as what would have been done
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Local store encryption mode
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Path to ONNX anomaly detection model
+    pub model_path: PathBuf,
+    /// ONNX session pool and threading
+    #[serde(default)]
+    pub model: ModelConfig,
+    /// On-device baseline learning (cold start before a trained model arrives)
+    #[serde(default)]
+    pub baseline: BaselineConfig,
+    /// Enable collectors
+    pub collectors: CollectorsConfig,
+    /// Feature extraction parameters
+    pub features: FeaturesConfig,
+    /// Risk scoring thresholds
+    pub risk: RiskConfig,
+    /// Uplink: controlled by Aiximius server policy, not user preference
+    pub uplink: UplinkConfig,
+    /// Actions the server may request over the uplink
+    #[serde(default)]
+    pub commands: CommandsConfig,
+    /// Response actions against processes, on server command or critical risk
+    #[serde(default)]
+    pub response: ResponseConfig,
+    /// Local control interface for tooling on the same host
+    #[serde(default)]
+    pub control: ControlConfig,
+    /// HTTP API on 127.0.0.1 for monitoring tools and scripts
+    #[serde(default)]
+    pub api: ApiConfig,
+    /// Desktop notifications of high-risk alerts for the user at the machine
+    pub notify: NotifyConfig,
+    /// Privileged collection helper for an agent running unprivileged
+    pub helper: HelperConfig,
+    /// Detection of and recovery from stuck cycles in the daemon
+    pub watchdog: WatchdogConfig,
+    /// Timing of the daemon's collection cycles and periodic tasks
+    pub scheduler: SchedulerConfig,
+    /// CPU, memory, and battery budgets the daemon throttles itself to (low-power mode)
+    pub governor: GovernorConfig,
+    /// Switch from root to an unprivileged account after startup
+    pub privileges: PrivilegesConfig,
+    /// Diagnostic bundles written when the agent panics
+    pub crash: CrashConfig,
+    /// Logging
+    pub log: LogConfig,
+    /// OpenTelemetry traces and metrics of the collection cycle
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    /// OTLP/HTTP base URL (e.g. http://otel-collector:4318); `OTEL_EXPORTER_OTLP_ENDPOINT` when unset
+    pub endpoint: Option<String>,
+    /// Headers sent with every export (e.g. an API key)
+    pub headers: BTreeMap<String, String>,
+    pub service_name: String,
+    pub export_interval_secs: u64,
+    /// Spans kept between exports; further spans are dropped and counted
+    pub max_queued_spans: usize,
+}
+
+/// One section per collector. The flat layout of earlier releases (`"process": true`,
+/// `process_interval_secs`, `file_interval_secs`, `watch_paths`) is still read.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "RawCollectorsConfig")]
+pub struct CollectorsConfig {
+    pub process: ProcessCollectorConfig,
+    pub network: NetworkCollectorConfig,
+    pub file_integrity: FileCollectorConfig,
+    pub privilege: PrivilegeCollectorConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ProcessCollectorConfig {
+    pub enabled: bool,
+    /// Poll interval (seconds); also the daemon's cycle interval (0 = single run)
+    pub interval_secs: u64,
+    /// Report kernel threads (Linux) as processes
+    pub include_kernel_threads: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct NetworkCollectorConfig {
+    pub enabled: bool,
+    /// Also report open TCP connections (Linux), not only interface totals
+    pub connections: bool,
+    /// Attribute connections to the process owning the socket
+    pub resolve_pids: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct FileCollectorConfig {
+    pub enabled: bool,
+    /// Scan interval (seconds)
+    pub interval_secs: u64,
+    /// Directories (or files) hashed; empty: the user's config and data directories and the temp
+    /// directory
+    pub paths: Vec<PathBuf>,
+    /// Wildcard patterns of paths not hashed (e.g. `*/.cache/*`, `*.iso`)
+    pub excludes: Vec<String>,
+    /// Files larger than this are not hashed (0 = no limit)
+    pub max_file_bytes: u64,
+    /// Directory levels descended below each path
+    pub max_depth: usize,
+    /// Files hashed per scan
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct PrivilegeCollectorConfig {
+    pub enabled: bool,
+    pub backend: PrivilegeBackend,
+    /// Auth log read by the `auth_log` backend; unset tries `/var/log/auth.log`, then
+    /// `/var/log/secure`
+    pub auth_log: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivilegeBackend {
+    /// Only events recorded by platform hooks
+    #[default]
+    Hooks,
+    /// Also sudo and su sessions and failures from the PAM lines of the system auth log (Linux)
+    AuthLog,
+}
+
+/// A collector section, or `true` / `false` for its defaults switched on or off (flat layout)
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+#[schemars(rename = "Section_for_{T}")]
+enum RawSection<T> {
+    Enabled(bool),
+    Config(T),
+}
+
+#[derive(Default, Deserialize, JsonSchema)]
+#[serde(default)]
+struct RawCollectorsConfig {
+    process: Option<RawSection<ProcessCollectorConfig>>,
+    network: Option<RawSection<NetworkCollectorConfig>>,
+    file_integrity: Option<RawSection<FileCollectorConfig>>,
+    privilege: Option<RawSection<PrivilegeCollectorConfig>>,
+    /// Flat layout: `process.interval_secs`
+    process_interval_secs: Option<u64>,
+    /// Flat layout: `file_integrity.interval_secs`
+    file_interval_secs: Option<u64>,
+    /// Flat layout: `file_integrity.paths`
+    watch_paths: Option<Vec<PathBuf>>,
+}
+
+/// Described as read: sections, `true` / `false`, or the flat settings
+impl JsonSchema for CollectorsConfig {
+    fn schema_name() -> String {
+        "CollectorsConfig".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        RawCollectorsConfig::json_schema(gen)
+    }
+}
+
+impl<T: Default> RawSection<T> {
+    /// The section; `true` / `false` is the default section with `enabled` set by `enable`
+    fn resolve(self, enable: fn(&mut T, bool)) -> T {
+        match self {
+            RawSection::Enabled(enabled) => {
+                let mut config = T::default();
+                enable(&mut config, enabled);
+                config
+            }
+            RawSection::Config(config) => config,
+        }
+    }
+}
+
+impl From<RawCollectorsConfig> for CollectorsConfig {
+    fn from(raw: RawCollectorsConfig) -> Self {
+        let mut config = CollectorsConfig {
+            process: raw.process.map(|s| s.resolve(|c, on| c.enabled = on)).unwrap_or_default(),
+            network: raw.network.map(|s| s.resolve(|c, on| c.enabled = on)).unwrap_or_default(),
+            file_integrity: raw.file_integrity.map(|s| s.resolve(|c, on| c.enabled = on)).unwrap_or_default(),
+            privilege: raw.privilege.map(|s| s.resolve(|c, on| c.enabled = on)).unwrap_or_default(),
+        };
+        // Flat settings win: a policy written for the flat layout merges them next to the sections
+        if let Some(secs) = raw.process_interval_secs {
+            config.process.interval_secs = secs;
+        }
+        if let Some(secs) = raw.file_interval_secs {
+            config.file_integrity.interval_secs = secs;
+        }
+        if let Some(paths) = raw.watch_paths {
+            config.file_integrity.paths = paths;
+        }
+        config
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FeaturesConfig {
+    /// Sliding window size for behavioral stats
+    pub window_events: usize,
+    /// Number of numerical features expected by model
+    pub feature_dim: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageEncryption {
+    /// AES-GCM on the payload column; ts, kind, and risk_score stay plaintext
+    Column,
+    /// Whole-database page encryption via SQLCipher (`sqlcipher` build feature), plus column encryption
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// Platform keystore (DPAPI / Keychain / keyctl) where available, else key file
+    Auto,
+    /// Key file only (`key_file`, default `data_dir/storage.key`)
+    File,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StorageConfig {
+    /// `column` (default) or `full`; `full` refuses to open the store if built without SQLCipher
+    pub encryption: StorageEncryption,
+    /// Where the device-bound storage secret comes from
+    #[serde(default = "default_key_source")]
+    pub key_source: KeySource,
+    /// Key file path (file provider, DPAPI blob, keyctl fallback)
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    /// Re-sign the event hash chain head every N appended events (0 = never sign)
+    #[serde(default = "default_chain_checkpoint_interval")]
+    pub chain_checkpoint_interval: u64,
+    /// Delete events, risk results, and feature vectors older than this (0 = keep forever)
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: u64,
+    /// Prune oldest rows until the database file is under this size (0 = unbounded)
+    #[serde(default = "default_max_db_bytes")]
+    pub max_db_bytes: u64,
+    /// How often the background retention task runs
+    #[serde(default = "default_retention_interval_secs")]
+    pub retention_interval_secs: u64,
+    /// Ring-buffer mode: hard cap on stored data; oldest rows are evicted on insert (0 = off)
+    #[serde(default)]
+    pub ring_buffer_bytes: u64,
+    /// Pending writes buffered for the background writer thread (0 = write synchronously)
+    #[serde(default = "default_write_queue_capacity")]
+    pub write_queue_capacity: usize,
+    /// Event fields (e.g. `name`, `exe`, `remote_addr`, `path`) tagged with a keyed HMAC on insert
+    /// so they can be searched without decrypting payloads (empty = no blind index)
+    #[serde(default)]
+    pub blind_index_fields: Vec<String>,
+    /// Write events, risk results, and feature vectors to one database file per UTC day;
+    /// retention then deletes whole files
+    #[serde(default)]
+    pub shard_by_day: bool,
+    /// Keep the store in memory only (no database or key file on disk; contents lost on exit)
+    #[serde(default)]
+    pub in_memory: bool,
+    /// Store each distinct event payload (minus its per-event id and timestamp) once and reference
+    /// it from repeats, e.g. unchanged process lists from polling collectors
+    #[serde(default = "default_dedup_payloads")]
+    pub dedup_payloads: bool,
+}
+
+fn default_config_version() -> u32 {
+    CONFIG_VERSION
+}
+
+fn default_key_source() -> KeySource {
+    KeySource::Auto
+}
+
+fn default_chain_checkpoint_interval() -> u64 {
+    256
+}
+
+fn default_max_age_days() -> u64 {
+    30
+}
+
+fn default_max_db_bytes() -> u64 {
+    256 * 1024 * 1024
+}
+
+fn default_retention_interval_secs() -> u64 {
+    3600
+}
+
+fn default_dedup_payloads() -> bool {
+    true
+}
+
+fn default_builtin_rules() -> bool {
+    true
+}
+
+fn default_builtin_correlations() -> bool {
+    true
+}
+
+fn default_class_attack() -> BTreeMap<String, Vec<AttackTag>> {
+    BTreeMap::from([
+        ("ransomware".to_string(), vec![AttackTag::new("T1486", "TA0040")]),
+        ("miner".to_string(), vec![AttackTag::new("T1496", "TA0040")]),
+        ("exfil".to_string(), vec![AttackTag::new("T1041", "TA0010")]),
+    ])
+}
+
+/// A string, or a list of strings
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// A string, or a list of strings (`null` is empty)
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(one)) => vec![one],
+        Some(OneOrMany::Many(many)) => many,
+        None => Vec::new(),
+    })
+}
+
+fn default_write_queue_capacity() -> usize {
+    4096
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ModelConfig {
+    /// Number of ONNX sessions shared by pipeline threads
+    pub session_pool_size: usize,
+    /// Intra-op threads per session (0 = ONNX Runtime default)
+    pub intra_threads: usize,
+    /// Run a dummy inference per session at startup
+    pub warm_up: bool,
+    /// Labels for the optional class-probability output (index order)
+    #[serde(default)]
+    pub class_labels: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BaselineConfig {
+    /// Learn a per-feature median/MAD baseline and score deviation from it
+    pub enabled: bool,
+    /// Learning period (seconds) counted from the first observed feature vector
+    pub learning_period_secs: u64,
+    /// Minimum samples required before the baseline is fitted
+    pub min_samples: usize,
+    /// Maximum samples retained during learning
+    pub max_samples: usize,
+    /// Robust z-score that maps to a baseline score of 0.5
+    pub z_scale: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RiskConfig {
+    /// Score above this is high risk (0.0–1.0)
+    pub high_threshold: f32,
+    /// Score above this is medium risk
+    pub medium_threshold: f32,
+    /// Derive thresholds from the locally stored score distribution
+    #[serde(default)]
+    pub auto_tune: ThresholdTuningConfig,
+    /// Heuristic rules fused with the model score
+    #[serde(default)]
+    pub rules: RulesConfig,
+    /// MITRE ATT&CK tags attached to results whose top model class has this label
+    #[serde(default = "default_class_attack")]
+    pub class_attack: BTreeMap<String, Vec<AttackTag>>,
+    /// Cumulative, time-decayed risk per process, user, and device
+    #[serde(default)]
+    pub entity: EntityRiskConfig,
+    /// Known-good activity excluded from scoring
+    #[serde(default)]
+    pub allowlist: AllowlistConfig,
+    /// Debouncing of level changes and alerts
+    #[serde(default)]
+    pub hysteresis: HysteresisConfig,
+    /// Cross-kind combinations within a time window that boost the score
+    #[serde(default)]
+    pub correlation: CorrelationConfig,
+    /// Days after the first scored window during which results are informational only (level
+    /// held at low, no alerts) while baselines are learned; 0 disables
+    #[serde(default)]
+    pub learning_period_days: u32,
+    /// How the model, rule, IOC, and entity-history scores combine into the result score
+    #[serde(default)]
+    pub fusion: FusionConfig,
+    /// Ordered progression through attack stages by the same entity
+    #[serde(default)]
+    pub kill_chain: KillChainConfig,
+    /// Thresholds and sensitivity for windows whose subject event has this kind (`process`,
+    /// `network`, `file_integrity`, `privilege`)
+    #[serde(default)]
+    pub kind_profiles: BTreeMap<String, ScoringProfile>,
+    /// The same by collector source; takes precedence over `kind_profiles`
+    #[serde(default)]
+    pub source_profiles: BTreeMap<String, ScoringProfile>,
+    /// Exponential smoothing of model scores per entity before fusion and thresholds
+    #[serde(default)]
+    pub smoothing: SmoothingConfig,
+    /// Temporary higher-fidelity collection when a window reaches elevated risk
+    #[serde(default)]
+    pub deep_scan: DeepScanConfig,
+    /// Collapsing of repeated equivalent alerts into one stored result with an occurrence count
+    #[serde(default)]
+    pub dedup: AlertDedupConfig,
+    /// How much this host and its critical paths and services matter
+    #[serde(default)]
+    pub criticality: CriticalityConfig,
+    /// Known-bad hashes, addresses, and domains matched against events
+    #[serde(default)]
+    pub ioc: IocConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct IocConfig {
+    pub enabled: bool,
+    /// Indicator files, one indicator per line: SHA-256 (hex), IP address or CIDR, or domain
+    /// (which also matches its subdomains); `#` starts a comment
+    pub files: Vec<PathBuf>,
+    /// Also fetch indicators from the uplink (`/api/v1/indicators`), cached in `data_dir` for
+    /// offline restarts
+    pub uplink: bool,
+    /// Files are re-read and the uplink list fetched this often
+    pub refresh_secs: u64,
+    /// IOC source score of a window with a match (`risk.fusion.weights.ioc`)
+    pub match_score: f32,
+    /// Hash process executables to match SHA-256 indicators (file integrity hashes always match)
+    pub hash_executables: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CriticalityConfig {
+    /// Weight of this host (1.0 = standard; e.g. 1.5 for a domain controller, 0.7 for a kiosk)
+    pub asset_weight: f32,
+    /// Wildcard patterns for executable and monitored file paths that matter on this host
+    pub critical_paths: Vec<String>,
+    /// Wildcard patterns for process names of services that matter on this host
+    pub critical_services: Vec<String>,
+    /// Further weight for windows whose subject or hit events involve a critical path or service
+    pub critical_weight: f32,
+    pub apply: CriticalityMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CriticalityMode {
+    /// The fused score is multiplied by the weight (capped at 1.0)
+    #[default]
+    Score,
+    /// The medium and high thresholds are divided by the weight
+    Thresholds,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct AlertDedupConfig {
+    pub enabled: bool,
+    /// Repeats within this long of an alert's first occurrence update its result; later ones start
+    /// a new result
+    pub window_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct DeepScanConfig {
+    pub enabled: bool,
+    /// Level (of the window itself, before hysteresis and the learning period) that starts a scan
+    pub min_level: crate::risk::RiskLevel,
+    /// Collection returns to normal this long after the last triggering window
+    pub cooldown_secs: u64,
+    /// Poll interval during the scan (the normal interval if that is shorter)
+    pub interval_secs: u64,
+    /// Files hashed per cycle at most (implicated paths and suspect process modules)
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SmoothingConfig {
+    pub enabled: bool,
+    /// Weight of the newest score (0.0–1.0; 1.0 = no smoothing)
+    pub alpha: f32,
+}
+
+/// Per-kind or per-source scoring; unset thresholds fall back to the effective global ones
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ScoringProfile {
+    pub medium_threshold: Option<f32>,
+    pub high_threshold: Option<f32>,
+    /// Multiplier on the model score before fusion (1.0 = unchanged)
+    pub sensitivity: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct KillChainConfig {
+    pub enabled: bool,
+    /// Stages in kill-chain order, each recognized by the ATT&CK tactics of a window's hits
+    pub stages: Vec<KillChainStage>,
+    /// A progression must complete within this long of its first stage
+    pub window_secs: u64,
+    /// Fused like a rule weight per stage after the first (0.0–1.0)
+    pub stage_boost: f32,
+    /// Progressions reaching this many stages are high risk regardless of score (0 = never)
+    pub high_stages: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct KillChainStage {
+    pub name: String,
+    /// ATT&CK tactic ids (e.g. `TA0003`) that put a window's entities at this stage
+    pub tactics: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct FusionConfig {
+    pub method: FusionMethod,
+    pub weights: FusionWeights,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FusionMethod {
+    /// Each weighted source independently raises the score: 1 - Π(1 - weight × score)
+    #[default]
+    NoisyOr,
+    /// Σ(weight × score) / Σ weight; a source without a signal counts as 0
+    WeightedMean,
+}
+
+/// Weight of each score source (0.0–1.0; 0 leaves a source out)
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct FusionWeights {
+    /// Model (and on-device baseline) score
+    pub model: f32,
+    /// Rule hits, correlation boosts, and the kill-chain boost, combined by noisy-OR
+    pub rules: f32,
+    /// Indicator-of-compromise matches
+    pub ioc: f32,
+    /// Prior accumulated risk of the window's entities, relative to `risk.entity.high_cumulative`
+    pub entity: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CorrelationConfig {
+    /// Include the built-in patterns
+    #[serde(default = "default_builtin_correlations")]
+    pub builtin: bool,
+    /// Additional patterns
+    #[serde(default)]
+    pub patterns: Vec<CorrelationPattern>,
+}
+
+/// Pattern that matches when every step has been seen within `window_secs`, in any order
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CorrelationPattern {
+    pub id: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Fused into the score like a rule weight (0.0–1.0)
+    pub boost: f32,
+    pub window_secs: u64,
+    pub steps: Vec<CorrelationStep>,
+    #[serde(default)]
+    pub attack: Vec<AttackTag>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CorrelationStep {
+    /// Executable not seen since the agent started (optionally only without a verified publisher)
+    NewProcess {
+        #[serde(default)]
+        unsigned_only: bool,
+    },
+    /// Connection to a remote address not seen since the agent started
+    NewRemote,
+    /// Privilege change (optionally only failed ones)
+    PrivilegeAttempt {
+        #[serde(default)]
+        failed_only: bool,
+    },
+    /// File created, modified, or deleted at a path matching any pattern
+    FileWrite { patterns: Vec<String> },
+    /// A heuristic rule matched
+    Rule { id: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct HysteresisConfig {
+    /// Consecutive windows at a higher level before the reported level rises (1 = immediately)
+    pub raise_windows: u32,
+    /// Consecutive windows at a lower level before the reported level falls (1 = immediately)
+    pub clear_windows: u32,
+    /// After an alert, further alerts at the same or a lower level are held back this long
+    pub cooldown_secs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct AllowlistConfig {
+    /// SHA-256 (hex) of executables, or of files reported by file integrity monitoring
+    pub exe_hashes: Vec<String>,
+    /// Code-signing publishers (exact, case-insensitive) of processes
+    pub publishers: Vec<String>,
+    /// Wildcard patterns for executable and monitored file paths
+    pub paths: Vec<String>,
+    /// Remote networks (`10.0.0.0/8`, `fd00::/8`, or a single address) of network events
+    pub remote_cidrs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct EntityRiskConfig {
+    /// Track per-entity risk and escalate the level of results for entities over the bounds
+    pub enabled: bool,
+    /// Time for an entity's accumulated risk to halve
+    pub half_life_secs: u64,
+    /// Window scores below this don't accumulate
+    pub min_score: f32,
+    /// Accumulated risk that raises a result to medium
+    pub medium_cumulative: f32,
+    /// Accumulated risk that raises a result to high
+    pub high_cumulative: f32,
+    /// Entities tracked at once; the lowest-risk ones are dropped beyond this
+    pub max_entities: usize,
+}
+
+/// MITRE ATT&CK technique (e.g. `T1003`, `T1548.003`) and the tactic it serves (e.g. `TA0006`)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+pub struct AttackTag {
+    pub technique: String,
+    pub tactic: String,
+}
+
+impl AttackTag {
+    pub fn new(technique: &str, tactic: &str) -> Self {
+        Self {
+            technique: technique.to_string(),
+            tactic: tactic.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RulesConfig {
+    /// Include the built-in rule set (offensive tools, execution from temp dirs, sensitive file
+    /// writes, privilege failure bursts)
+    #[serde(default = "default_builtin_rules")]
+    pub builtin: bool,
+    /// Additional rules
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+}
+
+/// Declarative rule: when `condition` holds for a window, `weight` (0.0–1.0) is fused into its score
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RuleConfig {
+    pub id: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub weight: f32,
+    pub condition: RuleCondition,
+    /// ATT&CK techniques a hit indicates
+    #[serde(default)]
+    pub attack: Vec<AttackTag>,
+}
+
+/// Rule condition over collected events. Patterns are case-insensitive wildcards
+/// (`*` any run of characters, `?` one character).
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleCondition {
+    /// Process name matches any pattern
+    ProcessName { patterns: Vec<String> },
+    /// Process executable path matches any pattern
+    ProcessPath { patterns: Vec<String> },
+    /// File created, modified, or deleted at a path matching any pattern
+    FileWrite { patterns: Vec<String> },
+    /// At least `count` failed privilege changes within `window_secs`
+    PrivilegeFailures { count: usize, window_secs: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ThresholdTuningConfig {
+    /// Replace static thresholds with quantiles of historical scores
+    pub enabled: bool,
+    /// History window (seconds) the score distribution is taken from
+    pub window_secs: u64,
+    /// Quantile of historical scores used as medium threshold (e.g. 0.99)
+    pub medium_quantile: f32,
+    /// Quantile of historical scores used as high threshold (e.g. 0.999)
+    pub high_quantile: f32,
+    /// Minimum historical scores before tuned thresholds apply
+    pub min_samples: usize,
+    /// How often thresholds are recomputed (seconds)
+    pub interval_secs: u64,
+    /// Lowest tuned threshold, so a history of near-zero scores can't rate everything high
+    #[serde(default = "default_min_tuned_threshold")]
+    pub min_threshold: f32,
+}
+
+fn default_min_tuned_threshold() -> f32 {
+    0.05
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct UplinkConfig {
+    /// Whether uplink is enabled (set by Aiximius server policy, not user)
+    pub enabled: bool,
+    /// Endpoint URLs in priority order (e.g. graph API base: http://graph:5001); a single URL is
+    /// accepted as well. Requests go to the first endpoint whose circuit breaker is closed and
+    /// return to a preferred one once its probe succeeds.
+    #[serde(deserialize_with = "one_or_many")]
+    #[schemars(with = "Option<OneOrMany>")]
+    pub endpoint: Vec<String>,
+    /// Report interval seconds when enabled
+    pub report_interval_secs: u64,
+    /// Device ID sent to graph/fusion (default: local-device)
+    pub device_id: Option<String>,
+    /// Reports waiting for the uplink thread; further reports are dropped while it is full.
+    /// 0 reports synchronously from the collection loop.
+    pub queue_capacity: usize,
+    /// Delay before retrying a failed outbox entry the first time; doubles with each further
+    /// failure (with jitter)
+    pub retry_base_secs: u64,
+    /// Upper bound of the outbox retry delay
+    pub retry_max_secs: u64,
+    /// Size limit of one batched events request (`/api/v1/ingest/batch`); 0 posts each event to
+    /// `/api/v1/events` on its own
+    pub event_batch_bytes: usize,
+    /// Share (0-100) of the events of low-risk reports that are sent; events implicated by a
+    /// rule or indicator hit, events of medium and high risk reports, and risk scores always go
+    pub low_risk_sample_percent: u8,
+    /// Upload budget per hour (request and stream bytes, after compression); once spent, sampled
+    /// low-risk events are held back until the next hour. 0 is unlimited.
+    pub bytes_per_hour: u64,
+    /// Send only state the server has not acknowledged yet (new or changed processes,
+    /// connections, files), with a full checkpoint every `checkpoint_secs`; the device is
+    /// registered again only at checkpoints
+    pub delta_reporting: bool,
+    /// Interval of full-state checkpoints with `delta_reporting`
+    pub checkpoint_secs: u64,
+    /// Consecutive failed requests that open the circuit breaker (requests are then refused
+    /// locally, reports stay queued); 0 never opens it
+    pub breaker_failures: u32,
+    /// While the breaker is open, one probe request is let through this often
+    pub breaker_probe_secs: u64,
+    /// Request body encoding; the client falls back to what the server accepts when it answers
+    /// 415 Unsupported Media Type
+    pub compression: UplinkCompression,
+    /// PEM client certificate (chain) presented for mutual TLS; may also hold the private key
+    pub client_cert_path: Option<PathBuf>,
+    /// PEM private key (PKCS#8, RSA, or EC) of the client certificate, when not in `client_cert_path`
+    pub client_key_path: Option<PathBuf>,
+    /// Private key of the client certificate held by the platform keystore instead: a
+    /// `keystore://<name>` reference to its PEM
+    pub client_key: Option<String>,
+    /// PEM CA certificates trusted for the endpoint in addition to the built-in roots
+    pub ca_cert_path: Option<PathBuf>,
+    /// Base64 SHA-256 hashes of accepted server public keys (SubjectPublicKeyInfo); some
+    /// certificate of the server's chain must match one (or a `pinned_cert_sha256` entry)
+    pub pinned_spki_sha256: Vec<String>,
+    /// Hex SHA-256 fingerprints of accepted server certificates (leaf or intermediate)
+    pub pinned_cert_sha256: Vec<String>,
+    /// Proxy for all uplink requests (`http://[user:password@]host:port`, or `https://` for a TLS
+    /// proxy); when unset, `HTTP_PROXY` / `HTTPS_PROXY` / `ALL_PROXY` apply if `system_proxy`
+    pub proxy: Option<String>,
+    /// Hosts, domains (`.example.com`), and networks reached without `proxy`, as in `NO_PROXY`;
+    /// when empty, the `NO_PROXY` environment variable applies
+    pub no_proxy: Vec<String>,
+    /// Honor the proxy environment variables when `proxy` is unset
+    pub system_proxy: bool,
+    /// Stream reports over a persistent WebSocket (`ws`/`wss` at `stream_path` of the endpoint)
+    /// as they are made, and take server messages on it; HTTP is used while it is down
+    pub streaming: bool,
+    /// Path of the stream endpoint
+    pub stream_path: String,
+    /// One-time code exchanged for a device token on first contact (the token is kept encrypted
+    /// in the store; the code is not needed afterwards)
+    pub enrollment_code: Option<String>,
+    /// Refresh the device token once it expires within this many seconds
+    pub token_refresh_secs: u64,
+    /// Base64 Ed25519 public keys whose signature makes a server policy acceptable; empty
+    /// disables server-managed configuration
+    pub policy_keys: Vec<String>,
+    /// Path of the policy endpoint
+    pub policy_path: String,
+    /// How often the policy is fetched (seconds)
+    pub policy_refresh_secs: u64,
+    /// How often the daemon reports it is alive, with watchdog incidents (seconds; 0 = never)
+    pub heartbeat_secs: u64,
+    /// Privacy filter applied to every payload before it is queued or sent
+    pub redaction: RedactionConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    /// Replace the usernames of local accounts (current user, home directories) in string values
+    pub local_usernames: bool,
+    /// Further usernames (or other words) to replace
+    pub usernames: Vec<String>,
+    /// Replace the account segment of home-directory paths (`/home/<user>`, `/Users/<user>`,
+    /// `C:\Users\<user>`)
+    pub home_paths: bool,
+    /// Drop the arguments of command lines (`cmdline` fields), keeping the program
+    pub cmdline_args: bool,
+    /// Fields whose whole value is replaced, by name at any depth (e.g. `note`, `cwd`)
+    pub fields: Vec<String>,
+    /// Regular expressions whose matches in string values are replaced (e.g. email addresses)
+    pub patterns: Vec<String>,
+    pub mode: RedactionMode,
+    /// Prepended to values before hashing, so fleet-wide hashes cannot be looked up in a
+    /// dictionary of common names
+    pub hash_salt: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionMode {
+    /// Replace values with `[redacted]`
+    #[default]
+    Strip,
+    /// Replace values with a salted SHA-256 prefix (`h:<16 hex>`), stable across devices sharing
+    /// the salt
+    Hash,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UplinkCompression {
+    None,
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+impl UplinkCompression {
+    /// `Content-Encoding` value; `None` for uncompressed bodies
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            UplinkCompression::None => None,
+            UplinkCompression::Gzip => Some("gzip"),
+            UplinkCompression::Zstd => Some("zstd"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CommandsConfig {
+    /// Take commands from the server (polled, and pushed over `uplink.streaming`)
+    pub enabled: bool,
+    /// How often pending commands are fetched (seconds)
+    pub poll_secs: u64,
+    /// Actions the server may request: `file_scan`, `process_details`, `set_interval`,
+    /// `isolate`, `release`, `kill_process`, `suspend_process`, `block_network`,
+    /// `unblock_network`, `undo`; anything else is refused
+    pub actions: Vec<String>,
+    /// Files hashed by one `file_scan` at most
+    pub max_scan_files: usize,
+    /// Program and arguments run for `isolate` (e.g. a firewall script); unset isolates the host
+    /// through the host firewall (see `response.firewall`)
+    pub isolate_command: Vec<String>,
+    /// Program and arguments run for `release`, undoing `isolate`
+    pub release_command: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ResponseConfig {
+    /// Allow response actions; off refuses them all, from server commands and the policy alike
+    pub enabled: bool,
+    /// Only log the response actions that would be taken, as in a dry run of the whole agent
+    pub dry_run: bool,
+    /// Each kind of action can be turned off on its own; one that is off is refused
+    pub actions: ResponseActions,
+    /// Rules mapping risk results to actions, evaluated after scoring; the actions of every
+    /// matching rule are taken
+    pub policy: Vec<ResponseRule>,
+    /// A high result at or above this score is critical
+    pub critical_score: f32,
+    /// When set, only processes whose name or path matches one of these wildcards are acted on
+    pub allowed: Vec<String>,
+    /// Processes (name or path wildcards) never acted on; neither are pid 0 and 1, the agent, or
+    /// its parent
+    pub protected: Vec<String>,
+    /// Host firewall blocks (`block_network`)
+    pub firewall: FirewallConfig,
+    /// Files moved out of reach (`quarantine`)
+    pub quarantine: QuarantineConfig,
+    /// Asking the user before the policy acts on a result below critical
+    pub approval: ApprovalConfig,
+}
+
+/// Kinds of response action allowed
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ResponseActions {
+    pub notify: bool,
+    pub kill_process: bool,
+    pub suspend_process: bool,
+    pub quarantine: bool,
+    pub block_network: bool,
+    pub isolate: bool,
+}
+
+/// Response policy rule: a result at `min_level` or above carrying every one of `tags` gets
+/// `actions`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResponseRule {
+    /// Name in logs and outcomes
+    pub id: String,
+    pub min_level: ResponseLevel,
+    /// Wildcards, each matching a rule id, correlation pattern, ATT&CK technique or tactic, or the
+    /// model's class of the result; empty matches any result
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub actions: Vec<ResponseAction>,
+}
+
+/// Risk level a response rule applies from; `critical` is a high result at or above
+/// `response.critical_score`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseLevel {
+    Medium,
+    High,
+    Critical,
+}
+
+/// Action of a response rule, against what the result implicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseAction {
+    /// Stop the implicated processes
+    SuspendProcess,
+    KillProcess,
+    /// Block the remote addresses of the implicated connections
+    BlockNetwork,
+    /// Isolate the host
+    Isolate,
+    /// Quarantine the implicated files and executables
+    Quarantine,
+    /// Show a desktop notification of what was done
+    Notify,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ApprovalConfig {
+    /// Ask the user at the desktop before the policy acts on a result below critical
+    pub enabled: bool,
+    /// How long the user has to answer (seconds)
+    pub timeout_secs: u64,
+    /// Answer taken when the user doesn't answer in time, or can't be asked
+    pub default: ApprovalDefault,
+    /// Program and arguments asked instead of the desktop dialog, with the question's title and
+    /// text as its last two arguments; exit status 0 allows
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalDefault {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct QuarantineConfig {
+    /// Files (path wildcards) never quarantined; neither are the agent and its `data_dir`
+    pub protected_paths: Vec<String>,
+    /// Largest file quarantined
+    pub max_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct FirewallConfig {
+    /// Firewall the blocks go to; `auto` picks nftables, else iptables, on Linux, pf on macOS,
+    /// and Windows Firewall (WFP) on Windows
+    pub backend: FirewallBackend,
+    /// How long a block lasts when the request sets no duration
+    pub default_duration_secs: u64,
+    /// Longest block; longer requests are cut to it
+    pub max_duration_secs: u64,
+    /// Addresses never blocked, and still reachable while isolated, besides loopback and the
+    /// uplink endpoints
+    pub protected_addresses: Vec<std::net::IpAddr>,
+    /// Salted PBKDF2 hash of the operator credential that lifts host isolation at the machine
+    /// (`dadm-agent release-isolation`; made with `release-isolation --hash`); unset leaves
+    /// releasing to the server
+    pub release_credential_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FirewallBackend {
+    Auto,
+    Nftables,
+    Iptables,
+    Pf,
+    /// Windows Firewall rules (`netsh advfirewall`), enforced through the Windows Filtering Platform
+    Wfp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ControlConfig {
+    /// Serve the control interface while the daemon runs
+    pub enabled: bool,
+    /// Unix socket (relative paths are under `data_dir`; default `control.sock`) or Windows named
+    /// pipe (default `\\.\pipe\dadm-agent`)
+    pub path: Option<PathBuf>,
+    /// Users (uid) allowed to connect besides root and the agent's own user (Unix)
+    pub allowed_uids: Vec<u32>,
+    /// Connections served at once; further ones are closed
+    pub max_connections: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// Serve the API while the daemon runs
+    pub enabled: bool,
+    /// Port on 127.0.0.1
+    pub port: u16,
+    /// Bearer token required on every path but `/health` (may be a `keystore://` reference);
+    /// unset uses a random token kept in `data_dir/api_token`
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct NotifyConfig {
+    pub enabled: bool,
+    /// Lowest level notified (only results that raised an alert)
+    pub min_level: crate::risk::RiskLevel,
+    /// Shortest time between two notifications (seconds); alerts in between are counted into the
+    /// next one
+    pub min_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct HelperConfig {
+    /// Have the helper collect `collectors` instead of collecting them in the agent
+    pub enabled: bool,
+    /// Unix socket the helper serves (and creates)
+    pub socket: PathBuf,
+    /// Collectors run by the helper: `process`, `network`, `file_integrity`, `privilege`
+    pub collectors: Vec<String>,
+    /// Users (uid) the helper serves besides root: the agent's user
+    pub allowed_uids: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    /// A collection or analysis running longer than this (seconds) is stuck
+    pub stall_secs: u64,
+    /// Pipeline restarts within an hour before the agent exits for its service manager to restart
+    pub max_restarts: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    /// Each run of a collection cycle or periodic task is delayed by a random share of its
+    /// interval up to this (0.0–0.5), so agents started together spread their uplink traffic
+    pub jitter: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct GovernorConfig {
+    pub enabled: bool,
+    /// How often the agent's own usage and the battery are measured (seconds)
+    pub sample_secs: u64,
+    /// CPU budget, in percent of one core
+    pub cpu_percent: f32,
+    /// Resident memory budget (MiB)
+    pub memory_mb: u64,
+    /// Interval multiplier at least applied on battery power (1 = none)
+    pub battery_stretch: u32,
+    /// Battery charge (percent) at or below which the agent throttles fully while discharging
+    pub low_battery_percent: f32,
+    /// Largest interval multiplier; windows shrink by the same factor
+    pub max_stretch: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct PrivilegesConfig {
+    pub enabled: bool,
+    /// Account the agent switches to when started as root (Unix)
+    pub user: String,
+    /// Group it switches to (Unix); none = the user's primary group
+    pub group: Option<String>,
+    /// Linux capabilities kept after the switch (`CAP_DAC_READ_SEARCH` or `dac_read_search`)
+    pub capabilities: Vec<String>,
+    /// Privileges kept in the process token on Windows; the others are removed
+    pub windows_privileges: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CrashConfig {
+    pub enabled: bool,
+    /// Recent log lines kept in memory for a bundle
+    pub log_lines: usize,
+    /// Bundles kept in `data_dir/crashes`; older ones are deleted
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: String,
+    pub json: bool,
+    /// With `json`, write Elastic Common Schema documents instead of the tracing JSON layout
+    pub ecs: bool,
+    /// Also write logs to stdout
+    pub stdout: bool,
+    /// Log file (relative paths are under `data_dir`); unset logs to stdout only
+    pub file: Option<PathBuf>,
+    /// Rotate the file before it grows past this size; 0 never rotates by size
+    pub max_file_bytes: u64,
+    /// Rotate the file once it is this old (seconds); 0 never rotates by age
+    pub max_file_age_secs: u64,
+    /// Rotated files kept (`<file>.1` newest); older ones are deleted
+    pub keep_files: usize,
+    /// Also send logs to syslog
+    pub syslog: SyslogConfig,
+    /// Also write logs to the Windows Event Log
+    pub eventlog: EventLogConfig,
+    /// Risk results as CEF / LEEF lines for SIEM ingestion
+    pub siem: SiemConfig,
+    /// Hash-chained log of security-relevant agent actions, apart from operational logs
+    pub audit: AuditConfig,
+    /// Privacy filter applied to every log line before it reaches a sink (as `uplink.redaction`)
+    pub redaction: RedactionConfig,
+    /// Risk results as structured alert lines, apart from operational logs
+    pub alerts: AlertStreamConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct AlertStreamConfig {
+    pub enabled: bool,
+    /// Lowest level written
+    pub min_level: crate::risk::RiskLevel,
+    /// File the alerts are appended to (relative paths are under `data_dir`; rotated like `file`)
+    pub file: Option<PathBuf>,
+    /// Stream socket the alerts are written to: `host:port` (TCP) or a Unix socket path
+    pub socket: Option<String>,
+    /// URL each alert is POSTed to as JSON
+    pub webhook: Option<String>,
+    /// Headers sent with webhook requests (e.g. an authorization token)
+    pub webhook_headers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Audit log file (relative paths are under `data_dir`); never rotated
+    pub file: PathBuf,
+    /// Sign each entry with a key derived from the storage secret (not with in-memory storage)
+    pub sign: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SiemConfig {
+    pub enabled: bool,
+    pub format: SiemFormat,
+    /// Lowest level written
+    pub min_level: crate::risk::RiskLevel,
+    /// Write only results that raised an alert, not every cycle a window stays elevated
+    pub alerts_only: bool,
+    /// File the lines go to (relative paths are under `data_dir`; rotated like `file`), when
+    /// not sent to syslog; stdout when unset
+    pub file: Option<PathBuf>,
+    /// Send the lines to syslog (each line is the MSG of one message)
+    pub syslog: SyslogConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SiemFormat {
+    /// ArcSight Common Event Format
+    #[default]
+    Cef,
+    /// QRadar Log Event Extended Format 1.0
+    Leef,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct EventLogConfig {
+    pub enabled: bool,
+    /// Event source name
+    pub source: String,
+    /// Log the source belongs to
+    pub log: String,
+    /// Register the source under the log at startup (administrator rights)
+    pub register: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SyslogConfig {
+    pub enabled: bool,
+    pub transport: SyslogTransport,
+    /// Socket path for `unix` (default `/dev/log`); `host:port` of the collector for `udp` / `tcp`
+    pub address: Option<String>,
+    /// Facility code (1 user, 3 daemon, 4 auth, 16-23 local0-local7)
+    pub facility: u8,
+    /// APP-NAME of the messages
+    pub app_name: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogTransport {
+    /// Local syslog daemon socket
+    #[default]
+    Unix,
+    Udp,
+    /// Octet-counted framing (RFC 6587)
+    Tcp,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            config_version: CONFIG_VERSION,
+            data_dir: PathBuf::from(".dadm"),
+            dry_run: false,
+            storage: StorageConfig::default(),
+            model_path: PathBuf::from("model.onnx"),
+            model: ModelConfig::default(),
+            baseline: BaselineConfig::default(),
+            collectors: CollectorsConfig::default(),
+            features: FeaturesConfig::default(),
+            risk: RiskConfig::default(),
+            uplink: UplinkConfig::default(),
+            commands: CommandsConfig::default(),
+            response: ResponseConfig::default(),
+            control: ControlConfig::default(),
+            api: ApiConfig::default(),
+            notify: NotifyConfig::default(),
+            helper: HelperConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            scheduler: SchedulerConfig::default(),
+            governor: GovernorConfig::default(),
+            privileges: PrivilegesConfig::default(),
+            crash: CrashConfig::default(),
+            log: LogConfig::default(),
+            telemetry: TelemetryConfig::default(),
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: None,
+            headers: BTreeMap::new(),
+            service_name: "dadm-agent".to_string(),
+            export_interval_secs: 60,
+            max_queued_spans: 10_000,
+        }
+    }
+}
+
+impl Default for ProcessCollectorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 0, // 0 = single shot; >0 = daemon interval (seconds)
+            include_kernel_threads: true,
+        }
+    }
+}
+
+impl Default for NetworkCollectorConfig {
+    fn default() -> Self {
+        Self { enabled: true, connections: false, resolve_pids: true }
+    }
+}
+
+impl Default for FileCollectorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 60,
+            paths: Vec::new(),
+            excludes: Vec::new(),
+            max_file_bytes: 64 * 1024 * 1024,
+            max_depth: 4,
+            max_files: 500,
+        }
+    }
+}
+
+impl Default for PrivilegeCollectorConfig {
+    fn default() -> Self {
+        Self { enabled: true, backend: PrivilegeBackend::Hooks, auth_log: None }
+    }
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self {
+            window_events: 100,
+            feature_dim: 64,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            encryption: StorageEncryption::Column,
+            key_source: KeySource::Auto,
+            key_file: None,
+            chain_checkpoint_interval: 256,
+            max_age_days: 30,
+            max_db_bytes: 256 * 1024 * 1024,
+            retention_interval_secs: 3600,
+            ring_buffer_bytes: 0,
+            write_queue_capacity: 4096,
+            blind_index_fields: Vec::new(),
+            shard_by_day: false,
+            in_memory: false,
+            dedup_payloads: true,
+        }
+    }
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            session_pool_size: 2,
+            intra_threads: 1,
+            warm_up: true,
+            class_labels: Vec::new(),
+        }
+    }
+}
+
+impl Default for BaselineConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            learning_period_secs: 86_400,
+            min_samples: 30,
+            max_samples: 2_000,
+            z_scale: 6.0,
+        }
+    }
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        Self {
+            high_threshold: 0.8,
+            medium_threshold: 0.5,
+            auto_tune: ThresholdTuningConfig::default(),
+            rules: RulesConfig::default(),
+            class_attack: default_class_attack(),
+            entity: EntityRiskConfig::default(),
+            allowlist: AllowlistConfig::default(),
+            hysteresis: HysteresisConfig::default(),
+            correlation: CorrelationConfig::default(),
+            learning_period_days: 0,
+            fusion: FusionConfig::default(),
+            kill_chain: KillChainConfig::default(),
+            kind_profiles: BTreeMap::new(),
+            source_profiles: BTreeMap::new(),
+            smoothing: SmoothingConfig::default(),
+            deep_scan: DeepScanConfig::default(),
+            dedup: AlertDedupConfig::default(),
+            criticality: CriticalityConfig::default(),
+            ioc: IocConfig::default(),
+        }
+    }
+}
+
+impl Default for IocConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            files: Vec::new(),
+            uplink: true,
+            refresh_secs: 3600,
+            match_score: 0.9,
+            hash_executables: true,
+        }
+    }
+}
+
+impl Default for CriticalityConfig {
+    fn default() -> Self {
+        Self {
+            asset_weight: 1.0,
+            critical_paths: Vec::new(),
+            critical_services: Vec::new(),
+            critical_weight: 1.5,
+            apply: CriticalityMode::Score,
+        }
+    }
+}
+
+impl Default for AlertDedupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_secs: 3600,
+        }
+    }
+}
+
+impl Default for DeepScanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_level: crate::risk::RiskLevel::Medium,
+            cooldown_secs: 600,
+            interval_secs: 10,
+            max_files: 5000,
+        }
+    }
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            alpha: 0.3,
+        }
+    }
+}
+
+impl Default for ScoringProfile {
+    fn default() -> Self {
+        Self {
+            medium_threshold: None,
+            high_threshold: None,
+            sensitivity: 1.0,
+        }
+    }
+}
+
+impl Default for KillChainConfig {
+    fn default() -> Self {
+        let stage = |name: &str, tactic: &str| KillChainStage {
+            name: name.to_string(),
+            tactics: vec![tactic.to_string()],
+        };
+        Self {
+            enabled: true,
+            stages: vec![
+                stage("execution", "TA0002"),
+                stage("persistence", "TA0003"),
+                stage("privilege-escalation", "TA0004"),
+                stage("exfiltration", "TA0010"),
+            ],
+            window_secs: 86_400,
+            stage_boost: 0.25,
+            high_stages: 3,
+        }
+    }
+}
+
+impl Default for FusionWeights {
+    fn default() -> Self {
+        Self {
+            model: 1.0,
+            rules: 1.0,
+            ioc: 1.0,
+            entity: 0.0,
+        }
+    }
+}
+
+impl Default for CorrelationConfig {
+    fn default() -> Self {
+        Self {
+            builtin: true,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+impl Default for HysteresisConfig {
+    fn default() -> Self {
+        Self {
+            raise_windows: 1,
+            clear_windows: 1,
+            cooldown_secs: 0,
+        }
+    }
+}
+
+impl Default for EntityRiskConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            half_life_secs: 3_600,
+            min_score: 0.5,
+            medium_cumulative: 1.5,
+            high_cumulative: 2.5,
+            max_entities: 10_000,
+        }
+    }
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        Self {
+            builtin: true,
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl Default for ThresholdTuningConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window_secs: 7 * 86_400,
+            medium_quantile: 0.99,
+            high_quantile: 0.999,
+            min_samples: 1_000,
+            interval_secs: 3_600,
+            min_threshold: default_min_tuned_threshold(),
+        }
+    }
+}
+
+impl Default for UplinkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: Vec::new(),
+            report_interval_secs: 300,
+            device_id: None,
+            queue_capacity: 64,
+            retry_base_secs: 5,
+            retry_max_secs: 3600,
+            event_batch_bytes: 256 * 1024,
+            low_risk_sample_percent: 100,
+            bytes_per_hour: 0,
+            delta_reporting: false,
+            checkpoint_secs: 3600,
+            breaker_failures: 5,
+            breaker_probe_secs: 60,
+            compression: UplinkCompression::default(),
+            client_cert_path: None,
+            client_key_path: None,
+            client_key: None,
+            ca_cert_path: None,
+            pinned_spki_sha256: Vec::new(),
+            pinned_cert_sha256: Vec::new(),
+            proxy: None,
+            no_proxy: Vec::new(),
+            system_proxy: true,
+            streaming: false,
+            stream_path: "/api/v1/stream".to_string(),
+            enrollment_code: None,
+            token_refresh_secs: 3600,
+            policy_keys: Vec::new(),
+            policy_path: "/api/v1/devices/policy".to_string(),
+            policy_refresh_secs: 900,
+            heartbeat_secs: 300,
+            redaction: RedactionConfig::default(),
+        }
+    }
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            local_usernames: true,
+            usernames: Vec::new(),
+            home_paths: true,
+            cmdline_args: true,
+            fields: Vec::new(),
+            patterns: Vec::new(),
+            mode: RedactionMode::Strip,
+            hash_salt: String::new(),
+        }
+    }
+}
+
+impl Default for CommandsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_secs: 60,
+            actions: [
+                "file_scan",
+                "process_details",
+                "set_interval",
+                "isolate",
+                "release",
+                "kill_process",
+                "suspend_process",
+                "block_network",
+                "unblock_network",
+                "undo",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            max_scan_files: 5000,
+            isolate_command: Vec::new(),
+            release_command: Vec::new(),
+        }
+    }
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            allowed_uids: Vec::new(),
+            max_connections: 16,
+        }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 7787,
+            token: None,
+        }
+    }
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_level: crate::risk::RiskLevel::High,
+            min_interval_secs: 300,
+        }
+    }
+}
+
+impl Default for HelperConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            socket: PathBuf::from("/var/run/dadm/helper.sock"),
+            collectors: crate::collectors::COLLECTORS.map(String::from).to_vec(),
+            allowed_uids: Vec::new(),
+        }
+    }
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            stall_secs: 600,
+            max_restarts: 3,
+        }
+    }
+}
+
+impl Default for ResponseConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dry_run: false,
+            actions: ResponseActions::default(),
+            policy: Vec::new(),
+            critical_score: 0.9,
+            allowed: Vec::new(),
+            protected: [
+                "init",
+                "systemd",
+                "kthreadd",
+                "sshd",
+                "launchd",
+                "kernel_task",
+                "WindowServer",
+                "loginwindow",
+                "System",
+                "smss.exe",
+                "csrss.exe",
+                "wininit.exe",
+                "winlogon.exe",
+                "services.exe",
+                "lsass.exe",
+                "svchost.exe",
+                "dadm-agent",
+                "dadm-agent.exe",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            firewall: FirewallConfig::default(),
+            quarantine: QuarantineConfig::default(),
+            approval: ApprovalConfig::default(),
+        }
+    }
+}
+
+impl Default for ResponseActions {
+    fn default() -> Self {
+        Self {
+            notify: true,
+            kill_process: true,
+            suspend_process: true,
+            quarantine: true,
+            block_network: true,
+            isolate: true,
+        }
+    }
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        Self { enabled: false, timeout_secs: 60, default: ApprovalDefault::Deny, command: Vec::new() }
+    }
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        Self {
+            protected_paths: [
+                "/bin/*",
+                "/sbin/*",
+                "/usr/bin/*",
+                "/usr/sbin/*",
+                "/usr/lib/*",
+                "/lib/*",
+                "/lib64/*",
+                "/boot/*",
+                "/etc/*",
+                "/System/*",
+                "C:\\Windows\\*",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            max_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for FirewallConfig {
+    fn default() -> Self {
+        Self {
+            backend: FirewallBackend::Auto,
+            default_duration_secs: 3600,
+            max_duration_secs: 86400,
+            protected_addresses: Vec::new(),
+            release_credential_hash: None,
+        }
+    }
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self { jitter: 0.1 }
+    }
+}
+
+impl Default for CrashConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            log_lines: 200,
+            keep: 10,
+        }
+    }
+}
+
+impl Default for GovernorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sample_secs: 30,
+            cpu_percent: 5.0,
+            memory_mb: 256,
+            battery_stretch: 2,
+            low_battery_percent: 20.0,
+            max_stretch: 8,
+        }
+    }
+}
+
+impl Default for PrivilegesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            user: "dadm".to_string(),
+            group: None,
+            // Reading any file, and other users' processes
+            capabilities: vec!["CAP_DAC_READ_SEARCH".to_string(), "CAP_SYS_PTRACE".to_string()],
+            windows_privileges: ["SeChangeNotifyPrivilege", "SeBackupPrivilege", "SeDebugPrivilege", "SeSecurityPrivilege"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            json: true,
+            ecs: false,
+            stdout: true,
+            file: None,
+            max_file_bytes: 10 * 1024 * 1024,
+            max_file_age_secs: 86_400,
+            keep_files: 5,
+            syslog: SyslogConfig::default(),
+            eventlog: EventLogConfig::default(),
+            siem: SiemConfig::default(),
+            audit: AuditConfig::default(),
+            redaction: RedactionConfig::default(),
+            alerts: AlertStreamConfig::default(),
+        }
+    }
+}
+
+impl Default for AlertStreamConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_level: crate::risk::RiskLevel::Medium,
+            file: None,
+            socket: None,
+            webhook: None,
+            webhook_headers: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            file: PathBuf::from("audit.log"),
+            sign: true,
+        }
+    }
+}
+
+impl Default for SiemConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            format: SiemFormat::Cef,
+            min_level: crate::risk::RiskLevel::High,
+            alerts_only: true,
+            file: None,
+            syslog: SyslogConfig::default(),
+        }
+    }
+}
+
+impl Default for EventLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source: "DADM Agent".to_string(),
+            log: "Application".to_string(),
+            register: true,
+        }
+    }
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            transport: SyslogTransport::Unix,
+            address: None,
+            facility: 3,
+            app_name: "dadm-agent".to_string(),
+        }
+    }
+}
+
+impl AgentConfig {
+    /// Load the layers of [`layers`]: defaults, the JSON file at `path` (or `DADM_CONFIG_PATH`)
+    /// and its local overrides if present, then the environment: DADM_DATA_DIR, DADM_MODEL_PATH,
+    /// DADM_UPLINK_ENABLED, DADM_UPLINK_ENDPOINT, DADM_DEVICE_ID, DADM_ENROLLMENT_CODE,
+    /// DADM_UPLINK_PROXY. An unreadable or invalid file is reported on stderr and the defaults
+    /// (with the environment) are used; so are settings upgraded from an older layout.
+    pub fn load(path: &std::path::Path) -> Self {
+        match Self::load_layers(path, &[]).and_then(Self::from_layers) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("config: {}; using defaults", e);
+                let mut layers = LayeredConfig::new();
+                layers.push(Layer::Env, &env_layer());
+                layers.config().unwrap_or_default()
+            }
+        }
+    }
+
+    /// Load like [`Self::load`], but with `keys` the config file must exist, and it and the local
+    /// overrides (when present) must parse and carry a detached Ed25519 signature
+    /// ([`signature_path`]: base64, or the 64 raw bytes) of their exact content by one of them.
+    /// Without `keys` this is [`Self::load`].
+    pub fn load_signed(path: &Path, keys: &[String]) -> Result<Self, BoxError> {
+        if keys.is_empty() {
+            return Ok(Self::load(path));
+        }
+        Self::from_layers(Self::load_layers(path, keys)?)
+    }
+
+    /// The merged config of `layers`, reporting upgraded settings on stderr
+    fn from_layers(layers: LayeredConfig) -> Result<Self, BoxError> {
+        for warning in layers.warnings() {
+            eprintln!("config: {}", warning);
+        }
+        layers.config()
+    }
+
+    /// The file, local override, and environment layers over the defaults; fails on an
+    /// unreadable, invalid, or (with `keys`) unsigned file, or environment overrides a signed
+    /// config doesn't allow ([`check_unsigned`])
+    pub fn load_layers(path: &Path, keys: &[String]) -> Result<LayeredConfig, BoxError> {
+        let path_override = std::env::var("DADM_CONFIG_PATH").ok();
+        let p = path_override.as_deref().map(Path::new).unwrap_or(path);
+        let mut layers = LayeredConfig::new();
+        for (layer, file) in [(Layer::System, p.to_path_buf()), (Layer::Local, local_path(p))] {
+            // Only a signed config can leave the system file out
+            if !file.exists() && (layer == Layer::Local || keys.is_empty()) {
+                continue;
+            }
+            let data = read_signed(&file, keys)?;
+            let fragment: serde_json::Value =
+                serde_json::from_slice(&data).map_err(|e| format!("config {}: {}", file.display(), e))?;
+            if !fragment.is_object() {
+                return Err(format!("config {}: not a JSON object", file.display()).into());
+            }
+            layers.push_file(layer, &file, &fragment);
+        }
+        let env = env_layer();
+        if !keys.is_empty() {
+            check_unsigned(Layer::Env, &env)?;
+        }
+        layers.push(Layer::Env, &env);
+        Ok(layers)
+    }
+}
+
+/// Content of the config file `path`; with `keys` its signature must verify under one of them
+fn read_signed(path: &Path, keys: &[String]) -> Result<Vec<u8>, BoxError> {
+    let data = std::fs::read(path).map_err(|e| {
+        let hint = if keys.is_empty() { "" } else { " (a signed config is required)" };
+        format!("config {}: {}{}", path.display(), e, hint)
+    })?;
+    if keys.is_empty() {
+        return Ok(data);
+    }
+    let sig_path = signature_path(path);
+    let sig = std::fs::read(&sig_path).map_err(|e| format!("config signature {}: {}", sig_path.display(), e))?;
+    let sig = match BASE64.decode(String::from_utf8_lossy(&sig).trim()) {
+        Ok(decoded) => decoded,
+        Err(_) if sig.len() == 64 => sig,
+        Err(e) => return Err(format!("config signature {}: {}", sig_path.display(), e).into()),
+    };
+    if !crate::policy::verify_ed25519(&data, &sig, keys).map_err(|e| format!("config signing {}", e))? {
+        return Err(format!("config {} is not signed by a pinned key", path.display()).into());
+    }
+    Ok(data)
+}
+
+/// Settings from the `DADM_*` environment variables
+fn env_layer() -> serde_json::Value {
+    let mut env = serde_json::json!({});
+    let var = |name: &str| std::env::var(name).ok();
+    if let Some(v) = var("DADM_DATA_DIR") {
+        env["data_dir"] = v.into();
+    }
+    if let Some(v) = var("DADM_MODEL_PATH") {
+        env["model_path"] = v.into();
+    }
+    if let Some(v) = var("DADM_UPLINK_ENABLED") {
+        env["uplink"]["enabled"] = (v == "1" || v.eq_ignore_ascii_case("true")).into();
+    }
+    if let Some(v) = var("DADM_UPLINK_ENDPOINT") {
+        let endpoints: Vec<String> = v.split(',').map(|e| e.trim().to_string()).filter(|e| !e.is_empty()).collect();
+        env["uplink"]["endpoint"] = endpoints.into();
+    }
+    if let Some(v) = var("DADM_DEVICE_ID") {
+        env["uplink"]["device_id"] = v.into();
+    }
+    if let Some(v) = var("DADM_ENROLLMENT_CODE") {
+        env["uplink"]["enrollment_code"] = v.into();
+    }
+    if let Some(v) = var("DADM_UPLINK_PROXY") {
+        env["uplink"]["proxy"] = v.into();
+    }
+    env
+}
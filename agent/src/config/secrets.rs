@@ -0,0 +1,107 @@
+//! Secrets kept out of config files: a secret setting (uplink proxy URL, enrollment code, mTLS
+//! client key, API token, telemetry and webhook header values) may be a `keystore://<name>`
+//! reference, resolved at startup from the platform keystore ([`crate::storage::secret_provider`]).
+//! The mTLS key is held there whole, so it needs no passphrase of its own. Secrets are put
+//! there with `dadm-agent secret set <name>`.
+
+use super::AgentConfig;
+use crate::storage::{secret_provider, KeySlot};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Prefix of a secret reference
+pub const SCHEME: &str = "keystore://";
+
+/// Settings that may hold a reference (dotted paths; `*` is any header name)
+pub const SECRET_SETTINGS: &[&str] = &[
+    "uplink.proxy",
+    "uplink.enrollment_code",
+    "uplink.client_key",
+    "api.token",
+    "telemetry.headers.*",
+    "log.alerts.webhook_headers.*",
+];
+
+/// Compare a presented secret (or its hash) with the expected one without leaking the position
+/// of the first difference through timing
+pub fn same_secret(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len() && given.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Name referenced by `value`, if it is a `keystore://` reference
+pub fn reference(value: &str) -> Option<&str> {
+    value.strip_prefix(SCHEME)
+}
+
+/// Whether `name` can name a secret: ASCII letters, digits, `.`, `_`, and `-`, not starting
+/// with `.`
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/// Value of the secret `name` in the keystore of `config`
+pub fn load(config: &AgentConfig, name: &str) -> Result<String, BoxError> {
+    if !valid_name(name) {
+        return Err(format!("invalid secret name {:?}", name).into());
+    }
+    let provider = secret_provider(&config.storage, &config.data_dir, name);
+    let secret = provider
+        .load(KeySlot::Current)
+        .map_err(|e| format!("secret {} ({}): {}", name, provider.name(), e))?
+        .ok_or_else(|| format!("secret {} not found in {} (dadm-agent secret set {})", name, provider.name(), name))?;
+    String::from_utf8(secret.to_vec()).map_err(|_| format!("secret {} is not UTF-8", name).into())
+}
+
+/// Store `value` as the secret `name`, replacing any previous value
+pub fn store(config: &AgentConfig, name: &str, value: &str) -> Result<(), BoxError> {
+    if !valid_name(name) {
+        return Err(format!("invalid secret name {:?}", name).into());
+    }
+    if value.is_empty() {
+        return Err(format!("secret {}: empty value", name).into());
+    }
+    secret_provider(&config.storage, &config.data_dir, name).store(KeySlot::Current, value.as_bytes())?;
+    Ok(())
+}
+
+/// Delete the secret `name` (no-op if absent)
+pub fn remove(config: &AgentConfig, name: &str) -> Result<(), BoxError> {
+    if !valid_name(name) {
+        return Err(format!("invalid secret name {:?}", name).into());
+    }
+    secret_provider(&config.storage, &config.data_dir, name).remove(KeySlot::Current)?;
+    Ok(())
+}
+
+impl AgentConfig {
+    /// Replace the `keystore://` references in [`SECRET_SETTINGS`] with the secrets they name;
+    /// fails on a missing or unreadable secret
+    pub fn resolve_secrets(&mut self) -> Result<(), BoxError> {
+        // A private key in the config would be as exposed as one in a plain file
+        if self.uplink.client_key.as_deref().is_some_and(|key| reference(key).is_none()) {
+            return Err("uplink.client_key must be a keystore:// reference".into());
+        }
+        let mut resolved = self.clone();
+        let uplink = &mut resolved.uplink;
+        let values = uplink
+            .proxy
+            .iter_mut()
+            .map(|v| ("uplink.proxy".to_string(), v))
+            .chain(uplink.enrollment_code.iter_mut().map(|v| ("uplink.enrollment_code".to_string(), v)))
+            .chain(uplink.client_key.iter_mut().map(|v| ("uplink.client_key".to_string(), v)))
+            .chain(resolved.api.token.iter_mut().map(|v| ("api.token".to_string(), v)))
+            .chain(resolved.telemetry.headers.iter_mut().map(|(k, v)| (format!("telemetry.headers.{}", k), v)))
+            .chain(
+                resolved.log.alerts.webhook_headers.iter_mut().map(|(k, v)| (format!("log.alerts.webhook_headers.{}", k), v)),
+            );
+        for (path, value) in values {
+            if let Some(name) = reference(value) {
+                *value = load(self, name).map_err(|e| format!("{}: {}", path, e))?;
+            }
+        }
+        *self = resolved;
+        Ok(())
+    }
+}
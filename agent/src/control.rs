@@ -0,0 +1,311 @@
+//! Local control interface for tooling (and a UI) on the same host, served while the daemon runs:
+//! newline-delimited JSON over a Unix domain socket (Linux, macOS) or a named pipe (Windows). Each
+//! request line, e.g. `{"op": "query", "since": 1700000000000, "min_risk": "medium"}`, gets one
+//! response line: `{"ok": true, "result": ...}` or `{"ok": false, "error": "..."}`.
+//!
+//! Unix peers are checked by their socket credentials: root, the agent's own user, and
+//! `control.allowed_uids` may connect. The named pipe is created with an ACL that lets only SYSTEM,
+//! administrators, and the agent's user open it, and refuses remote clients.
+
+use crate::config::ControlConfig;
+use crate::risk::RiskLevel;
+use crate::runtime::CancellationToken;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::task::JoinSet;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Longest request line accepted (bytes)
+const MAX_REQUEST: usize = 64 * 1024;
+
+/// Socket under `data_dir` when `control.path` is unset
+const DEFAULT_SOCKET: &str = "control.sock";
+/// Pipe when `control.path` is unset (Windows)
+const DEFAULT_PIPE: &str = r"\\.\pipe\dadm-agent";
+/// ACL of the pipe: SYSTEM, Administrators, and its owner (the agent's user), nobody else
+#[cfg(windows)]
+const PIPE_SDDL: &str = "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;OW)";
+
+/// Operation requested
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    /// The daemon's state: cycle, events per collector, latest risk, resource use, uplink health
+    Status,
+    /// Recent risk results at `log.alerts.min_level` or above, newest first
+    Alerts {
+        /// From this time on (ms; default: the last 24 hours)
+        #[serde(default)]
+        since: Option<i64>,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Stored events, oldest first
+    Events {
+        #[serde(default)]
+        since: Option<i64>,
+        #[serde(default)]
+        until: Option<i64>,
+        #[serde(default)]
+        kind: Option<String>,
+        /// At most this many (default 100)
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Stored risk results, oldest first
+    Query {
+        #[serde(default)]
+        since: Option<i64>,
+        #[serde(default)]
+        until: Option<i64>,
+        #[serde(default)]
+        min_risk: Option<RiskLevel>,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Set (`directives`) or remove (`reset`) the log level override; with neither, print it
+    LogLevel {
+        #[serde(default)]
+        directives: Option<String>,
+        #[serde(default)]
+        reset: bool,
+    },
+    /// Collect now instead of at the end of the interval
+    Scan,
+    /// Undo a response action kept in the response trail
+    Undo { id: String },
+}
+
+/// What requests act on; implemented by the daemon and called on the blocking pool
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: Request) -> Result<Value, BoxError>;
+}
+
+/// Socket or pipe the control interface is served on
+pub fn endpoint(config: &ControlConfig, data_dir: &Path) -> PathBuf {
+    match config.path {
+        Some(ref path) if cfg!(windows) => path.clone(),
+        Some(ref path) => data_dir.join(path),
+        None if cfg!(windows) => PathBuf::from(DEFAULT_PIPE),
+        None => data_dir.join(DEFAULT_SOCKET),
+    }
+}
+
+/// Serve requests on `endpoint` until `token` is cancelled; requests in progress are answered
+/// first
+pub async fn serve(config: ControlConfig, endpoint: PathBuf, handler: Arc<dyn Handler>, token: CancellationToken) {
+    if let Err(e) = listen(&config, &endpoint, handler, token).await {
+        tracing::warn!(path = %endpoint.display(), error = %e, "control interface unavailable");
+    }
+}
+
+#[cfg(unix)]
+async fn listen(
+    config: &ControlConfig,
+    endpoint: &Path,
+    handler: Arc<dyn Handler>,
+    token: CancellationToken,
+) -> Result<(), BoxError> {
+    use std::os::unix::fs::PermissionsExt;
+    // A socket left by an agent that didn't stop cleanly
+    match std::fs::remove_file(endpoint) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let listener = tokio::net::UnixListener::bind(endpoint)?;
+    std::fs::set_permissions(endpoint, std::fs::Permissions::from_mode(0o660))?;
+    // SAFETY: geteuid has no preconditions
+    let own = unsafe { libc::geteuid() };
+    tracing::info!(path = %endpoint.display(), "control interface listening");
+    let mut connections = JoinSet::new();
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!(error = %e, "control connection failed");
+                    continue;
+                }
+            },
+            Some(_) = connections.join_next() => continue,
+            _ = token.cancelled() => break,
+        };
+        let uid = match stream.peer_cred() {
+            Ok(cred) => cred.uid(),
+            Err(e) => {
+                tracing::warn!(error = %e, "control connection refused: no peer credentials");
+                continue;
+            }
+        };
+        if uid != 0 && uid != own && !config.allowed_uids.contains(&uid) {
+            tracing::warn!(uid, "control connection refused: user not allowed");
+            continue;
+        }
+        if connections.len() >= config.max_connections {
+            tracing::warn!(uid, "control connection refused: too many connections");
+            continue;
+        }
+        let (reader, writer) = stream.into_split();
+        connections.spawn(session(reader, writer, Arc::clone(&handler), token.clone()));
+    }
+    while connections.join_next().await.is_some() {}
+    let _ = std::fs::remove_file(endpoint);
+    Ok(())
+}
+
+#[cfg(windows)]
+async fn listen(
+    config: &ControlConfig,
+    endpoint: &Path,
+    handler: Arc<dyn Handler>,
+    token: CancellationToken,
+) -> Result<(), BoxError> {
+    let name = endpoint.as_os_str();
+    let mut security = PipeSecurity::new()?;
+    // First instance: fails if another process already serves the name
+    let mut server = security.create(name, true)?;
+    tracing::info!(path = %endpoint.display(), "control interface listening");
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            connected = server.connect() => connected?,
+            Some(_) = connections.join_next() => continue,
+            _ = token.cancelled() => break,
+        }
+        // The next client connects to a new instance
+        let client = std::mem::replace(&mut server, security.create(name, false)?);
+        if connections.len() >= config.max_connections {
+            tracing::warn!("control connection refused: too many connections");
+            continue;
+        }
+        let (reader, writer) = tokio::io::split(client);
+        connections.spawn(session(reader, writer, Arc::clone(&handler), token.clone()));
+    }
+    while connections.join_next().await.is_some() {}
+    Ok(())
+}
+
+/// Security attributes every instance of the pipe is created with ([`PIPE_SDDL`])
+#[cfg(windows)]
+struct PipeSecurity {
+    descriptor: windows::Win32::Security::PSECURITY_DESCRIPTOR,
+    attributes: windows::Win32::Security::SECURITY_ATTRIBUTES,
+}
+
+// SAFETY: the descriptor is owned by this value and never changed after creation
+#[cfg(windows)]
+unsafe impl Send for PipeSecurity {}
+
+#[cfg(windows)]
+impl PipeSecurity {
+    fn new() -> Result<Self, BoxError> {
+        use windows::core::PCWSTR;
+        use windows::Win32::Security::Authorization::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1};
+        use windows::Win32::Security::{PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES};
+        let sddl: Vec<u16> = PIPE_SDDL.encode_utf16().chain(Some(0)).collect();
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        // SAFETY: `sddl` is NUL-terminated; the descriptor is freed on drop
+        unsafe { ConvertStringSecurityDescriptorToSecurityDescriptorW(PCWSTR(sddl.as_ptr()), SDDL_REVISION_1, &mut descriptor, None) }
+            .map_err(|e| format!("control pipe ACL: {}", e))?;
+        let attributes = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: descriptor.0,
+            bInheritHandle: false.into(),
+        };
+        Ok(Self { descriptor, attributes })
+    }
+
+    /// A new instance of the pipe `name`; the first fails if another process serves the name
+    fn create(&mut self, name: &std::ffi::OsStr, first: bool) -> std::io::Result<tokio::net::windows::named_pipe::NamedPipeServer> {
+        let mut options = tokio::net::windows::named_pipe::ServerOptions::new();
+        options.first_pipe_instance(first).reject_remote_clients(true);
+        // SAFETY: the attributes and their descriptor live as long as `self`
+        unsafe { options.create_with_security_attributes_raw(name, &mut self.attributes as *mut _ as *mut std::ffi::c_void) }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for PipeSecurity {
+    fn drop(&mut self) {
+        use windows::Win32::Foundation::{LocalFree, HLOCAL};
+        // SAFETY: allocated by ConvertStringSecurityDescriptorToSecurityDescriptorW
+        unsafe {
+            let _ = LocalFree(HLOCAL(self.descriptor.0));
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn listen(_: &ControlConfig, _: &Path, _: Arc<dyn Handler>, _: CancellationToken) -> Result<(), BoxError> {
+    Err("not supported on this platform".into())
+}
+
+/// Answer the requests of one connection until it closes or `token` is cancelled
+async fn session<R, W>(reader: R, mut writer: W, handler: Arc<dyn Handler>, token: CancellationToken)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(reader);
+    loop {
+        let mut line = Vec::new();
+        let mut limited = (&mut reader).take(MAX_REQUEST as u64 + 1);
+        let read = tokio::select! {
+            read = limited.read_until(b'\n', &mut line) => read,
+            _ = token.cancelled() => break,
+        };
+        match read {
+            Ok(0) | Err(_) => break,
+            Ok(_) if line.iter().all(u8::is_ascii_whitespace) => continue,
+            Ok(_) => {}
+        }
+        let oversized = line.len() > MAX_REQUEST;
+        let response = if oversized {
+            json!({ "ok": false, "error": format!("request longer than {} bytes", MAX_REQUEST) })
+        } else {
+            match serde_json::from_slice::<Request>(&line) {
+                Ok(request) => {
+                    let handler = Arc::clone(&handler);
+                    match tokio::task::spawn_blocking(move || handler.handle(request)).await {
+                        Ok(Ok(result)) => json!({ "ok": true, "result": result }),
+                        Ok(Err(e)) => json!({ "ok": false, "error": e.to_string() }),
+                        Err(e) => json!({ "ok": false, "error": e.to_string() }),
+                    }
+                }
+                Err(e) => json!({ "ok": false, "error": format!("bad request: {}", e) }),
+            }
+        };
+        let mut bytes = response.to_string().into_bytes();
+        bytes.push(b'\n');
+        if writer.write_all(&bytes).await.is_err() || oversized {
+            break;
+        }
+    }
+}
+
+/// Send `request` to the control interface at `endpoint` and return its result (blocking)
+pub fn call(endpoint: &Path, request: &Request) -> Result<Value, BoxError> {
+    use std::io::{BufRead, Write};
+    #[cfg(unix)]
+    let stream = {
+        let stream = std::os::unix::net::UnixStream::connect(endpoint)?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(30)))?;
+        stream
+    };
+    #[cfg(not(unix))]
+    let stream = std::fs::OpenOptions::new().read(true).write(true).open(endpoint)?;
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    (&stream).write_all(&line)?;
+    let mut response = String::new();
+    std::io::BufReader::new(&stream).read_line(&mut response)?;
+    let response: Value = serde_json::from_str(&response).map_err(|e| format!("malformed control response: {}", e))?;
+    match response["ok"].as_bool() {
+        Some(true) => Ok(response["result"].clone()),
+        _ => Err(response["error"].as_str().unwrap_or("malformed control response").to_string().into()),
+    }
+}
@@ -0,0 +1,129 @@
+//! Crash bundles (`crash`): when the agent panics, a hook writes a diagnostic bundle to
+//! `data_dir/crashes` before the process unwinds or aborts: the panic message and location, the
+//! thread, a backtrace, the last `crash.log_lines` log lines, and what the agent last recorded
+//! about itself (the config fingerprint, store stats). The bundle goes through the log's privacy
+//! filter (`log.redaction`) before it is written. At the next start each new bundle is logged and
+//! audited (`agent_crashed`), and reported to the server in a heartbeat when the uplink is on.
+//! The newest `crash.keep` bundles are kept.
+
+use crate::config::CrashConfig;
+use crate::redact::Redactor;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Directory under `data_dir` holding the bundles
+pub const CRASH_DIR: &str = "crashes";
+
+/// What the agent last recorded about itself, by topic
+static CONTEXT: Mutex<BTreeMap<&'static str, Value>> = Mutex::new(BTreeMap::new());
+
+/// Record `value` under `topic` for a bundle written later
+pub fn set_context(topic: &'static str, value: Value) {
+    CONTEXT.lock().unwrap_or_else(|e| e.into_inner()).insert(topic, value);
+}
+
+/// Write a bundle to `data_dir/crashes` on every panic, then run the hook installed before
+pub fn install(config: &CrashConfig, data_dir: &Path, redactor: Option<Redactor>) {
+    if !config.enabled {
+        return;
+    }
+    let (dir, keep) = (data_dir.join(CRASH_DIR), config.keep);
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+            (Some(message), _) => message,
+            (_, Some(message)) => message.as_str(),
+            _ => "panic",
+        };
+        let location = info.location().map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()));
+        let thread = std::thread::current();
+        let backtrace = std::backtrace::Backtrace::force_capture().to_string();
+        let bundle = bundle(message, location.as_deref(), thread.name().unwrap_or("unnamed"), &backtrace);
+        match write_bundle(&dir, &bundle, redactor.as_ref(), keep) {
+            Ok(path) => eprintln!("crash bundle written to {}", path.display()),
+            Err(e) => eprintln!("cannot write crash bundle to {}: {}", dir.display(), e),
+        }
+        previous(info);
+    }));
+}
+
+/// Bundle of a panic with `message` at `location` on `thread`, with the recent log lines and
+/// the recorded context
+pub fn bundle(message: &str, location: Option<&str>, thread: &str, backtrace: &str) -> Value {
+    // The panicking thread may hold the lock
+    let context = match CONTEXT.try_lock() {
+        Ok(context) => json!(*context),
+        Err(std::sync::TryLockError::Poisoned(context)) => json!(*context.into_inner()),
+        Err(std::sync::TryLockError::WouldBlock) => json!({}),
+    };
+    json!({
+        "ts": chrono::Utc::now().timestamp_millis(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "pid": std::process::id(),
+        "thread": thread,
+        "message": message,
+        "location": location,
+        "backtrace": backtrace.lines().map(str::trim_end).collect::<Vec<_>>(),
+        "log": crate::logging::recent_lines(),
+        "context": context,
+        "audited": false,
+        "reported": false,
+    })
+}
+
+/// Write `bundle`, through `redactor` when set, as `dir/crash-<ts>-<pid>.json`, and delete the
+/// oldest bundles beyond `keep`
+pub fn write_bundle(dir: &Path, bundle: &Value, redactor: Option<&Redactor>, keep: usize) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let text = match redactor {
+        Some(redactor) => serde_json::to_string_pretty(&serde_json::from_str::<Value>(&redactor.body(&bundle.to_string()))?)?,
+        None => serde_json::to_string_pretty(bundle)?,
+    };
+    let path = dir.join(format!("crash-{}-{}.json", bundle["ts"], bundle["pid"]));
+    std::fs::write(&path, text)?;
+    let files = bundle_files(dir);
+    for old in &files[..files.len().saturating_sub(keep.max(1))] {
+        let _ = std::fs::remove_file(old);
+    }
+    Ok(path)
+}
+
+/// Bundle files in `dir`, oldest first
+fn bundle_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with("crash-") && n.ends_with(".json")))
+        .collect();
+    // Millisecond timestamps have the same number of digits for centuries
+    files.sort();
+    files
+}
+
+/// Bundles in `data_dir/crashes`, oldest first; unreadable ones are skipped
+pub fn bundles(data_dir: &Path) -> Vec<(PathBuf, Value)> {
+    bundle_files(&data_dir.join(CRASH_DIR))
+        .into_iter()
+        .filter_map(|path| {
+            let bundle = serde_json::from_slice(&std::fs::read(&path).ok()?).ok()?;
+            Some((path, bundle))
+        })
+        .collect()
+}
+
+/// Summary of `bundle` for the audit log
+pub fn summary(bundle: &Value) -> Value {
+    json!({
+        "ts": bundle["ts"],
+        "version": bundle["version"],
+        "pid": bundle["pid"],
+        "thread": bundle["thread"],
+        "message": bundle["message"],
+        "location": bundle["location"],
+    })
+}
@@ -0,0 +1,238 @@
+//! Offline model evaluation: replay labeled events through the feature pipeline and model,
+//! then report ROC AUC, average precision, and precision/recall at the configured thresholds.
+
+use crate::collectors::Event;
+use crate::config::{FeaturesConfig, RiskConfig};
+use crate::features::FeatureExtractor;
+use crate::model::OnnxDetector;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Event with ground-truth label (true = malicious)
+#[derive(Debug, Clone)]
+pub struct LabeledEvent {
+    pub event: Event,
+    pub label: Option<bool>,
+}
+
+/// Precision / recall / F1 at one threshold
+#[derive(Debug, Clone, Serialize)]
+pub struct ThresholdMetrics {
+    pub threshold: f32,
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalReport {
+    pub events: usize,
+    /// Labeled events scored, which the metrics cover
+    pub labeled: usize,
+    /// Labeled events that produced no feature vector, left out of the metrics
+    pub unscored: usize,
+    pub positives: usize,
+    pub negatives: usize,
+    /// ROC AUC; `None` if only one class is present
+    pub roc_auc: Option<f32>,
+    /// Area under precision-recall curve (average precision)
+    pub average_precision: Option<f32>,
+    pub medium: ThresholdMetrics,
+    pub high: ThresholdMetrics,
+}
+
+fn parse_label(v: &serde_json::Value) -> Option<bool> {
+    match v {
+        serde_json::Value::Bool(b) => Some(*b),
+        serde_json::Value::Number(n) => n.as_f64().map(|x| x >= 0.5),
+        serde_json::Value::String(s) => match s.to_ascii_lowercase().as_str() {
+            "1" | "true" | "malicious" | "anomaly" => Some(true),
+            "0" | "false" | "benign" | "normal" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Read events from JSONL (one `Event` per line, or `export` lines with the event in `payload`).
+/// An optional `"label"` field on each line (bool, 0/1, or "malicious"/"benign") is used as
+/// ground truth.
+pub fn read_events_jsonl(path: &Path) -> std::io::Result<Vec<LabeledEvent>> {
+    let file = std::fs::File::open(path)?;
+    let mut out = Vec::new();
+    for (n, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value = match serde_json::from_str(&line) {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::warn!(line = n + 1, error = %e, "skipping invalid JSONL line");
+                continue;
+            }
+        };
+        let label = value.get("label").and_then(parse_label);
+        let value = match value.get("payload") {
+            Some(payload) if payload.is_object() => payload.clone(),
+            _ => value,
+        };
+        match serde_json::from_value::<Event>(value) {
+            Ok(event) => out.push(LabeledEvent { event, label }),
+            Err(e) => tracing::warn!(line = n + 1, error = %e, "skipping line that is not an event"),
+        }
+    }
+    Ok(out)
+}
+
+/// Read labels as `event_id,label` lines (header and blank lines ignored).
+pub fn read_labels_csv(path: &Path) -> std::io::Result<HashMap<String, bool>> {
+    let data = std::fs::read_to_string(path)?;
+    let mut out = HashMap::new();
+    for line in data.lines() {
+        let mut parts = line.splitn(2, ',');
+        let (Some(id), Some(label)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let label = serde_json::Value::String(label.trim().to_string());
+        if let Some(l) = parse_label(&label) {
+            out.insert(id.trim().to_string(), l);
+        }
+    }
+    Ok(out)
+}
+
+/// Apply external labels (by event id), overriding inline labels.
+pub fn apply_labels(events: &mut [LabeledEvent], labels: &HashMap<String, bool>) {
+    for e in events.iter_mut() {
+        if let Some(l) = labels.get(&e.event.id) {
+            e.label = Some(*l);
+        }
+    }
+}
+
+/// Labeled events replayed through the model
+#[derive(Debug, Clone, Default)]
+pub struct Replayed {
+    /// (score, label) of each labeled event that produced a feature vector
+    pub scored: Vec<(f32, bool)>,
+    /// Labeled events that produced none, so have no score
+    pub unscored: usize,
+}
+
+/// Replay events in timestamp order through a fresh feature window and the model, scoring each
+/// labeled event.
+pub fn replay(
+    events: &[LabeledEvent],
+    features: &FeaturesConfig,
+    model: &OnnxDetector,
+) -> Replayed {
+    let mut ordered: Vec<&LabeledEvent> = events.iter().collect();
+    ordered.sort_by_key(|e| e.event.ts);
+    let extractor = FeatureExtractor::new(features.clone());
+    let mut out = Replayed::default();
+    for e in ordered {
+        let vectors = extractor.push(vec![e.event.clone()]);
+        let Some(label) = e.label else { continue };
+        match vectors.first() {
+            Some(fv) => out.scored.push((model.predict(fv), label)),
+            None => out.unscored += 1,
+        }
+    }
+    out
+}
+
+/// ROC AUC via the Mann-Whitney U statistic (ties count half).
+pub fn roc_auc(scored: &[(f32, bool)]) -> Option<f32> {
+    let mut sorted: Vec<(f32, bool)> = scored.to_vec();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let positives = sorted.iter().filter(|(_, l)| *l).count();
+    let negatives = sorted.len() - positives;
+    if positives == 0 || negatives == 0 {
+        return None;
+    }
+    // Average ranks over ties
+    let mut rank_sum_pos = 0.0f64;
+    let mut i = 0;
+    while i < sorted.len() {
+        let mut j = i;
+        while j + 1 < sorted.len() && sorted[j + 1].0 == sorted[i].0 {
+            j += 1;
+        }
+        let avg_rank = (i + j) as f64 / 2.0 + 1.0;
+        rank_sum_pos += avg_rank * sorted[i..=j].iter().filter(|(_, l)| *l).count() as f64;
+        i = j + 1;
+    }
+    let u = rank_sum_pos - (positives * (positives + 1)) as f64 / 2.0;
+    Some((u / (positives as f64 * negatives as f64)) as f32)
+}
+
+/// Average precision (area under the precision-recall curve, step interpolation).
+pub fn average_precision(scored: &[(f32, bool)]) -> Option<f32> {
+    let positives = scored.iter().filter(|(_, l)| *l).count();
+    if positives == 0 {
+        return None;
+    }
+    let mut sorted: Vec<(f32, bool)> = scored.to_vec();
+    sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut tp = 0usize;
+    let mut ap = 0.0f64;
+    for (k, (_, label)) in sorted.iter().enumerate() {
+        if *label {
+            tp += 1;
+            ap += tp as f64 / (k + 1) as f64;
+        }
+    }
+    Some((ap / positives as f64) as f32)
+}
+
+/// Precision / recall / F1 treating `score >= threshold` as a detection.
+pub fn threshold_metrics(scored: &[(f32, bool)], threshold: f32) -> ThresholdMetrics {
+    let (mut tp, mut fp, mut fneg) = (0usize, 0usize, 0usize);
+    for (score, label) in scored {
+        match (*score >= threshold, *label) {
+            (true, true) => tp += 1,
+            (true, false) => fp += 1,
+            (false, true) => fneg += 1,
+            (false, false) => {}
+        }
+    }
+    let precision = if tp + fp == 0 { 0.0 } else { tp as f32 / (tp + fp) as f32 };
+    let recall = if tp + fneg == 0 { 0.0 } else { tp as f32 / (tp + fneg) as f32 };
+    let f1 = if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    };
+    ThresholdMetrics {
+        threshold,
+        precision,
+        recall,
+        f1,
+        true_positives: tp,
+        false_positives: fp,
+        false_negatives: fneg,
+    }
+}
+
+/// Compute the full report from the replayed events; unscored ones are only counted.
+pub fn report(total_events: usize, replayed: &Replayed, risk: &RiskConfig) -> EvalReport {
+    let scored = &replayed.scored;
+    let positives = scored.iter().filter(|(_, l)| *l).count();
+    EvalReport {
+        events: total_events,
+        labeled: scored.len(),
+        unscored: replayed.unscored,
+        positives,
+        negatives: scored.len() - positives,
+        roc_auc: roc_auc(scored),
+        average_precision: average_precision(scored),
+        medium: threshold_metrics(scored, risk.medium_threshold),
+        high: threshold_metrics(scored, risk.high_threshold),
+    }
+}
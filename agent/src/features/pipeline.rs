@@ -4,28 +4,54 @@
 use crate::collectors::Event;
 use crate::config::FeaturesConfig;
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use chrono::Utc;
 
 pub struct FeatureExtractor {
     config: FeaturesConfig,
     window: Mutex<VecDeque<Event>>,
+    /// Current window size: `window_events`, or less while the resource governor throttles
+    window_events: AtomicUsize,
 }
 
 impl FeatureExtractor {
     pub fn new(config: FeaturesConfig) -> Self {
         Self {
+            window_events: AtomicUsize::new(config.window_events),
             config,
             window: Mutex::new(VecDeque::new()),
         }
     }
 
+    /// Resize the sliding window, to at most `window_events`; a smaller window drops its oldest
+    /// events with the next push
+    pub fn set_window_events(&self, events: usize) {
+        self.window_events.store(events.clamp(1, self.config.window_events.max(1)), Ordering::Relaxed);
+    }
+
+    /// Events in the sliding window, oldest first (e.g. to keep across a restart)
+    pub fn window(&self) -> Vec<Event> {
+        self.window.lock().expect("lock").iter().cloned().collect()
+    }
+
+    /// Refill the sliding window with `events` (from [`Self::window`]) without emitting a vector
+    pub fn restore(&self, events: Vec<Event>) {
+        let window_events = self.window_events.load(Ordering::Relaxed);
+        let mut w = self.window.lock().expect("lock");
+        w.extend(events);
+        while w.len() > window_events {
+            w.pop_front();
+        }
+    }
+
     /// Push events into the sliding window and optionally emit a feature vector per event (or batched)
     pub fn push(&self, events: Vec<Event>) -> Vec<FeatureVector> {
         let mut w = self.window.lock().expect("lock");
+        let window_events = self.window_events.load(Ordering::Relaxed);
         for e in events {
             w.push_back(e);
-            while w.len() > self.config.window_events {
+            while w.len() > window_events {
                 w.pop_front();
             }
         }
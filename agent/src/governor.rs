@@ -0,0 +1,164 @@
+//! Resource governor (`governor`) for low-power devices: every `sample_secs` the daemon measures
+//! its own CPU and resident memory and the battery, and throttles itself against the budgets. While
+//! CPU or memory is over budget the collection interval multiplier doubles each sample (up to
+//! `max_stretch`) and file integrity hashing is deferred; once both are well below budget it halves
+//! again. The feature window shrinks by the same factor. On battery power the multiplier is at
+//! least `battery_stretch`, and `max_stretch` at or below `low_battery_percent`. Deep scans,
+//! started on elevated risk, are not throttled.
+
+use crate::config::GovernorConfig;
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// Power source of a machine with a battery
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Battery {
+    /// Running on battery power
+    pub discharging: bool,
+    /// Charge in percent, when known
+    pub percent: Option<f32>,
+}
+
+/// One measurement
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Load {
+    /// CPU use since the previous measurement, in percent of one core
+    pub cpu_percent: f32,
+    pub memory_bytes: u64,
+    /// `None` without a battery
+    pub battery: Option<Battery>,
+}
+
+/// What the daemon holds back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Throttle {
+    /// Collection interval multiplier, and divisor of the feature window (1 = unthrottled)
+    pub stretch: u32,
+    /// File integrity hashing is skipped
+    pub defer_hashing: bool,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self { stretch: 1, defer_hashing: false }
+    }
+}
+
+pub struct Governor {
+    config: GovernorConfig,
+    system: Mutex<sysinfo::System>,
+    /// Current throttle and the measurement it follows
+    state: Mutex<(Throttle, Option<Load>)>,
+}
+
+impl Governor {
+    pub fn new(config: GovernorConfig) -> Self {
+        Self {
+            config,
+            system: Mutex::new(sysinfo::System::new()),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Measure this process's usage since the previous call, and the battery
+    pub fn measure(&self) -> Load {
+        let usage = crate::runtime::process_usage(&self.system).unwrap_or_default();
+        Load {
+            cpu_percent: usage["cpu_percent"].as_f64().unwrap_or(0.0) as f32,
+            memory_bytes: usage["memory_bytes"].as_u64().unwrap_or(0),
+            battery: battery(),
+        }
+    }
+
+    /// Throttle after `load`, applied from now on
+    pub fn adjust(&self, load: Load) -> Throttle {
+        let config = &self.config;
+        let max = config.max_stretch.max(1);
+        let memory = config.memory_mb.saturating_mul(1024 * 1024);
+        let over = load.cpu_percent > config.cpu_percent || load.memory_bytes > memory;
+        let under = load.cpu_percent < config.cpu_percent / 2.0 && load.memory_bytes < memory / 5 * 4;
+        let floor = match load.battery {
+            Some(b) if b.discharging && b.percent.is_some_and(|p| p <= config.low_battery_percent) => max,
+            Some(b) if b.discharging => config.battery_stretch.clamp(1, max),
+            _ => 1,
+        };
+        let mut state = self.state.lock().unwrap();
+        let current = state.0.stretch;
+        let stretch = match () {
+            _ if over => current.saturating_mul(2),
+            _ if under => current / 2,
+            _ => current,
+        };
+        let throttle = Throttle { stretch: stretch.clamp(floor, max), defer_hashing: over };
+        if throttle != state.0 {
+            tracing::info!(
+                stretch = throttle.stretch,
+                defer_hashing = throttle.defer_hashing,
+                cpu_percent = load.cpu_percent,
+                memory_bytes = load.memory_bytes,
+                on_battery = load.battery.is_some_and(|b| b.discharging),
+                "resource governor: throttle changed"
+            );
+        }
+        *state = (throttle, Some(load));
+        throttle
+    }
+
+    pub fn throttle(&self) -> Throttle {
+        self.state.lock().unwrap().0
+    }
+
+    /// `{throttle, load}`: the current throttle and the last measurement, for the daemon's status
+    pub fn status(&self) -> serde_json::Value {
+        let (throttle, load) = *self.state.lock().unwrap();
+        serde_json::json!({ "throttle": throttle, "load": load })
+    }
+}
+
+/// State of the machine's battery; `None` without one or where it can't be read
+#[cfg(target_os = "linux")]
+pub fn battery() -> Option<Battery> {
+    for entry in std::fs::read_dir("/sys/class/power_supply").ok()?.flatten() {
+        let dir = entry.path();
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).map(|s| s.trim().to_string()).unwrap_or_default();
+        // Peripherals (mice, keyboards) report a `Device` scope
+        if read("type") != "Battery" || read("scope") == "Device" {
+            continue;
+        }
+        return Some(Battery { discharging: read("status") == "Discharging", percent: read("capacity").parse().ok() });
+    }
+    None
+}
+
+#[cfg(target_os = "macos")]
+pub fn battery() -> Option<Battery> {
+    let output = std::process::Command::new("pmset").args(["-g", "batt"]).output().ok()?;
+    parse_pmset(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(windows)]
+pub fn battery() -> Option<Battery> {
+    use windows::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+    let mut status = SYSTEM_POWER_STATUS::default();
+    unsafe { GetSystemPowerStatus(&mut status) }.ok()?;
+    // 128: no system battery; 255: unknown
+    if status.BatteryFlag == 128 || status.BatteryFlag == 255 {
+        return None;
+    }
+    Some(Battery {
+        discharging: status.ACLineStatus == 0,
+        percent: (status.BatteryLifePercent <= 100).then_some(status.BatteryLifePercent as f32),
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn battery() -> Option<Battery> {
+    None
+}
+
+/// Battery state from the output of macOS `pmset -g batt`; `None` without an internal battery
+pub fn parse_pmset(output: &str) -> Option<Battery> {
+    let line = output.lines().find(|line| line.contains("InternalBattery"))?;
+    let percent = line.split(['\t', ' ', ';']).find_map(|word| word.strip_suffix('%')).and_then(|p| p.parse().ok());
+    Some(Battery { discharging: output.contains("'Battery Power'"), percent })
+}
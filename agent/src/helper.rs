@@ -0,0 +1,147 @@
+//! Privileged collection helper for an install split by privilege (launchd on macOS, see
+//! [`crate::launchd`]): `dadm-agent helper` runs as root and collects the kinds in
+//! `helper.collectors` for the agent, which runs as an unprivileged user, asks over a Unix socket
+//! each cycle, and does everything else (scoring, storage, uplink) itself.
+//!
+//! The protocol is the control interface's ([`crate::control`]): one JSON request line, e.g.
+//! `{"op": "collect", "kinds": ["process"]}`, answered by `{"ok": true, "result": [<events>]}` or
+//! `{"ok": false, "error": "..."}`. Collecting is the only operation, and kinds outside
+//! `helper.collectors` are not collected. Peers are checked by their socket credentials: only
+//! root and `helper.allowed_uids` are served.
+
+use crate::collectors::{CollectorPipeline, DeepScan, Event};
+use crate::config::HelperConfig;
+use crate::runtime::CancellationToken;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Longest request line accepted (bytes)
+#[cfg(unix)]
+const MAX_REQUEST: usize = 64 * 1024;
+/// How long the agent waits for a collection
+#[cfg(unix)]
+const COLLECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+/// Operation requested from the helper
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum HelperRequest {
+    /// Collect the enabled collectors among `kinds`, and the deep scan's files with `deep_scan`
+    Collect {
+        kinds: Vec<String>,
+        #[serde(default)]
+        deep_scan: Option<DeepScan>,
+    },
+}
+
+/// Serve collection on `helper.socket` until `token` is cancelled
+#[cfg(unix)]
+pub async fn serve(config: HelperConfig, collectors: Arc<CollectorPipeline>, token: CancellationToken) -> Result<(), BoxError> {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    if let Some(dir) = config.socket.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    match std::fs::remove_file(&config.socket) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let listener = tokio::net::UnixListener::bind(&config.socket)?;
+    // Anyone may connect; the peer's uid decides whether it is served
+    std::fs::set_permissions(&config.socket, std::fs::Permissions::from_mode(0o666))?;
+    tracing::info!(path = %config.socket.display(), allowed_uids = ?config.allowed_uids, "privileged helper listening");
+    let mut connections = tokio::task::JoinSet::new();
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!(error = %e, "helper connection failed");
+                    continue;
+                }
+            },
+            Some(_) = connections.join_next() => continue,
+            _ = token.cancelled() => break,
+        };
+        let uid = match stream.peer_cred() {
+            Ok(cred) => cred.uid(),
+            Err(e) => {
+                tracing::warn!(error = %e, "helper connection refused: no peer credentials");
+                continue;
+            }
+        };
+        if uid != 0 && !config.allowed_uids.contains(&uid) {
+            tracing::warn!(uid, "helper connection refused: user not allowed");
+            continue;
+        }
+        let (collectors, allowed) = (Arc::clone(&collectors), config.collectors.clone());
+        let token = token.clone();
+        connections.spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            loop {
+                let mut line = Vec::new();
+                let mut limited = (&mut reader).take(MAX_REQUEST as u64 + 1);
+                let read = tokio::select! {
+                    read = limited.read_until(b'\n', &mut line) => read,
+                    _ = token.cancelled() => break,
+                };
+                match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) if line.len() > MAX_REQUEST => break,
+                    Ok(_) => {}
+                }
+                let response = match serde_json::from_slice::<HelperRequest>(&line) {
+                    Ok(HelperRequest::Collect { mut kinds, deep_scan }) => {
+                        kinds.retain(|kind| allowed.contains(kind));
+                        let collectors = Arc::clone(&collectors);
+                        match tokio::task::spawn_blocking(move || collectors.collect_kinds(&kinds, deep_scan.as_ref())).await {
+                            Ok(events) => serde_json::json!({ "ok": true, "result": events }),
+                            Err(e) => serde_json::json!({ "ok": false, "error": e.to_string() }),
+                        }
+                    }
+                    Err(e) => serde_json::json!({ "ok": false, "error": format!("bad request: {}", e) }),
+                };
+                let mut bytes = response.to_string().into_bytes();
+                bytes.push(b'\n');
+                if writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    while connections.join_next().await.is_some() {}
+    let _ = std::fs::remove_file(&config.socket);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn serve(_: HelperConfig, _: Arc<CollectorPipeline>, _: CancellationToken) -> Result<(), BoxError> {
+    Err("the privileged helper needs Unix sockets".into())
+}
+
+/// Have the helper at `socket` collect `kinds` (and `deep_scan`) (blocking)
+#[cfg(unix)]
+pub fn collect(socket: &Path, kinds: &[String], deep_scan: Option<DeepScan>) -> Result<Vec<Event>, BoxError> {
+    use std::io::{BufRead, Write};
+    let stream = std::os::unix::net::UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(COLLECT_TIMEOUT))?;
+    let mut line = serde_json::to_vec(&HelperRequest::Collect { kinds: kinds.to_vec(), deep_scan })?;
+    line.push(b'\n');
+    (&stream).write_all(&line)?;
+    let mut response = String::new();
+    std::io::BufReader::new(&stream).read_line(&mut response)?;
+    let mut response: serde_json::Value = serde_json::from_str(&response).map_err(|e| format!("malformed helper response: {}", e))?;
+    match response["ok"].as_bool() {
+        Some(true) => Ok(serde_json::from_value(response["result"].take())?),
+        _ => Err(response["error"].as_str().unwrap_or("malformed helper response").to_string().into()),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn collect(_: &Path, _: &[String], _: Option<DeepScan>) -> Result<Vec<Event>, BoxError> {
+    Err("the privileged helper needs Unix sockets".into())
+}
@@ -0,0 +1,78 @@
+//! launchd property lists for a macOS install split by privilege: the helper
+//! ([`HELPER_LABEL`]) runs as root and does the privileged collection ([`crate::helper`]); the
+//! agent ([`AGENT_LABEL`]) runs as an unprivileged user and does everything else. Both are
+//! `LaunchDaemons`, started at boot and restarted when they exit.
+
+use std::path::Path;
+
+pub const AGENT_LABEL: &str = "com.aiximius.dadm.agent";
+pub const HELPER_LABEL: &str = "com.aiximius.dadm.helper";
+/// Directory the daemons' stdout and stderr go to
+const LOG_DIR: &str = "/Library/Logs/DADM";
+
+/// Which half of the install a property list is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Role {
+    /// The agent, run as `user`
+    Agent,
+    /// The privileged helper, run as root
+    Helper,
+}
+
+impl Role {
+    pub fn label(self) -> &'static str {
+        match self {
+            Role::Agent => AGENT_LABEL,
+            Role::Helper => HELPER_LABEL,
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Property list running `binary` with `config` in `role`; the agent runs as `user`. Install it as
+/// `/Library/LaunchDaemons/<label>.plist` (owned by root, mode 0644).
+pub fn plist(role: Role, binary: &Path, config: &Path, user: &str) -> String {
+    let (command, name) = match role {
+        Role::Agent => ("run", "agent"),
+        Role::Helper => ("helper", "helper"),
+    };
+    let arguments: String = [&*binary.to_string_lossy(), "--config", &config.to_string_lossy(), command]
+        .iter()
+        .map(|arg| format!("\t\t<string>{}</string>\n", escape(arg)))
+        .collect();
+    let user = match role {
+        Role::Agent => format!("\t<key>UserName</key>\n\t<string>{}</string>\n", escape(user)),
+        Role::Helper => String::new(),
+    };
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Label</key>
+	<string>{label}</string>
+	<key>ProgramArguments</key>
+	<array>
+{arguments}	</array>
+{user}	<key>RunAtLoad</key>
+	<true/>
+	<key>KeepAlive</key>
+	<true/>
+	<key>ThrottleInterval</key>
+	<integer>10</integer>
+	<key>ProcessType</key>
+	<string>Background</string>
+	<key>StandardOutPath</key>
+	<string>{log_dir}/{name}.log</string>
+	<key>StandardErrorPath</key>
+	<string>{log_dir}/{name}.log</string>
+</dict>
+</plist>
+"#,
+        label = role.label(),
+        log_dir = LOG_DIR,
+    )
+}
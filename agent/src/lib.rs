@@ -3,10 +3,31 @@
 //! Modular structure:
 //! - [`collectors`] — Process, network, file, privilege event collection
 //! - [`features`] — Statistical behavioral feature extraction pipeline
-//! - [`model`] — ONNX anomaly detection inference
+//! - [`model`] — ONNX anomaly detection inference, on-device baseline
 //! - [`storage`] — Encrypted local storage
 //! - [`risk`] — Risk scoring engine
 //! - [`logging`] — Structured JSON logging
+//! - [`eval`] — Offline model evaluation against labeled events
+//! - [`replay`] — Recorded events replayed through detection, cycle by cycle
+//! - [`selftest`] — Simulated suspicious activity checked against detection end to end
+//! - [`policy`] — Server-managed configuration (signed policy merged over local config)
+//! - [`commands`] — Server tasking (scans, process details, isolation) with audited results
+//! - [`response`] — Process kill / suspend on server command or critical risk, behind safety lists
+//! - [`redact`] — Privacy filter for uplink payloads and log lines
+//! - [`telemetry`] — OpenTelemetry traces and metrics of the collection cycle (OTLP/HTTP)
+//! - [`stats`] — Per-cycle pipeline statistics, aggregated into the heartbeat
+//! - [`cli`] — Command-line flags overriding config settings
+//! - [`runtime`] — Tokio daemon runtime: collector, analysis, and periodic tasks
+//! - [`control`] — Local control interface (Unix socket / named pipe) to the running daemon
+//! - [`api`] — Authenticated HTTP API on 127.0.0.1 for monitoring tools
+//! - [`top`] — Terminal dashboard of the running daemon
+//! - [`notify`] — Desktop notifications of high-risk alerts
+//! - [`helper`] — Privileged collection helper for an agent running unprivileged
+//! - [`launchd`] — launchd property lists for the agent and the helper (macOS)
+//! - [`watchdog`] — Stuck-cycle detection and pipeline recovery in the daemon
+//! - [`governor`] — CPU, memory, and battery budgets the daemon throttles itself to
+//! - [`privileges`] — Dropping root to an unprivileged account after startup
+//! - [`crash`] — Diagnostic bundles of panics, reported at the next start
 
 pub mod config;
 pub mod collectors;
@@ -16,11 +37,32 @@
 pub mod risk;
 pub mod logging;
 pub mod uplink;
+pub mod eval;
+pub mod replay;
+pub mod selftest;
+pub mod policy;
+pub mod commands;
+pub mod response;
+pub mod redact;
+pub mod telemetry;
+pub mod stats;
+pub mod cli;
+pub mod runtime;
+pub mod control;
+pub mod api;
+pub mod top;
+pub mod notify;
+pub mod helper;
+pub mod launchd;
+pub mod watchdog;
+pub mod governor;
+pub mod privileges;
+pub mod crash;
 
 pub use config::AgentConfig;
 pub use collectors::{Event, EventKind, CollectorPipeline};
 pub use features::{FeatureVector, FeatureExtractor};
-pub use model::OnnxDetector;
+pub use model::{OnnxDetector, BaselineDetector};
 pub use storage::SecureStore;
 pub use risk::{RiskEngine, RiskResult, RiskLevel};
 pub use logging::StructuredLogger;
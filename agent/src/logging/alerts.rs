@@ -0,0 +1,171 @@
+//! Alert stream (`log.alerts`): risk results at or above `min_level` (default medium) are written
+//! as [`LogEvent`] JSON lines to their own destinations, apart from operational logs: a rotated
+//! file, a stream socket (TCP `host:port` or a Unix socket path; newline-delimited, reconnected
+//! for the next alert after a failure), and / or a webhook receiving each alert as a POST. Webhook
+//! deliveries run on a background thread; alerts beyond its queue are dropped with a warning.
+
+use super::{syslog::connect_tcp, LogEvent, RotatingFile, ALERT_TARGET};
+use crate::config::{AlertStreamConfig, LogConfig};
+use crate::risk::{RiskLevel, RiskResult};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Alerts waiting for webhook delivery
+const WEBHOOK_QUEUE: usize = 256;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Socket {
+    Tcp(std::net::TcpStream),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixStream),
+}
+
+impl Socket {
+    fn connect(address: &str) -> io::Result<Self> {
+        #[cfg(unix)]
+        if address.starts_with('/') {
+            return Ok(Socket::Unix(std::os::unix::net::UnixStream::connect(address)?));
+        }
+        Ok(Socket::Tcp(connect_tcp(address)?))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.write_all(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.write_all(buf),
+        }
+    }
+}
+
+pub struct AlertStream {
+    min_level: RiskLevel,
+    file: Option<Mutex<RotatingFile>>,
+    socket: Option<(String, Mutex<Option<Socket>>)>,
+    webhook: Option<SyncSender<String>>,
+}
+
+fn webhook_loop(config: AlertStreamConfig, url: String, rx: mpsc::Receiver<String>) {
+    let client = match reqwest::blocking::Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::warn!(error = %e, "alert webhook unavailable");
+            return;
+        }
+    };
+    for line in rx {
+        let mut req = client.post(&url).header("content-type", "application/json").body(line);
+        for (name, value) in &config.webhook_headers {
+            req = req.header(name.as_str(), value.as_str());
+        }
+        match req.send() {
+            Ok(resp) if resp.status().is_success() => {}
+            Ok(resp) => tracing::warn!(status = %resp.status(), "alert webhook rejected alert"),
+            Err(e) => tracing::warn!(error = %e, "alert webhook delivery failed"),
+        }
+    }
+}
+
+impl AlertStream {
+    /// Open the destinations of `log.alerts`; `log.alerts.file` is relative to `data_dir` and
+    /// rotated like `log.file`. The socket is connected here so a wrong address shows at startup.
+    pub fn open(log: &LogConfig, data_dir: &Path) -> io::Result<Self> {
+        let config = &log.alerts;
+        let file = match config.file {
+            Some(ref file) => Some(Mutex::new(RotatingFile::open(
+                &data_dir.join(file),
+                log.max_file_bytes,
+                log.max_file_age_secs,
+                log.keep_files,
+            )?)),
+            None => None,
+        };
+        let socket = match config.socket {
+            Some(ref address) => Some((address.clone(), Mutex::new(Some(Socket::connect(address)?)))),
+            None => None,
+        };
+        let webhook = config.webhook.clone().map(|url| {
+            let (tx, rx) = mpsc::sync_channel(WEBHOOK_QUEUE);
+            let config = config.clone();
+            std::thread::spawn(move || webhook_loop(config, url, rx));
+            tx
+        });
+        if file.is_none() && socket.is_none() && webhook.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "log.alerts needs a file, socket, or webhook"));
+        }
+        Ok(Self { min_level: config.min_level, file, socket, webhook })
+    }
+
+    /// Whether `result` is written
+    pub fn selects(&self, result: &RiskResult) -> bool {
+        !result.learning && result.level >= self.min_level
+    }
+
+    /// `result` as one JSON line; `kind` is the type of its subject event
+    pub fn line(result: &RiskResult, kind: Option<&str>) -> String {
+        let ts = Utc
+            .timestamp_millis_opt(result.ts)
+            .single()
+            .unwrap_or_default()
+            .to_rfc3339_opts(SecondsFormat::Millis, true);
+        let techniques = result.techniques();
+        let tactics = result.tactics();
+        let event = LogEvent {
+            ts,
+            level: if result.level == RiskLevel::High { "ERROR" } else { "WARN" },
+            target: ALERT_TARGET,
+            message: "risk result",
+            event_id: Some(&result.event_id),
+            risk_score: Some(result.score),
+            risk_level: Some(result.level.as_str()),
+            kind,
+            error: None,
+            techniques: &techniques,
+            tactics: &tactics,
+        };
+        serde_json::to_string(&event).unwrap_or_default()
+    }
+
+    /// Write `result` to every destination when it is selected; returns whether it was. Every
+    /// destination is tried; the first failure is returned.
+    pub fn emit(&self, result: &RiskResult, kind: Option<&str>) -> io::Result<bool> {
+        if !self.selects(result) {
+            return Ok(false);
+        }
+        let line = Self::line(result, kind);
+        let mut outcome = Ok(());
+        if let Some(ref file) = self.file {
+            // One write, so rotation never splits a line
+            outcome = outcome.and(file.lock().unwrap().write_all(format!("{}\n", line).as_bytes()));
+        }
+        if let Some((ref address, ref socket)) = self.socket {
+            let mut socket = socket.lock().unwrap();
+            let written = match socket.as_mut() {
+                Some(s) => s.write_all(format!("{}\n", line).as_bytes()),
+                None => Socket::connect(address).and_then(|mut s| {
+                    let written = s.write_all(format!("{}\n", line).as_bytes());
+                    *socket = Some(s);
+                    written
+                }),
+            };
+            if written.is_err() {
+                *socket = None;
+            }
+            outcome = outcome.and(written);
+        }
+        if let Some(ref webhook) = self.webhook {
+            match webhook.try_send(line) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => tracing::warn!("alert webhook queue full; alert dropped"),
+                Err(TrySendError::Disconnected(_)) => {
+                    outcome = outcome.and(Err(io::Error::other("alert webhook stopped")));
+                }
+            }
+        }
+        outcome.map(|_| true)
+    }
+}
@@ -0,0 +1,237 @@
+//! Audit log (`log.audit`): an append-only file of security-relevant agent actions (start and
+//! stop, configuration changes, policy applications, model installs, response actions, key
+//! rotations), kept apart from operational logging. Each line is one JSON entry whose `hash`
+//! chains it to the previous entry's, so an edited, removed, or reordered line breaks the chain.
+//! With signing, each hash is also signed with an Ed25519 key derived from the device secret
+//! (`key` is its public key), so the chain cannot be rewritten without that secret.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Predecessor hash of the first entry
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// Separates the signing key derivation from other uses of the device secret
+const SIGNING_CONTEXT: &[u8] = b"dadm-agent audit log signing key v1";
+
+static AUDIT: OnceLock<AuditLog> = OnceLock::new();
+
+/// One line of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    /// When it was recorded (ms)
+    pub ts: i64,
+    /// What happened (e.g. `agent_started`, `policy_applied`, `command`)
+    pub event: String,
+    pub detail: Value,
+    /// `hash` of the previous entry
+    pub prev: String,
+    /// SHA-256 over `prev` and this entry's content
+    pub hash: String,
+    /// Base64 Ed25519 public key and signature of `hash`, when signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sig: Option<String>,
+}
+
+fn entry_hash(prev: &str, seq: u64, ts: i64, event: &str, detail: &Value) -> String {
+    let content = serde_json::json!({ "seq": seq, "ts": ts, "event": event, "detail": detail });
+    let mut hasher = Sha256::new();
+    hasher.update(prev.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(content.to_string().as_bytes());
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Signing key of the audit log
+pub struct AuditSigner {
+    pair: Ed25519KeyPair,
+}
+
+impl AuditSigner {
+    /// Key derived from the device secret (the store's key provider secret)
+    pub fn derive(secret: &[u8]) -> Self {
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret);
+        let seed = ring::hmac::sign(&key, SIGNING_CONTEXT);
+        let pair = Ed25519KeyPair::from_seed_unchecked(seed.as_ref()).expect("32-byte seed");
+        Self { pair }
+    }
+
+    /// Base64 raw public key
+    pub fn public_key(&self) -> String {
+        BASE64.encode(self.pair.public_key().as_ref())
+    }
+}
+
+struct Writer {
+    file: File,
+    seq: u64,
+    hash: String,
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    signer: Option<AuditSigner>,
+    writer: Mutex<Writer>,
+    /// `config_sha256` of the last entry that recorded one when the log was opened
+    last_config: Option<String>,
+}
+
+impl AuditLog {
+    /// Open `path` for appending, continuing the chain of its last entry
+    pub fn open(path: &Path, signer: Option<AuditSigner>) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let (mut seq, mut hash, mut last_config) = (0, GENESIS.to_string(), None);
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                // A torn last line (crash mid-write) is left for verification to report
+                if let Ok(entry) = serde_json::from_str::<AuditEntry>(&line?) {
+                    seq = entry.seq;
+                    hash = entry.hash;
+                    if let Some(config) = entry.detail.get("config_sha256").and_then(Value::as_str) {
+                        last_config = Some(config.to_string());
+                    }
+                }
+            }
+        }
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            signer,
+            writer: Mutex::new(Writer { file, seq, hash }),
+            last_config,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Configuration hash recorded last before this run
+    pub fn last_config(&self) -> Option<&str> {
+        self.last_config.as_deref()
+    }
+
+    /// Append an entry (written and synced before returning)
+    pub fn append(&self, event: &str, detail: Value) -> io::Result<AuditEntry> {
+        let mut writer = self.writer.lock().unwrap();
+        let seq = writer.seq + 1;
+        let ts = chrono::Utc::now().timestamp_millis();
+        let hash = entry_hash(&writer.hash, seq, ts, event, &detail);
+        let (key, sig) = match self.signer {
+            Some(ref signer) => (Some(signer.public_key()), Some(BASE64.encode(signer.pair.sign(hash.as_bytes()).as_ref()))),
+            None => (None, None),
+        };
+        let entry = AuditEntry { seq, ts, event: event.to_string(), detail, prev: writer.hash.clone(), hash, key, sig };
+        let line = serde_json::to_string(&entry).map_err(io::Error::other)?;
+        writer.file.write_all(format!("{}\n", line).as_bytes())?;
+        writer.file.sync_data()?;
+        writer.seq = seq;
+        writer.hash = entry.hash.clone();
+        Ok(entry)
+    }
+}
+
+/// Install the process-wide audit log used by [`record`]
+pub fn init(log: AuditLog) {
+    let _ = AUDIT.set(log);
+}
+
+/// The process-wide audit log, if one is installed
+pub fn global() -> Option<&'static AuditLog> {
+    AUDIT.get()
+}
+
+/// Append to the process-wide audit log (no-op without one); failures are logged
+pub fn record(event: &str, detail: Value) {
+    if let Some(log) = AUDIT.get() {
+        if let Err(e) = log.append(event, detail) {
+            tracing::error!(event, error = %e, "audit log write failed");
+        }
+    }
+}
+
+/// Result of [`verify`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AuditReport {
+    pub entries: u64,
+    pub signed: u64,
+    /// Public keys entries were signed with, in order of first use
+    pub keys: Vec<String>,
+    pub issues: Vec<String>,
+}
+
+impl AuditReport {
+    pub fn is_intact(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Walk the audit log at `path`, checking every hash link and signature. With `keys`, signatures
+/// must be made with one of them and unsigned entries are issues.
+pub fn verify(path: &Path, keys: &[String]) -> Result<AuditReport, BoxError> {
+    let mut report = AuditReport::default();
+    let mut prev = GENESIS.to_string();
+    let mut expected_seq = 1;
+    for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let entry: AuditEntry = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                report.issues.push(format!("line {} is not an audit entry: {}", n + 1, e));
+                continue;
+            }
+        };
+        report.entries += 1;
+        if entry.seq != expected_seq {
+            report.issues.push(format!("entry {} follows entry {} (entries missing or reordered)", entry.seq, expected_seq - 1));
+        }
+        if entry.prev != prev {
+            report.issues.push(format!("entry {} does not link to the previous entry", entry.seq));
+        }
+        if entry_hash(&entry.prev, entry.seq, entry.ts, &entry.event, &entry.detail) != entry.hash {
+            report.issues.push(format!("entry {} does not match its hash", entry.seq));
+        }
+        match (&entry.key, &entry.sig) {
+            (Some(key), Some(sig)) => {
+                report.signed += 1;
+                if !report.keys.contains(key) {
+                    report.keys.push(key.clone());
+                }
+                let valid = match (BASE64.decode(key), BASE64.decode(sig)) {
+                    (Ok(key), Ok(sig)) => UnparsedPublicKey::new(&ED25519, key).verify(entry.hash.as_bytes(), &sig).is_ok(),
+                    _ => false,
+                };
+                if !valid {
+                    report.issues.push(format!("entry {} signature is invalid", entry.seq));
+                } else if !keys.is_empty() && !keys.contains(key) {
+                    report.issues.push(format!("entry {} is signed with an unexpected key", entry.seq));
+                }
+            }
+            _ if !keys.is_empty() => report.issues.push(format!("entry {} is not signed", entry.seq)),
+            _ => {}
+        }
+        prev = entry.hash;
+        expected_seq = entry.seq + 1;
+    }
+    Ok(report)
+}
@@ -0,0 +1,196 @@
+//! Elastic Common Schema lines (`log.ecs`): each event becomes one JSON object with ECS field
+//! names (`@timestamp`, `log.level`, `event.*`, `threat.*`, ...), so Elastic ingests agent logs
+//! without an ingest pipeline. Risk results ([`super::ALERT_TARGET`]) are `event.kind: alert`
+//! with their score, severity, and ATT&CK techniques; fields without an ECS counterpart go under
+//! `dadm.*`.
+
+use super::ALERT_TARGET;
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// ECS version the lines follow
+pub const ECS_VERSION: &str = "8.11.0";
+const SERVICE: &str = "dadm-agent";
+
+/// ECS name of an event field, when it has one
+fn ecs_name(field: &str) -> Option<&'static str> {
+    Some(match field {
+        "message" => "message",
+        "event_id" => "event.id",
+        "score" | "risk_score" => "event.risk_score",
+        "error" => "error.message",
+        "techniques" => "threat.technique.id",
+        "tactics" => "threat.tactic.id",
+        "path" => "file.path",
+        "remote_addr" => "destination.address",
+        "url" => "url.full",
+        "user" => "user.name",
+        _ => return None,
+    })
+}
+
+/// `event.category` of a collector event kind
+fn category(kind: &str) -> &'static str {
+    match kind {
+        "process" => "process",
+        "network" => "network",
+        "file_integrity" => "file",
+        "privilege" => "iam",
+        _ => "host",
+    }
+}
+
+/// `event.severity` of a risk level (the values Elastic detection rules use)
+fn severity(level: &str) -> Option<u8> {
+    match level.to_ascii_lowercase().as_str() {
+        "low" => Some(21),
+        "medium" => Some(47),
+        "high" => Some(73),
+        _ => None,
+    }
+}
+
+/// Set the dotted `path` in `root`, creating objects on the way
+fn insert(root: &mut Map<String, Value>, path: &str, value: Value) {
+    match path.split_once('.') {
+        Some((head, rest)) => {
+            let entry = root.entry(head).or_insert_with(|| Value::Object(Map::new()));
+            if !entry.is_object() {
+                *entry = Value::Object(Map::new());
+            }
+            if let Value::Object(child) = entry {
+                insert(child, rest, value);
+            }
+        }
+        None => {
+            root.insert(path.to_string(), value);
+        }
+    }
+}
+
+/// Value of a `?` field: options unwrapped, lists and quoted strings read as JSON, anything else
+/// kept as text
+fn debug_value(text: &str) -> Value {
+    if text == "None" {
+        return Value::Null;
+    }
+    if let Some(inner) = text.strip_prefix("Some(").and_then(|t| t.strip_suffix(')')) {
+        return debug_value(inner);
+    }
+    match serde_json::from_str::<Value>(text) {
+        Ok(v @ (Value::Array(_) | Value::String(_))) => v,
+        _ => Value::String(text.to_string()),
+    }
+}
+
+/// Collects an event's fields
+#[derive(Default)]
+struct Fields(Vec<(String, Value)>);
+
+impl Visit for Fields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.push((field.name().to_string(), Value::from(value)));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push((field.name().to_string(), Value::from(value)));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push((field.name().to_string(), Value::from(value)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push((field.name().to_string(), Value::from(value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name().to_string(), Value::from(value)));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push((field.name().to_string(), debug_value(&format!("{:?}", value))));
+    }
+}
+
+/// [`FormatEvent`] writing ECS JSON lines
+pub struct EcsFormat {
+    hostname: String,
+    pid: u32,
+}
+
+impl Default for EcsFormat {
+    fn default() -> Self {
+        Self { hostname: sysinfo::System::host_name().unwrap_or_default(), pid: std::process::id() }
+    }
+}
+
+impl EcsFormat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ECS object of an event with `level`, `target`, and `fields`
+    fn document(&self, level: &str, target: &str, fields: Vec<(String, Value)>) -> Value {
+        let alert = target == ALERT_TARGET;
+        let mut doc = Map::new();
+        insert(&mut doc, "@timestamp", Value::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)));
+        insert(&mut doc, "log.level", Value::from(level.to_ascii_lowercase()));
+        insert(&mut doc, "log.logger", Value::from(target));
+        insert(&mut doc, "ecs.version", Value::from(ECS_VERSION));
+        insert(&mut doc, "service.name", Value::from(SERVICE));
+        insert(&mut doc, "service.version", Value::from(env!("CARGO_PKG_VERSION")));
+        insert(&mut doc, "host.hostname", Value::from(self.hostname.as_str()));
+        insert(&mut doc, "process.pid", Value::from(self.pid));
+        insert(&mut doc, "event.kind", Value::from(if alert { "alert" } else { "event" }));
+        insert(&mut doc, "event.module", Value::from("dadm"));
+        insert(&mut doc, "event.dataset", Value::from(if alert { "dadm.alert" } else { "dadm.agent" }));
+        for (name, value) in fields {
+            if value.is_null() || value.as_array().is_some_and(|a| a.is_empty()) {
+                continue;
+            }
+            match name.as_str() {
+                "level" if alert => {
+                    let level = value.as_str().unwrap_or_default().to_ascii_lowercase();
+                    if let Some(severity) = severity(&level) {
+                        insert(&mut doc, "event.severity", Value::from(severity));
+                    }
+                    insert(&mut doc, "dadm.risk_level", Value::from(level));
+                }
+                "kind" => {
+                    insert(&mut doc, "event.category", Value::from(vec![category(value.as_str().unwrap_or_default())]));
+                    insert(&mut doc, "dadm.kind", value);
+                }
+                "techniques" | "tactics" => {
+                    insert(&mut doc, "threat.framework", Value::from("MITRE ATT&CK"));
+                    insert(&mut doc, ecs_name(&name).unwrap_or_default(), value);
+                }
+                _ => match ecs_name(&name) {
+                    Some(ecs) => insert(&mut doc, ecs, value),
+                    None => insert(&mut doc, &format!("dadm.{}", name), value),
+                },
+            }
+        }
+        Value::Object(doc)
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for EcsFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, _ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let meta = event.metadata();
+        let doc = self.document(meta.level().as_str(), meta.target(), fields.0);
+        writeln!(writer, "{}", doc)
+    }
+}
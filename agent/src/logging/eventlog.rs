@@ -0,0 +1,146 @@
+//! Windows Event Log sink (`log.eventlog`): each log line is reported as one event of the
+//! configured source, with the JSON line (or text) as its message. The event type follows the
+//! level; risk results ([`super::ALERT_TARGET`]) get their own event id so collectors can select
+//! them. The source is registered under the log on first use (needs administrator rights; when it
+//! cannot be registered, events are still written, but Event Viewer shows no message template).
+
+use crate::config::EventLogConfig;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+use windows::core::{HSTRING, PCWSTR};
+use windows::Win32::Foundation::{HANDLE, PSID};
+use windows::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE,
+    EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+};
+use windows::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_SET_VALUE, REG_DWORD, REG_EXPAND_SZ,
+    REG_OPTION_NON_VOLATILE,
+};
+
+/// Event id of operational log lines
+pub const OPERATIONAL_EVENT_ID: u32 = 1000;
+/// Event id of risk results
+pub const ALERT_EVENT_ID: u32 = 2000;
+/// Message file whose templates are `%1` for every event id (shipped with the .NET Framework)
+const MESSAGE_FILE: &str = r"%SystemRoot%\Microsoft.NET\Framework64\v4.0.30319\EventLogMessages.dll";
+/// Longest string ReportEvent accepts (characters)
+const MAX_MESSAGE_CHARS: usize = 31_839;
+
+struct Source(HANDLE);
+
+impl Drop for Source {
+    fn drop(&mut self) {
+        // SAFETY: the handle came from RegisterEventSourceW and is released once
+        unsafe {
+            let _ = DeregisterEventSource(self.0);
+        }
+    }
+}
+
+/// [`MakeWriter`] for the fmt layer; each event's line is reported as one event
+#[derive(Clone)]
+pub struct EventLog {
+    source: Arc<Source>,
+}
+
+fn utf16(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Create `HKLM\SYSTEM\CurrentControlSet\Services\EventLog\<log>\<source>` with its message file
+fn register(config: &EventLogConfig) -> windows::core::Result<()> {
+    let subkey = HSTRING::from(format!(r"SYSTEM\CurrentControlSet\Services\EventLog\{}\{}", config.log, config.source));
+    let message_file: Vec<u8> = utf16(MESSAGE_FILE).iter().flat_map(|c| c.to_le_bytes()).collect();
+    // Error, warning, information
+    let types = 7u32.to_le_bytes();
+    let mut key = HKEY::default();
+    // SAFETY: every pointer refers to a live local; the key is closed below
+    unsafe {
+        RegCreateKeyExW(HKEY_LOCAL_MACHINE, &subkey, 0, PCWSTR::null(), REG_OPTION_NON_VOLATILE, KEY_SET_VALUE, None, &mut key, None)?;
+        let result = RegSetValueExW(key, &HSTRING::from("EventMessageFile"), 0, REG_EXPAND_SZ, Some(&message_file))
+            .and_then(|_| RegSetValueExW(key, &HSTRING::from("TypesSupported"), 0, REG_DWORD, Some(&types)));
+        let _ = RegCloseKey(key);
+        result
+    }
+}
+
+impl EventLog {
+    /// Open the source of `config`, registering it first with `register`
+    pub fn connect(config: &EventLogConfig) -> io::Result<Self> {
+        if config.register {
+            if let Err(e) = register(config) {
+                eprintln!("cannot register event source {:?}: {}", config.source, e);
+            }
+        }
+        // SAFETY: the source name outlives the call
+        let handle = unsafe { RegisterEventSourceW(PCWSTR::null(), &HSTRING::from(config.source.as_str())) }.map_err(io::Error::other)?;
+        Ok(Self { source: Arc::new(Source(handle)) })
+    }
+}
+
+/// Collects one event's line and reports it when dropped
+pub struct EventLogLine {
+    log: EventLog,
+    kind: REPORT_EVENT_TYPE,
+    id: u32,
+    line: Vec<u8>,
+}
+
+impl Write for EventLogLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for EventLogLine {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.line);
+        let text: String = text.trim_end().chars().take(MAX_MESSAGE_CHARS).collect();
+        if text.is_empty() {
+            return;
+        }
+        let message = utf16(&text);
+        // SAFETY: the message is NUL-terminated and outlives the call
+        unsafe {
+            let _ = ReportEventW(
+                self.log.source.0,
+                self.kind,
+                0,
+                self.id,
+                PSID(std::ptr::null_mut()),
+                0,
+                Some(&[PCWSTR(message.as_ptr())]),
+                None,
+            );
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for EventLog {
+    type Writer = EventLogLine;
+
+    fn make_writer(&'a self) -> EventLogLine {
+        EventLogLine { log: self.clone(), kind: EVENTLOG_INFORMATION_TYPE, id: OPERATIONAL_EVENT_ID, line: Vec::new() }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> EventLogLine {
+        let kind = match *meta.level() {
+            Level::ERROR => EVENTLOG_ERROR_TYPE,
+            Level::WARN => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        let id = match meta.target() == super::ALERT_TARGET {
+            true => ALERT_EVENT_ID,
+            false => OPERATIONAL_EVENT_ID,
+        };
+        EventLogLine { log: self.clone(), kind, id, line: Vec::new() }
+    }
+}
@@ -0,0 +1,108 @@
+//! Log file sink (`log.file`) with rotation: the file is rotated once it would exceed
+//! `log.max_file_bytes` or is older than `log.max_file_age_secs`. Rotated files are renamed
+//! `<file>.1` (newest) to `<file>.<keep_files>`; older ones are deleted.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub struct RotatingFile {
+    path: PathBuf,
+    /// 0 never rotates by size
+    max_bytes: u64,
+    /// `None` never rotates by age
+    max_age: Option<Duration>,
+    /// Rotated files kept
+    keep: usize,
+    file: File,
+    /// Size of the current file
+    written: u64,
+    /// When the current file was started
+    started: SystemTime,
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl RotatingFile {
+    /// Open `path` for appending (creating it and its directory)
+    pub fn open(path: &Path, max_bytes: u64, max_age_secs: u64, keep: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = open_append(path)?;
+        let meta = file.metadata()?;
+        let started = meta.created().or_else(|_| meta.modified()).unwrap_or_else(|_| SystemTime::now());
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_age: (max_age_secs > 0).then(|| Duration::from_secs(max_age_secs)),
+            keep,
+            file,
+            written: meta.len(),
+            started,
+        })
+    }
+
+    /// `<file>.<n>`
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    /// Whether the current file is done before `len` more bytes go in
+    fn due(&self, len: usize) -> bool {
+        if self.written == 0 {
+            return false;
+        }
+        let full = self.max_bytes > 0 && self.written.saturating_add(len as u64) > self.max_bytes;
+        let old = self
+            .max_age
+            .is_some_and(|max| SystemTime::now().duration_since(self.started).unwrap_or_default() >= max);
+        full || old
+    }
+
+    /// Shift the rotated files up by one, retire the current file as `<file>.1`, and start a new one
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let oldest = self.rotated(self.keep);
+            if oldest.exists() {
+                std::fs::remove_file(&oldest)?;
+            }
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = open_append(&self.path)?;
+        self.written = 0;
+        self.started = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.due(buf.len()) {
+            // A failed rotation keeps appending to the current file rather than losing lines
+            let _ = self.rotate();
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
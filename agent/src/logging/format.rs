@@ -1,11 +1,17 @@
 //! JSON log lines: one JSON object per line (ndjson) for ingestion and audit.
 
+use super::{EcsFormat, RecentLog, Redacting, RotatingFile, Syslog};
+use crate::config::LogConfig;
+use crate::redact::Redactor;
 use serde::Serialize;
 use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
 
 #[derive(Serialize)]
 pub struct LogEvent<'a> {
@@ -23,30 +29,97 @@ pub struct LogEvent<'a> {
     pub kind: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<&'a str>,
+    /// MITRE ATT&CK technique ids of a risk result
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub techniques: &'a [&'a str],
+    /// MITRE ATT&CK tactic ids of a risk result
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub tactics: &'a [&'a str],
 }
 
 /// Initialize tracing with JSON format (one JSON object per line)
 pub struct StructuredLogger;
 
+pub(super) type BoxLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Formatting layer of `config` (text, JSON, or ECS JSON) writing to `writer`, through
+/// `redactor` when set
+fn fmt_layer<W>(config: &LogConfig, ansi: bool, redactor: Option<&Arc<Redactor>>, writer: W) -> BoxLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match redactor {
+        // Escape codes would split words apart from the names they color
+        Some(redactor) => plain_layer(config, false, Redacting::new(Arc::clone(redactor), writer)),
+        None => plain_layer(config, ansi, writer),
+    }
+}
+
+fn plain_layer<W>(config: &LogConfig, ansi: bool, writer: W) -> BoxLayer
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    if config.json && config.ecs {
+        tracing_subscriber::fmt::layer().event_format(EcsFormat::new()).with_writer(writer).boxed()
+    } else if config.json {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_span_events(FmtSpan::NONE)
+            .with_writer(writer)
+            .boxed()
+    } else {
+        tracing_subscriber::fmt::layer().with_ansi(ansi).with_writer(writer).boxed()
+    }
+}
+
 impl StructuredLogger {
-    /// Install global subscriber: JSON lines to stdout, level from RUST_LOG or default.
-    pub fn init(json: bool, default_level: &str) {
-        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level));
-        if json {
-            let fmt = tracing_subscriber::fmt::layer()
-                .json()
-                .with_span_events(FmtSpan::NONE)
-                .with_writer(std::io::stdout);
-            tracing_subscriber::registry()
-                .with(filter)
-                .with(fmt)
-                .init();
-        } else {
-            tracing_subscriber::registry()
-                .with(filter)
-                .with(tracing_subscriber::fmt::layer().with_writer(std::io::stdout))
-                .init();
+    /// Install global subscriber: JSON lines (or text) to stdout and / or the rotated `log.file`
+    /// (relative to `data_dir`), `log.syslog`, and `log.eventlog`, level from RUST_LOG or `log.level`. If the file
+    /// cannot be opened, logs go to stdout. With `log.redaction`, every sink gets redacted lines.
+    /// The last lines are also kept in memory when [`super::keep_recent`] asked for them.
+    /// The filter can be replaced later with [`super::set_level`].
+    pub fn init(config: &LogConfig, data_dir: &Path) {
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.level));
+        let redactor = Redactor::new(&config.redaction).map(Arc::new);
+        let redactor = redactor.as_ref();
+        let mut layers: Vec<BoxLayer> = Vec::new();
+        let mut stdout = config.stdout;
+        if let Some(ref file) = config.file {
+            let path = data_dir.join(file);
+            match RotatingFile::open(&path, config.max_file_bytes, config.max_file_age_secs, config.keep_files) {
+                Ok(file) => layers.push(fmt_layer(config, false, redactor, Mutex::new(file))),
+                Err(e) => {
+                    eprintln!("cannot open log file {}: {}; logging to stdout", path.display(), e);
+                    stdout = true;
+                }
+            }
+        }
+        if config.syslog.enabled {
+            match Syslog::connect(&config.syslog) {
+                Ok(syslog) => layers.push(fmt_layer(config, false, redactor, syslog)),
+                Err(e) => eprintln!("cannot reach syslog ({:?}): {}", config.syslog.transport, e),
+            }
+        }
+        #[cfg(windows)]
+        if config.eventlog.enabled {
+            match super::EventLog::connect(&config.eventlog) {
+                Ok(eventlog) => layers.push(fmt_layer(config, false, redactor, eventlog)),
+                Err(e) => eprintln!("cannot open event source {:?}: {}", config.eventlog.source, e),
+            }
+        }
+        #[cfg(not(windows))]
+        if config.eventlog.enabled {
+            eprintln!("log.eventlog is only available on Windows");
+        }
+        if stdout {
+            layers.push(fmt_layer(config, true, redactor, std::io::stdout));
+        }
+        if super::recent::capacity() > 0 {
+            layers.push(fmt_layer(config, false, redactor, RecentLog));
         }
+        let (filter, handle) = reload::Layer::new(filter);
+        tracing_subscriber::registry().with(layers).with(filter).init();
+        super::level::install(handle);
     }
 
     /// Emit a single structured log line (e.g. for risk result) without going through tracing
@@ -0,0 +1,125 @@
+//! Runtime log level changes: the filter installed by [`super::StructuredLogger::init`] can be
+//! replaced while the daemon runs. The watcher applies the directives in the override file
+//! (written by `dadm-agent log-level`) when it changes, and on SIGHUP (Unix) re-reads the config
+//! file. Without an override, the level is `RUST_LOG`, else `log.level` as at startup.
+
+use crate::config::{pinned_config_keys, AgentConfig};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+use tracing_subscriber::layer::Layered;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+pub(super) type FilterHandle = reload::Handle<EnvFilter, Layered<Vec<super::format::BoxLayer>, Registry>>;
+
+/// Override file under `data_dir`
+pub const LEVEL_FILE: &str = "log_level";
+const POLL: Duration = Duration::from_secs(1);
+
+static HANDLE: OnceLock<FilterHandle> = OnceLock::new();
+
+pub(super) fn install(handle: FilterHandle) {
+    let _ = HANDLE.set(handle);
+}
+
+/// Replace the active filter with `directives` (`EnvFilter` syntax, e.g. `debug` or
+/// `info,dadm_agent::uplink=trace`)
+pub fn set_level(directives: &str) -> Result<(), BoxError> {
+    let filter = EnvFilter::try_new(directives)?;
+    let handle = HANDLE.get().ok_or("logging is not initialized")?;
+    handle.reload(filter)?;
+    Ok(())
+}
+
+/// Directives of the active filter
+pub fn current_level() -> Option<String> {
+    HANDLE.get().and_then(|h| h.with_current(|f| f.to_string()).ok())
+}
+
+/// Directives in effect without an override: `RUST_LOG`, else `log.level`; `None` when the config
+/// file fails its signature check
+fn default_level(config_path: &Path) -> Option<String> {
+    if let Some(level) = std::env::var("RUST_LOG").ok().filter(|v| !v.trim().is_empty()) {
+        return Some(level);
+    }
+    match AgentConfig::load_signed(config_path, &pinned_config_keys()) {
+        Ok(config) => Some(config.log.level),
+        Err(e) => {
+            tracing::warn!(error = %e, "config not reloaded; log level unchanged");
+            None
+        }
+    }
+}
+
+/// Override directives in `path`, if any
+pub fn read_override(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path).ok().map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+/// Set the override in `path` to `directives` (checked first), or remove it with `None`; the
+/// watcher applies it within a second
+pub fn write_override(path: &Path, directives: Option<&str>) -> Result<(), BoxError> {
+    let Some(directives) = directives else {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    };
+    EnvFilter::try_new(directives)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, format!("{}\n", directives))?;
+    Ok(())
+}
+
+/// Set by the SIGHUP handler
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_hangup(_: libc::c_int) {
+    HANGUP.store(true, Ordering::Relaxed);
+}
+
+/// Whether SIGHUP arrived since the last call
+fn hangup() -> bool {
+    HANGUP.swap(false, Ordering::Relaxed)
+}
+
+fn apply(directives: &str, reason: &str) {
+    match set_level(directives) {
+        Ok(()) => tracing::info!(level = directives, reason, "log level changed"),
+        Err(e) => tracing::warn!(level = directives, error = %e, "invalid log level; unchanged"),
+    }
+}
+
+/// Start the watcher thread for the override file `override_path` and SIGHUP; runs until exit
+pub fn watch_level(config_path: PathBuf, override_path: PathBuf) {
+    #[cfg(unix)]
+    // SAFETY: the handler only stores to an atomic
+    unsafe {
+        libc::signal(libc::SIGHUP, on_hangup as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+    std::thread::spawn(move || {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut seen: Option<SystemTime> = None;
+        loop {
+            let stamp = modified(&override_path);
+            let changed = stamp != seen;
+            seen = stamp;
+            if hangup() || changed {
+                match read_override(&override_path) {
+                    Some(directives) => apply(&directives, "override"),
+                    None => {
+                        if let Some(level) = default_level(&config_path) {
+                            apply(&level, "config");
+                        }
+                    }
+                }
+            }
+            std::thread::sleep(POLL);
+        }
+    });
+}
@@ -1,5 +1,29 @@
 //! Structured JSON logging for events, risk results, and agent lifecycle.
 
+mod alerts;
+pub mod audit;
+mod ecs;
+#[cfg(windows)]
+mod eventlog;
+mod file;
 mod format;
+mod level;
+mod recent;
+mod redacting;
+mod siem;
+mod syslog;
 
-pub use format::StructuredLogger;
+#[cfg(windows)]
+pub use eventlog::EventLog;
+pub use alerts::AlertStream;
+pub use ecs::{EcsFormat, ECS_VERSION};
+pub use file::RotatingFile;
+pub use format::{LogEvent, StructuredLogger};
+pub use level::{current_level, read_override, set_level, watch_level, write_override, LEVEL_FILE};
+pub use recent::{keep_recent, recent_lines, RecentLog};
+pub use redacting::Redacting;
+pub use siem::SiemOutput;
+pub use syslog::Syslog;
+
+/// Target of risk result log lines, which sinks may route apart from operational logs
+pub const ALERT_TARGET: &str = "dadm_agent::alert";
@@ -0,0 +1,70 @@
+//! Recent log lines kept in memory for crash bundles (`crash.log_lines`): a sink like the others,
+//! formatted and redacted the same way, that keeps the last lines instead of writing them.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tracing_subscriber::fmt::MakeWriter;
+
+static CAPACITY: AtomicUsize = AtomicUsize::new(0);
+static LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Keep the last `lines` log lines from [`super::StructuredLogger::init`] on; 0 keeps none
+pub fn keep_recent(lines: usize) {
+    CAPACITY.store(lines, Ordering::Relaxed);
+}
+
+pub(super) fn capacity() -> usize {
+    CAPACITY.load(Ordering::Relaxed)
+}
+
+/// The lines kept, oldest first. Doesn't wait for a lock held elsewhere (e.g. by a thread that
+/// panicked while logging); returns nothing then.
+pub fn recent_lines() -> Vec<String> {
+    match LINES.try_lock() {
+        Ok(lines) => lines.iter().cloned().collect(),
+        Err(std::sync::TryLockError::Poisoned(lines)) => lines.into_inner().iter().cloned().collect(),
+        Err(std::sync::TryLockError::WouldBlock) => Vec::new(),
+    }
+}
+
+/// [`MakeWriter`] of the kept lines
+pub struct RecentLog;
+
+/// Collects one event's line and keeps it when dropped
+pub struct RecentLine(Vec<u8>);
+
+impl Write for RecentLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RecentLine {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.0);
+        let text = text.trim_end();
+        if text.is_empty() {
+            return;
+        }
+        let mut lines = LINES.lock().unwrap_or_else(|e| e.into_inner());
+        while lines.len() >= capacity().max(1) {
+            lines.pop_front();
+        }
+        lines.push_back(text.to_string());
+    }
+}
+
+impl<'a> MakeWriter<'a> for RecentLog {
+    type Writer = RecentLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RecentLine(Vec::new())
+    }
+}
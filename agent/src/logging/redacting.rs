@@ -0,0 +1,65 @@
+//! Log redaction (`log.redaction`): wraps a sink's writer so each formatted line passes through
+//! the [`Redactor`] before it is written. JSON lines are redacted field by field (field rules and
+//! command-line arguments apply); text lines are scrubbed as plain text.
+
+use crate::redact::Redactor;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tracing::Metadata;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// [`MakeWriter`] redacting every line before handing it to the inner writer
+pub struct Redacting<M> {
+    redactor: Arc<Redactor>,
+    inner: M,
+}
+
+impl<M> Redacting<M> {
+    pub fn new(redactor: Arc<Redactor>, inner: M) -> Self {
+        Self { redactor, inner }
+    }
+}
+
+/// Collects one event's line and writes it redacted when dropped
+pub struct RedactingLine<'a, W: Write> {
+    redactor: &'a Redactor,
+    inner: W,
+    line: Vec<u8>,
+}
+
+impl<W: Write> Write for RedactingLine<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for RedactingLine<'_, W> {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.line);
+        let text = text.trim_end();
+        if text.is_empty() {
+            return;
+        }
+        // One write, so sinks that split on writes (syslog, event log, rotation) see the whole line
+        let line = format!("{}\n", self.redactor.body(text));
+        let _ = self.inner.write_all(line.as_bytes());
+        let _ = self.inner.flush();
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingLine<'a, M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingLine { redactor: &self.redactor, inner: self.inner.make_writer(), line: Vec::new() }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        RedactingLine { redactor: &self.redactor, inner: self.inner.make_writer_for(meta), line: Vec::new() }
+    }
+}
@@ -0,0 +1,189 @@
+//! SIEM alert output (`log.siem`): risk results at or above `min_level` are written as CEF
+//! (ArcSight) or LEEF 1.0 (QRadar) lines to syslog, a rotated file, or stdout, so SIEMs parse them
+//! with their built-in parsers. By default only results that raised an alert are written, not
+//! every cycle a window stays elevated.
+
+use super::{RotatingFile, Syslog};
+use crate::config::{LogConfig, SiemFormat};
+use crate::risk::{RiskLevel, RiskResult};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+const VENDOR: &str = "Aiximius";
+const PRODUCT: &str = "DADM Agent";
+
+enum Sink {
+    Syslog(Syslog),
+    File(Mutex<RotatingFile>),
+    Stdout,
+}
+
+pub struct SiemOutput {
+    format: SiemFormat,
+    min_level: RiskLevel,
+    alerts_only: bool,
+    hostname: String,
+    sink: Sink,
+}
+
+/// CEF header field: `\` and `|` escaped
+fn cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+/// CEF extension value: `\` and `=` escaped, line breaks as `\n` / `\r`
+fn cef_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// LEEF attribute value: no tabs or line breaks (the attribute delimiter)
+fn leef_value(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+/// Event id and name of `result`: the first rule, correlation pattern, or class behind it
+fn signature(result: &RiskResult) -> (String, String) {
+    let level = result.level.as_str();
+    let cause = result
+        .rule_hits
+        .first()
+        .map(|h| h.rule.clone())
+        .or_else(|| result.correlations.first().map(|c| c.pattern.clone()))
+        .or_else(|| (!result.ioc_hits.is_empty()).then(|| "ioc".to_string()))
+        .or_else(|| result.top_class.as_ref().map(|c| c.label.clone()));
+    match cause {
+        Some(cause) => (cause.clone(), format!("DADM {} risk: {}", level, cause)),
+        None => (format!("risk-{}", level), format!("DADM {} risk", level)),
+    }
+}
+
+impl SiemOutput {
+    /// Open the sink of `log.siem`: syslog when `log.siem.syslog.enabled`, else `log.siem.file`
+    /// (relative to `data_dir`, rotated like `log.file`), else stdout
+    pub fn open(log: &LogConfig, data_dir: &Path) -> io::Result<Self> {
+        let config = &log.siem;
+        let sink = if config.syslog.enabled {
+            Sink::Syslog(Syslog::connect(&config.syslog)?)
+        } else if let Some(ref file) = config.file {
+            let file = RotatingFile::open(&data_dir.join(file), log.max_file_bytes, log.max_file_age_secs, log.keep_files)?;
+            Sink::File(Mutex::new(file))
+        } else {
+            Sink::Stdout
+        };
+        Ok(Self {
+            format: config.format,
+            min_level: config.min_level,
+            alerts_only: config.alerts_only,
+            hostname: sysinfo::System::host_name().unwrap_or_default(),
+            sink,
+        })
+    }
+
+    /// Whether `result` is written
+    pub fn selects(&self, result: &RiskResult) -> bool {
+        !result.learning && result.level >= self.min_level && (result.alert || !self.alerts_only)
+    }
+
+    /// `result` as one line in the configured format
+    pub fn line(&self, result: &RiskResult) -> String {
+        let (id, name) = signature(result);
+        let join = |items: Vec<&str>| items.join(",");
+        let fields: Vec<(&str, String)> = [
+            ("rules", join(result.rule_hits.iter().map(|h| h.rule.as_str()).collect())),
+            ("correlations", join(result.correlations.iter().map(|c| c.pattern.as_str()).collect())),
+            ("techniques", join(result.techniques())),
+            ("tactics", join(result.tactics())),
+            ("iocs", join(result.ioc_hits.iter().map(|h| h.indicator.as_str()).collect())),
+            ("class", result.top_class.as_ref().map(|c| c.label.clone()).unwrap_or_default()),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .collect();
+        let severity = (result.score.clamp(0.0, 1.0) * 10.0).round() as u8;
+        match self.format {
+            SiemFormat::Cef => {
+                let mut ext = vec![
+                    format!("rt={}", result.ts),
+                    format!("dvchost={}", cef_value(&self.hostname)),
+                    format!("externalId={}", cef_value(&result.event_id)),
+                    format!("cat={}", result.level.as_str()),
+                    format!("start={}", result.window_start),
+                    format!("end={}", result.window_end),
+                    format!("cfp1={:.4}", result.score),
+                    "cfp1Label=score".to_string(),
+                ];
+                // Custom string fields cs1..cs6, labeled
+                for (n, (label, value)) in fields.iter().enumerate() {
+                    ext.push(format!("cs{}={}", n + 1, cef_value(value)));
+                    ext.push(format!("cs{}Label={}", n + 1, label));
+                }
+                format!(
+                    "CEF:0|{}|{}|{}|{}|{}|{}|{}",
+                    VENDOR,
+                    PRODUCT,
+                    env!("CARGO_PKG_VERSION"),
+                    cef_header(&id),
+                    cef_header(&name),
+                    severity,
+                    ext.join(" ")
+                )
+            }
+            SiemFormat::Leef => {
+                let time = Utc
+                    .timestamp_millis_opt(result.ts)
+                    .single()
+                    .unwrap_or_default()
+                    .to_rfc3339_opts(SecondsFormat::Millis, true);
+                let mut attrs = vec![
+                    format!("devTime={}", time),
+                    "devTimeFormat=yyyy-MM-dd'T'HH:mm:ss.SSSX".to_string(),
+                    format!("sev={}", severity.max(1)),
+                    format!("cat={}", result.level.as_str()),
+                    format!("identHostName={}", leef_value(&self.hostname)),
+                    format!("eventId={}", leef_value(&result.event_id)),
+                    format!("name={}", leef_value(&name)),
+                    format!("score={:.4}", result.score),
+                ];
+                attrs.extend(fields.iter().map(|(label, value)| format!("{}={}", label, leef_value(value))));
+                format!(
+                    "LEEF:1.0|{}|{}|{}|{}|{}",
+                    VENDOR,
+                    PRODUCT,
+                    env!("CARGO_PKG_VERSION"),
+                    cef_header(&id),
+                    attrs.join("\t")
+                )
+            }
+        }
+    }
+
+    /// Write `result` when it is selected; returns whether it was
+    pub fn emit(&self, result: &RiskResult) -> io::Result<bool> {
+        if !self.selects(result) {
+            return Ok(false);
+        }
+        let line = self.line(result);
+        match self.sink {
+            Sink::Syslog(ref syslog) => {
+                let severity = match result.level {
+                    RiskLevel::High => 3,
+                    RiskLevel::Medium => 4,
+                    RiskLevel::Low => 6,
+                };
+                syslog.send(severity, &line)?;
+            }
+            Sink::File(ref file) => {
+                // One write, so rotation never splits a line
+                file.lock().unwrap().write_all(format!("{}\n", line).as_bytes())?;
+            }
+            Sink::Stdout => println!("{}", line),
+        }
+        Ok(true)
+    }
+}
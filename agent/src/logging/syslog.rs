@@ -0,0 +1,194 @@
+//! Syslog sink (`log.syslog`): each log line becomes one RFC 5424 message, sent to the local
+//! syslog socket (`/dev/log`), or to a remote collector over UDP or TCP (octet-counted framing,
+//! RFC 6587). The severity follows the event level. Messages that cannot be delivered are dropped;
+//! a TCP connection is reopened for the next message.
+
+use crate::config::{SyslogConfig, SyslogTransport};
+use chrono::{SecondsFormat, Utc};
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Local syslog socket when no `address` is set
+#[cfg(unix)]
+const LOCAL_SOCKET: &str = "/dev/log";
+/// Bound on connecting to and writing to a TCP collector
+const TCP_TIMEOUT: Duration = Duration::from_secs(2);
+
+enum Transport {
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram, String),
+    Udp(UdpSocket),
+    Tcp(String, Option<TcpStream>),
+}
+
+impl Transport {
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Transport::Unix(socket, path) => socket.send_to(message, path.as_str()).map(|_| ()),
+            Transport::Udp(socket) => socket.send(message).map(|_| ()),
+            Transport::Tcp(address, stream) => {
+                if stream.is_none() {
+                    *stream = Some(connect_tcp(address)?);
+                }
+                let mut framed = format!("{} ", message.len()).into_bytes();
+                framed.extend_from_slice(message);
+                let result = stream.as_mut().map_or(Ok(()), |s| s.write_all(&framed));
+                if result.is_err() {
+                    *stream = None;
+                }
+                result
+            }
+        }
+    }
+}
+
+pub(super) fn connect_tcp(address: &str) -> io::Result<TcpStream> {
+    use std::net::ToSocketAddrs;
+    let addr = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("cannot resolve {}", address)))?;
+    let stream = TcpStream::connect_timeout(&addr, TCP_TIMEOUT)?;
+    stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Header fields shared by every message
+struct Header {
+    facility: u8,
+    hostname: String,
+    app_name: String,
+    procid: u32,
+}
+
+/// [`MakeWriter`] for the fmt layer; each event's line is sent as one message
+#[derive(Clone)]
+pub struct Syslog {
+    header: Arc<Header>,
+    transport: Arc<Mutex<Transport>>,
+}
+
+/// RFC 5424 header fields are printable ASCII without spaces
+fn header_field(value: &str, max: usize) -> String {
+    let field: String = value.chars().filter(|c| c.is_ascii_graphic()).take(max).collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+impl Syslog {
+    /// Open the transport of `config`
+    pub fn connect(config: &SyslogConfig) -> io::Result<Self> {
+        let address = config.address.clone();
+        let remote = || {
+            address
+                .clone()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "log.syslog.address is required for udp and tcp"))
+        };
+        let transport = match config.transport {
+            #[cfg(unix)]
+            SyslogTransport::Unix => Transport::Unix(
+                std::os::unix::net::UnixDatagram::unbound()?,
+                address.unwrap_or_else(|| LOCAL_SOCKET.to_string()),
+            ),
+            #[cfg(not(unix))]
+            SyslogTransport::Unix => {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "no local syslog socket on this platform"));
+            }
+            SyslogTransport::Udp => {
+                let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+                socket.connect(remote()?)?;
+                Transport::Udp(socket)
+            }
+            SyslogTransport::Tcp => {
+                let address = remote()?;
+                // The first connection is made here so a wrong address shows at startup
+                let stream = connect_tcp(&address)?;
+                Transport::Tcp(address, Some(stream))
+            }
+        };
+        let hostname = sysinfo::System::host_name().unwrap_or_default();
+        Ok(Self {
+            header: Arc::new(Header {
+                facility: config.facility.min(23),
+                hostname: header_field(&hostname, 255),
+                app_name: header_field(&config.app_name, 48),
+                procid: std::process::id(),
+            }),
+            transport: Arc::new(Mutex::new(transport)),
+        })
+    }
+
+    /// Send `text` as one message with `severity` (0 emergency to 7 debug)
+    pub fn send(&self, severity: u8, text: &str) -> io::Result<()> {
+        let header = &self.header;
+        let message = format!(
+            "<{}>1 {} {} {} {} - - {}",
+            header.facility as u16 * 8 + severity.min(7) as u16,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            header.hostname,
+            header.app_name,
+            header.procid,
+            text
+        );
+        let mut transport = self.transport.lock().map_err(|_| io::Error::other("syslog transport poisoned"))?;
+        transport.send(message.as_bytes())
+    }
+}
+
+/// Severity of `level` (RFC 5424 section 6.2.1)
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+/// Collects one event's line and sends it when dropped
+pub struct SyslogLine {
+    syslog: Syslog,
+    severity: u8,
+    line: Vec<u8>,
+}
+
+impl Write for SyslogLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogLine {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.line);
+        let text = text.trim_end();
+        if !text.is_empty() {
+            let _ = self.syslog.send(self.severity, text);
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = SyslogLine;
+
+    fn make_writer(&'a self) -> SyslogLine {
+        SyslogLine { syslog: self.clone(), severity: 6, line: Vec::new() }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> SyslogLine {
+        SyslogLine { syslog: self.clone(), severity: severity(meta.level()), line: Vec::new() }
+    }
+}
@@ -1,21 +1,59 @@
 //! DADM Agent entrypoint: offline-first, optional uplink (Aiximius-controlled).
 //! Runs a single cycle or a daemon loop with configurable interval; when uplink is enabled,
 //! reports device, events, and risk to the graph API.
+//!
+//! Subcommands (see [`dadm_agent::cli`] and `--help`): `run` (the default) runs one cycle or the
+//! daemon, `scan-once` one cycle; `status` prints the daemon's last status; `query` stored risk
+//! results (`--since 1h --min-risk medium`); `export` stored events as JSONL / CSV / Parquet;
+//! `prune` applies retention now (or `--before`); `config schema|effective|validate` inspects the
+//! config; `evaluate` and `replay` score recorded events offline; `selftest` checks detection
+//! against simulated suspicious activity; `verify-chain`, `scrub`,
+//! `risk-trend`, `feedback`, `policy-audit`, `command-audit`, `response-audit`, `undo`,
+//! `log-level`, `verify-audit`, `rotate-key`, and `secret` are the analyst and maintenance tools. Global flags (`--config`,
+//! `--data-dir`, `--once`, `--interval`, `--log-level`, `--no-uplink`, `--dry-run`) override the
+//! config and environment for the run. Inspection commands accept
+//! `--db <path> [--secret-file <path>]` to read a copied store read-only.
 
+use clap::Parser;
 use dadm_agent::{
-    config::AgentConfig,
-    collectors::CollectorPipeline,
+    cli::{Cli, Command as CliCommand, ConfigCommand, EvaluateArgs, ExportArgs, FeedbackArgs, QueryArgs, ReplayArgs, SecretCommand, StoreArgs, TrendArgs},
+    api,
+    config::{AgentConfig, Layer, StorageConfig, StorageEncryption},
+    control,
+    crash,
+    commands::{self, Command, CommandContext},
+    eval,
+    helper,
+    launchd,
+    policy::{self, ModelRelease},
+    privileges,
+    replay::{self, Replayer, ReplaySummary},
+    response::{firewall, isolation, ResponseEngine, UndoActor},
+    selftest,
+    stats::{CycleStats, PipelineStats},
+    collectors::{CollectorPipeline, Event},
     features::FeatureExtractor,
-    model::OnnxDetector,
-    storage::SecureStore,
-    risk::{RiskEngine, RiskLevel},
-    logging::StructuredLogger,
-    uplink::UplinkClient,
+    model::{release, BaselineDetector, OnnxDetector},
+    governor::Governor,
+    notify::Notifier,
+    storage::{key_provider, random_secret, EventFilter, KeySlot, SecureStore, WriteQueue, WrongKey},
+    risk::{RiskEngine, RiskLevel, RiskResult, TrendQuery},
+    runtime::{self, Batch, CancellationToken},
+    logging::{self, audit::{self, AuditLog, AuditSigner}, AlertStream, SiemOutput, StructuredLogger},
+    redact::Redactor,
+    telemetry::{CycleTrace, Telemetry},
+    top,
+    uplink::{UplinkClient, UplinkQueue},
+    watchdog::{Recovery, Watchdog},
 };
-use std::path::Path;
-use std::sync::Arc;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tracing::info;
+use zeroize::Zeroizing;
 
 fn detect_platform() -> &'static str {
     if cfg!(target_os = "windows") {
@@ -31,120 +69,1728 @@ fn detect_platform() -> &'static str {
     }
 }
 
-fn run_one_cycle(
-    collectors: &CollectorPipeline,
-    features: &Arc<FeatureExtractor>,
-    model: &Arc<OnnxDetector>,
-    risk_engine: &RiskEngine,
-    store: &Arc<SecureStore>,
-    uplink: Option<&UplinkClient>,
+/// Secret used by releases before key providers existed; stores created with it are migrated.
+const LEGACY_STORE_SECRET: &[u8] = b"device-secret-placeholder";
+
+/// Open the store with the device-bound secret from the configured key provider. Finishes a
+/// staged key rotation (secret in the provider's `Next` slot) and migrates stores still keyed
+/// with the legacy placeholder secret.
+fn open_store(config: &AgentConfig) -> Result<SecureStore, Box<dyn std::error::Error + Send + Sync>> {
+    if config.storage.in_memory {
+        info!("storage is in-memory; nothing is persisted");
+        return SecureStore::open_in_memory_with(&random_secret(), &config.storage);
+    }
+    let provider = key_provider(&config.storage, &config.data_dir);
+    let secret = provider.load_or_create()?;
+    let next = provider.load(KeySlot::Next)?;
+    info!(provider = provider.name(), rotation_pending = next.is_some(), "storage key loaded");
+
+    let path = config.data_dir.join("store.db");
+    let store = match SecureStore::open_with(&path, &secret, &config.storage) {
+        Ok(s) => s,
+        // Only another key can help; I/O, lock, or corruption errors are the store's
+        Err(e) if !e.is::<WrongKey>() => return Err(e),
+        Err(e) => match next {
+            // Rotation completed in the store before the provider promoted the new secret.
+            Some(ref n) => SecureStore::open_with(&path, n, &config.storage)?,
+            None => {
+                let legacy = SecureStore::open_with(&path, LEGACY_STORE_SECRET, &config.storage).map_err(|_| e)?;
+                legacy.rotate_key(&secret)?;
+                info!("store migrated from legacy placeholder key");
+                legacy
+            }
+        },
+    };
+    if let Some(n) = next {
+        let progress = store.rotate_key(&n)?;
+        provider.store(KeySlot::Current, &n)?;
+        provider.remove(KeySlot::Next)?;
+        info!(
+            from = progress.from_version,
+            to = progress.to_version,
+            rows = progress.rows_reencrypted,
+            "storage key rotated"
+        );
+        audit::record(
+            "key_rotated",
+            json!({ "from": progress.from_version, "to": progress.to_version, "rows": progress.rows_reencrypted }),
+        );
+    }
+    Ok(store)
+}
+
+/// Install the audit log (`log.audit`), signed with a key derived from the storage secret. The
+/// agent runs on without one when it cannot be opened.
+fn open_audit(config: &AgentConfig) {
+    let cfg = &config.log.audit;
+    if !cfg.enabled {
+        return;
+    }
+    let signer = match cfg.sign && !config.storage.in_memory {
+        true => match key_provider(&config.storage, &config.data_dir).load_or_create() {
+            Ok(secret) => Some(AuditSigner::derive(&secret)),
+            Err(e) => {
+                tracing::warn!(error = %e, "no storage secret; audit log entries are not signed");
+                None
+            }
+        },
+        false => None,
+    };
+    let path = config.data_dir.join(&cfg.file);
+    let key = signer.as_ref().map(AuditSigner::public_key);
+    match AuditLog::open(&path, signer) {
+        Ok(log) => {
+            info!(path = %path.display(), key = key.as_deref().unwrap_or("none"), "audit log opened");
+            audit::init(log);
+        }
+        Err(e) => tracing::error!(path = %path.display(), error = %e, "cannot open audit log"),
+    }
+}
+
+/// SHA-256 (hex) of `config` as serialized
+fn config_sha256(config: &AgentConfig) -> String {
+    let json = serde_json::to_vec(config).unwrap_or_default();
+    Sha256::digest(&json).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `rotate-key` entrypoint: stage a fresh secret in the key provider and re-encrypt the store.
+fn run_rotate_key(config: &AgentConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if config.storage.in_memory {
+        return Err("storage.in_memory is set; there is no persistent key to rotate".into());
+    }
+    let provider = key_provider(&config.storage, &config.data_dir);
+    if provider.load(KeySlot::Next)?.is_none() {
+        provider.store(KeySlot::Next, &random_secret())?;
+    }
+    let store = open_store(config)?;
+    info!(key_version = store.key_version(), "storage key rotation complete");
+    Ok(())
+}
+
+/// Persist the on-device baseline and the risk engine's learning state.
+fn save_learned(baseline: &BaselineDetector, risk_engine: &RiskEngine, data_dir: &Path) {
+    if let Err(e) = baseline.save(&data_dir.join("baseline.json")) {
+        tracing::warn!(error = %e, "failed to save baseline state");
+    }
+    if let Err(e) = risk_engine.save_learned(&data_dir.join("risk_learned.json")) {
+        tracing::warn!(error = %e, "failed to save risk learning state");
+    }
+}
+
+/// The daemon's state: last cycle analyzed, events per collector, latest risk result, the agent's
+/// resource usage, uplink health, the resource governor's throttle, network blocks and isolation
+/// in place, whether it is a dry run
+fn daemon_status(cycle: u64, pipeline: &Pipeline) -> serde_json::Value {
+    let mut status = pipeline.activity.snapshot();
+    status["ts"] = json!(chrono::Utc::now().timestamp_millis());
+    status["pid"] = json!(std::process::id());
+    status["cycle"] = json!(cycle);
+    status["uplink"] = json!(pipeline.uplink.as_ref().map(UplinkQueue::health));
+    status["governor"] = json!(pipeline.governor.as_ref().map(Governor::status));
+    status["firewall_blocks"] = json!(pipeline.response.firewall().active());
+    status["isolation"] = json!(pipeline.response.firewall().isolation());
+    status["dry_run"] = json!(pipeline.dry_run);
+    status
+}
+
+/// Write the daemon's state to `data_dir/status.json`, replaced whole after each cycle, for
+/// `dadm-agent status` and monitoring
+fn write_status(data_dir: &Path, cycle: u64, pipeline: &Pipeline) {
+    if let Err(e) = replace_json(&data_dir.join("status.json"), &daemon_status(cycle, pipeline)) {
+        tracing::warn!(error = %e, "failed to write status file");
+    }
+}
+
+/// Write `value` to `path` through a temporary file, so readers never see a partial file
+fn replace_json(path: &Path, value: &serde_json::Value) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Marker of how the last run ended: written with `clean: false` at startup and replaced with
+/// `clean: true` at the end of an orderly shutdown
+const SHUTDOWN_MARKER: &str = "shutdown.json";
+
+/// Record that the agent is running; a marker still saying so is a run that crashed or was killed
+fn mark_started(data_dir: &Path) {
+    let path = data_dir.join(SHUTDOWN_MARKER);
+    let previous = std::fs::read(&path).ok().and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok());
+    if let Some(previous) = previous.filter(|p| p["clean"] == false) {
+        tracing::warn!(pid = %previous["pid"], started = %previous["started"], "previous run did not shut down cleanly");
+        audit::record("unclean_shutdown", previous);
+    }
+    let marker = json!({ "clean": false, "pid": std::process::id(), "started": chrono::Utc::now().timestamp_millis() });
+    if let Err(e) = replace_json(&path, &marker) {
+        tracing::warn!(error = %e, "failed to write shutdown marker");
+    }
+}
+
+/// Record an orderly shutdown, ending with `error` if the run failed
+fn mark_stopped(data_dir: &Path, error: Option<String>) {
+    let marker = json!({
+        "clean": true,
+        "pid": std::process::id(),
+        "stopped": chrono::Utc::now().timestamp_millis(),
+        "error": error,
+    });
+    if let Err(e) = replace_json(&data_dir.join(SHUTDOWN_MARKER), &marker) {
+        tracing::warn!(error = %e, "failed to write shutdown marker");
+    }
+}
+
+/// Store figures for crash bundles
+fn store_stats(store: &SecureStore) -> serde_json::Value {
+    json!({
+        "db_bytes": store.db_size().ok(),
+        "schema_version": store.schema_version().ok(),
+        "key_version": store.key_version(),
+    })
+}
+
+/// Log and audit crash bundles of earlier runs, and report them to the server over `uplink`.
+/// Each bundle is audited once and reported once.
+fn report_crashes(data_dir: &Path, uplink: Option<&UplinkClient>) {
+    for (path, mut bundle) in crash::bundles(data_dir) {
+        let before = bundle.clone();
+        if bundle["audited"] != true {
+            tracing::warn!(bundle = %path.display(), message = %bundle["message"], location = %bundle["location"], "an earlier run crashed");
+            audit::record("agent_crashed", json!({ "bundle": path.file_name().map(|n| n.to_string_lossy()), "crash": crash::summary(&bundle) }));
+            bundle["audited"] = json!(true);
+        }
+        if let Some(u) = uplink.filter(|_| bundle["reported"] != true) {
+            let mut crash = bundle.clone();
+            if let Some(fields) = crash.as_object_mut() {
+                fields.remove("audited");
+                fields.remove("reported");
+            }
+            match u.heartbeat(detect_platform(), json!({ "crash": crash })) {
+                Ok(()) => bundle["reported"] = json!(true),
+                Err(e) => tracing::warn!(error = %e, "crash report failed; retried at the next start"),
+            }
+        }
+        if bundle != before {
+            if let Err(e) = replace_json(&path, &bundle) {
+                tracing::warn!(error = %e, bundle = %path.display(), "failed to update crash bundle");
+            }
+        }
+    }
+}
+
+/// Store secret holding the feature window across restarts (encrypted like the other secrets)
+const FEATURE_WINDOW: &str = "feature_window";
+
+fn restore_window(features: &FeatureExtractor, store: &SecureStore) {
+    let restored = store.secret(FEATURE_WINDOW).and_then(|window| match window {
+        Some(bytes) => Ok(serde_json::from_slice::<Vec<Event>>(&bytes)?),
+        None => Ok(Vec::new()),
+    });
+    match restored {
+        Ok(events) if events.is_empty() => {}
+        Ok(events) => {
+            info!(events = events.len(), "feature window restored");
+            features.restore(events);
+        }
+        Err(e) => tracing::warn!(error = %e, "failed to restore feature window"),
+    }
+}
+
+/// Orderly end of a run: drain the store write queue and the pending uplink reports, deliver
+/// what the outbox holds, save the feature window and learned state, checkpoint the event chain,
+/// and fold the store's write-ahead log into its file
+fn shutdown(pipeline: &mut Pipeline, store: &SecureStore, data_dir: &Path) {
+    pipeline.writes.close();
+    if pipeline.writes.dropped() > 0 {
+        tracing::warn!(dropped = pipeline.writes.dropped(), "store writes dropped under backpressure");
+    }
+    if let Some(u) = pipeline.uplink.as_mut() {
+        u.close();
+        if u.dropped() > 0 {
+            tracing::warn!(dropped = u.dropped(), "uplink reports dropped while the server was slow");
+        }
+        if let Err(e) = u.client().drain_outbox() {
+            tracing::warn!(error = %e, "uplink outbox not drained; it is kept for the next run");
+        }
+    }
+    let window = serde_json::to_vec(&pipeline.features.window()).map_err(Into::into);
+    if let Err(e) = window.and_then(|window| store.set_secret(FEATURE_WINDOW, &window)) {
+        tracing::warn!(error = %e, "failed to save feature window");
+    }
+    save_learned(&pipeline.baseline, &pipeline.risk_engine, data_dir);
+    if let Err(e) = store.checkpoint_chain() {
+        tracing::warn!(error = %e, "failed to sign event chain checkpoint");
+    }
+    if let Err(e) = store.checkpoint_wal() {
+        tracing::warn!(error = %e, "failed to checkpoint the store's write-ahead log");
+    }
+    flush_telemetry(pipeline.telemetry.as_deref());
+}
+
+/// `helper` entrypoint: collect for the unprivileged agent over `helper.socket` until Ctrl+C or
+/// SIGTERM
+fn run_helper(config: &AgentConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    info!(socket = %config.helper.socket.display(), collectors = ?config.helper.collectors, "DADM privileged helper starting");
+    let collectors = Arc::new(CollectorPipeline::new(&config.collectors));
+    let rt = runtime::build()?;
+    let stop = CancellationToken::new();
+    rt.spawn(runtime::cancel_on_signal(stop.clone()));
+    rt.block_on(helper::serve(config.helper.clone(), collectors, stop))
+}
+
+/// `status` entrypoint: print the status the daemon last wrote
+fn run_status(config: &AgentConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let path = config.data_dir.join("status.json");
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("status: {}: {}", path.display(), e))?;
+    println!("{}", text);
+    Ok(())
+}
+
+/// Reload indicator files and, with `risk.ioc.uplink`, fetch the uplink's indicators. The fetched
+/// list is cached in `data_dir` and used when the uplink can't be reached.
+fn refresh_indicators(risk_engine: &RiskEngine, uplink: Option<&UplinkClient>, data_dir: &Path) {
+    let cfg = &risk_engine.config().ioc;
+    if !cfg.enabled {
+        return;
+    }
+    let ioc = risk_engine.ioc();
+    let local = ioc.reload_files();
+    let cache = data_dir.join("indicators_uplink.txt");
+    let fetched = match uplink.filter(|_| cfg.uplink) {
+        Some(u) => match u.fetch_indicators() {
+            Ok(list) => {
+                if let Err(e) = std::fs::write(&cache, list.join("\n")) {
+                    tracing::warn!(error = %e, "failed to cache uplink indicators");
+                }
+                Some(list)
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to fetch indicators; using cached list");
+                None
+            }
+        },
+        None => None,
+    };
+    let remote = match fetched {
+        Some(list) => ioc.set_remote(&list),
+        None if cfg.uplink => match std::fs::read_to_string(&cache) {
+            Ok(text) => ioc.set_remote(&text.lines().map(str::to_owned).collect::<Vec<_>>()),
+            Err(_) => 0,
+        },
+        None => 0,
+    };
+    if local + remote > 0 {
+        info!(local, uplink = remote, "indicators of compromise loaded");
+    }
+}
+
+/// Act on messages the server pushed over the uplink stream: `indicators` replaces the uplink's
+/// indicator list (and its cache) as a fetch would; `command`s are returned for [`run_commands`].
+fn handle_server_messages(risk_engine: &RiskEngine, uplink: &UplinkClient, data_dir: &Path) -> Vec<Command> {
+    let mut commands = Vec::new();
+    for message in uplink.take_server_messages() {
+        match message.kind.as_str() {
+            "command" => match serde_json::from_value(serde_json::Value::Object(message.body)) {
+                Ok(command) => commands.push(command),
+                Err(e) => tracing::warn!(error = %e, "malformed command message from uplink"),
+            },
+            "indicators" if risk_engine.config().ioc.enabled && risk_engine.config().ioc.uplink => {
+                let list: Vec<String> = match message.body.get("indicators").cloned().map(serde_json::from_value) {
+                    Some(Ok(list)) => list,
+                    _ => {
+                        tracing::warn!("malformed indicators message from uplink");
+                        continue;
+                    }
+                };
+                if let Err(e) = std::fs::write(data_dir.join("indicators_uplink.txt"), list.join("\n")) {
+                    tracing::warn!(error = %e, "failed to cache uplink indicators");
+                }
+                let remote = risk_engine.ioc().set_remote(&list);
+                info!(uplink = remote, "indicators of compromise updated by server");
+            }
+            kind => tracing::debug!(kind, "uplink server message ignored"),
+        }
+    }
+    commands
+}
+
+/// Execute server commands not handled before, record each in the audit trail, and report its
+/// result.
+fn run_commands(commands: Vec<Command>, ctx: &mut CommandContext, uplink: &UplinkClient, store: &SecureStore) {
+    for command in commands {
+        match store.command_handled(&command.id) {
+            Ok(false) => {}
+            Ok(true) => {
+                tracing::debug!(id = %command.id, "server command already handled");
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!(id = %command.id, error = %e, "failed to check server command; skipped");
+                continue;
+            }
+        }
+        let result = commands::execute(&command, ctx, chrono::Utc::now().timestamp_millis());
+        info!(id = %command.id, action = %command.action, status = result.status.as_str(), "server command handled");
+        audit::record(
+            "command",
+            json!({ "id": command.id, "action": command.action, "args": command.args, "status": result.status.as_str() }),
+        );
+        if let Err(e) = store.record_command(&result) {
+            tracing::warn!(id = %command.id, error = %e, "failed to record server command");
+        }
+        if let Err(e) = uplink.report_command_result(&result) {
+            tracing::warn!(id = %command.id, error = %e, "command result queued; uplink delivery failed");
+        }
+    }
+}
+
+/// `local` with the last applied server policy (verified again) merged over it; `local` when
+/// there is none or it no longer verifies
+fn stored_policy_config(local: &AgentConfig, store: &SecureStore) -> AgentConfig {
+    let keys = &local.uplink.policy_keys;
+    if keys.is_empty() {
+        return local.clone();
+    }
+    let stored = match store.current_policy() {
+        Ok(Some(signed)) => signed.verify(keys).and_then(|p| {
+            let mut config = policy::apply(local, &p)?;
+            if let Some(ref release) = p.model {
+                match release::installed(&local.data_dir, &release.version) {
+                    Some(dir) => release::apply(&mut config, &dir)?,
+                    None => tracing::warn!(release = %release.version, "policy model release missing; using local model"),
+                }
+            }
+            Ok((p.version, config))
+        }),
+        Ok(None) => return local.clone(),
+        Err(e) => Err(e),
+    };
+    match stored {
+        Ok((version, config)) => {
+            info!(version, "server policy in effect");
+            config
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "stored server policy not applied; using local config");
+            local.clone()
+        }
+    }
+}
+
+/// Fetch and install the files of a policy's model release unless it is installed already
+fn install_release(release: &ModelRelease, data_dir: &Path, uplink: &UplinkClient) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(dir) = release::installed(data_dir, &release.version) {
+        return Ok(dir);
+    }
+    let model = uplink.fetch_artifact(&release.model.url)?;
+    let recipe = match release.recipe {
+        Some(ref artifact) => Some(uplink.fetch_artifact(&artifact.url)?),
+        None => None,
+    };
+    let dir = release::install(data_dir, release, &model, recipe.as_deref())?;
+    info!(release = %release.version, path = %dir.display(), "model release installed");
+    audit::record("model_installed", json!({ "release": release.version, "path": dir.display().to_string() }));
+    Ok(dir)
+}
+
+/// Fetch the server policy (`uplink.policy_keys`) and, when it is newer than the applied one,
+/// merge it over `local` and record what it changes relative to `current`. A policy with a model
+/// release is applied only once the release is installed (otherwise it is tried again with the
+/// next refresh). Returns the new config if any setting changed.
+fn refresh_policy(local: &AgentConfig, current: &AgentConfig, uplink: &UplinkClient, store: &SecureStore) -> Option<AgentConfig> {
+    let keys = &local.uplink.policy_keys;
+    if keys.is_empty() {
+        return None;
+    }
+    let signed = match uplink.fetch_policy() {
+        Ok(Some(signed)) => signed,
+        Ok(None) => return None,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to fetch server policy");
+            return None;
+        }
+    };
+    let applied = match store.current_policy() {
+        Ok(stored) => stored.and_then(|p| p.verify(keys).ok()).map(|p| p.version),
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to read applied server policy");
+            return None;
+        }
+    };
+    let result = signed.verify(keys).and_then(|policy| match applied {
+        Some(v) if policy.version == v => Ok(None),
+        Some(v) if policy.version < v => Err(format!("version {} is older than applied version {}", policy.version, v).into()),
+        _ => {
+            let mut next = policy::apply(local, &policy)?;
+            if let Some(ref release) = policy.model {
+                let dir = install_release(release, &local.data_dir, uplink)?;
+                release::apply(&mut next, &dir)?;
+                // The running model's release stays until the new one has taken over
+                let running = current.model_path.parent().unwrap_or(Path::new(""));
+                if let Err(e) = release::prune(&local.data_dir, &[dir.as_path(), running]) {
+                    tracing::warn!(error = %e, "failed to remove old model releases");
+                }
+            }
+            let changes = policy::changes(current, &next);
+            store.record_policy(&signed, policy.version, &changes, chrono::Utc::now().timestamp_millis())?;
+            Ok(Some((policy.version, next, changes)))
+        }
+    });
+    match result {
+        Ok(Some((version, next, changes))) => {
+            for change in &changes {
+                info!(version, setting = %change.path, old = %change.old, new = %change.new, "server policy changes setting");
+            }
+            let settings: Vec<&str> = changes.iter().map(|c| c.path.as_str()).collect();
+            audit::record("policy_applied", json!({ "version": version, "settings": settings }));
+            (!changes.is_empty()).then_some(next)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            tracing::warn!(error = %e, "server policy rejected");
+            None
+        }
+    }
+}
+
+/// Re-derive risk thresholds from the stored score history (`risk.auto_tune`).
+fn tune_thresholds(risk_engine: &RiskEngine, store: &SecureStore) {
+    let cfg = &risk_engine.config().auto_tune;
+    if !cfg.enabled {
+        return;
+    }
+    let since = chrono::Utc::now().timestamp_millis() - (cfg.window_secs as i64) * 1000;
+    match store.risk_scores_since(since) {
+        Ok(scores) => {
+            if risk_engine.tune(&scores).is_none() {
+                info!(samples = scores.len(), "not enough score history, or no spread in it; keeping the thresholds");
+            }
+        }
+        Err(e) => tracing::warn!(error = %e, "failed to read score history"),
+    }
+}
+
+/// Send what the export thread has not sent yet
+fn flush_telemetry(telemetry: Option<&Telemetry>) {
+    if let Some(Err(e)) = telemetry.map(Telemetry::flush) {
+        tracing::warn!(error = %e, "telemetry export failed");
+    }
+}
+
+/// Detection stages and the outputs of their results, shared by the daemon's tasks
+struct Pipeline {
+    collectors: Arc<CollectorPipeline>,
+    features: FeatureExtractor,
+    model: OnnxDetector,
+    baseline: BaselineDetector,
+    risk_engine: RiskEngine,
+    writes: WriteQueue,
+    uplink: Option<UplinkQueue>,
+    siem: Option<SiemOutput>,
+    alerts: Option<AlertStream>,
+    notifier: Option<Notifier>,
+    /// Self-throttling against `governor`'s budgets, in daemon mode
+    governor: Option<Governor>,
+    telemetry: Option<Arc<Telemetry>>,
+    /// Counts and latest result for the status
+    activity: runtime::Activity,
+    /// Cycle statistics for the heartbeat
+    stats: PipelineStats,
+    /// Response policy on results, and the response actions of server commands
+    response: ResponseEngine,
+    /// Results are logged as what would have been stored, not stored (`dry_run`)
+    dry_run: bool,
+}
+
+/// Score `batch` and hand the result to the outputs, traced as one cycle
+fn analyze(pipeline: &Pipeline, batch: Batch) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let trace = CycleTrace::started_at(pipeline.telemetry.as_deref(), batch.start_unix_nanos, batch.started);
+    trace.stage_done("collection", batch.start_unix_nanos, batch.collection);
+    let outcome = run_cycle_stages(pipeline, batch.cycle, batch.events, &trace);
+    trace.finish(outcome.is_ok());
+    outcome
+}
+
+fn run_cycle_stages(
+    pipeline: &Pipeline,
+    cycle: u64,
+    events: Vec<Event>,
+    trace: &CycleTrace,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let events = collectors.collect_snapshot();
+    let Pipeline {
+        collectors,
+        features,
+        model,
+        baseline,
+        risk_engine,
+        writes,
+        uplink,
+        siem,
+        alerts,
+        notifier,
+        governor: _,
+        telemetry,
+        activity,
+        stats,
+        response,
+        dry_run,
+    } = pipeline;
     info!(count = events.len(), "collected events");
 
-    let feature_vectors = features.push(events.clone());
-    let score = feature_vectors
-        .first()
-        .map(|fv| model.predict(fv))
-        .unwrap_or(0.0);
-    let result = feature_vectors
-        .first()
-        .map(|fv| risk_engine.score(fv.event_id.clone(), score, fv.ts))
-        .unwrap_or_else(|| risk_engine.score(String::new(), score, 0));
-
-    for ev in &events {
-        let payload = serde_json::to_string(ev)?;
-        store.insert_event(
-            &ev.id,
-            ev.ts.timestamp_millis(),
-            ev.source.as_str(),
-            &payload,
-            Some(result.score),
-        )?;
-    }
-    if result.level != RiskLevel::Low {
+    // Allowlisted events are stored but not scored
+    let (scored, suppressed) = risk_engine.suppress(events.clone());
+    let feature_vectors = trace.stage("features", || features.push(scored.clone()));
+    let prediction = trace.stage("inference", || {
+        feature_vectors
+            .first()
+            .map(|fv| {
+                let mut p = model.predict_detailed(fv);
+                // Baseline deviation complements the model (covers cold start with no trained model).
+                if let Some(b) = baseline.observe(fv) {
+                    p.score = p.score.max(b);
+                }
+                p
+            })
+            .unwrap_or_default()
+    });
+    let mut result = trace.stage("scoring", || match feature_vectors.first() {
+        Some(fv) => risk_engine.score_events(fv.event_id.clone(), prediction.score, prediction.class, fv.ts, &scored),
+        None => {
+            // No full feature window yet: rules can still match, attributed to the newest event
+            let newest = scored.iter().max_by_key(|e| e.ts);
+            let id = newest.map(|e| e.id.clone()).unwrap_or_default();
+            let ts = newest.map_or(0, |e| e.ts.timestamp_millis());
+            risk_engine.score_events(id, prediction.score, prediction.class, ts, &scored)
+        }
+    });
+    if let Some(telemetry) = telemetry.as_deref() {
+        telemetry.add("dadm.events.collected", events.len() as u64, &[]);
+        telemetry.add("dadm.events.suppressed", (events.len() - scored.len()) as u64, &[]);
+        telemetry.add("dadm.risk.results", 1, &[("level", result.level.as_str())]);
+    }
+    result.suppressed = suppressed;
+    activity.record(&events, &result);
+    if let (Some(first), Some(last)) = (
+        scored.iter().map(|e| e.ts.timestamp_millis()).min(),
+        scored.iter().map(|e| e.ts.timestamp_millis()).max(),
+    ) {
+        result.window_start = first;
+        result.window_end = last.max(result.ts);
+        result.event_ids = scored.iter().map(|e| e.id.clone()).collect();
+    }
+
+    trace.stage("storage", || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if *dry_run {
+            info!(
+                events = events.len(),
+                feature_vectors = feature_vectors.len(),
+                risk_result = !feature_vectors.is_empty() || !result.rule_hits.is_empty(),
+                "dry run: would have stored the cycle's results"
+            );
+            return Ok(());
+        }
+        for ev in &events {
+            // Serialized copy is scrubbed once it has been encrypted into the store
+            let payload = Zeroizing::new(serde_json::to_string(ev)?);
+            writes.insert_event(
+                &ev.id,
+                ev.ts.timestamp_millis(),
+                ev.source.as_str(),
+                payload,
+                Some(result.score),
+            )?;
+        }
+        for fv in &feature_vectors {
+            writes.insert_features(fv)?;
+        }
+        if !feature_vectors.is_empty() || !result.rule_hits.is_empty() {
+            writes.insert_risk_result(&result)?;
+        }
+        Ok(())
+    })?;
+    // Learning-period results are logged as informational with the level they would have had
+    if result.level != RiskLevel::Low || (result.learning && result.raw_level.is_some()) {
         info!(
+            target: logging::ALERT_TARGET,
             event_id = %result.event_id,
             score = result.score,
             level = ?result.level,
+            raw_level = ?result.raw_level,
+            learning = result.learning,
+            class = result.top_class.as_ref().map(|c| c.label.as_str()),
+            rules = ?result.rule_hits.iter().map(|h| h.rule.as_str()).collect::<Vec<_>>(),
+            correlations = ?result.correlations.iter().map(|c| c.pattern.as_str()).collect::<Vec<_>>(),
+            kill_chain = ?result.kill_chain.first().map(|k| &k.stages),
+            iocs = ?result.ioc_hits.iter().map(|h| h.indicator.as_str()).collect::<Vec<_>>(),
+            techniques = ?result.techniques(),
+            tactics = ?result.tactics(),
+            escalated = result.escalated,
+            alert = result.alert,
             "risk result"
         );
     }
+    if let Some(siem) = siem {
+        if let Err(e) = siem.emit(&result) {
+            tracing::warn!(error = %e, "SIEM output failed");
+        }
+    }
+    if let Some(alerts) = alerts {
+        let kind = events.iter().find(|e| e.id == result.event_id).map(|e| e.kind.name());
+        if let Err(e) = alerts.emit(&result, kind) {
+            tracing::warn!(error = %e, "alert stream output failed");
+        }
+    }
+    if let Some(notifier) = notifier {
+        notifier.emit(&result);
+    }
+    response.respond(&result, &scored, chrono::Utc::now().timestamp_millis());
+
+    if let Some(scan) = risk_engine.deep_scan(&result, &scored) {
+        collectors.escalate(scan);
+    }
 
     if let Some(u) = uplink {
-        let _ = u.report(detect_platform(), &events, &result);
+        trace.stage("uplink", || u.report(detect_platform(), &events, &result));
     }
+    stats.record(&CycleStats::new(cycle, &events, &result, &trace.stages(), trace.elapsed()));
 
     Ok(())
 }
 
+/// Apply `storage.max_age_days` / `storage.max_db_bytes` to the store.
+fn enforce_retention(store: &SecureStore, config: &StorageConfig) {
+    let now = chrono::Utc::now().timestamp_millis();
+    match store.enforce_retention(config.max_age_days, config.max_db_bytes, now) {
+        Ok(report) if report.pruned_by_age + report.pruned_by_size > 0 => info!(
+            pruned_by_age = report.pruned_by_age,
+            pruned_by_size = report.pruned_by_size,
+            db_bytes = report.db_bytes,
+            "retention pruned events"
+        ),
+        Ok(_) => {}
+        Err(e) => tracing::warn!(error = %e, "retention failed"),
+    }
+    crash::set_context("store", store_stats(store));
+}
+
+/// `evaluate` entrypoint: replay JSONL or stored events against labels and print a JSON report.
+fn run_evaluate(config: &AgentConfig, args: EvaluateArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut events = if let Some(path) = args.events {
+        eval::read_events_jsonl(&path)?
+    } else {
+        let store = open_store(config)?;
+        store
+            .events_since(args.since)?
+            .into_iter()
+            .filter_map(|(_, _, payload)| serde_json::from_str(&payload).ok())
+            .map(|event| eval::LabeledEvent { event, label: None })
+            .collect()
+    };
+    if let Some(path) = args.labels {
+        eval::apply_labels(&mut events, &eval::read_labels_csv(&path)?);
+    }
+
+    let model = OnnxDetector::load_with(&config.model_path, config.features.feature_dim, &config.model)?;
+    let replayed = eval::replay(&events, &config.features, &model);
+    let report = eval::report(events.len(), &replayed, &config.risk);
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// `replay` entrypoint: score recorded events cycle by cycle at the requested pace, printing a
+/// JSON line per cycle and a summary line.
+fn run_replay(config: &AgentConfig, args: ReplayArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut config = config.clone();
+    if !args.learning {
+        config.risk.learning_period_days = 0;
+    }
+    let events: Vec<Event> = eval::read_events_jsonl(&args.input)?.into_iter().map(|e| e.event).collect();
+    let cycle_secs = args.cycle_secs.unwrap_or(match config.collectors.process.interval_secs {
+        0 => 60,
+        secs => secs,
+    });
+    let replayer = Replayer::new(&config)?;
+    let mut summary = ReplaySummary::default();
+    let mut previous: Option<i64> = None;
+    for (cycle, (start, batch)) in replay::cycles(events, cycle_secs as i64 * 1000).into_iter().enumerate() {
+        if let Some(previous) = previous.filter(|_| args.speed > 0.0) {
+            std::thread::sleep(Duration::from_secs_f64((start - previous) as f64 / 1000.0 / args.speed));
+        }
+        previous = Some(start);
+        let events = batch.len();
+        let result = replayer.analyze(batch);
+        summary.add(events, &result);
+        if result.level >= args.min_risk.unwrap_or(RiskLevel::Low) {
+            println!("{}", replay::cycle_line(cycle, start, events, &result));
+        }
+    }
+    println!("{}", json!({ "summary": summary }));
+    Ok(())
+}
+
+/// Print the self-test report; fails when a scenario didn't raise its rule
+fn run_selftest(config: &AgentConfig, sandbox: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let report = selftest::run(config, sandbox.as_deref());
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    for scenario in report.scenarios.iter().filter(|s| !s.passed) {
+        tracing::warn!(scenario = scenario.name, rule = scenario.expected_rule, error = ?scenario.error, "self-test scenario failed");
+    }
+    if !report.passed {
+        return Err("self-test failed".into());
+    }
+    Ok(())
+}
+
+/// Store for inspection commands: a copied store given with `--db` is opened read-only (secret from
+/// `--secret-file`, else the key provider); otherwise the local store.
+fn open_inspect_store(config: &AgentConfig, args: StoreArgs) -> Result<SecureStore, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(db) = args.db else {
+        return open_store(config);
+    };
+    let secret = match args.secret_file {
+        Some(path) => Zeroizing::new(std::fs::read(path)?),
+        None => key_provider(&config.storage, &config.data_dir)
+            .load(KeySlot::Current)?
+            .ok_or("no storage secret; pass --secret-file")?,
+    };
+    info!(db = %db.display(), "opening store read-only");
+    SecureStore::open_readonly_with(&db, &secret, &config.storage)
+}
+
+/// Stored risk results in the range at `min_risk` or above, oldest first, at most `limit`
+fn query_risk(
+    store: &SecureStore,
+    since: Option<i64>,
+    until: Option<i64>,
+    min_risk: Option<RiskLevel>,
+    limit: Option<usize>,
+) -> Result<Vec<RiskResult>, Box<dyn std::error::Error + Send + Sync>> {
+    // The level filter applies after the store's limit, so the limit is applied here then
+    let fetch_limit = limit.filter(|_| min_risk.is_none());
+    let mut results = store.risk_results(since.unwrap_or(0), until, fetch_limit)?;
+    if let Some(min) = min_risk {
+        results.retain(|r| r.level >= min);
+    }
+    results.truncate(limit.unwrap_or(usize::MAX));
+    Ok(results)
+}
+
+/// `query` entrypoint: print the stored risk results in the range, at `--min-risk` or above, one
+/// JSON object per line.
+fn run_query(config: &AgentConfig, args: QueryArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let store = open_inspect_store(config, args.store)?;
+    let results = query_risk(&store, args.since, args.until, args.min_risk, args.limit)?;
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    for result in &results {
+        serde_json::to_writer(&mut out, result)?;
+        std::io::Write::write_all(&mut out, b"\n")?;
+    }
+    std::io::Write::flush(&mut out)?;
+    Ok(())
+}
+
+/// `export` entrypoint: stream stored events as JSONL / CSV / Parquet to `--out` (default stdout).
+fn run_export(config: &AgentConfig, args: ExportArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let filter = EventFilter {
+        since: args.since,
+        until: args.until,
+        kind: args.kind,
+        min_risk: args.min_risk,
+        field_equals: args.field_equals,
+        limit: args.limit,
+        ..EventFilter::default()
+    };
+    let format = args.format;
+    let store = open_inspect_store(config, args.store)?;
+    let rows = match args.out {
+        Some(path) => store.export(&filter, format, args.redact, std::io::BufWriter::new(std::fs::File::create(&path)?))?,
+        None => store.export(&filter, format, args.redact, std::io::stdout())?,
+    };
+    info!(rows, ?format, "export complete");
+    Ok(())
+}
+
+/// `prune` entrypoint: delete what is older than `before`, or apply `storage.max_age_days` /
+/// `storage.max_db_bytes` now; prints what was deleted.
+fn run_prune(config: &AgentConfig, before: Option<i64>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let store = open_store(config)?;
+    let report = match before {
+        Some(ts) => json!({ "before": ts, "pruned": store.prune_before(ts)? }),
+        None => {
+            let now = chrono::Utc::now().timestamp_millis();
+            serde_json::to_value(store.enforce_retention(config.storage.max_age_days, config.storage.max_db_bytes, now)?)?
+        }
+    };
+    audit::record("store_pruned", report.clone());
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// `verify-chain` entrypoint: print the chain report; fails if tampering was detected.
+fn run_verify_chain(config: &AgentConfig, args: StoreArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let store = open_inspect_store(config, args)?;
+    let report = store.verify_chain()?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.is_intact() {
+        return Err(format!("event chain verification failed ({} issues)", report.issues.len()).into());
+    }
+    Ok(())
+}
+
+/// `secret` entrypoint: `secret set <name>` stores the value read from stdin (one trailing
+/// newline removed) in the keystore, for `keystore://<name>` references in the config; `secret
+/// rm <name>` deletes it.
+fn run_secret(config: &AgentConfig, command: SecretCommand) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use dadm_agent::config::secrets;
+    match command {
+        SecretCommand::Set { name } => {
+            let mut value = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut value)?;
+            let value = value.strip_suffix('\n').map(|v| v.strip_suffix('\r').unwrap_or(v)).unwrap_or(&value);
+            secrets::store(config, &name, value)?;
+            eprintln!("secret {} stored; reference it as {}{}", name, secrets::SCHEME, name);
+        }
+        SecretCommand::Rm { name } => secrets::remove(config, &name)?,
+    }
+    Ok(())
+}
+
+/// Operator credential read from stdin, one trailing newline removed
+fn read_credential() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut credential = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut credential)?;
+    let trimmed = credential.strip_suffix('\n').map(|c| c.strip_suffix('\r').unwrap_or(c)).unwrap_or(&credential);
+    Ok(trimmed.to_string())
+}
+
+/// `release-isolation` entrypoint: lift the host isolation with the operator credential read from
+/// stdin, checked against `response.firewall.release_credential_hash`.
+fn run_release_isolation(config: &AgentConfig) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let isolation = isolation::release_locally(&config.response, &config.data_dir, &read_credential()?)?;
+    eprintln!("host isolation (since {}, {}) released", isolation.created_at, isolation.trigger);
+    Ok(())
+}
+
+/// `log-level` entrypoint: set or remove the log level override the daemon applies within a
+/// second (`data_dir/log_level`), or print it.
+fn run_log_level(
+    config: &AgentConfig,
+    directives: Option<String>,
+    reset: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let path = config.data_dir.join(logging::LEVEL_FILE);
+    match directives {
+        Some(directives) => {
+            logging::write_override(&path, Some(&directives))?;
+            info!(level = %directives, "log level override set");
+        }
+        None if reset => {
+            logging::write_override(&path, None)?;
+            info!("log level override removed");
+        }
+        None => match logging::read_override(&path) {
+            Some(directives) => println!("{}", directives),
+            None => println!("no override; using {}", std::env::var("RUST_LOG").unwrap_or_else(|_| config.log.level.clone())),
+        },
+    }
+    Ok(())
+}
+
+/// Server policy stored in the local store, if there is a store and it verifies
+fn stored_policy(local: &AgentConfig) -> Result<Option<policy::Policy>, Box<dyn std::error::Error + Send + Sync>> {
+    let db = local.data_dir.join("store.db");
+    if local.uplink.policy_keys.is_empty() || !db.exists() {
+        return Ok(None);
+    }
+    let store = open_inspect_store(local, StoreArgs { db: Some(db), secret_file: None })?;
+    match store.current_policy()? {
+        Some(signed) => Ok(Some(signed.verify(&local.uplink.policy_keys)?)),
+        None => Ok(None),
+    }
+}
+
+/// Problems in a config that parses: settings that contradict each other, or that the agent can't
+/// run with
+fn config_problems(config: &AgentConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    let risk = &config.risk;
+    if !(0.0..=1.0).contains(&risk.medium_threshold) || !(0.0..=1.0).contains(&risk.high_threshold) {
+        problems.push("risk thresholds must be within 0-1".to_string());
+    }
+    if risk.medium_threshold > risk.high_threshold {
+        problems.push("risk.medium_threshold is above risk.high_threshold".to_string());
+    }
+    if config.features.feature_dim == 0 {
+        problems.push("features.feature_dim is 0".to_string());
+    }
+    if config.uplink.enabled && config.uplink.endpoint.is_empty() {
+        problems.push("uplink.enabled without an uplink.endpoint".to_string());
+    }
+    if config.storage.encryption == StorageEncryption::Full && !cfg!(feature = "sqlcipher") {
+        problems.push("storage.encryption is full, but the agent was built without the sqlcipher feature".to_string());
+    }
+    if let Err(e) = privileges::capability_mask(&config.privileges.capabilities) {
+        problems.push(format!("privileges.capabilities: {}", e));
+    }
+    for (action, cap) in privileges::missing_response_capabilities(&config.privileges, &config.response) {
+        problems.push(format!("response.actions.{} needs {}, which privileges.capabilities drops", action, cap));
+    }
+    if let Some(unknown) = config.helper.collectors.iter().find(|k| !dadm_agent::collectors::COLLECTORS.contains(&k.as_str())) {
+        problems.push(format!("helper.collectors: unknown collector {}", unknown));
+    }
+    let approval = &config.response.approval;
+    if approval.enabled && approval.timeout_secs == 0 {
+        problems.push("response.approval.timeout_secs must be above 0".to_string());
+    }
+    if let Err(e) = config.clone().resolve_secrets() {
+        problems.push(e.to_string());
+    }
+    problems
+}
+
+/// `config` entrypoint: `config schema` prints the JSON Schema of the config file; `config
+/// effective` the merged config (with `cli`'s flags and the stored server policy) and the layer
+/// each setting came from; `config validate` checks it and fails on a problem.
+fn run_config(cli: &Cli, config_path: &Path, command: &ConfigCommand) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let ConfigCommand::Schema = command {
+        println!("{}", serde_json::to_string_pretty(&dadm_agent::config::schema())?);
+        return Ok(());
+    }
+    let config_keys = dadm_agent::config::pinned_config_keys();
+    let mut layers = AgentConfig::load_layers(config_path, &config_keys)?;
+    if !config_keys.is_empty() {
+        dadm_agent::config::check_unsigned(Layer::Cli, &cli.layer())?;
+    }
+    layers.push(Layer::Cli, &cli.layer());
+    let files: Vec<_> = layers.files().iter().map(|(layer, path)| json!({ "layer": layer, "path": path })).collect();
+    if let ConfigCommand::Validate = command {
+        let problems = match layers.config() {
+            Ok(config) => config_problems(&config),
+            Err(e) => vec![e.to_string()],
+        };
+        let report = json!({ "files": files, "warnings": layers.warnings(), "problems": problems });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if !problems.is_empty() {
+            return Err(format!("config validation failed ({} problems)", problems.len()).into());
+        }
+        return Ok(());
+    }
+    let mut policy_version = None;
+    match stored_policy(&layers.config()?) {
+        Ok(Some(policy)) => {
+            layers.push(Layer::Policy, &policy.config);
+            policy_version = Some(policy.version);
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("server policy not included: {}", e),
+    }
+    let effective = json!({
+        "files": files,
+        "policy_version": policy_version,
+        "warnings": layers.warnings(),
+        "config": layers.config()?,
+        "sources": layers.sources()?,
+    });
+    println!("{}", serde_json::to_string_pretty(&effective)?);
+    Ok(())
+}
+
+/// `verify-audit` entrypoint: print the audit log report; fails if tampering was detected.
+fn run_verify_audit(
+    config: &AgentConfig,
+    file: Option<PathBuf>,
+    keys: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let path = file.unwrap_or_else(|| config.data_dir.join(&config.log.audit.file));
+    let report = audit::verify(&path, keys)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.is_intact() {
+        return Err(format!("audit log verification failed ({} issues)", report.issues.len()).into());
+    }
+    Ok(())
+}
+
+/// `risk-trend` entrypoint: print the device's (or `--entity`'s) risk history as JSON.
+fn run_risk_trend(config: &AgentConfig, args: TrendArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let until = args.until.unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+    let mut query = TrendQuery::new(args.since.unwrap_or(until - 24 * 3_600_000), until);
+    query.entity = args.entity;
+    if let Some(secs) = args.bucket_secs {
+        query.bucket_secs = secs;
+    }
+    if let Some(buckets) = args.rolling {
+        query.rolling_buckets = buckets;
+    }
+    let store = open_inspect_store(config, args.store)?;
+    println!("{}", serde_json::to_string_pretty(&store.risk_trend(&query)?)?);
+    Ok(())
+}
+
+/// `feedback` entrypoint: record a verdict on the risk result for an event and report it upstream
+/// when uplink is enabled, or with `--candidates` print suggested allowlist entries.
+fn run_feedback(config: &AgentConfig, args: FeedbackArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let store = Arc::new(open_store(config)?);
+    let (Some(event_id), Some(verdict)) = (args.event_id, args.verdict) else {
+        println!("{}", serde_json::to_string_pretty(&store.suppression_candidates(args.min_count)?)?);
+        return Ok(());
+    };
+    let feedback = store.record_feedback(&event_id, verdict, args.note, chrono::Utc::now().timestamp_millis())?;
+    info!(event_id, verdict = feedback.verdict.as_str(), "feedback recorded");
+    if config.uplink.enabled {
+        let uplink = UplinkClient::new(config.uplink.clone())
+            .map(|u| u.with_outbox(Arc::clone(&store)).with_token_store(Arc::clone(&store)));
+        if let Some(uplink) = uplink {
+            if let Err(e) = uplink.report_feedback(&feedback) {
+                tracing::warn!(error = %e, "feedback queued; uplink delivery failed");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `policy-audit` entrypoint: print the applied server policies and the settings each changed.
+fn run_policy_audit(config: &AgentConfig, args: StoreArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let store = open_inspect_store(config, args)?;
+    println!("{}", serde_json::to_string_pretty(&store.policy_audit()?)?);
+    Ok(())
+}
+
+/// `command-audit` entrypoint: print the server commands handled since `since` (default all)
+/// with their arguments, status, and results.
+fn run_command_audit(config: &AgentConfig, since: i64, args: StoreArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let store = open_inspect_store(config, args)?;
+    println!("{}", serde_json::to_string_pretty(&store.command_audit(since)?)?);
+    Ok(())
+}
+
+/// `response-audit` entrypoint: print the response actions taken, with their undo
+fn run_response_audit(config: &AgentConfig, since: i64, args: StoreArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let store = open_inspect_store(config, args)?;
+    println!("{}", serde_json::to_string_pretty(&store.response_audit(since)?)?);
+    Ok(())
+}
+
+/// `undo` entrypoint: have the running daemon undo a response action and print its record
+fn run_undo(config: &AgentConfig, id: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let endpoint = control::endpoint(&config.control, &config.data_dir);
+    let record = control::call(&endpoint, &control::Request::Undo { id })
+        .map_err(|e| format!("undo over {} failed: {} (is the daemon running with control.enabled?)", endpoint.display(), e))?;
+    println!("{}", serde_json::to_string_pretty(&record)?);
+    if record["undone"]["status"] != "done" {
+        return Err(format!("undo {}: {}", record["undone"]["status"].as_str().unwrap_or("failed"), record["undone"]["outcome"]["error"].as_str().unwrap_or("unknown error")).into());
+    }
+    Ok(())
+}
+
+/// `scrub` entrypoint: print the scrub report; fails if corruption or tampering was found.
+/// `--quarantine` moves corrupt rows aside (local store only; `--db` copies are read-only).
+fn run_scrub(config: &AgentConfig, quarantine: bool, args: StoreArgs) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let store = open_inspect_store(config, args)?;
+    let report = store.scrub(quarantine)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.is_clean() {
+        return Err(format!(
+            "store scrub found {} corrupt rows ({} quarantined), {} integrity and {} chain issues",
+            report.corrupt.len(),
+            report.quarantined,
+            report.integrity.len(),
+            report.chain.issues.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let config_path = std::env::var("DADM_CONFIG_PATH")
-        .map(std::path::PathBuf::from)
-        .unwrap_or_else(|_| std::path::PathBuf::from("config.json"));
-    let config = AgentConfig::load(&config_path);
+    let cli = Cli::parse();
+    let config_path = cli.config_path();
+    // Config loads and log level reloads (SIGHUP, override reset) read these
+    if cli.config.is_some() {
+        std::env::set_var("DADM_CONFIG_PATH", &config_path);
+    }
+    if let Some(ref level) = cli.log_level {
+        std::env::set_var("RUST_LOG", level);
+    }
+    // Runs as the console user, who may not be able to read the config
+    if let Some(CliCommand::Prompt { secs, ref text }) = cli.command {
+        std::process::exit(dadm_agent::response::approval::prompt(text, secs));
+    }
+    // Config tooling reads the layers itself, and works without a (valid) config
+    if let Some(CliCommand::Config(ref command)) = cli.command {
+        return run_config(&cli, &config_path, command);
+    }
+    if let Some(CliCommand::Launchd { role, ref user, ref binary }) = cli.command {
+        let binary = match binary {
+            Some(binary) => binary.clone(),
+            None => std::env::current_exe()?,
+        };
+        print!("{}", launchd::plist(role, &binary, &std::env::current_dir()?.join(&config_path), user));
+        return Ok(());
+    }
+    // With keys pinned at build time, a config without a valid signature stops the agent
+    let config_keys = dadm_agent::config::pinned_config_keys();
+    let mut config = AgentConfig::load_signed(&config_path, &config_keys)?;
+    if !config_keys.is_empty() {
+        dadm_agent::config::check_unsigned(Layer::Cli, &cli.layer())?;
+    }
+    cli.apply(&mut config);
+    let command = cli.command.unwrap_or(CliCommand::Run);
+    let command = match command {
+        CliCommand::Secret(secret) => return run_secret(&config, secret),
+        // Log lines would draw over the dashboard
+        CliCommand::Top { refresh_secs } => {
+            let endpoint = control::endpoint(&config.control, &config.data_dir);
+            return top::run(&endpoint, Duration::from_secs(refresh_secs));
+        }
+        command => command,
+    };
+    config.resolve_secrets()?;
 
-    StructuredLogger::init(config.log.json, &config.log.level);
+    if matches!(command, CliCommand::Run | CliCommand::ScanOnce) && config.crash.enabled {
+        logging::keep_recent(config.crash.log_lines);
+    }
+    StructuredLogger::init(&config.log, &config.data_dir);
 
-    info!(data_dir = ?config.data_dir, "DADM agent starting");
+    match command {
+        CliCommand::Run | CliCommand::ScanOnce => {}
+        CliCommand::Status => return run_status(&config),
+        CliCommand::Query(args) => return run_query(&config, args),
+        CliCommand::Export(args) => return run_export(&config, args),
+        CliCommand::Prune { before } => {
+            std::fs::create_dir_all(&config.data_dir)?;
+            open_audit(&config);
+            return run_prune(&config, before);
+        }
+        CliCommand::Evaluate(args) => return run_evaluate(&config, args),
+        CliCommand::Replay(args) => return run_replay(&config, args),
+        CliCommand::Selftest { sandbox } => return run_selftest(&config, sandbox),
+        CliCommand::VerifyChain(args) => return run_verify_chain(&config, args),
+        CliCommand::Scrub { quarantine, store } => return run_scrub(&config, quarantine, store),
+        CliCommand::RiskTrend(args) => return run_risk_trend(&config, args),
+        CliCommand::Feedback(args) => return run_feedback(&config, args),
+        CliCommand::PolicyAudit(args) => return run_policy_audit(&config, args),
+        CliCommand::CommandAudit { since, store } => return run_command_audit(&config, since, store),
+        CliCommand::ResponseAudit { since, store } => return run_response_audit(&config, since, store),
+        CliCommand::Undo { id } => return run_undo(&config, id),
+        CliCommand::LogLevel { directives, reset } => return run_log_level(&config, directives, reset),
+        CliCommand::VerifyAudit { file, keys } => return run_verify_audit(&config, file, &keys),
+        CliCommand::RotateKey => {
+            std::fs::create_dir_all(&config.data_dir)?;
+            open_audit(&config);
+            return run_rotate_key(&config);
+        }
+        CliCommand::ReleaseIsolation { hash: true } => {
+            println!("{}", isolation::hash_credential(&read_credential()?, isolation::CREDENTIAL_ITERATIONS));
+            return Ok(());
+        }
+        CliCommand::ReleaseIsolation { hash: false } => {
+            std::fs::create_dir_all(&config.data_dir)?;
+            open_audit(&config);
+            return run_release_isolation(&config);
+        }
+        CliCommand::Helper => return run_helper(&config),
+        CliCommand::Config(_) | CliCommand::Launchd { .. } | CliCommand::Secret(_) | CliCommand::Top { .. } | CliCommand::Prompt { .. } => {
+            unreachable!("handled before the config is resolved")
+        }
+    }
+
+    info!(data_dir = ?config.data_dir, config_signed = !config_keys.is_empty(), dry_run = config.dry_run, "DADM agent starting");
 
     std::fs::create_dir_all(&config.data_dir)?;
-    let store_path = config.data_dir.join("store.db");
-    let secret = b"device-secret-placeholder"; // In production: from Secure Enclave / Keystore
-    let store = Arc::new(SecureStore::open(&store_path, secret)?);
+    crash::install(&config.crash, &config.data_dir, Redactor::new(&config.log.redaction));
+    logging::watch_level(config_path.clone(), config.data_dir.join(logging::LEVEL_FILE));
+    open_audit(&config);
+    let config_hash = config_sha256(&config);
+    crash::set_context("config_sha256", json!(config_hash));
+    if let Some(log) = audit::global() {
+        if let Some(last) = log.last_config().filter(|last| *last != config_hash) {
+            audit::record("config_changed", json!({ "from": last, "to": config_hash }));
+        }
+    }
+    audit::record(
+        "agent_started",
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "pid": std::process::id(),
+            "config_sha256": config_hash,
+            "config_signed": !config_keys.is_empty(),
+            "dry_run": config.dry_run,
+        }),
+    );
+    mark_started(&config.data_dir);
+    let store = Arc::new(open_store(&config)?);
+    crash::set_context("store", store_stats(&store));
+    // Settings last set by the server apply from the start, also while it can't be reached
+    let mut effective = stored_policy_config(&config, &store);
+    let client = match config.uplink.enabled {
+        true => UplinkClient::new(config.uplink.clone()).map(|u| u.with_token_store(Arc::clone(&store)).with_dry_run(config.dry_run)),
+        false => None,
+    };
+    // A dry run would mark the bundles reported without sending them
+    report_crashes(&config.data_dir, client.as_ref().filter(|_| !config.dry_run));
+    if let Some(next) = client.and_then(|u| refresh_policy(&config, &effective, &u, &store)) {
+        effective = next;
+    }
+    // Root was needed for what is open by now; the rest of the run does without it
+    if config.privileges.enabled {
+        for (action, cap) in privileges::missing_response_capabilities(&config.privileges, &effective.response) {
+            tracing::warn!(action, capability = cap, "response action needs a capability privileges.capabilities drops; it will fail");
+        }
+        if let Some(dropped) = privileges::drop_privileges(&config.privileges, &config.data_dir)? {
+            info!(%dropped, "privileges dropped");
+            audit::record("privileges_dropped", dropped);
+        }
+    }
+    // Daemon tasks of every run share the runtime; Ctrl+C / SIGTERM cancel `stop`
+    let rt = runtime::build()?;
+    let stop = CancellationToken::new();
+    if effective.collectors.process.interval_secs > 0 {
+        rt.spawn(runtime::cancel_on_signal(stop.clone()));
+    }
+    let watchdog = Arc::new(Watchdog::new(effective.watchdog.clone()));
+    let outcome = loop {
+        match run_agent(&config, &effective, &store, &rt, &stop, &watchdog) {
+            Ok(Some(next)) => effective = next,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    audit::record("agent_stopped", json!({ "error": outcome.as_ref().err().map(|e| e.to_string()) }));
+    mark_stopped(&config.data_dir, outcome.as_ref().err().map(|e| e.to_string()));
+    outcome
+}
+
+/// Collect, score, and report with `config` (the local config with the server policy merged
+/// over it) until `stop` is cancelled, as tasks on `rt`; without a collection interval, once.
+/// Returns the config to run with next when a newer server policy was applied meanwhile or the
+/// `watchdog` restarts the pipeline, after shutting down as on a stop.
+fn run_agent(
+    local: &AgentConfig,
+    config: &AgentConfig,
+    store: &Arc<SecureStore>,
+    rt: &tokio::runtime::Runtime,
+    stop: &CancellationToken,
+    watchdog: &Arc<Watchdog>,
+) -> Result<Option<AgentConfig>, Box<dyn std::error::Error + Send + Sync>> {
+    let writes = WriteQueue::new(Arc::clone(store), config.storage.write_queue_capacity);
 
-    let collectors = CollectorPipeline::new(&config.collectors);
-    let features = Arc::new(FeatureExtractor::new(config.features.clone()));
-    let model = Arc::new(OnnxDetector::load(&config.model_path, config.features.feature_dim)?);
+    let collectors = Arc::new(CollectorPipeline::new(&config.collectors).with_helper(&config.helper));
+    let features = FeatureExtractor::new(config.features.clone());
+    restore_window(&features, store);
+    let model = OnnxDetector::load_with(&config.model_path, config.features.feature_dim, &config.model)?;
+    let baseline = BaselineDetector::load(&config.data_dir.join("baseline.json"), config.baseline.clone());
     let risk_engine = RiskEngine::new(config.risk.clone());
+    risk_engine.load_learned(&config.data_dir.join("risk_learned.json"));
+    if risk_engine.is_learning(chrono::Utc::now().timestamp_millis()) {
+        info!(days = config.risk.learning_period_days, "risk learning period active; results are informational");
+    }
 
-    let uplink: Option<UplinkClient> = if config.uplink.enabled {
+    let uplink: Option<UplinkQueue> = if config.uplink.enabled {
         UplinkClient::new(config.uplink.clone())
+            .map(|u| u.with_outbox(Arc::clone(store)).with_token_store(Arc::clone(store)).with_dry_run(config.dry_run))
+            .map(|u| UplinkQueue::new(u, config.uplink.queue_capacity))
     } else {
         None
     };
 
-    let interval_secs = config.collectors.process_interval_secs;
-    let run_daemon = interval_secs > 0;
+    let device_id = config.uplink.device_id.as_deref().unwrap_or("local-device");
+    let telemetry = Telemetry::new(&config.telemetry, device_id);
+    let siem = match config.log.siem.enabled {
+        true => match SiemOutput::open(&config.log, &config.data_dir) {
+            Ok(siem) => Some(siem),
+            Err(e) => {
+                tracing::warn!(error = %e, "SIEM output unavailable");
+                None
+            }
+        },
+        false => None,
+    };
+    let alerts = match config.log.alerts.enabled {
+        true => match AlertStream::open(&config.log, &config.data_dir) {
+            Ok(alerts) => Some(alerts),
+            Err(e) => {
+                tracing::warn!(error = %e, "alert stream unavailable");
+                None
+            }
+        },
+        false => None,
+    };
+    let notifier = config.notify.enabled.then(|| Notifier::start(&config.notify));
+    let governor = config.governor.enabled.then(|| Governor::new(config.governor.clone()));
+    let activity = runtime::Activity::default();
+    let endpoints: &[String] = if config.uplink.enabled { &config.uplink.endpoint } else { &[] };
+    let proxy = config.uplink.proxy.as_deref().filter(|_| config.uplink.enabled);
+    let response = ResponseEngine::new(&config.response, &config.notify, &config.data_dir, endpoints, config.dry_run)
+        .with_uplink_proxy(proxy)
+        .with_store(Arc::clone(store));
+    let mut pipeline = Pipeline {
+        collectors,
+        features,
+        model,
+        baseline,
+        risk_engine,
+        writes,
+        uplink,
+        siem,
+        alerts,
+        notifier,
+        governor,
+        telemetry,
+        activity,
+        stats: PipelineStats::default(),
+        response,
+        dry_run: config.dry_run,
+    };
+
+    if config.collectors.process.interval_secs > 0 {
+        return run_daemon(local, config, store, pipeline, rt, stop, watchdog);
+    }
+    enforce_retention(store, &config.storage);
+    tune_thresholds(&pipeline.risk_engine, store);
+    refresh_indicators(&pipeline.risk_engine, pipeline.uplink.as_ref().map(UplinkQueue::client), &config.data_dir);
+    analyze(&pipeline, Batch::collect(&pipeline.collectors, 1))?;
+    shutdown(&mut pipeline, store, &config.data_dir);
+    info!("DADM agent cycle complete");
+    Ok(None)
+}
+
+/// Collection batches waiting for analysis; collection waits while this many are queued
+const BATCH_QUEUE: usize = 4;
+
+/// Alerts and events returned by the control interface when the request sets no limit
+const CONTROL_ALERTS: usize = 50;
+const CONTROL_EVENTS: usize = 100;
+
+/// Answers control requests from local tooling with the daemon's state
+struct ControlOps {
+    pipeline: Arc<Pipeline>,
+    store: Arc<SecureStore>,
+    data_dir: PathBuf,
+    alert_level: RiskLevel,
+    /// Last cycle analyzed
+    cycle: Arc<AtomicU64>,
+    /// Notified to collect at once
+    scan: Arc<tokio::sync::Notify>,
+}
 
-    if run_daemon {
-        info!(interval_secs, "daemon mode (Ctrl+C to stop)");
-        static STOP: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
-        let _ = ctrlc::set_handler(|| {
-            STOP.store(true, std::sync::atomic::Ordering::Relaxed);
+impl control::Handler for ControlOps {
+    fn handle(&self, request: control::Request) -> Result<serde_json::Value, Box<dyn std::error::Error + Send + Sync>> {
+        use control::Request;
+        Ok(match request {
+            Request::Status => daemon_status(self.cycle.load(Ordering::Relaxed), &self.pipeline),
+            Request::Alerts { since, limit } => {
+                let since = since.unwrap_or_else(|| chrono::Utc::now().timestamp_millis() - 24 * 3_600_000);
+                let mut alerts = query_risk(&self.store, Some(since), None, Some(self.alert_level), None)?;
+                alerts.reverse();
+                alerts.truncate(limit.unwrap_or(CONTROL_ALERTS));
+                json!(alerts)
+            }
+            Request::Events { since, until, kind, limit } => {
+                let filter = EventFilter { since, until, kind, limit: Some(limit.unwrap_or(CONTROL_EVENTS)), ..EventFilter::default() };
+                let events = self.store.query(&filter)?.into_iter().map(|e| {
+                    let payload = serde_json::from_str(&e.payload).unwrap_or(serde_json::Value::String(e.payload));
+                    json!({ "id": e.id, "ts": e.ts, "kind": e.kind, "risk_score": e.risk_score, "payload": payload })
+                });
+                json!(events.collect::<Vec<_>>())
+            }
+            Request::Query { since, until, min_risk, limit } => json!(query_risk(&self.store, since, until, min_risk, limit)?),
+            Request::LogLevel { directives, reset } => {
+                let path = self.data_dir.join(logging::LEVEL_FILE);
+                if let Some(ref directives) = directives {
+                    logging::write_override(&path, Some(directives))?;
+                    info!(level = %directives, "log level override set over the control interface");
+                } else if reset {
+                    logging::write_override(&path, None)?;
+                    info!("log level override removed over the control interface");
+                }
+                json!({ "override": logging::read_override(&path), "active": logging::current_level() })
+            }
+            Request::Scan => {
+                self.scan.notify_one();
+                json!({ "requested": true })
+            }
+            Request::Undo { id } => {
+                let record = self.pipeline.response.undo(&id, UndoActor::Operator, "operator", chrono::Utc::now().timestamp_millis())?;
+                info!(id = %id, action = %record.action, "response action undone over the control interface");
+                json!(record)
+            }
+        })
+    }
+}
+
+/// Daemon mode: the collector task feeds batches to the analysis task, and retention, indicator
+/// refresh, threshold tuning, policy refresh, and server commands run as periodic tasks, until
+/// `stop` is cancelled or a newer server policy ends this run
+fn run_daemon(
+    local: &AgentConfig,
+    config: &AgentConfig,
+    store: &Arc<SecureStore>,
+    pipeline: Pipeline,
+    rt: &tokio::runtime::Runtime,
+    stop: &CancellationToken,
+    watchdog: &Arc<Watchdog>,
+) -> Result<Option<AgentConfig>, Box<dyn std::error::Error + Send + Sync>> {
+    let interval_secs = Arc::new(AtomicU64::new(config.collectors.process.interval_secs));
+    info!(interval_secs = config.collectors.process.interval_secs, "daemon mode (Ctrl+C to stop)");
+    // Indicators and tuned thresholds are in place for the first batch
+    refresh_indicators(&pipeline.risk_engine, pipeline.uplink.as_ref().map(UplinkQueue::client), &config.data_dir);
+    tune_thresholds(&pipeline.risk_engine, store);
+
+    let pipeline = Arc::new(pipeline);
+    // Cancelled on a stop, or to end this run when a server policy was applied
+    let run = stop.child_token();
+    let reload: Arc<Mutex<Option<AgentConfig>>> = Arc::default();
+    let data_dir = config.data_dir.clone();
+    let mut tasks = tokio::task::JoinSet::new();
+    let (batches, mut queued) = tokio::sync::mpsc::channel::<Batch>(BATCH_QUEUE);
+    let jitter = config.scheduler.jitter;
+    let scan = Arc::new(tokio::sync::Notify::new());
+    let cycle = Arc::new(AtomicU64::new(0));
+    tasks.spawn_on(
+        runtime::collect(
+            Arc::clone(&pipeline.collectors),
+            Arc::clone(&interval_secs),
+            config.scheduler.jitter,
+            batches,
+            Arc::clone(&scan),
+            Arc::clone(watchdog),
+            run.clone(),
+        ),
+        rt.handle(),
+    );
+    {
+        let (pipeline, data_dir, analyzed_cycle) = (Arc::clone(&pipeline), data_dir.clone(), Arc::clone(&cycle));
+        let (watchdog, ending) = (Arc::clone(watchdog), run.clone());
+        tasks.spawn_on(
+            async move {
+                while let Some(batch) = queued.recv().await {
+                    let (pipeline, data_dir, cycle) = (Arc::clone(&pipeline), data_dir.clone(), batch.cycle);
+                    let analyzed_cycle = Arc::clone(&analyzed_cycle);
+                    let analyzed = tokio::task::spawn_blocking(move || {
+                        if let Err(e) = analyze(&pipeline, batch) {
+                            tracing::warn!(cycle, error = %e, "cycle failed");
+                        }
+                        analyzed_cycle.store(cycle, Ordering::Relaxed);
+                        write_status(&data_dir, cycle, &pipeline);
+                    });
+                    let analyzed = match watchdog.stall_limit() {
+                        Some(limit) => match tokio::time::timeout(limit, analyzed).await {
+                            Ok(analyzed) => analyzed,
+                            Err(_) => {
+                                watchdog.stalled("analysis", cycle, false, std::time::Instant::now());
+                                ending.cancel();
+                                break;
+                            }
+                        },
+                        None => analyzed.await,
+                    };
+                    if let Err(e) = analyzed {
+                        tracing::warn!(cycle, error = %e, "analysis failed");
+                    }
+                }
+            },
+            rt.handle(),
+        );
+    }
+    {
+        let (store, storage) = (Arc::clone(store), config.storage.clone());
+        let period = Duration::from_secs(storage.retention_interval_secs);
+        tasks.spawn_on(runtime::every(Duration::ZERO, period, jitter, run.clone(), move || enforce_retention(&store, &storage)), rt.handle());
+    }
+    {
+        let (pipeline, data_dir) = (Arc::clone(&pipeline), data_dir.clone());
+        let period = Duration::from_secs(config.risk.ioc.refresh_secs);
+        tasks.spawn_on(
+            runtime::every(period, period, jitter, run.clone(), move || {
+                refresh_indicators(&pipeline.risk_engine, pipeline.uplink.as_ref().map(UplinkQueue::client), &data_dir)
+            }),
+            rt.handle(),
+        );
+    }
+    {
+        let (pipeline, store, data_dir) = (Arc::clone(&pipeline), Arc::clone(store), data_dir.clone());
+        let period = Duration::from_secs(config.risk.auto_tune.interval_secs);
+        tasks.spawn_on(
+            runtime::every(period, period, jitter, run.clone(), move || {
+                tune_thresholds(&pipeline.risk_engine, &store);
+                // Checkpoint what has been learned so a crash doesn't lose it
+                save_learned(&pipeline.baseline, &pipeline.risk_engine, &data_dir);
+            }),
+            rt.handle(),
+        );
+    }
+    {
+        let pipeline = Arc::clone(&pipeline);
+        tasks.spawn_on(
+            runtime::every(firewall::EXPIRY_CHECK, firewall::EXPIRY_CHECK, jitter, run.clone(), move || {
+                pipeline.response.firewall().expire(chrono::Utc::now().timestamp_millis());
+            }),
+            rt.handle(),
+        );
+    }
+    if pipeline.governor.is_some() {
+        let (pipeline, window_events) = (Arc::clone(&pipeline), config.features.window_events);
+        let period = Duration::from_secs(config.governor.sample_secs);
+        tasks.spawn_on(
+            runtime::every(Duration::ZERO, period, jitter, run.clone(), move || {
+                let Some(governor) = pipeline.governor.as_ref() else {
+                    return;
+                };
+                let throttle = governor.adjust(governor.measure());
+                pipeline.collectors.set_throttle(throttle);
+                pipeline.features.set_window_events(window_events / throttle.stretch as usize);
+            }),
+            rt.handle(),
+        );
+    }
+    if config.response.enabled && config.response.approval.enabled {
+        // The user's answers are acted on as they come, apart from analysis
+        let pipeline = Arc::clone(&pipeline);
+        tasks.spawn_on(
+            runtime::every(Duration::ZERO, Duration::from_secs(1), jitter, run.clone(), move || {
+                pipeline.response.settle(chrono::Utc::now().timestamp_millis());
+            }),
+            rt.handle(),
+        );
+    }
+    if pipeline.uplink.is_some() && config.uplink.heartbeat_secs > 0 {
+        let (pipeline, watchdog, cycle) = (Arc::clone(&pipeline), Arc::clone(watchdog), Arc::clone(&cycle));
+        let period = Duration::from_secs(config.uplink.heartbeat_secs);
+        tasks.spawn_on(
+            runtime::every(Duration::ZERO, period, jitter, run.clone(), move || {
+                let Some(u) = pipeline.uplink.as_ref() else {
+                    return;
+                };
+                let incidents = watchdog.take_incidents();
+                let stats = pipeline.stats.take();
+                let health = json!({
+                    "cycle": cycle.load(Ordering::Relaxed),
+                    "watchdog_incidents": incidents,
+                    "cycle_stats": stats,
+                });
+                if let Err(e) = u.client().heartbeat(detect_platform(), health) {
+                    tracing::warn!(error = %e, "heartbeat failed");
+                    watchdog.restore_incidents(incidents);
+                    pipeline.stats.restore(stats);
+                }
+            }),
+            rt.handle(),
+        );
+    }
+    if config.control.enabled || config.api.enabled {
+        let ops: Arc<dyn control::Handler> = Arc::new(ControlOps {
+            pipeline: Arc::clone(&pipeline),
+            store: Arc::clone(store),
+            data_dir: data_dir.clone(),
+            alert_level: config.log.alerts.min_level,
+            cycle,
+            scan,
         });
-        let mut cycle: u64 = 0;
-        while !STOP.load(std::sync::atomic::Ordering::Relaxed) {
-            cycle += 1;
-            if let Err(e) = run_one_cycle(
-                &collectors,
-                &features,
-                &model,
-                &risk_engine,
-                &store,
-                uplink.as_ref(),
-            ) {
-                tracing::warn!(cycle, error = %e, "cycle failed");
-            }
-            for _ in 0..(interval_secs as u32) {
-                if STOP.load(std::sync::atomic::Ordering::Relaxed) {
-                    break;
+        if config.control.enabled {
+            let endpoint = control::endpoint(&config.control, &data_dir);
+            tasks.spawn_on(control::serve(config.control.clone(), endpoint, Arc::clone(&ops), run.clone()), rt.handle());
+        }
+        if config.api.enabled {
+            match api::token(&config.api, &data_dir) {
+                Ok(token) => {
+                    tasks.spawn_on(api::serve(config.api.clone(), token, ops, run.clone()), rt.handle());
                 }
-                std::thread::sleep(Duration::from_secs(1));
+                Err(e) => tracing::warn!(error = %e, "HTTP API unavailable: no token"),
             }
         }
-        info!("DADM agent stopping");
-    } else {
-        run_one_cycle(
-            &collectors,
-            &features,
-            &model,
-            &risk_engine,
-            &store,
-            uplink.as_ref(),
-        )?;
-        info!("DADM agent cycle complete");
     }
+    if pipeline.uplink.is_some() {
+        // Fetched at startup, before this run
+        let (pipeline, store, reload, ending) = (Arc::clone(&pipeline), Arc::clone(store), Arc::clone(&reload), run.clone());
+        let (local, current) = (local.clone(), config.clone());
+        let period = Duration::from_secs(config.uplink.policy_refresh_secs);
+        tasks.spawn_on(
+            runtime::every(period, period, jitter, run.clone(), move || {
+                let Some(u) = pipeline.uplink.as_ref() else {
+                    return;
+                };
+                if let Some(next) = refresh_policy(&local, &current, u.client(), &store) {
+                    *reload.lock().unwrap() = Some(next);
+                    ending.cancel();
+                }
+            }),
+            rt.handle(),
+        );
+    }
+    if pipeline.uplink.is_some() {
+        // Pushed commands are handled within a second; polled ones every `commands.poll_secs`
+        let (pipeline, store, interval_secs) = (Arc::clone(&pipeline), Arc::clone(store), Arc::clone(&interval_secs));
+        let (commands_config, data_dir) = (config.commands.clone(), data_dir.clone());
+        let mut poll = runtime::Schedule::new(std::time::Instant::now(), Duration::ZERO, Duration::from_secs(commands_config.poll_secs), jitter);
+        tasks.spawn_on(
+            runtime::every(Duration::ZERO, Duration::from_secs(1), jitter, run.clone(), move || {
+                let Some(u) = pipeline.uplink.as_ref() else {
+                    return;
+                };
+                // While isolated, stay on the uplink addresses the firewall lets through
+                u.client().pin_hosts(&pipeline.response.firewall().isolation().map(|i| i.pinned).unwrap_or_default());
+                let mut commands = handle_server_messages(&pipeline.risk_engine, u.client(), &data_dir);
+                if commands_config.enabled && std::time::Instant::now() >= poll.next() {
+                    match u.client().fetch_commands() {
+                        Ok(fetched) => commands.extend(fetched),
+                        Err(e) => tracing::warn!(error = %e, "failed to fetch server commands"),
+                    }
+                    poll.advance(std::time::Instant::now());
+                }
+                if !commands_config.enabled && !commands.is_empty() {
+                    tracing::warn!(count = commands.len(), "server commands ignored (commands.enabled is off)");
+                } else if !commands.is_empty() {
+                    let mut secs = interval_secs.load(Ordering::Relaxed);
+                    let mut ctx = CommandContext {
+                        config: &commands_config,
+                        collectors: &pipeline.collectors,
+                        interval_secs: &mut secs,
+                        response: Some(&pipeline.response),
+                        dry_run: pipeline.dry_run,
+                    };
+                    run_commands(commands, &mut ctx, u.client(), &store);
+                    interval_secs.store(secs, Ordering::Relaxed);
+                }
+            }),
+            rt.handle(),
+        );
+    }
+    rt.block_on(async {
+        while let Some(joined) = tasks.join_next().await {
+            if let Err(e) = joined {
+                tracing::warn!(error = %e, "daemon task failed");
+                run.cancel();
+            }
+        }
+    });
 
-    Ok(())
+    let mut reload = reload.lock().unwrap().take();
+    let recovery = watchdog.take_outcome();
+    match recovery {
+        Some(Recovery::Restart) => {
+            info!("watchdog: restarting the pipeline");
+            reload = Some(reload.unwrap_or_else(|| config.clone()));
+        }
+        Some(Recovery::Exit) => info!("DADM agent stopping: pipeline stuck"),
+        _ if reload.is_some() => info!("server policy applied; restarting collection"),
+        _ => info!("DADM agent stopping"),
+    }
+    let stuck = || format!("watchdog: pipeline still stuck after {} restarts within an hour", config.watchdog.max_restarts);
+    let mut pipeline = match Arc::try_unwrap(pipeline) {
+        Ok(pipeline) => pipeline,
+        // The stuck cycle holds the pipeline; its queues and learned state are left with it
+        Err(_) if recovery.is_some() => {
+            tracing::warn!("stuck pipeline abandoned without a flush");
+            return match recovery {
+                Some(Recovery::Exit) => Err(stuck().into()),
+                _ => Ok(reload),
+            };
+        }
+        Err(_) => return Err("daemon tasks still running at shutdown".into()),
+    };
+    shutdown(&mut pipeline, store, &config.data_dir);
+    if recovery == Some(Recovery::Exit) {
+        return Err(stuck().into());
+    }
+    Ok(reload)
 }
@@ -0,0 +1,157 @@
+//! On-device baseline detector for new devices. During a learning period it collects feature
+//! vectors, then fits a per-feature robust baseline (median / MAD) and scores deviation from it.
+//! Complements the ONNX model during the cold-start window before a trained model arrives.
+
+use crate::config::BaselineConfig;
+use crate::features::FeatureVector;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Scale factor turning MAD into a consistent estimator of standard deviation.
+const MAD_TO_SIGMA: f32 = 1.4826;
+/// Floor for the robust spread so constant features don't produce infinite deviation, and a
+/// change in the noise of a near-constant one doesn't score as an outlier.
+const MIN_SPREAD: f32 = 0.01;
+/// Floor for the robust spread relative to the feature's median magnitude
+const MIN_RELATIVE_SPREAD: f32 = 0.05;
+
+/// Fitted per-feature baseline (median and median absolute deviation)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineFit {
+    pub median: Vec<f32>,
+    pub mad: Vec<f32>,
+    /// Number of samples the baseline was fitted on
+    pub samples: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BaselineState {
+    /// Timestamp (ms) of the first observed feature vector
+    started_at: Option<i64>,
+    samples: Vec<Vec<f32>>,
+    fit: Option<BaselineFit>,
+}
+
+pub struct BaselineDetector {
+    config: BaselineConfig,
+    state: Mutex<BaselineState>,
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let lower = values[(values.len() - 1) / 2];
+    let upper = values[values.len() / 2];
+    (lower + upper) / 2.0
+}
+
+impl BaselineFit {
+    fn from_samples(samples: &[Vec<f32>]) -> Self {
+        let dim = samples.iter().map(|s| s.len()).max().unwrap_or(0);
+        let mut med = Vec::with_capacity(dim);
+        let mut mad = Vec::with_capacity(dim);
+        for i in 0..dim {
+            let mut col: Vec<f32> = samples.iter().map(|s| s.get(i).copied().unwrap_or(0.0)).collect();
+            let m = median(&mut col);
+            let mut dev: Vec<f32> = col.iter().map(|v| (v - m).abs()).collect();
+            med.push(m);
+            mad.push(median(&mut dev));
+        }
+        Self {
+            median: med,
+            mad,
+            samples: samples.len(),
+        }
+    }
+
+    /// Largest robust z-score across features
+    pub fn max_deviation(&self, values: &[f32]) -> f32 {
+        self.median
+            .iter()
+            .zip(self.mad.iter())
+            .zip(values.iter())
+            .map(|((m, d), v)| (v - m).abs() / (d * MAD_TO_SIGMA).max(MIN_SPREAD).max(m.abs() * MIN_RELATIVE_SPREAD))
+            .fold(0.0, f32::max)
+    }
+}
+
+impl BaselineDetector {
+    pub fn new(config: BaselineConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BaselineState::default()),
+        }
+    }
+
+    /// Restore learning state from `path` (if present) so the learning period survives restarts.
+    pub fn load(path: &Path, config: BaselineConfig) -> Self {
+        let state = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|data| serde_json::from_str::<BaselineState>(&data).ok())
+            .unwrap_or_default();
+        Self {
+            config,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Persist learning state (samples while learning, fitted baseline afterwards)
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let state = self.state.lock().expect("lock");
+        let data = serde_json::to_string(&*state)?;
+        std::fs::write(path, data)
+    }
+
+    /// True until the baseline has been fitted
+    pub fn is_learning(&self) -> bool {
+        self.state.lock().expect("lock").fit.is_none()
+    }
+
+    /// Fitted baseline, if the learning period has completed
+    pub fn fit(&self) -> Option<BaselineFit> {
+        self.state.lock().expect("lock").fit.clone()
+    }
+
+    /// Feed a feature vector. While learning, the vector is retained and `None` is returned;
+    /// once fitted, returns a deviation score in [0, 1].
+    pub fn observe(&self, features: &FeatureVector) -> Option<f32> {
+        if !self.config.enabled {
+            return None;
+        }
+        let mut state = self.state.lock().expect("lock");
+        if let Some(ref fit) = state.fit {
+            return Some(self.score_with(fit, features));
+        }
+
+        let started_at = *state.started_at.get_or_insert(features.ts);
+        if state.samples.len() < self.config.max_samples {
+            state.samples.push(features.as_slice().to_vec());
+        }
+        let elapsed_ms = features.ts.saturating_sub(started_at);
+        let period_ms = (self.config.learning_period_secs as i64).saturating_mul(1000);
+        if elapsed_ms >= period_ms && state.samples.len() >= self.config.min_samples {
+            let fit = BaselineFit::from_samples(&state.samples);
+            tracing::info!(samples = fit.samples, "baseline learning complete");
+            state.samples.clear();
+            state.fit = Some(fit);
+        }
+        None
+    }
+
+    /// Score without learning; `None` while the baseline is not yet fitted.
+    pub fn score(&self, features: &FeatureVector) -> Option<f32> {
+        if !self.config.enabled {
+            return None;
+        }
+        let state = self.state.lock().expect("lock");
+        state.fit.as_ref().map(|fit| self.score_with(fit, features))
+    }
+
+    fn score_with(&self, fit: &BaselineFit, features: &FeatureVector) -> f32 {
+        let z = fit.max_deviation(features.as_slice());
+        (z / (z + self.config.z_scale.max(f32::EPSILON))).clamp(0.0, 1.0)
+    }
+}
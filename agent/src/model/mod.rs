@@ -1,5 +1,9 @@
-//! ONNX anomaly detection model inference.
+//! ONNX anomaly detection model inference, plus an on-device baseline for cold start.
+//! Server-distributed model releases are installed by [`release`].
 
 mod onnx;
+mod baseline;
+pub mod release;
 
-pub use onnx::OnnxDetector;
+pub use onnx::{ClassPrediction, OnnxDetector, Prediction};
+pub use baseline::{BaselineDetector, BaselineFit};
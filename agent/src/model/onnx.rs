@@ -1,10 +1,15 @@
 //! ONNX Runtime inference for anomaly score. Input: [1, feature_dim] f32, Output: score.
 //! Uses `ort` crate; if model file is missing, runs in no-op mode (returns 0.0).
+//! A small pool of sessions lets concurrent pipeline threads predict without serializing.
+//! Models with a second output of class probabilities also yield the top threat class.
 
+use crate::config::ModelConfig;
 use crate::features::FeatureVector;
 use ndarray::Array2;
+use serde::{Deserialize, Serialize};
 use std::path::Path;
-use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
 
 static ORT_ENV: OnceLock<ort::Environment> = OnceLock::new();
 
@@ -17,69 +22,160 @@ fn init_env() -> &'static ort::Environment {
     })
 }
 
+/// Most probable class from a multi-output model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassPrediction {
+    pub label: String,
+    pub probability: f32,
+}
+
+/// Full model output: anomaly score plus top class when the model provides class probabilities
+#[derive(Debug, Clone, Default)]
+pub struct Prediction {
+    pub score: f32,
+    pub class: Option<ClassPrediction>,
+}
+
 pub struct OnnxDetector {
-    session: Option<ort::Session>,
+    sessions: Vec<Mutex<ort::Session>>,
+    next: AtomicUsize,
     input_name: String,
     feature_dim: usize,
+    class_labels: Vec<String>,
 }
 
 impl OnnxDetector {
     /// Load model from path. If path missing or invalid, detector runs in no-op mode (returns 0.0).
     pub fn load(path: &Path, feature_dim: usize) -> Result<Self, ort::Error> {
+        Self::load_with(path, feature_dim, &ModelConfig::default())
+    }
+
+    /// Load model with session pool / threading options; warms up each session when configured.
+    pub fn load_with(path: &Path, feature_dim: usize, config: &ModelConfig) -> Result<Self, ort::Error> {
         let _env = init_env();
         let path = path.to_path_buf();
         if !path.exists() {
             tracing::warn!(path = %path.display(), "ONNX model not found; inference disabled");
             return Ok(Self {
-                session: None,
+                sessions: Vec::new(),
+                next: AtomicUsize::new(0),
                 input_name: String::new(),
                 feature_dim,
+                class_labels: config.class_labels.clone(),
             });
         }
 
-        let session = ort::Session::builder()?
-            .commit_from_file(&path)?;
+        let mut sessions = Vec::with_capacity(config.session_pool_size.max(1));
+        for _ in 0..config.session_pool_size.max(1) {
+            let mut builder = ort::Session::builder()?;
+            if config.intra_threads > 0 {
+                builder = builder.with_intra_threads(config.intra_threads)?;
+            }
+            sessions.push(Mutex::new(builder.commit_from_file(&path)?));
+        }
 
-        let input_name = session
-            .inputs
+        let input_name = sessions
             .first()
-            .map(|i| i.name.clone())
+            .and_then(|s| s.lock().ok()?.inputs.first().map(|i| i.name.clone()))
             .unwrap_or_else(|| "input".to_string());
 
-        Ok(Self {
-            session: Some(session),
+        let detector = Self {
+            sessions,
+            next: AtomicUsize::new(0),
             input_name,
             feature_dim,
-        })
+            class_labels: config.class_labels.clone(),
+        };
+        if config.warm_up {
+            detector.warm_up();
+        }
+        Ok(detector)
+    }
+
+    /// Number of pooled sessions (0 when inference is disabled)
+    pub fn pool_size(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Run one dummy inference per session so the first real prediction doesn't pay
+    /// for lazy allocation / graph optimization.
+    pub fn warm_up(&self) {
+        let dummy = vec![0.0f32; self.feature_dim];
+        let start = std::time::Instant::now();
+        for session in &self.sessions {
+            if let Ok(session) = session.lock() {
+                let _ = self.run(&session, &dummy);
+            }
+        }
+        tracing::info!(
+            sessions = self.sessions.len(),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "ONNX sessions warmed up"
+        );
     }
 
     /// Run inference; returns anomaly score in [0, 1]. Returns 0.0 if no model loaded.
     pub fn predict(&self, features: &FeatureVector) -> f32 {
-        let Some(ref session) = self.session else {
-            return 0.0;
-        };
+        self.predict_detailed(features).score
+    }
+
+    /// Run inference; returns score and, for multi-output models, the top class.
+    pub fn predict_detailed(&self, features: &FeatureVector) -> Prediction {
+        if self.sessions.is_empty() {
+            return Prediction::default();
+        }
 
         let dim = self.feature_dim.min(features.values.len());
-        let arr = Array2::from_shape_vec((1, dim), features.values[..dim].to_vec()).unwrap();
-        let input = match ort::Value::from_array(arr.into_dyn()) {
-            Ok(v) => v,
-            Err(_) => return 0.0,
-        };
+        let values = &features.values[..dim];
 
-        let outputs = match session.run(ort::inputs![self.input_name.as_str() => input]?) {
-            Ok(o) => o,
-            Err(_) => return 0.0,
-        };
+        // Prefer an idle session; otherwise wait on the next one round-robin.
+        for session in &self.sessions {
+            if let Ok(session) = session.try_lock() {
+                return self.run(&session, values).unwrap_or_default();
+            }
+        }
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % self.sessions.len();
+        match self.sessions[idx].lock() {
+            Ok(session) => self.run(&session, values).unwrap_or_default(),
+            Err(_) => Prediction::default(),
+        }
+    }
 
-        let out = match outputs.get(0) {
-            Some(t) => t,
-            None => return 0.0,
-        };
-        let view = match out.try_extract_raw_tensor::<f32>() {
-            Ok(v) => v,
-            Err(_) => return 0.0,
+    fn run(&self, session: &ort::Session, values: &[f32]) -> Option<Prediction> {
+        let arr = Array2::from_shape_vec((1, values.len()), values.to_vec()).ok()?;
+        let input = ort::Value::from_array(arr.into_dyn()).ok()?;
+        let outputs = session.run(ort::inputs![self.input_name.as_str() => input].ok()?).ok()?;
+        let (_, score) = outputs.get(0)?.try_extract_raw_tensor::<f32>().ok()?;
+        let probs = match outputs.get(1) {
+            Some(probs) => Some(probs.try_extract_raw_tensor::<f32>().ok()?.1),
+            None => None,
         };
-        let score = view.as_slice().first().copied().unwrap_or(0.0);
-        score.clamp(0.0, 1.0)
+        Some(Prediction::from_outputs(score, probs, &self.class_labels))
     }
 }
+
+impl Prediction {
+    /// Prediction from a model's outputs: the anomaly score, and the class probabilities of a
+    /// multi-output model, labeled from `class_labels` (else `class_<index>`)
+    pub fn from_outputs(score: &[f32], probs: Option<&[f32]>, class_labels: &[String]) -> Self {
+        Self {
+            score: score.first().copied().unwrap_or(0.0).clamp(0.0, 1.0),
+            class: probs.and_then(|probs| top_class(probs, class_labels)),
+        }
+    }
+}
+
+/// Highest-probability class
+fn top_class(probs: &[f32], class_labels: &[String]) -> Option<ClassPrediction> {
+    let (idx, p) = probs
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, p)| p.is_finite())
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let label = class_labels.get(idx).cloned().unwrap_or_else(|| format!("class_{}", idx));
+    Some(ClassPrediction {
+        label,
+        probability: p.clamp(0.0, 1.0),
+    })
+}
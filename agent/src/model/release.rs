@@ -0,0 +1,108 @@
+//! Model releases distributed through the server policy ([`crate::policy::Policy::model`]): the
+//! ONNX model and the feature recipe it was trained with are fetched from the uplink, checked
+//! against the SHA-256 hashes the signed policy pins, and installed under
+//! `data_dir/models/<version>/`. A release is staged in a hidden directory and renamed into place,
+//! so a release directory is either complete or absent.
+
+use crate::config::{AgentConfig, FeaturesConfig};
+use crate::policy::ModelRelease;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const MODELS_DIR: &str = "models";
+const MODEL_FILE: &str = "model.onnx";
+/// Feature recipe: the `features` config section the model expects
+const RECIPE_FILE: &str = "recipe.json";
+
+fn release_dir(data_dir: &Path, version: &str) -> Result<PathBuf, BoxError> {
+    let safe = !version.is_empty()
+        && !version.starts_with('.')
+        && version.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if !safe {
+        return Err(format!("invalid model release version {:?}", version).into());
+    }
+    Ok(data_dir.join(MODELS_DIR).join(version))
+}
+
+fn check_hash(name: &str, bytes: &[u8], expected: &str) -> Result<(), BoxError> {
+    let actual: String = Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect();
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(format!("{} SHA-256 {} does not match release hash {}", name, actual, expected).into());
+    }
+    Ok(())
+}
+
+fn write_synced(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+/// Directory of `version` when it is installed
+pub fn installed(data_dir: &Path, version: &str) -> Option<PathBuf> {
+    let dir = release_dir(data_dir, version).ok()?;
+    dir.join(MODEL_FILE).is_file().then_some(dir)
+}
+
+/// Verify the fetched files of `release` and install them; returns the release directory.
+/// Nothing is installed when a hash does not match or the recipe is not a `features` section.
+pub fn install(data_dir: &Path, release: &ModelRelease, model: &[u8], recipe: Option<&[u8]>) -> Result<PathBuf, BoxError> {
+    let dir = release_dir(data_dir, &release.version)?;
+    check_hash("model", model, &release.model.sha256)?;
+    match (&release.recipe, recipe) {
+        (Some(artifact), Some(bytes)) => {
+            check_hash("recipe", bytes, &artifact.sha256)?;
+            let features: FeaturesConfig = serde_json::from_slice(bytes).map_err(|e| format!("recipe: {}", e))?;
+            if features.feature_dim == 0 {
+                return Err("recipe: feature_dim must be positive".into());
+            }
+        }
+        (None, None) => {}
+        _ => return Err("recipe does not match the release".into()),
+    }
+    if dir.join(MODEL_FILE).is_file() {
+        return Ok(dir);
+    }
+    let staging = data_dir.join(MODELS_DIR).join(format!(".{}.partial", release.version));
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    std::fs::create_dir_all(&staging)?;
+    write_synced(&staging.join(MODEL_FILE), model)?;
+    if let Some(bytes) = recipe {
+        write_synced(&staging.join(RECIPE_FILE), bytes)?;
+    }
+    if dir.exists() {
+        // Left over without a model; replaced whole
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::rename(&staging, &dir)?;
+    Ok(dir)
+}
+
+/// Point `config` at the release installed in `dir`: its model, and its recipe as `features`
+pub fn apply(config: &mut AgentConfig, dir: &Path) -> Result<(), BoxError> {
+    config.model_path = dir.join(MODEL_FILE);
+    let recipe = dir.join(RECIPE_FILE);
+    if recipe.is_file() {
+        config.features = serde_json::from_slice(&std::fs::read(&recipe)?).map_err(|e| format!("recipe: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Remove installed releases other than `keep` (and abandoned staging directories)
+pub fn prune(data_dir: &Path, keep: &[&Path]) -> Result<usize, BoxError> {
+    let root = data_dir.join(MODELS_DIR);
+    let mut removed = 0;
+    for entry in std::fs::read_dir(&root)? {
+        let path = entry?.path();
+        if path.is_dir() && !keep.iter().any(|k| *k == path) {
+            std::fs::remove_dir_all(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
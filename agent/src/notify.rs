@@ -0,0 +1,129 @@
+//! Desktop notifications (`notify`) for users on machines nobody else watches: risk results that
+//! raised an alert at `min_level` or above (default high) are shown as a native notification:
+//! through the freedesktop notification service over D-Bus (Linux), Notification Center (macOS),
+//! or a toast (Windows), in the session of the user the agent runs as. At most one is shown per
+//! `min_interval_secs`; alerts in between are counted into the next. Notifications are shown from
+//! a background thread; one that can't be shown is logged and dropped.
+
+use crate::config::NotifyConfig;
+use crate::risk::{RiskLevel, RiskResult};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Notifications waiting to be shown
+const QUEUE: usize = 16;
+const APP_NAME: &str = "DADM Agent";
+
+/// A notification to show
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notice {
+    pub level: RiskLevel,
+    pub summary: String,
+    pub body: String,
+}
+
+#[derive(Default)]
+struct Throttle {
+    /// When the last notification was due
+    last: Option<Instant>,
+    /// Alerts not notified since `last`
+    held: usize,
+}
+
+pub struct Notifier {
+    min_level: RiskLevel,
+    min_interval: Duration,
+    throttle: Mutex<Throttle>,
+    tx: SyncSender<Notice>,
+}
+
+impl Notifier {
+    /// Start the thread showing notifications
+    pub fn start(config: &NotifyConfig) -> Self {
+        let (tx, rx) = mpsc::sync_channel::<Notice>(QUEUE);
+        std::thread::spawn(move || {
+            for notice in rx {
+                if let Err(e) = show(&notice) {
+                    tracing::warn!(error = %e, "desktop notification failed");
+                }
+            }
+        });
+        Self {
+            min_level: config.min_level,
+            min_interval: Duration::from_secs(config.min_interval_secs),
+            throttle: Mutex::default(),
+            tx,
+        }
+    }
+
+    /// Notification for `result` at `now`, when one is due
+    pub fn notice(&self, result: &RiskResult, now: Instant) -> Option<Notice> {
+        if !result.alert || result.learning || result.level < self.min_level {
+            return None;
+        }
+        let held = self.due(now)?;
+        let mut body = format!("Score {:.2}: {}", result.score, result.reasons().join(", "));
+        match held {
+            0 => {}
+            1 => body.push_str("\n1 more alert since the last notification"),
+            held => body.push_str(&format!("\n{} more alerts since the last notification", held)),
+        }
+        let summary = match result.level {
+            RiskLevel::High => "High risk detected on this device",
+            RiskLevel::Medium => "Medium risk detected on this device",
+            RiskLevel::Low => "Risk detected on this device",
+        };
+        Some(Notice { level: result.level, summary: summary.to_string(), body })
+    }
+
+    /// Alerts held back since the last notification, when one is due at `now`
+    fn due(&self, now: Instant) -> Option<usize> {
+        let mut throttle = self.throttle.lock().unwrap();
+        if throttle.last.is_some_and(|last| now.duration_since(last) < self.min_interval) {
+            throttle.held += 1;
+            return None;
+        }
+        throttle.last = Some(now);
+        Some(std::mem::take(&mut throttle.held))
+    }
+
+    /// Notification of the response actions `taken` on `result` at `now`, when one is due; any
+    /// level is notified
+    pub fn response_notice(&self, result: &RiskResult, taken: &[String], now: Instant) -> Option<Notice> {
+        self.due(now)?;
+        let mut body = format!("Score {:.2}: {}", result.score, result.reasons().join(", "));
+        if !taken.is_empty() {
+            body.push_str(&format!("\nTaken: {}", taken.join(", ")));
+        }
+        let summary = "Threat response on this device".to_string();
+        Some(Notice { level: result.level, summary, body })
+    }
+
+    /// Show a notification for `result` when one is due
+    pub fn emit(&self, result: &RiskResult) {
+        if let Some(notice) = self.notice(result, Instant::now()) {
+            self.send(notice);
+        }
+    }
+
+    /// Show `notice`
+    pub fn send(&self, notice: Notice) {
+        match self.tx.try_send(notice) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => tracing::warn!("notification queue full; notification dropped"),
+            Err(TrySendError::Disconnected(_)) => tracing::warn!("desktop notifications stopped"),
+        }
+    }
+}
+
+fn show(notice: &Notice) -> Result<(), notify_rust::error::Error> {
+    let mut notification = notify_rust::Notification::new();
+    notification.appname(APP_NAME).summary(&notice.summary).body(&notice.body);
+    #[cfg(all(unix, not(target_os = "macos")))]
+    notification.urgency(match notice.level {
+        RiskLevel::High => notify_rust::Urgency::Critical,
+        _ => notify_rust::Urgency::Normal,
+    });
+    notification.show().map(|_| ())
+}
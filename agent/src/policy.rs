@@ -0,0 +1,191 @@
+//! Server-managed configuration: the uplink serves a policy, a fragment of the agent config signed
+//! with an Ed25519 key listed in `uplink.policy_keys`, which is merged over the local config.
+//! Only the settings in [`MANAGED`] (collector toggles and cadence, watch paths, risk thresholds,
+//! uplink cadence) can be set; a policy touching anything else is rejected as a whole. Each
+//! policy carries a version and only newer versions are applied, so an old policy can't be
+//! replayed. What changed is recorded in the store ([`crate::SecureStore::record_policy`]).
+//! A policy may also roll out a model release ([`ModelRelease`], installed by
+//! [`crate::model::release`]); it takes effect only once the release is installed.
+
+use crate::config::AgentConfig;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Config settings (dotted paths, with everything below them) a policy may set
+pub const MANAGED: &[&str] = &[
+    "collectors.process",
+    "collectors.network",
+    "collectors.file_integrity",
+    "collectors.privilege",
+    // Flat collector settings of earlier releases
+    "collectors.process_interval_secs",
+    "collectors.file_interval_secs",
+    "collectors.watch_paths",
+    "risk.high_threshold",
+    "risk.medium_threshold",
+    "risk.kind_profiles",
+    "risk.source_profiles",
+    "risk.ioc.refresh_secs",
+    "uplink.report_interval_secs",
+    "uplink.policy_refresh_secs",
+];
+
+/// Policy as served: `policy` is the JSON text of a [`Policy`] and `signature` the base64 Ed25519
+/// signature of exactly that text
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedPolicy {
+    pub policy: String,
+    pub signature: String,
+}
+
+/// Signed content of a policy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Policy {
+    /// Increases with every policy the server issues
+    pub version: u64,
+    /// Config fragment, e.g. `{"collectors": {"network": false}, "risk": {"high_threshold": 0.8}}`
+    pub config: Value,
+    /// Model to run instead of the local `model_path` and `features`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<ModelRelease>,
+}
+
+/// Model release: the ONNX model and the feature recipe (a `features` config section) it expects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelRelease {
+    /// Release name; also its directory under `data_dir/models`
+    pub version: String,
+    pub model: Artifact,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipe: Option<Artifact>,
+}
+
+/// File fetched from the uplink, pinned by the signed policy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    /// Path on the uplink endpoint, or an absolute URL
+    pub url: String,
+    /// Hex SHA-256 of the file
+    pub sha256: String,
+}
+
+/// One setting changed by applying a policy (`null` where it was or became unset)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PolicyChange {
+    pub path: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// Audit record of an applied policy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyAuditEntry {
+    /// When it was applied (ms)
+    pub ts: i64,
+    pub version: u64,
+    pub changes: Vec<PolicyChange>,
+}
+
+/// Whether `signature` of `message` verifies under one of `keys` (base64 raw Ed25519 public
+/// keys); a malformed key is an error
+pub fn verify_ed25519(message: &[u8], signature: &[u8], keys: &[String]) -> Result<bool, BoxError> {
+    for key in keys {
+        let raw = BASE64.decode(key.trim()).map_err(|e| format!("key {}: {}", key, e))?;
+        if UnparsedPublicKey::new(&ED25519, &raw).verify(message, signature).is_ok() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+impl SignedPolicy {
+    /// The policy, if `signature` verifies under one of `keys` (base64 raw Ed25519 public keys)
+    pub fn verify(&self, keys: &[String]) -> Result<Policy, BoxError> {
+        let signature = BASE64.decode(self.signature.trim()).map_err(|e| format!("policy signature: {}", e))?;
+        if !verify_ed25519(self.policy.as_bytes(), &signature, keys).map_err(|e| format!("policy {}", e))? {
+            return Err("policy signature does not verify under any of uplink.policy_keys".into());
+        }
+        Ok(serde_json::from_str(&self.policy)?)
+    }
+}
+
+fn managed(path: &str) -> bool {
+    MANAGED
+        .iter()
+        .any(|m| path == *m || path.strip_prefix(m).is_some_and(|rest| rest.starts_with('.')))
+}
+
+/// Set the leaves of `fragment` into `target`; objects are merged key by key, anything else
+/// replaces the value at its path
+fn merge_into(target: &mut Value, fragment: &Value, path: &str) -> Result<(), BoxError> {
+    match fragment {
+        Value::Object(fields) if !managed(path) || target.is_object() => {
+            if !target.is_object() {
+                *target = Value::Object(Default::default());
+            }
+            for (key, value) in fields {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                let slot = target.as_object_mut().expect("object above").entry(key.clone()).or_insert(Value::Null);
+                merge_into(slot, value, &path)?;
+            }
+            Ok(())
+        }
+        // `"network": false` (flat layout) switches a collector section, keeping its settings
+        Value::Bool(on) if path.starts_with("collectors.") && target.get("enabled").is_some() => {
+            target["enabled"] = Value::Bool(*on);
+            Ok(())
+        }
+        _ if managed(path) => {
+            *target = fragment.clone();
+            Ok(())
+        }
+        _ => Err(format!("policy sets {}, which is not server-managed", path).into()),
+    }
+}
+
+/// `local` with `policy` merged over it. Fails, leaving the config to the caller, if the policy
+/// sets an unmanaged setting, a value of the wrong type, inconsistent thresholds, or would turn
+/// the daemon into a single run.
+pub fn apply(local: &AgentConfig, policy: &Policy) -> Result<AgentConfig, BoxError> {
+    if !policy.config.is_object() {
+        return Err("policy config is not an object".into());
+    }
+    let mut value = serde_json::to_value(local)?;
+    merge_into(&mut value, &policy.config, "")?;
+    let config: AgentConfig = serde_json::from_value(value).map_err(|e| format!("policy config: {}", e))?;
+    let (medium, high) = (config.risk.medium_threshold, config.risk.high_threshold);
+    if !(0.0..=1.0).contains(&medium) || !(0.0..=1.0).contains(&high) || medium > high {
+        return Err(format!("policy thresholds out of order: medium {} high {}", medium, high).into());
+    }
+    if local.collectors.process.interval_secs > 0 && config.collectors.process.interval_secs == 0 {
+        return Err("policy would stop the daemon (collectors.process.interval_secs 0)".into());
+    }
+    Ok(config)
+}
+
+fn diff(old: &Value, new: &Value, path: &str, out: &mut Vec<PolicyChange>) {
+    match (old, new) {
+        (Value::Object(a), Value::Object(b)) => {
+            let keys: std::collections::BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                diff(a.get(key).unwrap_or(&Value::Null), b.get(key).unwrap_or(&Value::Null), &path, out);
+            }
+        }
+        _ if old != new => out.push(PolicyChange { path: path.to_string(), old: old.clone(), new: new.clone() }),
+        _ => {}
+    }
+}
+
+/// Settings that differ between `old` and `new`, by dotted path
+pub fn changes(old: &AgentConfig, new: &AgentConfig) -> Vec<PolicyChange> {
+    let mut out = Vec::new();
+    if let (Ok(old), Ok(new)) = (serde_json::to_value(old), serde_json::to_value(new)) {
+        diff(&old, &new, "", &mut out);
+    }
+    out
+}
@@ -0,0 +1,286 @@
+//! Privilege dropping (`privileges`): an agent started as root for its privileged collectors
+//! opens what needs root first (logs, the audit log, the store and its keys, the uplink identity),
+//! then hands `data_dir` to `privileges.user` and switches to that account for the rest of its
+//! run. On Linux it keeps the capabilities in `privileges.capabilities` (e.g. reading any file
+//! and other users' processes) and drops every other one from its bounding set, so they can't be
+//! regained through setuid programs either; commands it runs inherit the kept ones. On Windows
+//! the account stays, and every privilege of the process token outside
+//! `privileges.windows_privileges` is removed.
+
+use crate::config::{PrivilegesConfig, ResponseConfig};
+use serde_json::{json, Value};
+use std::path::Path;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Linux capabilities by name and number (`linux/capability.h`)
+const CAPABILITIES: [&str; 41] = [
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// Capabilities that would let the account become root again, so can't be kept
+const ROOT_CAPABILITIES: [&str; 3] = ["CAP_SETUID", "CAP_SETGID", "CAP_SYS_ADMIN"];
+
+/// Bit mask of the capabilities `names` (`CAP_SYS_PTRACE` or `sys_ptrace`)
+pub fn capability_mask(names: &[String]) -> Result<u64, String> {
+    names.iter().try_fold(0u64, |mask, name| {
+        let upper = name.to_ascii_uppercase();
+        let full = if upper.starts_with("CAP_") { upper } else { format!("CAP_{}", upper) };
+        if ROOT_CAPABILITIES.contains(&full.as_str()) {
+            return Err(format!("{} would allow regaining root", full));
+        }
+        match CAPABILITIES.iter().position(|cap| *cap == full) {
+            Some(bit) => Ok(mask | 1 << bit),
+            None => Err(format!("unknown capability {}", name)),
+        }
+    })
+}
+
+/// Capabilities response actions need for processes, files, and the firewall of other accounts
+const RESPONSE_CAPABILITIES: [(&str, &str); 5] = [
+    ("kill_process", "CAP_KILL"),
+    ("suspend_process", "CAP_KILL"),
+    ("quarantine", "CAP_DAC_OVERRIDE"),
+    ("block_network", "CAP_NET_ADMIN"),
+    ("isolate", "CAP_NET_ADMIN"),
+];
+
+/// Response actions turned on in `response` that `privileges` leaves without a capability they
+/// need after the switch (Linux), as `(action, capability)`
+pub fn missing_response_capabilities(privileges: &PrivilegesConfig, response: &ResponseConfig) -> Vec<(&'static str, &'static str)> {
+    if !cfg!(target_os = "linux") || !privileges.enabled || !response.enabled {
+        return Vec::new();
+    }
+    let kept = capability_mask(&privileges.capabilities).unwrap_or(0);
+    let actions = &response.actions;
+    let on = [actions.kill_process, actions.suspend_process, actions.quarantine, actions.block_network, actions.isolate];
+    RESPONSE_CAPABILITIES
+        .iter()
+        .zip(on)
+        .filter(|&(&(_, cap), on)| on && kept & capability_mask(&[cap.to_string()]).unwrap_or(0) == 0)
+        .map(|(&pair, _)| pair)
+        .collect()
+}
+
+/// Drop privileges as `config` says, after handing `data_dir` to the account. Returns what
+/// changed, for the audit log, or `None` when there was nothing to drop (not started as root).
+#[cfg(unix)]
+pub fn drop_privileges(config: &PrivilegesConfig, data_dir: &Path) -> Result<Option<Value>, BoxError> {
+    if unsafe { libc::geteuid() } != 0 {
+        return Ok(None);
+    }
+    let mask = capability_mask(&config.capabilities).map_err(|e| format!("privileges.capabilities: {}", e))?;
+    let (uid, primary) = lookup_user(&config.user)?;
+    let gid = match config.group {
+        Some(ref group) => lookup_group(group)?,
+        None => primary,
+    };
+    if uid == 0 {
+        return Err(format!("privileges.user {} is root", config.user).into());
+    }
+    // Everything the agent writes from now on is under data_dir
+    for entry in walkdir::WalkDir::new(data_dir).follow_links(false) {
+        let path = entry?.into_path();
+        let c_path = std::ffi::CString::new(std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()))?;
+        if unsafe { libc::lchown(c_path.as_ptr(), uid, gid) } != 0 {
+            return Err(format!("chown {}: {}", path.display(), std::io::Error::last_os_error()).into());
+        }
+    }
+    switch_user(uid, gid, mask)?;
+    if holds_root() {
+        return Err("privileges: still root after the switch".into());
+    }
+    let kept: Vec<&str> = if cfg!(target_os = "linux") {
+        CAPABILITIES.iter().enumerate().filter(|(bit, _)| mask & 1 << bit != 0).map(|(_, name)| *name).collect()
+    } else {
+        Vec::new()
+    };
+    Ok(Some(json!({ "user": config.user, "uid": uid, "gid": gid, "capabilities": kept })))
+}
+
+/// Whether the real, effective, or saved user ID is still root
+#[cfg(target_os = "linux")]
+fn holds_root() -> bool {
+    let (mut real, mut effective, mut saved) = (0, 0, 0);
+    let failed = unsafe { libc::getresuid(&mut real, &mut effective, &mut saved) } != 0;
+    failed || real == 0 || effective == 0 || saved == 0
+}
+
+/// Whether the real or effective user ID is still root (`setuid` also replaces the saved one)
+#[cfg(all(unix, not(target_os = "linux")))]
+fn holds_root() -> bool {
+    unsafe { libc::getuid() == 0 || libc::geteuid() == 0 }
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<(libc::uid_t, libc::gid_t), BoxError> {
+    let c_name = std::ffi::CString::new(name)?;
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwnam_r(c_name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if rc != 0 || found.is_null() {
+        return Err(format!("privileges.user: no user {}", name).into());
+    }
+    Ok((entry.pw_uid, entry.pw_gid))
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Result<libc::gid_t, BoxError> {
+    let c_name = std::ffi::CString::new(name)?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe { libc::getgrnam_r(c_name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if rc != 0 || found.is_null() {
+        return Err(format!("privileges.group: no group {}", name).into());
+    }
+    Ok(entry.gr_gid)
+}
+
+/// Switch every thread to `uid` / `gid`; the calling thread, and threads it starts later, keep the
+/// capabilities in `mask`
+#[cfg(target_os = "linux")]
+fn switch_user(uid: libc::uid_t, gid: libc::gid_t, mask: u64) -> Result<(), BoxError> {
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: libc::c_int,
+    }
+    #[repr(C)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    const CAP_VERSION_3: u32 = 0x2008_0522;
+    let fail = |what: &str| format!("privileges: {}: {}", what, std::io::Error::last_os_error());
+    // prctl arguments are unsigned longs
+    let prctl = |option: libc::c_int, arg: u64, arg2: u64| unsafe { libc::prctl(option, arg as libc::c_ulong, arg2 as libc::c_ulong, 0 as libc::c_ulong, 0 as libc::c_ulong) };
+
+    unsafe {
+        if prctl(libc::PR_SET_KEEPCAPS, 1, 0) != 0 {
+            return Err(fail("keep capabilities").into());
+        }
+        // Capabilities the kernel doesn't know fail with EINVAL
+        for bit in 0..64 {
+            if mask & 1 << bit == 0 {
+                prctl(libc::PR_CAPBSET_DROP, bit, 0);
+            }
+        }
+        if libc::setgroups(1, &gid) != 0 || libc::setresgid(gid, gid, gid) != 0 {
+            return Err(fail("switch group").into());
+        }
+        if libc::setresuid(uid, uid, uid) != 0 {
+            return Err(fail("switch user").into());
+        }
+        let mut header = CapHeader { version: CAP_VERSION_3, pid: 0 };
+        let (low, high) = (mask as u32, (mask >> 32) as u32);
+        let data = [
+            CapData { effective: low, permitted: low, inheritable: low },
+            CapData { effective: high, permitted: high, inheritable: high },
+        ];
+        if libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) != 0 {
+            return Err(fail("set capabilities").into());
+        }
+        for bit in (0..64).filter(|bit| mask & 1 << bit != 0) {
+            if prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE as u64, bit) != 0 {
+                tracing::warn!(capability = CAPABILITIES[bit as usize], "capability not passed on to commands");
+            }
+        }
+        prctl(libc::PR_SET_KEEPCAPS, 0, 0);
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn switch_user(uid: libc::uid_t, gid: libc::gid_t, _: u64) -> Result<(), BoxError> {
+    unsafe {
+        if libc::setgroups(1, &gid) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+            return Err(format!("privileges: switch user: {}", std::io::Error::last_os_error()).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn drop_privileges(config: &PrivilegesConfig, _: &Path) -> Result<Option<Value>, BoxError> {
+    use windows::core::{PCWSTR, PWSTR};
+    use windows::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows::Win32::Security::{
+        AdjustTokenPrivileges, GetTokenInformation, LookupPrivilegeNameW, TokenPrivileges, SE_PRIVILEGE_ENABLED,
+        SE_PRIVILEGE_REMOVED, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_PRIVILEGES_ATTRIBUTES, TOKEN_QUERY,
+    };
+    use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    let mut token = HANDLE::default();
+    unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY, &mut token) }?;
+    let adjusted = (|| -> Result<Vec<String>, BoxError> {
+        let mut len = 0u32;
+        let _ = unsafe { GetTokenInformation(token, TokenPrivileges, None, 0, &mut len) };
+        // u64s keep the buffer aligned for TOKEN_PRIVILEGES
+        let mut buf = vec![0u64; len as usize / 8 + 1];
+        unsafe { GetTokenInformation(token, TokenPrivileges, Some(buf.as_mut_ptr().cast()), len, &mut len) }?;
+        let privileges = buf.as_mut_ptr().cast::<TOKEN_PRIVILEGES>();
+        let entries = unsafe { std::slice::from_raw_parts_mut((*privileges).Privileges.as_mut_ptr(), (*privileges).PrivilegeCount as usize) };
+        let mut removed = Vec::new();
+        for entry in entries.iter_mut() {
+            let mut name = [0u16; 128];
+            let mut name_len = name.len() as u32;
+            unsafe { LookupPrivilegeNameW(PCWSTR::null(), &entry.Luid, PWSTR(name.as_mut_ptr()), &mut name_len) }?;
+            let name = String::from_utf16_lossy(&name[..name_len as usize]);
+            entry.Attributes = if config.windows_privileges.iter().any(|keep| keep.eq_ignore_ascii_case(&name)) {
+                TOKEN_PRIVILEGES_ATTRIBUTES(entry.Attributes.0 & SE_PRIVILEGE_ENABLED.0)
+            } else {
+                removed.push(name);
+                SE_PRIVILEGE_REMOVED
+            };
+        }
+        unsafe { AdjustTokenPrivileges(token, false, Some(privileges), 0, None, None) }?;
+        Ok(removed)
+    })();
+    unsafe {
+        let _ = CloseHandle(token);
+    }
+    Ok(Some(json!({ "removed": adjusted?, "kept": config.windows_privileges })))
+}
@@ -0,0 +1,180 @@
+//! Privacy filter for uplink payloads (`uplink.redaction`) and log lines (`log.redaction`): every
+//! JSON body is rewritten before it is queued or sent, and every log line before it reaches a sink,
+//! so the outbox, the server, and log files only see redacted values. Usernames (the local
+//! accounts, and any listed), the account segment of home-directory paths, command-line arguments
+//! (`cmdline` / `command_line` fields), matches of the configured patterns, and whole values of the
+//! listed fields are removed or, with `mode: hash`, replaced by a salted hash so they can still be
+//! correlated.
+
+use crate::config::{RedactionConfig, RedactionMode};
+use regex::Regex;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Stands in for a stripped value
+const REDACTED: &str = "[redacted]";
+/// Directories holding one home directory per account
+const HOME_ROOTS: &[&str] = &["/home/", "/Users/", "\\Users\\"];
+/// Fields holding a command line
+const CMDLINE_FIELDS: &[&str] = &["cmdline", "command_line"];
+
+pub struct Redactor {
+    config: RedactionConfig,
+    /// Longest first, so a name containing another is replaced whole
+    usernames: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+/// Local account names: the current user and the home directories present
+fn local_usernames() -> Vec<String> {
+    let mut names: Vec<String> = ["USER", "USERNAME", "LOGNAME"].iter().filter_map(|v| std::env::var(v).ok()).collect();
+    let accounts = dirs::home_dir().and_then(|home| home.parent().map(|p| p.to_path_buf()));
+    if let Some(root) = accounts.filter(|root| root.file_name().is_some_and(|n| n == "home" || n == "Users")) {
+        if let Ok(entries) = std::fs::read_dir(root) {
+            names.extend(entries.flatten().filter_map(|e| e.file_name().into_string().ok()));
+        }
+    }
+    names
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+impl Redactor {
+    /// `None` when redaction is off. Invalid patterns are reported on stderr and skipped.
+    pub fn new(config: &RedactionConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let mut usernames: Vec<String> = config.usernames.clone();
+        if config.local_usernames {
+            usernames.extend(local_usernames());
+        }
+        // Very short names would match inside ordinary words
+        usernames.retain(|name| name.chars().count() >= 3 && !name.starts_with('.'));
+        usernames.sort_by_key(|name| std::cmp::Reverse(name.len()));
+        usernames.dedup();
+        let patterns = config
+            .patterns
+            .iter()
+            .filter_map(|p| match Regex::new(p) {
+                Ok(re) => Some(re),
+                Err(e) => {
+                    eprintln!("ignoring invalid redaction pattern {:?}: {}", p, e);
+                    None
+                }
+            })
+            .collect();
+        Some(Self { config: config.clone(), usernames, patterns })
+    }
+
+    /// What replaces `value`
+    fn replacement(&self, value: &str) -> String {
+        match self.config.mode {
+            RedactionMode::Strip => REDACTED.to_string(),
+            RedactionMode::Hash => {
+                let digest = Sha256::digest(format!("{}{}", self.config.hash_salt, value).as_bytes());
+                let hex: String = digest.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+                format!("h:{}", hex)
+            }
+        }
+    }
+
+    /// `text` with usernames (as whole words), home-directory accounts, and pattern matches replaced
+    fn scrub(&self, text: &str) -> String {
+        let mut out = text.to_string();
+        // Patterns first, so a username inside a match (e.g. an email address) does not split it
+        for pattern in &self.patterns {
+            if pattern.is_match(&out) {
+                out = pattern.replace_all(&out, |caps: &regex::Captures| self.replacement(&caps[0])).into_owned();
+            }
+        }
+        if self.config.home_paths {
+            for root in HOME_ROOTS {
+                let mut from = 0;
+                while let Some(at) = out[from..].find(root) {
+                    let start = from + at + root.len();
+                    let end = out[start..].find(|c: char| c == '/' || c == '\\' || c == '"' || c.is_whitespace()).map_or(out.len(), |i| start + i);
+                    let account = &out[start..end];
+                    let replacement = if account.is_empty() { String::new() } else { self.replacement(account) };
+                    out.replace_range(start..end, &replacement);
+                    from = start + replacement.len();
+                }
+            }
+        }
+        for name in &self.usernames {
+            let mut from = 0;
+            while let Some(at) = out[from..].find(name.as_str()) {
+                let start = from + at;
+                let end = start + name.len();
+                let bounded = !out[..start].ends_with(is_word) && !out[end..].starts_with(is_word);
+                if bounded {
+                    let replacement = self.replacement(name);
+                    out.replace_range(start..end, &replacement);
+                    from = start + replacement.len();
+                } else {
+                    from = end;
+                }
+            }
+        }
+        out
+    }
+
+    /// Arguments of a command line redacted, the program kept (scrubbed)
+    fn command_line(&self, value: &Value) -> Value {
+        let redact_args = |args: &[&str]| -> Vec<String> {
+            match self.config.mode {
+                RedactionMode::Strip => Vec::new(),
+                RedactionMode::Hash => args.iter().map(|arg| self.replacement(arg)).collect(),
+            }
+        };
+        match value {
+            Value::String(line) => {
+                let mut parts = line.split_whitespace();
+                let program = parts.next().map(|p| self.scrub(p)).unwrap_or_default();
+                let args: Vec<&str> = parts.collect();
+                let mut out = vec![program];
+                out.extend(redact_args(&args));
+                Value::String(out.join(" "))
+            }
+            Value::Array(items) => {
+                let strings: Vec<&str> = items.iter().filter_map(Value::as_str).collect();
+                let Some((program, args)) = strings.split_first() else {
+                    return value.clone();
+                };
+                let mut out = vec![Value::String(self.scrub(program))];
+                out.extend(redact_args(args).into_iter().map(Value::String));
+                Value::Array(out)
+            }
+            other => self.value(other, ""),
+        }
+    }
+
+    fn value(&self, value: &Value, field: &str) -> Value {
+        if !field.is_empty() && self.config.fields.iter().any(|f| f == field) {
+            return match value {
+                Value::Null => Value::Null,
+                Value::String(s) => Value::String(self.replacement(s)),
+                other => Value::String(self.replacement(&other.to_string())),
+            };
+        }
+        if self.config.cmdline_args && CMDLINE_FIELDS.contains(&field) {
+            return self.command_line(value);
+        }
+        match value {
+            Value::String(s) => Value::String(self.scrub(s)),
+            Value::Array(items) => Value::Array(items.iter().map(|v| self.value(v, field)).collect()),
+            Value::Object(fields) => Value::Object(fields.iter().map(|(k, v)| (k.clone(), self.value(v, k))).collect()),
+            other => other.clone(),
+        }
+    }
+
+    /// Redacted copy of the JSON text `body` (scrubbed as plain text if it is not JSON)
+    pub fn body(&self, body: &str) -> String {
+        match serde_json::from_str::<Value>(body) {
+            Ok(value) => self.value(&value, "").to_string(),
+            Err(_) => self.scrub(body),
+        }
+    }
+}
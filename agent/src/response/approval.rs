@@ -0,0 +1,427 @@
+//! User approval (`response.approval`): on BYOD and developer machines the policy doesn't act
+//! silently. Before it acts on a result below critical, the user at the desktop is asked, with
+//! the targets named, and has `timeout_secs` to answer: through a notification with Allow / Deny
+//! buttons (freedesktop notification service, Linux), a dialog (macOS), or a message box
+//! (Windows), or through `command`. The dialog is shown to the user logged in at the console
+//! (Linux: the active session of `seat0`; macOS: the owner of `/dev/console`; Windows: the active
+//! console session). An agent running as root or as a service starts `dadm-agent prompt` in that
+//! session, as that user; one running as the console user asks itself. Without an answer in time
+//! (a notification dismissed counts as none), or when the user can't be asked, `default`
+//! applies. Questions are asked one at a time on their own thread ([`Asker`]), so analysis
+//! doesn't wait for the user. Critical results, notifications, and server commands aren't asked
+//! about. Each question and its answer is logged and audited (`response_approval`).
+
+use crate::commands::CommandStatus;
+use crate::config::{ApprovalConfig, ApprovalDefault};
+use crate::logging::audit;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::Serialize;
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const TITLE: &str = "DADM Agent: allow threat response?";
+/// Time a dialog program gets past the timeout to report it
+const GRACE: Duration = Duration::from_secs(5);
+const POLL: Duration = Duration::from_millis(100);
+/// Exit status of `dadm-agent prompt` for each answer; any other is a failure
+const PROMPT_ALLOWED: i32 = 0;
+const PROMPT_DENIED: i32 = 1;
+const PROMPT_TIMED_OUT: i32 = 2;
+const PROMPT_FAILED: i32 = 3;
+
+/// What the user said
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Answer {
+    Allowed,
+    Denied,
+    /// No answer within `timeout_secs`
+    TimedOut,
+    /// The user couldn't be asked
+    Unavailable,
+}
+
+/// Outcome of asking the user
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalOutcome {
+    /// `approval`
+    pub action: &'static str,
+    /// The actions asked about
+    pub actions: Vec<String>,
+    pub answer: Answer,
+    pub trigger: String,
+    /// `done` when the actions may be taken, else `refused`
+    pub status: CommandStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A question waiting for its turn
+struct Question {
+    actions: Vec<String>,
+    reason: String,
+    trigger: String,
+    reply: Sender<ApprovalOutcome>,
+}
+
+/// Asks questions one at a time on its own thread, so the user sees one dialog at a time and
+/// the caller doesn't wait for the answer
+pub struct Asker {
+    questions: Mutex<Sender<Question>>,
+}
+
+impl Asker {
+    pub fn start(config: &ApprovalConfig) -> Self {
+        let (tx, rx) = mpsc::channel::<Question>();
+        let config = config.clone();
+        std::thread::Builder::new()
+            .name("dadm-approval".into())
+            .spawn(move || {
+                for q in rx {
+                    let _ = q.reply.send(ask(&config, q.actions, &q.reason, &q.trigger));
+                }
+            })
+            .expect("spawn approval thread");
+        Self { questions: Mutex::new(tx) }
+    }
+
+    /// Ask about `actions` (see [`ask`]) after the questions before; the outcome arrives on the
+    /// returned channel
+    pub fn ask(&self, actions: Vec<String>, reason: &str, trigger: &str) -> Receiver<ApprovalOutcome> {
+        let (reply, answer) = mpsc::channel();
+        let question = Question { actions, reason: reason.to_string(), trigger: trigger.to_string(), reply };
+        let _ = self.questions.lock().unwrap().send(question);
+        answer
+    }
+}
+
+/// Ask the user whether to take `actions`, described as in a list, because of `reason`
+pub fn ask(config: &ApprovalConfig, actions: Vec<String>, reason: &str, trigger: &str) -> ApprovalOutcome {
+    let fallback = match config.default {
+        ApprovalDefault::Allow => "go ahead",
+        ApprovalDefault::Deny => "not act",
+    };
+    let text = format!(
+        "{}\n\nThe agent wants to:\n- {}\n\nWithout an answer within {}s it will {}.",
+        reason,
+        actions.join("\n- "),
+        config.timeout_secs,
+        fallback
+    );
+    let answer = match question(config, &text, Duration::from_secs(config.timeout_secs)) {
+        Ok(Some(true)) => Answer::Allowed,
+        Ok(Some(false)) => Answer::Denied,
+        Ok(None) => Answer::TimedOut,
+        Err(e) => {
+            tracing::warn!(error = %e, "couldn't ask the user to allow the response");
+            Answer::Unavailable
+        }
+    };
+    let allowed = match answer {
+        Answer::Allowed => true,
+        Answer::Denied => false,
+        Answer::TimedOut | Answer::Unavailable => config.default == ApprovalDefault::Allow,
+    };
+    let error = match answer {
+        _ if allowed => None,
+        Answer::Denied => Some("denied by the user".to_string()),
+        Answer::TimedOut => Some(format!("no answer within {}s (response.approval.default is deny)", config.timeout_secs)),
+        _ => Some("the user couldn't be asked (response.approval.default is deny)".to_string()),
+    };
+    let status = if error.is_none() { CommandStatus::Done } else { CommandStatus::Refused };
+    let outcome = ApprovalOutcome { action: "approval", actions, answer, trigger: trigger.to_string(), status, error };
+    tracing::warn!(trigger, answer = ?outcome.answer, status = status.as_str(), actions = ?outcome.actions, "response approval");
+    audit::record("response_approval", serde_json::to_value(&outcome).unwrap_or_default());
+    outcome
+}
+
+/// The user's answer (`true` allows), `None` without one within `limit`
+fn question(config: &ApprovalConfig, text: &str, limit: Duration) -> Result<Option<bool>, String> {
+    match config.command.split_first() {
+        Some((program, args)) => {
+            let mut command = Command::new(program);
+            command.args(args).args([TITLE, text]).stderr(Stdio::null());
+            let output = run(command, limit).map_err(|e| format!("{}: {}", program, e))?;
+            Ok(output.map(|o| o.status.success()))
+        }
+        None => desktop(text, limit),
+    }
+}
+
+/// `dadm-agent prompt`: ask at this desktop for an agent outside the user's session, and return
+/// the exit status. `text` is base64, so it reaches the program intact on any platform.
+pub fn prompt(text: &str, secs: u64) -> i32 {
+    let Some(text) = BASE64.decode(text).ok().and_then(|t| String::from_utf8(t).ok()) else {
+        eprintln!("the question isn't base64 UTF-8");
+        return PROMPT_FAILED;
+    };
+    match dialog(&text, Duration::from_secs(secs)) {
+        Ok(Some(true)) => PROMPT_ALLOWED,
+        Ok(Some(false)) => PROMPT_DENIED,
+        Ok(None) => PROMPT_TIMED_OUT,
+        Err(e) => {
+            eprintln!("{}", e);
+            PROMPT_FAILED
+        }
+    }
+}
+
+/// Arguments of `dadm-agent prompt` asking `text` within `limit`
+fn prompt_args(text: &str, limit: Duration) -> Vec<String> {
+    vec!["prompt".into(), "--secs".into(), limit.as_secs().to_string(), BASE64.encode(text)]
+}
+
+/// The answer `dadm-agent prompt` exited with
+fn prompt_answer(code: Option<i32>, stderr: &[u8]) -> Result<Option<bool>, String> {
+    match code {
+        Some(PROMPT_ALLOWED) => Ok(Some(true)),
+        Some(PROMPT_DENIED) => Ok(Some(false)),
+        Some(PROMPT_TIMED_OUT) => Ok(None),
+        code => Err(format!("prompt in the console session failed (exit code {:?}): {}", code, String::from_utf8_lossy(stderr).trim())),
+    }
+}
+
+/// Ask the user at the console: in this process when it runs as them, else through
+/// `dadm-agent prompt` started in their session
+#[cfg(unix)]
+fn desktop(text: &str, limit: Duration) -> Result<Option<bool>, String> {
+    let Some(mut command) = console_command(&prompt_args(text, limit))? else {
+        return dialog(text, limit);
+    };
+    command.stderr(Stdio::piped());
+    match run(command, limit + GRACE).map_err(|e| format!("prompt: {}", e))? {
+        Some(output) => prompt_answer(output.status.code(), &output.stderr),
+        None => Ok(None),
+    }
+}
+
+/// `dadm-agent` with `args`, run as the console user `uid` in their session; `None` when this
+/// process is that user already
+#[cfg(unix)]
+fn console_command(args: &[String]) -> Result<Option<Command>, String> {
+    let uid = console_uid()?;
+    // SAFETY: no arguments
+    let euid = unsafe { libc::geteuid() };
+    if uid == euid {
+        return Ok(None);
+    }
+    if euid != 0 {
+        return Err(format!("only an agent running as root can ask the console user (uid {})", uid));
+    }
+    let (name, gid, home) = account(uid)?;
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    Ok(Some(session_command(uid, gid, &name, &home, exe, args)))
+}
+
+/// User of the active session on `seat0` (logind)
+#[cfg(all(unix, not(target_os = "macos")))]
+fn console_uid() -> Result<u32, String> {
+    let loginctl = |args: &[&str]| -> Result<String, String> {
+        let output = Command::new("loginctl").args(args).output().map_err(|e| format!("loginctl: {}", e))?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let session = loginctl(&["show-seat", "seat0", "--property=ActiveSession", "--value"])?;
+    if session.is_empty() {
+        return Err("no one is logged in at the console".to_string());
+    }
+    let user = loginctl(&["show-session", &session, "--property=User", "--value"])?;
+    user.parse().map_err(|_| format!("no user for console session {}", session))
+}
+
+/// Owner of `/dev/console`; root while the login window shows
+#[cfg(target_os = "macos")]
+fn console_uid() -> Result<u32, String> {
+    use std::os::unix::fs::MetadataExt;
+    let uid = std::fs::metadata("/dev/console").map_err(|e| format!("/dev/console: {}", e))?.uid();
+    match uid {
+        0 => Err("no one is logged in at the console".to_string()),
+        uid => Ok(uid),
+    }
+}
+
+/// Run as `uid` with the session bus of their login session
+#[cfg(all(unix, not(target_os = "macos")))]
+fn session_command(uid: u32, gid: u32, name: &str, home: &str, exe: std::path::PathBuf, args: &[String]) -> Command {
+    use std::os::unix::process::CommandExt;
+    let runtime = format!("/run/user/{}", uid);
+    let mut command = Command::new(exe);
+    command
+        .args(args)
+        .uid(uid)
+        .gid(gid)
+        .env_clear()
+        .env("HOME", home)
+        .env("USER", name)
+        .env("DBUS_SESSION_BUS_ADDRESS", format!("unix:path={}/bus", runtime))
+        .env("XDG_RUNTIME_DIR", runtime);
+    command
+}
+
+/// Run as `name` in their GUI (Aqua) session
+#[cfg(target_os = "macos")]
+fn session_command(uid: u32, _gid: u32, name: &str, _home: &str, exe: std::path::PathBuf, args: &[String]) -> Command {
+    let mut command = Command::new("launchctl");
+    command.args(["asuser", &uid.to_string(), "sudo", "-H", "-u", name]).arg(exe).args(args);
+    command
+}
+
+/// Name, primary group, and home directory of the account `uid`
+#[cfg(unix)]
+fn account(uid: u32) -> Result<(String, u32, String), String> {
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwuid_r(uid, &mut entry, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if rc != 0 || found.is_null() {
+        return Err(format!("no account for uid {}", uid));
+    }
+    // SAFETY: the entry's strings point into `buf`, which is still alive
+    let text = |p: *const libc::c_char| unsafe { std::ffi::CStr::from_ptr(p) }.to_string_lossy().into_owned();
+    Ok((text(entry.pw_name), entry.pw_gid, text(entry.pw_dir)))
+}
+
+/// Ask the user at the console: in this process when it runs in their session, else through
+/// `dadm-agent prompt` started there with their token (which takes a service running as
+/// LocalSystem)
+#[cfg(windows)]
+fn desktop(text: &str, limit: Duration) -> Result<Option<bool>, String> {
+    use windows::core::{PCWSTR, PWSTR};
+    use windows::Win32::Foundation::{CloseHandle, HANDLE, WAIT_TIMEOUT};
+    use windows::Win32::System::RemoteDesktop::{ProcessIdToSessionId, WTSGetActiveConsoleSessionId, WTSQueryUserToken};
+    use windows::Win32::System::Threading::{
+        CreateProcessAsUserW, GetExitCodeProcess, TerminateProcess, WaitForSingleObject, CREATE_NO_WINDOW, PROCESS_INFORMATION, STARTUPINFOW,
+    };
+    // SAFETY: no arguments
+    let console = unsafe { WTSGetActiveConsoleSessionId() };
+    if console == u32::MAX {
+        return Err("no one is logged in at the console".to_string());
+    }
+    let mut own = 0u32;
+    // SAFETY: `own` outlives the call
+    unsafe { ProcessIdToSessionId(std::process::id(), &mut own) }.map_err(|e| e.to_string())?;
+    if own == console {
+        return dialog(text, limit);
+    }
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    // The arguments are digits, words, and base64: nothing to quote
+    let line = std::iter::once(format!("\"{}\"", exe.display())).chain(prompt_args(text, limit)).collect::<Vec<_>>().join(" ");
+    let mut line: Vec<u16> = line.encode_utf16().chain(Some(0)).collect();
+    let mut desktop: Vec<u16> = "winsta0\\default".encode_utf16().chain(Some(0)).collect();
+    // SAFETY: the buffers outlive the calls; every handle obtained is closed
+    unsafe {
+        let mut token = HANDLE::default();
+        WTSQueryUserToken(console, &mut token).map_err(|e| format!("the console user's token: {}", e))?;
+        let startup = STARTUPINFOW {
+            cb: std::mem::size_of::<STARTUPINFOW>() as u32,
+            lpDesktop: PWSTR(desktop.as_mut_ptr()),
+            ..Default::default()
+        };
+        let mut info = PROCESS_INFORMATION::default();
+        let created = CreateProcessAsUserW(
+            token,
+            PCWSTR::null(),
+            PWSTR(line.as_mut_ptr()),
+            None,
+            None,
+            false,
+            CREATE_NO_WINDOW,
+            None,
+            PCWSTR::null(),
+            &startup,
+            &mut info,
+        );
+        let _ = CloseHandle(token);
+        created.map_err(|e| format!("prompt in the console session: {}", e))?;
+        let _ = CloseHandle(info.hThread);
+        let millis = u32::try_from((limit + GRACE).as_millis()).unwrap_or(u32::MAX);
+        let answer = if WaitForSingleObject(info.hProcess, millis) == WAIT_TIMEOUT {
+            let _ = TerminateProcess(info.hProcess, PROMPT_TIMED_OUT as u32);
+            Ok(None)
+        } else {
+            let mut code = 0u32;
+            match GetExitCodeProcess(info.hProcess, &mut code) {
+                Ok(()) => prompt_answer(Some(code as i32), &[]),
+                Err(e) => Err(format!("prompt in the console session: {}", e)),
+            }
+        };
+        let _ = CloseHandle(info.hProcess);
+        answer
+    }
+}
+
+/// Output of `command`, `None` if it didn't exit within `limit` (it is killed)
+fn run(mut command: Command, limit: Duration) -> std::io::Result<Option<Output>> {
+    let mut child = command.stdin(Stdio::null()).stdout(Stdio::piped()).spawn()?;
+    let deadline = Instant::now() + limit;
+    loop {
+        if child.try_wait()?.is_some() {
+            return child.wait_with_output().map(Some);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(POLL);
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn dialog(text: &str, limit: Duration) -> Result<Option<bool>, String> {
+    let handle = notify_rust::Notification::new()
+        .appname("DADM Agent")
+        .summary(TITLE)
+        .body(text)
+        .action("allow", "Allow")
+        .action("deny", "Deny")
+        .urgency(notify_rust::Urgency::Critical)
+        .timeout(limit)
+        .show()
+        .map_err(|e| e.to_string())?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    // Waits until the notification is closed, which a server ignoring the timeout may never do
+    std::thread::spawn(move || handle.wait_for_action(|action| drop(tx.send(action.to_string()))));
+    Ok(match rx.recv_timeout(limit).as_deref() {
+        Ok("allow") => Some(true),
+        Ok("deny") => Some(false),
+        _ => None,
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn dialog(text: &str, limit: Duration) -> Result<Option<bool>, String> {
+    let script = r#"display dialog (item 2 of argv) with title (item 1 of argv) buttons {"Deny", "Allow"} default button "Deny" giving up after (item 3 of argv as integer)"#;
+    let mut command = Command::new("osascript");
+    command.args(["-e", "on run argv", "-e", script, "-e", "end run", TITLE, text, &limit.as_secs().to_string()]);
+    let Some(output) = run(command, limit + GRACE).map_err(|e| format!("osascript: {}", e))? else {
+        return Ok(None);
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.contains("gave up:true") {
+        return Ok(None);
+    }
+    Ok(Some(output.status.success() && stdout.contains("button returned:Allow")))
+}
+
+#[cfg(windows)]
+fn dialog(text: &str, limit: Duration) -> Result<Option<bool>, String> {
+    // Buttons Yes / No (4) and a warning icon (48); returns 6 (Yes), 7 (No), or -1 (timed out)
+    let script = "exit (New-Object -ComObject WScript.Shell).Popup($env:DADM_TEXT, [int]$env:DADM_SECS, $env:DADM_TITLE, 4 + 48)";
+    let mut command = Command::new("powershell");
+    command
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .env("DADM_TEXT", format!("{}\n\nAllow?", text))
+        .env("DADM_TITLE", TITLE)
+        .env("DADM_SECS", limit.as_secs().to_string());
+    let Some(output) = run(command, limit + GRACE).map_err(|e| format!("powershell: {}", e))? else {
+        return Ok(None);
+    };
+    match output.status.code() {
+        Some(6) => Ok(Some(true)),
+        Some(7) => Ok(Some(false)),
+        Some(-1) => Ok(None),
+        code => Err(format!("message box failed (exit code {:?})", code)),
+    }
+}
@@ -0,0 +1,760 @@
+//! Network blocks (`block_network`): host firewall rules that drop traffic to and from a remote
+//! address, optionally only on one port, or all traffic of a process, until they expire. On
+//! Linux the rules go to nftables (a table `dadm_<id>` per block) or iptables (rules commented
+//! `dadm-<id>`), on macOS to the pf anchor `dadm/<id>` (the main ruleset must load
+//! `anchor "dadm/*"`), and on Windows to Windows Firewall rules named `dadm-<id>`, which the
+//! Windows Filtering Platform enforces. A process is blocked on Linux by moving it (and what it
+//! starts) into a cgroup of its own, `dadm-blocked/<id>`, that the rules match, and on Windows
+//! by its executable; pf can't match processes.
+//!
+//! Blocks are kept in `data_dir` ([`BLOCKS_FILE`]): at startup the ones still running are put
+//! back in place, as rules don't survive a reboot, and the expired ones are lifted, even if they
+//! expired while the agent was stopped. Loopback, the uplink endpoints, and
+//! `response.firewall.protected_addresses` are never blocked; processes go through the safety
+//! lists of the process actions. Every block and unblock, made, refused, or failed, is logged
+//! and audited (`network_block`, `network_unblock`).
+//!
+//! Host isolation, which blocks everything but the uplink, goes through the same firewall; see
+//! [`super::isolation`].
+
+use super::isolation::{Isolation, ISOLATION_FILE};
+use super::{disabled, lookup, refusal, ResponseAction};
+use crate::commands::CommandStatus;
+use crate::config::{FirewallBackend, ResponseConfig};
+use crate::logging::audit;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Blocks in place, under `data_dir`
+pub const BLOCKS_FILE: &str = "firewall_blocks.json";
+/// How often the daemon lifts expired blocks
+pub const EXPIRY_CHECK: Duration = Duration::from_secs(5);
+
+/// What a block drops
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BlockTarget {
+    /// Traffic to and from `ip`; with `port`, only TCP and UDP on that remote port
+    Remote { ip: IpAddr, port: Option<u16> },
+    /// All traffic of a process
+    Process { pid: u32, name: String, exe: Option<String> },
+}
+
+/// A block in place
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub id: String,
+    pub target: BlockTarget,
+    pub backend: FirewallBackend,
+    /// What asked for it: `command:<id>` or `risk:<event id>`
+    pub trigger: String,
+    /// When it was put in place and when it is lifted (ms)
+    pub created_at: i64,
+    pub expires_at: i64,
+    /// Cgroup the blocked process was in before (Linux)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<String>,
+}
+
+/// Program run against the firewall
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub program: &'static str,
+    pub args: Vec<String>,
+    /// Written to its standard input
+    pub stdin: Option<String>,
+}
+
+pub(super) fn step<S: Into<String>>(program: &'static str, args: impl IntoIterator<Item = S>, stdin: Option<String>) -> Step {
+    Step { program, args: args.into_iter().map(Into::into).collect(), stdin }
+}
+
+/// Cgroup (relative to the cgroup root) a process blocked by `id` is moved into
+pub fn cgroup_path(id: &str) -> String {
+    format!("dadm-blocked/{}", id)
+}
+
+impl Block {
+    /// Rule, anchor, or table name of the block
+    pub fn name(&self) -> String {
+        format!("dadm-{}", self.id)
+    }
+
+    /// The process is moved into a cgroup the rules match
+    fn uses_cgroup(&self) -> bool {
+        matches!(self.target, BlockTarget::Process { .. }) && matches!(self.backend, FirewallBackend::Nftables | FirewallBackend::Iptables)
+    }
+
+    /// Programs that put the block in place; a target the backend can't match is an error
+    pub fn apply_steps(&self) -> Result<Vec<Step>, String> {
+        match self.backend {
+            FirewallBackend::Nftables => {
+                let (output, input) = match &self.target {
+                    BlockTarget::Remote { ip, port } => {
+                        let family = if ip.is_ipv4() { "ip" } else { "ip6" };
+                        let on_port = |field: &str| port.map_or(String::new(), |port| format!(" meta l4proto {{ tcp, udp }} th {} {}", field, port));
+                        (
+                            format!("{} daddr {}{} drop", family, ip, on_port("dport")),
+                            format!("{} saddr {}{} drop", family, ip, on_port("sport")),
+                        )
+                    }
+                    BlockTarget::Process { .. } => {
+                        let rule = format!("socket cgroupv2 level 2 \"{}\" drop", cgroup_path(&self.id));
+                        (rule.clone(), rule)
+                    }
+                };
+                // Declared, deleted, and defined again in one transaction, so applying twice
+                // leaves one copy
+                let table = format!("inet dadm_{}", self.id);
+                let script = format!(
+                    "table {table}\ndelete table {table}\ntable {table} {{\n\
+                     \tchain output {{\n\t\ttype filter hook output priority 0; policy accept;\n\t\t{output}\n\t}}\n\
+                     \tchain input {{\n\t\ttype filter hook input priority 0; policy accept;\n\t\t{input}\n\t}}\n}}\n",
+                    table = table,
+                    output = output,
+                    input = input,
+                );
+                Ok(vec![step("nft", ["-f", "-"], Some(script))])
+            }
+            FirewallBackend::Iptables => Ok(self.iptables("-I")),
+            FirewallBackend::Pf => {
+                let rules = match &self.target {
+                    BlockTarget::Remote { ip, port: None } => format!("block drop quick out to {}\nblock drop quick in from {}\n", ip, ip),
+                    BlockTarget::Remote { ip, port: Some(port) } => format!(
+                        "block drop quick out proto {{ tcp udp }} to {} port {}\nblock drop quick in proto {{ tcp udp }} from {} port {}\n",
+                        ip, port, ip, port
+                    ),
+                    BlockTarget::Process { .. } => return Err("pf can't block a process's traffic".into()),
+                };
+                Ok(vec![step("pfctl", ["-a".to_string(), format!("dadm/{}", self.id), "-f".into(), "-".into()], Some(rules))])
+            }
+            FirewallBackend::Wfp => {
+                let matches: Vec<Vec<String>> = match &self.target {
+                    BlockTarget::Remote { ip, port: None } => vec![vec![format!("remoteip={}", ip), "protocol=any".into()]],
+                    BlockTarget::Remote { ip, port: Some(port) } => ["TCP", "UDP"]
+                        .iter()
+                        .map(|protocol| vec![format!("remoteip={}", ip), format!("protocol={}", protocol), format!("remoteport={}", port)])
+                        .collect(),
+                    BlockTarget::Process { exe: Some(exe), .. } => vec![vec![format!("program={}", exe)]],
+                    BlockTarget::Process { exe: None, .. } => return Err("the process's executable is unknown".into()),
+                };
+                let name = format!("name={}", self.name());
+                let mut steps = Vec::new();
+                for dir in ["out", "in"] {
+                    for m in &matches {
+                        let rule = ["advfirewall", "firewall", "add", "rule", &name, &format!("dir={}", dir), "action=block"];
+                        steps.push(step("netsh", rule.iter().map(|a| a.to_string()).chain(m.iter().cloned()), None));
+                    }
+                }
+                Ok(steps)
+            }
+            FirewallBackend::Auto => Err("no firewall backend".into()),
+        }
+    }
+
+    /// Programs that lift the block
+    pub fn remove_steps(&self) -> Vec<Step> {
+        match self.backend {
+            FirewallBackend::Nftables => vec![step("nft", ["delete".to_string(), "table".into(), "inet".into(), format!("dadm_{}", self.id)], None)],
+            FirewallBackend::Iptables => self.iptables("-D"),
+            FirewallBackend::Pf => vec![step("pfctl", ["-a".to_string(), format!("dadm/{}", self.id), "-F".into(), "rules".into()], None)],
+            FirewallBackend::Wfp => vec![step(
+                "netsh",
+                ["advfirewall".to_string(), "firewall".into(), "delete".into(), "rule".into(), format!("name={}", self.name())],
+                None,
+            )],
+            FirewallBackend::Auto => Vec::new(),
+        }
+    }
+
+    /// iptables rules of the block, inserted (`-I`) or deleted (`-D`)
+    fn iptables(&self, op: &str) -> Vec<Step> {
+        let mut rules: Vec<(&'static str, &str, Vec<String>)> = Vec::new();
+        match &self.target {
+            BlockTarget::Remote { ip, port } => {
+                let program = if ip.is_ipv4() { "iptables" } else { "ip6tables" };
+                let protocols: &[Option<&str>] = if port.is_some() { &[Some("tcp"), Some("udp")] } else { &[None] };
+                for protocol in protocols {
+                    for (chain, address, port_flag) in [("OUTPUT", "-d", "--dport"), ("INPUT", "-s", "--sport")] {
+                        let mut matched = vec![address.to_string(), ip.to_string()];
+                        if let (Some(protocol), Some(port)) = (protocol, port) {
+                            matched.extend(["-p".to_string(), protocol.to_string(), port_flag.to_string(), port.to_string()]);
+                        }
+                        rules.push((program, chain, matched));
+                    }
+                }
+            }
+            BlockTarget::Process { .. } => {
+                for program in ["iptables", "ip6tables"] {
+                    for chain in ["OUTPUT", "INPUT"] {
+                        rules.push((program, chain, vec!["-m".into(), "cgroup".into(), "--path".into(), cgroup_path(&self.id)]));
+                    }
+                }
+            }
+        }
+        let name = self.name();
+        rules
+            .into_iter()
+            .map(|(program, chain, matched)| {
+                let args = ["-w", op, chain]
+                    .into_iter()
+                    .map(String::from)
+                    .chain(matched)
+                    .chain(["-m", "comment", "--comment", &name, "-j", "DROP"].into_iter().map(String::from));
+                step(program, args, None)
+            })
+            .collect()
+    }
+}
+
+/// Outcome of a block, unblock, isolation, or release
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockOutcome {
+    /// `block_network`, `unblock_network`, `isolate`, or `release`
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<BlockTarget>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<FirewallBackend>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    /// Addresses still reachable while isolated, besides loopback
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<IpAddr>,
+    /// What asked for it: `command:<id>`, `risk:<event id>`, `expired`, or `operator` (a local
+    /// release)
+    pub trigger: String,
+    pub status: CommandStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Only logged (`dry_run`)
+    pub dry_run: bool,
+}
+
+impl BlockOutcome {
+    pub(super) fn new(action: &'static str, trigger: &str, backend: Option<FirewallBackend>, dry_run: bool) -> Self {
+        Self {
+            action,
+            id: None,
+            target: None,
+            backend,
+            expires_at: None,
+            allowed: Vec::new(),
+            trigger: trigger.to_string(),
+            status: CommandStatus::Done,
+            error: None,
+            dry_run,
+        }
+    }
+
+    pub(super) fn record(&self) {
+        let event = match self.action {
+            "block_network" => "network_block",
+            "unblock_network" => "network_unblock",
+            "isolate" => "host_isolated",
+            _ => "host_released",
+        };
+        match (self.status, self.dry_run) {
+            (CommandStatus::Done, true) => tracing::info!(action = self.action, id = ?self.id, target = ?self.target, trigger = %self.trigger, "dry run: would have changed the firewall"),
+            (CommandStatus::Done, false) => tracing::warn!(action = self.action, id = ?self.id, target = ?self.target, trigger = %self.trigger, "firewall changed"),
+            _ => tracing::warn!(action = self.action, id = ?self.id, target = ?self.target, trigger = %self.trigger, error = ?self.error, "firewall not changed"),
+        }
+        audit::record(event, serde_json::to_value(self).unwrap_or_default());
+    }
+}
+
+/// Firewall a backend of `configured` uses on this host
+fn detect(configured: FirewallBackend) -> Option<FirewallBackend> {
+    match configured {
+        FirewallBackend::Auto if cfg!(target_os = "linux") => {
+            [("nft", FirewallBackend::Nftables), ("iptables", FirewallBackend::Iptables)]
+                .into_iter()
+                .find(|(program, _)| installed(program))
+                .map(|(_, backend)| backend)
+        }
+        FirewallBackend::Auto if cfg!(target_os = "macos") => Some(FirewallBackend::Pf),
+        FirewallBackend::Auto if cfg!(windows) => Some(FirewallBackend::Wfp),
+        FirewallBackend::Auto => None,
+        backend => Some(backend),
+    }
+}
+
+fn installed(program: &str) -> bool {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(["/usr/sbin", "/sbin"].into_iter().map(PathBuf::from))
+        .any(|dir| dir.join(program).is_file())
+}
+
+/// Addresses isolation lets through for the uplink, and the host names pinned to them
+pub type UplinkAddresses = (Vec<IpAddr>, BTreeMap<String, Vec<IpAddr>>);
+
+/// Host of the uplink URL `endpoint` (an endpoint or the proxy) and the addresses it resolves to
+fn resolve_url(endpoint: &str) -> Option<(String, Vec<IpAddr>)> {
+    use std::net::ToSocketAddrs;
+    let url = reqwest::Url::parse(endpoint).ok()?;
+    let (host, port) = (url.host_str()?, url.port_or_known_default()?);
+    let host = host.trim_matches(|c| c == '[' || c == ']');
+    let addresses = (host, port).to_socket_addrs().map(|addrs| addrs.map(|a| a.ip()).collect()).unwrap_or_default();
+    Some((host.to_string(), addresses))
+}
+
+/// Addresses the uplink `endpoint` resolves to
+fn endpoint_addresses(endpoint: &str) -> Vec<IpAddr> {
+    resolve_url(endpoint).map(|(_, addresses)| addresses).unwrap_or_default()
+}
+
+pub(super) fn run(step: &Step) -> Result<(), String> {
+    use std::io::Write;
+    use std::process::Stdio;
+    let failed = |e: std::io::Error| format!("{}: {}", step.program, e);
+    let mut child = std::process::Command::new(step.program)
+        .args(&step.args)
+        .stdin(if step.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(failed)?;
+    if let (Some(input), Some(mut stdin)) = (&step.stdin, child.stdin.take()) {
+        stdin.write_all(input.as_bytes()).map_err(failed)?;
+    }
+    let output = child.wait_with_output().map_err(failed)?;
+    if !output.status.success() {
+        return Err(format!("{} {} failed: {}", step.program, step.args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod cgroup {
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    const ROOT: &str = "/sys/fs/cgroup";
+
+    fn dir(path: &str) -> PathBuf {
+        Path::new(ROOT).join(path.trim_start_matches('/'))
+    }
+
+    /// Move `pid` into the cgroup `path`; returns the cgroup it was in
+    pub fn enter(pid: u32, path: &str) -> io::Result<String> {
+        let cgroups = std::fs::read_to_string(format!("/proc/{}/cgroup", pid))?;
+        let current = cgroups
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "the process is not in a cgroup v2 hierarchy"))?;
+        let dir = dir(path);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("cgroup.procs"), pid.to_string())?;
+        Ok(current.to_string())
+    }
+
+    /// Move every process in the cgroup `path` back to `original` (the root cgroup if it is
+    /// gone) and remove it
+    pub fn leave(path: &str, original: &str) -> io::Result<()> {
+        let dir = dir(path);
+        let procs = match std::fs::read_to_string(dir.join("cgroup.procs")) {
+            Ok(procs) => procs,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let back = Some(self::dir(original)).filter(|d| d.join("cgroup.procs").exists()).unwrap_or_else(|| PathBuf::from(ROOT));
+        for pid in procs.lines() {
+            // Processes that exited meanwhile can't be moved
+            let _ = std::fs::write(back.join("cgroup.procs"), pid);
+        }
+        std::fs::remove_dir(&dir)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod cgroup {
+    use std::io;
+
+    pub fn enter(_pid: u32, _path: &str) -> io::Result<String> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "cgroups are Linux only"))
+    }
+
+    pub fn leave(_path: &str, _original: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Put `block` in place, moving its process into its cgroup first when the rules match one
+fn put(block: &mut Block) -> Result<(), String> {
+    let steps = block.apply_steps()?;
+    if let (true, BlockTarget::Process { pid, .. }) = (block.uses_cgroup(), &block.target) {
+        let original = cgroup::enter(*pid, &cgroup_path(&block.id)).map_err(|e| format!("cgroup: {}", e))?;
+        block.cgroup.get_or_insert(original);
+    }
+    if let Err(e) = steps.iter().try_for_each(run) {
+        let _ = take_down(block);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Lift `block`: every step is run, and the first error returned
+fn take_down(block: &Block) -> Result<(), String> {
+    let mut outcome = Ok(());
+    for step in block.remove_steps() {
+        if let Err(e) = run(&step) {
+            outcome = outcome.and(Err(e));
+        }
+    }
+    if let (true, Some(original)) = (block.uses_cgroup(), &block.cgroup) {
+        if let Err(e) = cgroup::leave(&cgroup_path(&block.id), original) {
+            outcome = outcome.and(Err(format!("cgroup: {}", e)));
+        }
+    }
+    outcome
+}
+
+/// Network blocks and host isolation of the agent
+pub struct Firewall {
+    config: ResponseConfig,
+    /// Firewall blocks go to; none on a host without a supported one
+    backend: Option<FirewallBackend>,
+    /// Addresses never blocked: the uplink endpoints' and `response.firewall.protected_addresses`
+    protected: Vec<IpAddr>,
+    /// Uplink endpoints, resolved again when isolating
+    endpoints: Vec<String>,
+    /// `uplink.proxy`, resolved again when isolating
+    proxy: Option<String>,
+    path: PathBuf,
+    isolation_path: PathBuf,
+    blocks: Mutex<Vec<Block>>,
+    dry_run: bool,
+}
+
+impl Firewall {
+    /// Blocks with `config`, kept in `data_dir`, never of the uplink `endpoints`; the blocks kept
+    /// from an earlier run are put back in place or, when expired, lifted, and so is an isolation
+    pub fn open(config: &ResponseConfig, data_dir: &Path, endpoints: &[String], dry_run: bool) -> Self {
+        let mut protected = config.firewall.protected_addresses.clone();
+        protected.extend(endpoints.iter().flat_map(|e| endpoint_addresses(e)));
+        let firewall = Self {
+            config: config.clone(),
+            backend: detect(config.firewall.backend),
+            protected,
+            endpoints: endpoints.to_vec(),
+            proxy: None,
+            path: data_dir.join(BLOCKS_FILE),
+            isolation_path: data_dir.join(ISOLATION_FILE),
+            blocks: Mutex::new(Vec::new()),
+            dry_run: dry_run || config.dry_run,
+        };
+        let kept: Vec<Block> = match std::fs::read(&firewall.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                tracing::warn!(error = %e, "firewall blocks unreadable; not restored");
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::warn!(error = %e, "firewall blocks unreadable; not restored");
+                Vec::new()
+            }
+        };
+        if !kept.is_empty() {
+            firewall.restore(kept, chrono::Utc::now().timestamp_millis());
+        }
+        if let Some(isolation) = firewall.isolation() {
+            // Taken down first, as rules that survived (no reboot) would be doubled
+            let _ = isolation.take_down();
+            match isolation.put() {
+                Ok(()) => tracing::warn!(trigger = %isolation.trigger, allowed = ?isolation.allowed, "host isolation restored"),
+                Err(e) => tracing::error!(error = %e, "host isolation not restored"),
+            }
+        }
+        firewall
+    }
+
+    /// Never block the uplink `proxy` either, and keep it reachable while isolated
+    pub fn with_proxy(mut self, proxy: Option<&str>) -> Self {
+        self.protected.extend(proxy.map(endpoint_addresses).unwrap_or_default());
+        self.proxy = proxy.map(str::to_string);
+        self
+    }
+
+    /// What isolation must leave reachable for the uplink, resolved now: the addresses (endpoints
+    /// or, with a proxy, the proxy; the DNS resolvers; the protected addresses), and the host names
+    /// to pin to them. Fails when the uplink would be cut off.
+    pub fn uplink_addresses(&self) -> Result<UplinkAddresses, String> {
+        let mut allowed = self.protected.clone();
+        let mut pinned = BTreeMap::new();
+        let (mut endpoints_resolved, mut proxy_resolved) = (false, false);
+        for (url, (host, addresses)) in self.endpoints.iter().chain(&self.proxy).filter_map(|url| Some((url, resolve_url(url)?))) {
+            if self.proxy.as_ref() == Some(url) {
+                proxy_resolved = !addresses.is_empty();
+            } else {
+                endpoints_resolved |= !addresses.is_empty();
+            }
+            for ip in &addresses {
+                if !allowed.contains(ip) {
+                    allowed.push(*ip);
+                }
+            }
+            if host.parse::<IpAddr>().is_err() && !addresses.is_empty() {
+                pinned.insert(host, addresses);
+            }
+        }
+        match self.proxy {
+            // Through a proxy the endpoints needn't resolve here, but the proxy must
+            Some(_) if !proxy_resolved => {
+                return Err("the uplink proxy doesn't resolve; isolating would cut the agent off".to_string());
+            }
+            None if !self.endpoints.is_empty() && !endpoints_resolved => {
+                return Err("the uplink endpoints don't resolve; isolating would cut the agent off".to_string());
+            }
+            _ => {}
+        }
+        for ip in super::isolation::resolvers() {
+            if !allowed.contains(&ip) {
+                allowed.push(ip);
+            }
+        }
+        Ok((allowed, pinned))
+    }
+
+    /// Isolation in place, as kept in `data_dir`; an operator may have released it meanwhile
+    pub fn isolation(&self) -> Option<Isolation> {
+        Isolation::load(&self.isolation_path)
+    }
+
+    /// Isolate the host until released: drop all traffic but loopback, the uplink
+    /// ([`Self::uplink_addresses`]), and address configuration
+    pub fn isolate(&self, trigger: &str, now_ms: i64) -> BlockOutcome {
+        let mut outcome = BlockOutcome::new("isolate", trigger, self.backend, self.dry_run);
+        // Resolved again, in case the endpoints moved since startup
+        let uplink = self.uplink_addresses();
+        let off = disabled(&self.config, ResponseAction::Isolate);
+        let rejected = match (self.backend, &uplink) {
+            _ if off.is_some() => off,
+            (None, _) => Some("no supported firewall on this host".to_string()),
+            (Some(_), Err(e)) => Some(e.clone()),
+            (Some(_), Ok(_)) => None,
+        };
+        if let Some(reason) = rejected {
+            outcome.status = CommandStatus::Refused;
+            outcome.error = Some(reason);
+        } else if let (Some(backend), Ok((allowed, pinned))) = (self.backend, uplink) {
+            let isolation = Isolation { backend, allowed, pinned, trigger: trigger.to_string(), created_at: now_ms };
+            outcome.allowed = isolation.allowed.clone();
+            if !self.dry_run {
+                // Replaces an isolation in place, e.g. with endpoints that moved
+                if let Some(current) = self.isolation() {
+                    let _ = current.take_down();
+                }
+                let isolated = isolation.put().and_then(|_| isolation.save(&self.isolation_path).map_err(|e| e.to_string()));
+                if let Err(e) = isolated {
+                    let _ = isolation.take_down();
+                    outcome.status = CommandStatus::Failed;
+                    outcome.error = Some(e);
+                }
+            }
+        }
+        outcome.record();
+        outcome
+    }
+
+    /// Lift the isolation; it stays recorded if the firewall could not be restored
+    pub fn release(&self, trigger: &str) -> BlockOutcome {
+        let mut outcome = BlockOutcome::new("release", trigger, self.backend, self.dry_run);
+        let released = match self.isolation() {
+            None => Err("the host is not isolated".to_string()),
+            Some(isolation) => {
+                outcome.allowed = isolation.allowed.clone();
+                match self.dry_run {
+                    true => Ok(()),
+                    false => isolation.take_down().and_then(|_| std::fs::remove_file(&self.isolation_path).map_err(|e| e.to_string())),
+                }
+            }
+        };
+        if let Err(e) = released {
+            outcome.status = CommandStatus::Failed;
+            outcome.error = Some(e);
+        }
+        outcome.record();
+        outcome
+    }
+
+    /// Firewall blocks go to
+    pub fn backend(&self) -> Option<FirewallBackend> {
+        self.backend
+    }
+
+    /// Blocks in place
+    pub fn active(&self) -> Vec<Block> {
+        self.blocks.lock().unwrap().clone()
+    }
+
+    fn restore(&self, kept: Vec<Block>, now_ms: i64) {
+        let mut blocks = self.blocks.lock().unwrap();
+        for mut block in kept {
+            let gone = match &block.target {
+                _ if block.expires_at <= now_ms => Some("expired"),
+                BlockTarget::Process { pid, name, .. } if !lookup(*pid).is_some_and(|t| t.name.eq_ignore_ascii_case(name)) => Some("process exited"),
+                _ => None,
+            };
+            if let Some(reason) = gone {
+                self.lifted(&block, reason, take_down(&block)).record();
+                continue;
+            }
+            // Taken down first, as rules that survived (no reboot) would be doubled
+            let _ = take_down(&block);
+            match put(&mut block) {
+                Ok(()) => {
+                    tracing::info!(id = %block.id, target = ?block.target, expires_at = block.expires_at, "firewall block restored");
+                    blocks.push(block);
+                }
+                Err(e) => tracing::warn!(id = %block.id, target = ?block.target, error = %e, "firewall block not restored"),
+            }
+        }
+        self.save(&blocks);
+    }
+
+    fn save(&self, blocks: &[Block]) {
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let saved = serde_json::to_vec_pretty(blocks)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&tmp, json))
+            .and_then(|_| std::fs::rename(&tmp, &self.path));
+        if let Err(e) = saved {
+            tracing::warn!(error = %e, "failed to save firewall blocks");
+        }
+    }
+
+    /// Block traffic to and from `ip`, only on `port` when set, for `duration_secs` (default
+    /// `response.firewall.default_duration_secs`)
+    pub fn block_remote(&self, ip: IpAddr, port: Option<u16>, duration_secs: Option<u64>, trigger: &str, now_ms: i64) -> BlockOutcome {
+        let refused = if ip.is_loopback() || ip.is_unspecified() {
+            Some(format!("{} is a local address", ip))
+        } else if self.protected.contains(&ip) {
+            Some(format!("{} is protected (uplink endpoint or response.firewall.protected_addresses)", ip))
+        } else {
+            None
+        };
+        let rejected = refused.map(|reason| (CommandStatus::Refused, reason));
+        self.insert(BlockTarget::Remote { ip, port }, rejected, duration_secs, trigger, now_ms)
+    }
+
+    /// Block all traffic of `pid`, expected to be `expected_name` when given, for `duration_secs`
+    pub fn block_process(&self, pid: u32, expected_name: Option<&str>, duration_secs: Option<u64>, trigger: &str, now_ms: i64) -> BlockOutcome {
+        let found = lookup(pid);
+        let target = BlockTarget::Process {
+            pid,
+            name: found.as_ref().map(|t| t.name.clone()).unwrap_or_default(),
+            exe: found.as_ref().and_then(|t| t.exe.clone()),
+        };
+        let rejected = match found {
+            None => Some((CommandStatus::Failed, format!("no process {}", pid))),
+            Some(ref found) => refusal(&self.config, pid, found, expected_name).map(|reason| (CommandStatus::Refused, reason)),
+        };
+        self.insert(target, rejected, duration_secs, trigger, now_ms)
+    }
+
+    fn insert(&self, target: BlockTarget, rejected: Option<(CommandStatus, String)>, duration_secs: Option<u64>, trigger: &str, now_ms: i64) -> BlockOutcome {
+        let firewall = &self.config.firewall;
+        let duration = duration_secs.filter(|d| *d > 0).unwrap_or(firewall.default_duration_secs).min(firewall.max_duration_secs);
+        let mut outcome = BlockOutcome { target: Some(target.clone()), ..BlockOutcome::new("block_network", trigger, self.backend, self.dry_run) };
+        let off = disabled(&self.config, ResponseAction::BlockNetwork);
+        let rejected = match self.backend {
+            _ if off.is_some() => off.map(|reason| (CommandStatus::Refused, reason)),
+            None => Some((CommandStatus::Refused, "no supported firewall on this host".to_string())),
+            Some(_) if duration == 0 => Some((CommandStatus::Refused, "blocks are off (response.firewall.max_duration_secs is 0)".to_string())),
+            Some(_) => rejected,
+        };
+        if let Some((status, error)) = rejected {
+            outcome.status = status;
+            outcome.error = Some(error);
+        } else if let Some(backend) = self.backend {
+            let mut block = Block {
+                id: uuid::Uuid::new_v4().simple().to_string()[..12].to_string(),
+                target,
+                backend,
+                trigger: trigger.to_string(),
+                created_at: now_ms,
+                expires_at: now_ms + duration as i64 * 1000,
+                cgroup: None,
+            };
+            outcome.id = Some(block.id.clone());
+            outcome.expires_at = Some(block.expires_at);
+            if !self.dry_run {
+                match put(&mut block) {
+                    Ok(()) => {
+                        let mut blocks = self.blocks.lock().unwrap();
+                        blocks.push(block);
+                        self.save(&blocks);
+                    }
+                    Err(e) => {
+                        outcome.status = CommandStatus::Failed;
+                        outcome.error = Some(e);
+                    }
+                }
+            }
+        }
+        outcome.record();
+        outcome
+    }
+
+    fn lifted(&self, block: &Block, trigger: &str, taken_down: Result<(), String>) -> BlockOutcome {
+        BlockOutcome {
+            id: Some(block.id.clone()),
+            target: Some(block.target.clone()),
+            expires_at: Some(block.expires_at),
+            status: if taken_down.is_ok() { CommandStatus::Done } else { CommandStatus::Failed },
+            error: taken_down.err(),
+            ..BlockOutcome::new("unblock_network", trigger, Some(block.backend), false)
+        }
+    }
+
+    /// Lift the block `id` before it expires
+    pub fn unblock(&self, id: &str, trigger: &str) -> BlockOutcome {
+        let mut blocks = self.blocks.lock().unwrap();
+        let outcome = match blocks.iter().position(|b| b.id == id) {
+            Some(i) if self.dry_run => BlockOutcome { dry_run: true, ..self.lifted(&blocks[i], trigger, Ok(())) },
+            Some(i) => {
+                // Dropped even if lifting failed; the failure is audited
+                let block = blocks.remove(i);
+                self.save(&blocks);
+                self.lifted(&block, trigger, take_down(&block))
+            }
+            None => BlockOutcome {
+                id: Some(id.to_string()),
+                status: CommandStatus::Failed,
+                error: Some(format!("no block {}", id)),
+                ..BlockOutcome::new("unblock_network", trigger, None, self.dry_run)
+            },
+        };
+        drop(blocks);
+        outcome.record();
+        outcome
+    }
+
+    /// Lift the blocks expired at `now_ms`
+    pub fn expire(&self, now_ms: i64) -> Vec<BlockOutcome> {
+        let mut blocks = self.blocks.lock().unwrap();
+        if !blocks.iter().any(|b| b.expires_at <= now_ms) {
+            return Vec::new();
+        }
+        let (expired, kept): (Vec<Block>, Vec<Block>) = std::mem::take(&mut *blocks).into_iter().partition(|b| b.expires_at <= now_ms);
+        *blocks = kept;
+        self.save(&blocks);
+        drop(blocks);
+        expired
+            .iter()
+            .map(|block| {
+                let outcome = self.lifted(block, "expired", take_down(block));
+                outcome.record();
+                outcome
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,320 @@
+//! Host isolation (`isolate`): the host firewall drops all traffic but loopback, the uplink
+//! endpoints and proxy, the DNS resolvers, `response.firewall.protected_addresses`, and address
+//! configuration (DHCP, IPv6 neighbor discovery; not on Windows), to contain a compromised
+//! machine while the server can still reach the agent. The uplink's host names stay pinned to
+//! the addresses let through for as long as the isolation lasts. It is kept in `data_dir` ([`ISOLATION_FILE`]) and put back in place
+//! at startup, so it holds across restarts and reboots until the server sends `release` or an
+//! operator at the machine runs `dadm-agent release-isolation` with the credential whose salted
+//! PBKDF2 hash is `response.firewall.release_credential_hash` ([`release_locally`]). Isolating and
+//! releasing, and failed release attempts, are logged and audited (`host_isolated`,
+//! `host_released`).
+
+use super::firewall::{run, step, BlockOutcome, Step};
+use crate::commands::CommandStatus;
+use crate::config::{FirewallBackend, ResponseConfig};
+use serde::{Deserialize, Serialize};
+use crate::config::secrets::same_secret;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ring::pbkdf2;
+use std::num::NonZeroU32;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Isolation in place, under `data_dir`
+pub const ISOLATION_FILE: &str = "isolation.json";
+/// Table, chain, anchor, and rule name of the isolation
+const NAME: &str = "dadm-isolation";
+
+/// Host isolation in place
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Isolation {
+    pub backend: FirewallBackend,
+    /// Addresses still reachable, besides loopback
+    pub allowed: Vec<IpAddr>,
+    /// Uplink host names (endpoints, proxy) and the addresses they resolved to when isolating;
+    /// the uplink connects to these until released, as DNS may move them out of `allowed`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pinned: BTreeMap<String, Vec<IpAddr>>,
+    /// What asked for it: `command:<id>` or `risk:<event id>`
+    pub trigger: String,
+    /// When it was put in place (ms)
+    pub created_at: i64,
+}
+
+/// DNS servers the host is configured with (loopback ones left out, as loopback stays open)
+pub fn resolvers() -> Vec<IpAddr> {
+    let mut found: Vec<IpAddr> = Vec::new();
+    #[cfg(unix)]
+    for path in ["/etc/resolv.conf", "/run/systemd/resolve/resolv.conf"] {
+        let text = std::fs::read_to_string(path).unwrap_or_default();
+        found.extend(text.lines().filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let address = words.next().filter(|w| *w == "nameserver").and(words.next())?;
+            address.split('%').next()?.parse::<IpAddr>().ok()
+        }));
+    }
+    #[cfg(windows)]
+    if let Ok(output) = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", "(Get-DnsClientServerAddress).ServerAddresses"])
+        .output()
+    {
+        found.extend(String::from_utf8_lossy(&output.stdout).lines().filter_map(|line| line.trim().parse::<IpAddr>().ok()));
+    }
+    let mut resolvers = Vec::new();
+    for ip in found {
+        if !ip.is_loopback() && !resolvers.contains(&ip) {
+            resolvers.push(ip);
+        }
+    }
+    resolvers
+}
+
+/// Ranges (`first-last`) of the IPv4 or IPv6 address space outside `allowed`
+pub fn complement(allowed: &[IpAddr], v6: bool) -> Vec<String> {
+    let format = |n: u128| if v6 { Ipv6Addr::from(n).to_string() } else { Ipv4Addr::from(n as u32).to_string() };
+    let max = if v6 { u128::MAX } else { u32::MAX as u128 };
+    let mut points: Vec<u128> = allowed
+        .iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V4(ip) if !v6 => Some(u32::from(*ip) as u128),
+            IpAddr::V6(ip) if v6 => Some(u128::from(*ip)),
+            _ => None,
+        })
+        .collect();
+    points.sort_unstable();
+    points.dedup();
+    let mut ranges = Vec::new();
+    let mut start = Some(0u128);
+    for point in points {
+        let Some(first) = start else { break };
+        if point > first {
+            ranges.push(format!("{}-{}", format(first), format(point - 1)));
+        }
+        start = point.checked_add(1).filter(|next| *next <= max);
+    }
+    if let Some(first) = start {
+        ranges.push(format!("{}-{}", format(first), format(max)));
+    }
+    ranges
+}
+
+impl Isolation {
+    fn addresses(&self, v6: bool) -> Vec<String> {
+        self.allowed.iter().filter(|ip| ip.is_ipv6() == v6).map(|ip| ip.to_string()).collect()
+    }
+
+    /// Programs that put the isolation in place
+    pub fn apply_steps(&self) -> Result<Vec<Step>, String> {
+        let (v4, v6) = (self.addresses(false), self.addresses(true));
+        match self.backend {
+            FirewallBackend::Nftables => {
+                let chain = |hook: &str, interface: &str, address: &str, dhcp: &str, dhcp6: &str| {
+                    let mut rules = vec![format!("{} \"lo\" accept", interface)];
+                    if !v4.is_empty() {
+                        rules.push(format!("ip {} {{ {} }} accept", address, v4.join(", ")));
+                    }
+                    if !v6.is_empty() {
+                        rules.push(format!("ip6 {} {{ {} }} accept", address, v6.join(", ")));
+                    }
+                    rules.push(dhcp.to_string());
+                    rules.push(dhcp6.to_string());
+                    rules.push("icmpv6 type { nd-router-solicit, nd-router-advert, nd-neighbor-solicit, nd-neighbor-advert } accept".into());
+                    format!(
+                        "\tchain {} {{\n\t\ttype filter hook {} priority 0; policy drop;\n{}\t}}\n",
+                        hook,
+                        hook,
+                        rules.iter().map(|r| format!("\t\t{}\n", r)).collect::<String>()
+                    )
+                };
+                let table = "inet dadm_isolation";
+                let script = format!(
+                    "table {table}\ndelete table {table}\ntable {table} {{\n{}{}}}\n",
+                    chain("output", "oifname", "daddr", "udp sport 68 udp dport 67 accept", "udp dport 547 accept"),
+                    chain("input", "iifname", "saddr", "udp sport 67 udp dport 68 accept", "udp sport 547 accept"),
+                    table = table,
+                );
+                Ok(vec![step("nft", ["-f", "-"], Some(script))])
+            }
+            FirewallBackend::Iptables => {
+                let mut steps = Vec::new();
+                for (program, addresses) in [("iptables", &v4), ("ip6tables", &v6)] {
+                    for (chain, hook, interface, address) in [("dadm-isolation-out", "OUTPUT", "-o", "-d"), ("dadm-isolation-in", "INPUT", "-i", "-s")] {
+                        let out = hook == "OUTPUT";
+                        let mut rules: Vec<Vec<String>> = vec![vec![interface.into(), "lo".into()]];
+                        rules.extend(addresses.iter().map(|ip| vec![address.to_string(), ip.clone()]));
+                        let dhcp = match (program, out) {
+                            ("iptables", true) => "-p udp --sport 68 --dport 67",
+                            ("iptables", false) => "-p udp --sport 67 --dport 68",
+                            (_, true) => "-p udp --dport 547",
+                            (_, false) => "-p udp --sport 547",
+                        };
+                        rules.push(dhcp.split(' ').map(String::from).collect());
+                        if program == "ip6tables" {
+                            for kind in ["router-solicitation", "router-advertisement", "neighbour-solicitation", "neighbour-advertisement"] {
+                                rules.push(["-p", "ipv6-icmp", "--icmpv6-type", kind].map(String::from).to_vec());
+                            }
+                        }
+                        steps.push(step(program, ["-w", "-N", chain], None));
+                        for rule in rules {
+                            let args = ["-w", "-A", chain].into_iter().map(String::from).chain(rule).chain(["-j".to_string(), "RETURN".into()]);
+                            steps.push(step(program, args, None));
+                        }
+                        steps.push(step(program, ["-w", "-A", chain, "-j", "DROP"], None));
+                        steps.push(step(program, ["-w", "-I", hook, "-j", chain], None));
+                    }
+                }
+                Ok(steps)
+            }
+            FirewallBackend::Pf => {
+                let mut rules = String::from(
+                    "pass quick on lo0 all\n\
+                     pass out quick inet proto udp from any port 68 to any port 67\n\
+                     pass in quick inet proto udp from any port 67 to any port 68\n\
+                     pass out quick inet6 proto udp to any port 547\n\
+                     pass in quick inet6 proto udp from any port 547\n\
+                     pass quick inet6 proto icmp6 icmp6-type { routersol, routeradv, neighbrsol, neighbradv }\n",
+                );
+                if !self.allowed.is_empty() {
+                    let all: Vec<String> = self.allowed.iter().map(|ip| ip.to_string()).collect();
+                    rules.push_str(&format!("pass out quick to {{ {} }}\npass in quick from {{ {} }}\n", all.join(", "), all.join(", ")));
+                }
+                rules.push_str("block drop quick all\n");
+                Ok(vec![step("pfctl", ["-a", "dadm/isolation", "-f", "-"], Some(rules))])
+            }
+            FirewallBackend::Wfp => {
+                // Block rules win over allow rules, so everything outside the allowed addresses
+                // is blocked; the firewall doesn't filter loopback
+                let mut ranges = complement(&self.allowed, false);
+                ranges.extend(complement(&self.allowed, true));
+                let remote = format!("remoteip={}", ranges.join(","));
+                Ok(["out", "in"]
+                    .iter()
+                    .map(|dir| {
+                        let dir = format!("dir={}", dir);
+                        let args = ["advfirewall", "firewall", "add", "rule", &format!("name={}", NAME), &dir, "action=block", "protocol=any", &remote];
+                        step("netsh", args, None)
+                    })
+                    .collect())
+            }
+            FirewallBackend::Auto => Err("no firewall backend".into()),
+        }
+    }
+
+    /// Programs that lift the isolation
+    pub fn remove_steps(&self) -> Vec<Step> {
+        match self.backend {
+            FirewallBackend::Nftables => vec![step("nft", ["delete", "table", "inet", "dadm_isolation"], None)],
+            FirewallBackend::Iptables => {
+                let mut steps = Vec::new();
+                for program in ["iptables", "ip6tables"] {
+                    for (chain, hook) in [("dadm-isolation-out", "OUTPUT"), ("dadm-isolation-in", "INPUT")] {
+                        steps.push(step(program, ["-w", "-D", hook, "-j", chain], None));
+                        steps.push(step(program, ["-w", "-F", chain], None));
+                        steps.push(step(program, ["-w", "-X", chain], None));
+                    }
+                }
+                steps
+            }
+            FirewallBackend::Pf => vec![step("pfctl", ["-a", "dadm/isolation", "-F", "rules"], None)],
+            FirewallBackend::Wfp => vec![step("netsh", ["advfirewall", "firewall", "delete", "rule", &format!("name={}", NAME)], None)],
+            FirewallBackend::Auto => Vec::new(),
+        }
+    }
+
+    /// Put the isolation in place; a failed step takes it down again
+    pub(super) fn put(&self) -> Result<(), String> {
+        if let Err(e) = self.apply_steps()?.iter().try_for_each(run) {
+            let _ = self.take_down();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Lift the isolation: every step is run, and the first error returned
+    pub(super) fn take_down(&self) -> Result<(), String> {
+        self.remove_steps().iter().map(run).fold(Ok(()), Result::and)
+    }
+
+    /// Isolation kept at `path`
+    pub fn load(path: &Path) -> Option<Isolation> {
+        let bytes = std::fs::read(path).ok()?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| tracing::warn!(error = %e, "isolation state unreadable"))
+            .ok()
+    }
+
+    pub(super) fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+/// PBKDF2 rounds of a new release credential hash
+pub const CREDENTIAL_ITERATIONS: u32 = 600_000;
+const CREDENTIAL_SCHEME: &str = "pbkdf2-sha256";
+
+/// `response.firewall.release_credential_hash` of `credential`: PBKDF2-HMAC-SHA256 over a random
+/// salt, as `pbkdf2-sha256$<iterations>$<salt>$<hash>` (base64)
+pub fn hash_credential(credential: &str, iterations: u32) -> String {
+    let iterations = NonZeroU32::new(iterations).unwrap_or(NonZeroU32::MIN);
+    let salt: [u8; 16] = rand::random();
+    let mut hash = [0u8; 32];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, credential.as_bytes(), &mut hash);
+    format!("{}${}${}${}", CREDENTIAL_SCHEME, iterations, BASE64.encode(salt), BASE64.encode(hash))
+}
+
+/// Whether `credential` is the one `hash` ([`hash_credential`]) was made from
+fn verify_credential(credential: &str, hash: &str) -> Result<bool, String> {
+    let malformed = || "response.firewall.release_credential_hash is malformed; make it with release-isolation --hash".to_string();
+    let mut parts = hash.trim().split('$');
+    let (Some(CREDENTIAL_SCHEME), Some(iterations), Some(salt), Some(expected), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(malformed());
+    };
+    let iterations: NonZeroU32 = iterations.parse().map_err(|_| malformed())?;
+    let salt = BASE64.decode(salt).map_err(|_| malformed())?;
+    let expected = BASE64.decode(expected).map_err(|_| malformed())?;
+    if expected.is_empty() {
+        return Err(malformed());
+    }
+    let mut derived = vec![0u8; expected.len()];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, credential.as_bytes(), &mut derived);
+    Ok(same_secret(&derived, &expected))
+}
+
+/// Lift the isolation kept in `data_dir` at the machine, with the operator `credential`; every
+/// attempt is audited
+pub fn release_locally(config: &ResponseConfig, data_dir: &Path, credential: &str) -> Result<Isolation, BoxError> {
+    let path = data_dir.join(ISOLATION_FILE);
+    let isolation = Isolation::load(&path).ok_or("the host is not isolated")?;
+    let mut outcome = BlockOutcome::new("release", "operator", Some(isolation.backend), false);
+    outcome.allowed = isolation.allowed.clone();
+    let verified = match config.firewall.release_credential_hash {
+        None => Err("local release is off (response.firewall.release_credential_hash is unset)".to_string()),
+        Some(ref hash) => match verify_credential(credential, hash) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("wrong credential".to_string()),
+            Err(e) => Err(e),
+        },
+    };
+    if let Err(e) = verified {
+        outcome.status = CommandStatus::Refused;
+        outcome.error = Some(e.clone());
+        outcome.record();
+        return Err(e.into());
+    }
+    let lifted = isolation.take_down().and_then(|_| std::fs::remove_file(&path).map_err(|e| e.to_string()));
+    if let Err(ref e) = lifted {
+        outcome.status = CommandStatus::Failed;
+        outcome.error = Some(e.clone());
+    }
+    outcome.record();
+    lifted?;
+    Ok(isolation)
+}
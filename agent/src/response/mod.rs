@@ -0,0 +1,427 @@
+//! Response actions (`response`): kill or suspend a process, on a server command
+//! (`kill_process` / `suspend_process`) or through the response policy ([`policy`]). Nothing
+//! runs unless `response.enabled`, and no kind of action turned off in `response.actions`. Every
+//! target is checked against a strict safety list before it is touched: pid 0 and 1, the agent
+//! and its parent, `response.protected` names and paths are refused, and with `response.allowed`
+//! only matching processes are acted on. A pid that now belongs to another process than expected
+//! is refused. Every action, taken, refused, or failed, is logged and recorded in the audit log
+//! (`response_action`). In a dry run (`dry_run` or `response.dry_run`) actions are only logged.
+//!
+//! Network blocks through the host firewall are in [`firewall`], host isolation in [`isolation`],
+//! file quarantine in [`quarantine`]. Actions taken are kept in the store and can be undone; see
+//! [`trail`]. The user can be asked before the policy acts; see [`approval`].
+
+use crate::collectors::{Event, EventKind};
+use crate::commands::CommandStatus;
+use crate::config::ResponseConfig;
+use crate::logging::audit;
+use crate::risk::feedback::implicated_event_ids;
+use crate::risk::rules::wildcard_match;
+use crate::risk::{RiskLevel, RiskResult};
+use serde::Serialize;
+
+pub mod approval;
+pub mod firewall;
+pub mod isolation;
+pub mod policy;
+pub mod quarantine;
+pub mod trail;
+
+pub use crate::config::ResponseAction;
+pub use policy::ResponseEngine;
+pub use trail::{ResponseRecord, UndoActor};
+
+/// Response action against a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessAction {
+    Kill,
+    Suspend,
+}
+
+impl ProcessAction {
+    pub fn as_str(&self) -> &'static str {
+        self.kind().as_str()
+    }
+
+    pub fn kind(&self) -> ResponseAction {
+        match self {
+            ProcessAction::Kill => ResponseAction::KillProcess,
+            ProcessAction::Suspend => ResponseAction::SuspendProcess,
+        }
+    }
+}
+
+impl ResponseAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResponseAction::SuspendProcess => "suspend_process",
+            ResponseAction::KillProcess => "kill_process",
+            ResponseAction::BlockNetwork => "block_network",
+            ResponseAction::Isolate => "isolate",
+            ResponseAction::Quarantine => "quarantine",
+            ResponseAction::Notify => "notify",
+        }
+    }
+}
+
+/// Why `action` may not be taken at all, if it mayn't
+fn disabled(config: &ResponseConfig, action: ResponseAction) -> Option<String> {
+    let actions = &config.actions;
+    let on = match action {
+        ResponseAction::SuspendProcess => actions.suspend_process,
+        ResponseAction::KillProcess => actions.kill_process,
+        ResponseAction::BlockNetwork => actions.block_network,
+        ResponseAction::Isolate => actions.isolate,
+        ResponseAction::Quarantine => actions.quarantine,
+        ResponseAction::Notify => actions.notify,
+    };
+    match (config.enabled, on) {
+        (false, _) => Some("response actions are off (response.enabled)".to_string()),
+        (true, false) => Some(format!("{} is off (response.actions.{})", action.as_str(), action.as_str())),
+        (true, true) => None,
+    }
+}
+
+/// Outcome of an action against a process
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionOutcome {
+    pub action: &'static str,
+    pub pid: u32,
+    /// Process name, executable, and state (e.g. `Sleeping`) before the action, when the pid was
+    /// found
+    pub name: Option<String>,
+    pub exe: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    /// What asked for it: `command:<id>` or `risk:<event id>`
+    pub trigger: String,
+    pub status: CommandStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Only logged (`dry_run`)
+    pub dry_run: bool,
+}
+
+/// Outcome of a response action
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum ResponseOutcome {
+    Process(ActionOutcome),
+    Network(firewall::BlockOutcome),
+    File(quarantine::QuarantineOutcome),
+    Notice(policy::NoticeOutcome),
+    Approval(approval::ApprovalOutcome),
+}
+
+impl ResponseOutcome {
+    pub fn action(&self) -> &'static str {
+        match self {
+            ResponseOutcome::Process(o) => o.action,
+            ResponseOutcome::Network(o) => o.action,
+            ResponseOutcome::File(o) => o.action,
+            ResponseOutcome::Notice(o) => o.action,
+            ResponseOutcome::Approval(o) => o.action,
+        }
+    }
+
+    pub fn status(&self) -> CommandStatus {
+        match self {
+            ResponseOutcome::Process(o) => o.status,
+            ResponseOutcome::Network(o) => o.status,
+            ResponseOutcome::File(o) => o.status,
+            ResponseOutcome::Notice(o) => o.status,
+            ResponseOutcome::Approval(o) => o.status,
+        }
+    }
+
+    pub fn dry_run(&self) -> bool {
+        match self {
+            ResponseOutcome::Process(o) => o.dry_run,
+            ResponseOutcome::Network(o) => o.dry_run,
+            ResponseOutcome::File(o) => o.dry_run,
+            ResponseOutcome::Notice(o) => o.dry_run,
+            ResponseOutcome::Approval(_) => false,
+        }
+    }
+
+    pub fn trigger(&self) -> &str {
+        match self {
+            ResponseOutcome::Process(o) => &o.trigger,
+            ResponseOutcome::Network(o) => &o.trigger,
+            ResponseOutcome::File(o) => &o.trigger,
+            ResponseOutcome::Notice(o) => &o.trigger,
+            ResponseOutcome::Approval(o) => &o.trigger,
+        }
+    }
+
+    /// State of the target before the action, for the response trail
+    pub fn before(&self) -> serde_json::Value {
+        match self {
+            ResponseOutcome::Process(o) => serde_json::json!({ "pid": o.pid, "name": o.name, "exe": o.exe, "state": o.state }),
+            ResponseOutcome::File(o) => serde_json::json!({ "path": o.path, "sha256": o.sha256, "size": o.size }),
+            ResponseOutcome::Network(_) | ResponseOutcome::Notice(_) | ResponseOutcome::Approval(_) => serde_json::Value::Null,
+        }
+    }
+
+    /// What was acted on, for the notification
+    fn describe(&self) -> String {
+        match self {
+            ResponseOutcome::Process(o) => format!("{} {} ({})", o.action, o.pid, o.name.as_deref().unwrap_or("?")),
+            ResponseOutcome::Network(o) => match o.target {
+                Some(ref target) => format!("{} {}", o.action, serde_json::to_string(target).unwrap_or_default()),
+                None => o.action.to_string(),
+            },
+            ResponseOutcome::File(o) => format!("{} {}", o.action, o.path),
+            ResponseOutcome::Notice(o) => o.action.to_string(),
+            ResponseOutcome::Approval(o) => o.action.to_string(),
+        }
+    }
+}
+
+/// A process as found now
+struct Target {
+    name: String,
+    exe: Option<String>,
+    parent: Option<u32>,
+    state: String,
+}
+
+fn lookup(pid: u32) -> Option<Target> {
+    let pid = sysinfo::Pid::from_u32(pid);
+    let mut system = sysinfo::System::new();
+    if !system.refresh_process(pid) {
+        return None;
+    }
+    system.process(pid).map(|p| Target {
+        name: p.name().to_string(),
+        exe: p.exe().and_then(|e| e.to_str().map(String::from)),
+        parent: p.parent().map(|p| p.as_u32()),
+        state: p.status().to_string(),
+    })
+}
+
+fn matches(patterns: &[String], target: &Target) -> bool {
+    patterns
+        .iter()
+        .any(|p| wildcard_match(p, &target.name) || target.exe.as_deref().is_some_and(|exe| wildcard_match(p, exe)))
+}
+
+/// Why `pid` (found as `target`) must not be acted on, if it mustn't
+fn refusal(config: &ResponseConfig, pid: u32, target: &Target, expected_name: Option<&str>) -> Option<String> {
+    let own = std::process::id();
+    if pid <= 1 || pid == own {
+        return Some(format!("process {} is protected", pid));
+    }
+    if lookup(own).and_then(|agent| agent.parent) == Some(pid) {
+        return Some(format!("process {} is the agent's parent", pid));
+    }
+    if let Some(expected) = expected_name.filter(|expected| !expected.eq_ignore_ascii_case(&target.name)) {
+        return Some(format!("process {} is now {}, not {}", pid, target.name, expected));
+    }
+    if matches(&config.protected, target) {
+        return Some(format!("{} is protected (response.protected)", target.name));
+    }
+    if !config.allowed.is_empty() && !matches(&config.allowed, target) {
+        return Some(format!("{} is not in response.allowed", target.name));
+    }
+    None
+}
+
+/// What is done to a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    Kill,
+    Stop,
+    Continue,
+}
+
+impl From<ProcessAction> for Signal {
+    fn from(action: ProcessAction) -> Self {
+        match action {
+            ProcessAction::Kill => Signal::Kill,
+            ProcessAction::Suspend => Signal::Stop,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal(pid: u32, signal: Signal) -> std::io::Result<()> {
+    let signal = match signal {
+        Signal::Kill => libc::SIGKILL,
+        Signal::Stop => libc::SIGSTOP,
+        Signal::Continue => libc::SIGCONT,
+    };
+    // SAFETY: kill has no memory preconditions
+    if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn signal(pid: u32, signal: Signal) -> std::io::Result<()> {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32};
+    use windows::Win32::System::Threading::{
+        OpenProcess, OpenThread, ResumeThread, SuspendThread, TerminateProcess, PROCESS_TERMINATE, THREAD_SUSPEND_RESUME,
+    };
+
+    let io = |e: windows::core::Error| std::io::Error::from_raw_os_error(e.code().0 & 0xFFFF);
+    match signal {
+        Signal::Kill => unsafe {
+            let process = OpenProcess(PROCESS_TERMINATE, false, pid).map_err(io)?;
+            let terminated = TerminateProcess(process, 1).map_err(io);
+            let _ = CloseHandle(process);
+            terminated
+        },
+        // Windows has no process-wide stop: every thread of the process is suspended (resumed)
+        Signal::Stop | Signal::Continue => unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0).map_err(io)?;
+            let mut entry = THREADENTRY32 { dwSize: std::mem::size_of::<THREADENTRY32>() as u32, ..Default::default() };
+            let mut suspended = 0;
+            let mut next = Thread32First(snapshot, &mut entry);
+            while next.is_ok() {
+                if entry.th32OwnerProcessID == pid {
+                    if let Ok(thread) = OpenThread(THREAD_SUSPEND_RESUME, false, entry.th32ThreadID) {
+                        let count = if signal == Signal::Stop { SuspendThread(thread) } else { ResumeThread(thread) };
+                        if count != u32::MAX {
+                            suspended += 1;
+                        }
+                        let _ = CloseHandle(thread);
+                    }
+                }
+                next = Thread32Next(snapshot, &mut entry);
+            }
+            let _ = CloseHandle(snapshot);
+            if suspended == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "no thread could be suspended or resumed"));
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Take `action` against `pid`, expected to be `expected_name` when given, for `trigger`; the
+/// outcome is logged and audited
+pub fn act_on_process(
+    config: &ResponseConfig,
+    action: ProcessAction,
+    pid: u32,
+    expected_name: Option<&str>,
+    trigger: &str,
+    dry_run: bool,
+) -> ActionOutcome {
+    let dry_run = dry_run || config.dry_run;
+    let target = lookup(pid);
+    let mut outcome = ActionOutcome {
+        action: action.as_str(),
+        pid,
+        name: target.as_ref().map(|t| t.name.clone()),
+        exe: target.as_ref().and_then(|t| t.exe.clone()),
+        state: target.as_ref().map(|t| t.state.clone()),
+        trigger: trigger.to_string(),
+        status: CommandStatus::Done,
+        error: None,
+        dry_run,
+    };
+    let off = disabled(config, action.kind());
+    let refused = match target {
+        _ if off.is_some() => off,
+        None => {
+            outcome.status = CommandStatus::Failed;
+            outcome.error = Some(format!("no process {}", pid));
+            None
+        }
+        Some(ref target) => refusal(config, pid, target, expected_name),
+    };
+    if let Some(reason) = refused {
+        outcome.status = CommandStatus::Refused;
+        outcome.error = Some(reason);
+    } else if outcome.status == CommandStatus::Done && !dry_run {
+        if let Err(e) = signal(pid, action.into()) {
+            outcome.status = CommandStatus::Failed;
+            outcome.error = Some(e.to_string());
+        }
+    }
+    match (outcome.status, dry_run) {
+        (CommandStatus::Done, true) => tracing::info!(action = outcome.action, pid, name = ?outcome.name, trigger, "dry run: would have acted on the process"),
+        (CommandStatus::Done, false) => tracing::warn!(action = outcome.action, pid, name = ?outcome.name, trigger, "response action taken"),
+        _ => tracing::warn!(action = outcome.action, pid, name = ?outcome.name, trigger, error = ?outcome.error, "response action not taken"),
+    }
+    audit::record("response_action", serde_json::to_value(&outcome).unwrap_or_default());
+    outcome
+}
+
+/// Resume `pid`, stopped by `suspend_process` and expected to be `expected_name`, for `trigger`;
+/// only pid 0 and 1, the agent, and a pid now belonging to another process are refused. The
+/// outcome is logged and audited
+pub fn resume_process(config: &ResponseConfig, pid: u32, expected_name: &str, trigger: &str, dry_run: bool) -> ActionOutcome {
+    let dry_run = dry_run || config.dry_run;
+    let target = lookup(pid);
+    let mut outcome = ActionOutcome {
+        action: "resume_process",
+        pid,
+        name: target.as_ref().map(|t| t.name.clone()),
+        exe: target.as_ref().and_then(|t| t.exe.clone()),
+        state: target.as_ref().map(|t| t.state.clone()),
+        trigger: trigger.to_string(),
+        status: CommandStatus::Done,
+        error: None,
+        dry_run,
+    };
+    let rejected = match target {
+        None => Some((CommandStatus::Failed, format!("no process {}", pid))),
+        Some(_) if pid <= 1 || pid == std::process::id() => Some((CommandStatus::Refused, format!("process {} is protected", pid))),
+        Some(ref target) if !target.name.eq_ignore_ascii_case(expected_name) => {
+            Some((CommandStatus::Refused, format!("process {} is now {}, not {}", pid, target.name, expected_name)))
+        }
+        Some(_) => None,
+    };
+    if let Some((status, error)) = rejected {
+        outcome.status = status;
+        outcome.error = Some(error);
+    } else if !dry_run {
+        if let Err(e) = signal(pid, Signal::Continue) {
+            outcome.status = CommandStatus::Failed;
+            outcome.error = Some(e.to_string());
+        }
+    }
+    match (outcome.status, dry_run) {
+        (CommandStatus::Done, true) => tracing::info!(pid, name = ?outcome.name, trigger, "dry run: would have resumed the process"),
+        (CommandStatus::Done, false) => tracing::warn!(pid, name = ?outcome.name, trigger, "process resumed"),
+        _ => tracing::warn!(pid, name = ?outcome.name, trigger, error = ?outcome.error, "process not resumed"),
+    }
+    audit::record("response_action", serde_json::to_value(&outcome).unwrap_or_default());
+    outcome
+}
+
+/// A high result at or above `response.critical_score`
+pub fn is_critical(config: &ResponseConfig, result: &RiskResult) -> bool {
+    result.level == RiskLevel::High && result.score >= config.critical_score
+}
+
+/// Ids of the events `result` implicates: its subject's and those of its rule hits, IOC hits,
+/// and correlations
+fn implicated_ids(result: &RiskResult) -> Vec<String> {
+    let mut ids = implicated_event_ids(result);
+    for id in result.correlations.iter().flat_map(|c| &c.event_ids) {
+        if !ids.contains(id) {
+            ids.push(id.clone());
+        }
+    }
+    ids
+}
+
+/// Processes implicated in `result` among `events`, as pid and name
+pub fn implicated(result: &RiskResult, events: &[Event]) -> Vec<(u32, String)> {
+    let ids = implicated_ids(result);
+    let mut out: Vec<(u32, String)> = Vec::new();
+    for event in events.iter().filter(|e| ids.contains(&e.id)) {
+        if let EventKind::Process(p) = &event.kind {
+            if !out.iter().any(|(pid, _)| *pid == p.pid) {
+                out.push((p.pid, p.name.clone()));
+            }
+        }
+    }
+    out
+}
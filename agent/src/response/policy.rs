@@ -0,0 +1,429 @@
+//! Response policy (`response.policy`): after scoring, every result is matched against the
+//! policy's rules, each a minimum level (`medium`, `high`, or `critical`: high and at least
+//! `response.critical_score`) and tags the result must all carry (rule ids, correlation
+//! patterns, ATT&CK techniques and tactics, the model's class). The actions of all matching
+//! rules are taken once each, against what the result implicates (its subject and the events
+//! behind its rule, IOC, and correlation hits), in a fixed order: processes are suspended and
+//! killed before their connections are blocked, the host isolated, and their files quarantined,
+//! and the notification comes last to say what was done. A target acted on is not acted on again
+//! for later results of the same detection, and results of the learning period are not acted on.
+//! With `response.approval`, the user is asked first about results below critical
+//! ([`super::approval`]), and the actions wait for the answer without holding up analysis
+//! ([`ResponseEngine::settle`]); targets not allowed are not asked about again either.
+//! Each action goes through its own checks and audit; see the [`super`] modules. Actions taken are
+//! kept in the response trail ([`super::trail`]), whether the policy or the server asked for
+//! them, and undone through the engine.
+
+use super::approval;
+use super::firewall::Firewall;
+use super::quarantine::{self, quarantine_file};
+use super::trail::{ResponseRecord, Undo, UndoActor};
+use super::{act_on_process, disabled, implicated, implicated_ids, is_critical, resume_process, ProcessAction, ResponseAction, ResponseOutcome};
+use crate::collectors::{Event, EventKind, FileIntegrityChange};
+use crate::commands::CommandStatus;
+use crate::config::{NotifyConfig, ResponseConfig, ResponseLevel, ResponseRule};
+use crate::notify::Notifier;
+use crate::risk::rules::wildcard_match;
+use crate::logging::audit;
+use crate::risk::{RiskLevel, RiskResult};
+use crate::storage::SecureStore;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Targets remembered as acted on; past this many the memory starts over
+const MAX_DONE: usize = 4096;
+
+/// Action planned against a target
+enum Step {
+    Process(ProcessAction, u32, String),
+    Block(IpAddr),
+    Isolate,
+    Quarantine(String, Option<String>),
+    Notify,
+}
+
+impl Step {
+    /// The step as the user is asked about it; notifications aren't asked about
+    fn question(&self) -> Option<String> {
+        match self {
+            Step::Process(ProcessAction::Kill, pid, name) => Some(format!("Kill process {} ({})", name, pid)),
+            Step::Process(ProcessAction::Suspend, pid, name) => Some(format!("Suspend process {} ({})", name, pid)),
+            Step::Block(ip) => Some(format!("Block traffic with {}", ip)),
+            Step::Isolate => Some("Isolate this device from the network".to_string()),
+            Step::Quarantine(path, _) => Some(format!("Quarantine {}", path)),
+            Step::Notify => None,
+        }
+    }
+}
+
+/// Steps waiting for the user's answer
+struct Pending {
+    result: RiskResult,
+    steps: Vec<Step>,
+    trigger: String,
+    reason: Value,
+    answer: Receiver<approval::ApprovalOutcome>,
+}
+
+/// Outcome of a `notify` action
+#[derive(Debug, Clone, Serialize)]
+pub struct NoticeOutcome {
+    pub action: &'static str,
+    /// The actions taken that the notification reports
+    pub taken: Vec<String>,
+    pub trigger: String,
+    pub status: CommandStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Only logged (`dry_run`)
+    pub dry_run: bool,
+}
+
+/// Tags of `result` rules match on
+fn tags(result: &RiskResult) -> Vec<&str> {
+    let mut tags: Vec<&str> = result.rule_hits.iter().map(|h| h.rule.as_str()).collect();
+    tags.extend(result.correlations.iter().map(|c| c.pattern.as_str()));
+    tags.extend(result.techniques());
+    tags.extend(result.tactics());
+    tags.extend(result.top_class.as_ref().map(|c| c.label.as_str()));
+    tags
+}
+
+/// Level of `result` for the policy; none below medium
+pub fn level(config: &ResponseConfig, result: &RiskResult) -> Option<ResponseLevel> {
+    match result.level {
+        _ if is_critical(config, result) => Some(ResponseLevel::Critical),
+        RiskLevel::High => Some(ResponseLevel::High),
+        RiskLevel::Medium => Some(ResponseLevel::Medium),
+        RiskLevel::Low => None,
+    }
+}
+
+/// Files implicated in `result` among `events`, as path and SHA-256 when seen: changed files
+/// and the executables of processes
+fn implicated_files(result: &RiskResult, events: &[Event]) -> Vec<(String, Option<String>)> {
+    let ids = implicated_ids(result);
+    let mut out: Vec<(String, Option<String>)> = Vec::new();
+    for event in events.iter().filter(|e| ids.contains(&e.id)) {
+        let file = match &event.kind {
+            EventKind::FileIntegrity(f) if !matches!(f.event, FileIntegrityChange::Deleted) => (f.path.clone(), Some(f.hash_sha256.clone())),
+            EventKind::Process(p) => match p.exe {
+                Some(ref exe) => (exe.clone(), None),
+                None => continue,
+            },
+            _ => continue,
+        };
+        if !out.iter().any(|(path, _)| *path == file.0) {
+            out.push(file);
+        }
+    }
+    out
+}
+
+/// Remote addresses of the connections implicated in `result` among `events`
+fn implicated_remotes(result: &RiskResult, events: &[Event]) -> Vec<IpAddr> {
+    let ids = implicated_ids(result);
+    let mut out: Vec<IpAddr> = Vec::new();
+    for event in events.iter().filter(|e| ids.contains(&e.id)) {
+        if let EventKind::Network(n) = &event.kind {
+            if let Some(ip) = n.remote_addr.as_deref().and_then(|a| a.parse().ok()).filter(|ip| !out.contains(ip)) {
+                out.push(ip);
+            }
+        }
+    }
+    out
+}
+
+/// Evaluates the response policy and takes its actions
+pub struct ResponseEngine {
+    config: ResponseConfig,
+    data_dir: PathBuf,
+    firewall: Firewall,
+    /// Shows `notify` actions, when a rule has one
+    notifier: Option<Notifier>,
+    /// Targets acted on, as `action:target`
+    done: Mutex<HashSet<String>>,
+    /// Response trail; without it actions aren't kept and can't be undone
+    store: Option<Arc<SecureStore>>,
+    /// Asks the user, with `response.approval`
+    asker: Option<approval::Asker>,
+    pending: Mutex<Vec<Pending>>,
+    dry_run: bool,
+}
+
+impl ResponseEngine {
+    /// Engine with `config`, keeping its state in `data_dir`, never blocking the uplink
+    /// `endpoints`; notifications are throttled by `notify.min_interval_secs`
+    pub fn new(config: &ResponseConfig, notify: &NotifyConfig, data_dir: &Path, endpoints: &[String], dry_run: bool) -> Self {
+        let notifies = config.policy.iter().any(|r| r.actions.contains(&ResponseAction::Notify));
+        let notifier = notifies.then(|| Notifier::start(&NotifyConfig { enabled: true, min_level: RiskLevel::Low, ..notify.clone() }));
+        Self {
+            config: config.clone(),
+            data_dir: data_dir.to_path_buf(),
+            firewall: Firewall::open(config, data_dir, endpoints, dry_run),
+            notifier,
+            done: Mutex::default(),
+            store: None,
+            asker: config.approval.enabled.then(|| approval::Asker::start(&config.approval)),
+            pending: Mutex::default(),
+            dry_run: dry_run || config.dry_run,
+        }
+    }
+
+    /// Keep the uplink `proxy` reachable too (see [`Firewall::with_proxy`])
+    pub fn with_uplink_proxy(mut self, proxy: Option<&str>) -> Self {
+        self.firewall = self.firewall.with_proxy(proxy);
+        self
+    }
+
+    /// Keep the actions taken in `store`
+    pub fn with_store(mut self, store: Arc<SecureStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn config(&self) -> &ResponseConfig {
+        &self.config
+    }
+
+    /// Network blocks and host isolation
+    pub fn firewall(&self) -> &Firewall {
+        &self.firewall
+    }
+
+    /// Rules `result` matches
+    pub fn matching(&self, result: &RiskResult) -> Vec<&ResponseRule> {
+        let Some(level) = level(&self.config, result).filter(|_| !result.learning) else {
+            return Vec::new();
+        };
+        let tags = tags(result);
+        self.config
+            .policy
+            .iter()
+            .filter(|rule| level >= rule.min_level && rule.tags.iter().all(|p| tags.iter().any(|t| wildcard_match(p, t))))
+            .collect()
+    }
+
+    /// Whether `target` has not been acted on with `action` yet; it now has
+    fn first(&self, action: ResponseAction, target: &str) -> bool {
+        let mut done = self.done.lock().unwrap();
+        if done.len() >= MAX_DONE {
+            done.clear();
+        }
+        done.insert(format!("{}:{}", action.as_str(), target))
+    }
+
+    /// Take the actions of the rules `result` matches against what it implicates among `events`.
+    /// Actions the user is asked about are taken later, by [`Self::settle`].
+    pub fn respond(&self, result: &RiskResult, events: &[Event], now_ms: i64) -> Vec<ResponseOutcome> {
+        let rules = self.matching(result);
+        if !self.config.enabled || rules.is_empty() {
+            return Vec::new();
+        }
+        let mut actions: Vec<ResponseAction> = rules.iter().flat_map(|r| r.actions.iter().copied()).collect();
+        actions.sort_unstable();
+        actions.dedup();
+        tracing::warn!(
+            event_id = %result.event_id,
+            rules = ?rules.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(),
+            actions = ?actions.iter().map(ResponseAction::as_str).collect::<Vec<_>>(),
+            "response policy matched"
+        );
+        let trigger = format!("risk:{}", result.event_id);
+        let reason = json!({
+            "rules": rules.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(),
+            "level": result.level,
+            "score": result.score,
+            "reasons": result.reasons(),
+        });
+        let mut steps = Vec::new();
+        for action in actions {
+            match action {
+                ResponseAction::SuspendProcess | ResponseAction::KillProcess => {
+                    let process_action = if action == ResponseAction::KillProcess { ProcessAction::Kill } else { ProcessAction::Suspend };
+                    for (pid, name) in implicated(result, events) {
+                        if self.first(action, &format!("{}:{}", pid, name)) {
+                            steps.push(Step::Process(process_action, pid, name));
+                        }
+                    }
+                }
+                ResponseAction::BlockNetwork => {
+                    for ip in implicated_remotes(result, events) {
+                        if self.first(action, &ip.to_string()) {
+                            steps.push(Step::Block(ip));
+                        }
+                    }
+                }
+                ResponseAction::Isolate => {
+                    if self.firewall.isolation().is_none() {
+                        steps.push(Step::Isolate);
+                    }
+                }
+                ResponseAction::Quarantine => {
+                    for (path, sha256) in implicated_files(result, events) {
+                        if self.first(action, &path) {
+                            steps.push(Step::Quarantine(path, sha256));
+                        }
+                    }
+                }
+                ResponseAction::Notify => steps.push(Step::Notify),
+            }
+        }
+        let asked: Vec<String> = steps.iter().filter_map(Step::question).collect();
+        if !asked.is_empty() && !is_critical(&self.config, result) {
+            if let Some(asker) = self.asker.as_ref().filter(|_| !self.dry_run) {
+                let why = format!("{:?} risk (score {:.2}): {}", result.level, result.score, result.reasons().join(", "));
+                let answer = asker.ask(asked, &why, &trigger);
+                self.pending.lock().unwrap().push(Pending { result: result.clone(), steps, trigger, reason, answer });
+                return Vec::new();
+            }
+            if self.config.approval.enabled {
+                tracing::info!(event_id = %result.event_id, actions = ?asked, "dry run: would have asked the user to allow the response");
+            }
+        }
+        self.take(result, steps, &trigger, &reason, now_ms, Vec::new())
+    }
+
+    /// Take the actions the user has answered about (or whose question timed out) since the last
+    /// call; the daemon calls this every second
+    pub fn settle(&self, now_ms: i64) -> Vec<ResponseOutcome> {
+        let mut answered = Vec::new();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            let mut waiting = Vec::new();
+            for p in pending.drain(..) {
+                match p.answer.try_recv() {
+                    Ok(approval) => answered.push((p, approval)),
+                    Err(TryRecvError::Empty) => waiting.push(p),
+                    Err(TryRecvError::Disconnected) => tracing::warn!(trigger = %p.trigger, "response approval failed; not acting"),
+                }
+            }
+            *pending = waiting;
+        }
+        let mut outcomes = Vec::new();
+        for (mut p, approval) in answered {
+            if approval.status != CommandStatus::Done {
+                p.steps.retain(|step| matches!(step, Step::Notify));
+            }
+            p.reason["approval"] = json!(approval.answer);
+            outcomes.extend(self.take(&p.result, p.steps, &p.trigger, &p.reason, now_ms, vec![ResponseOutcome::Approval(approval)]));
+        }
+        outcomes
+    }
+
+    /// Take `steps` for `result`, after `outcomes` (the approval), and keep them
+    fn take(&self, result: &RiskResult, steps: Vec<Step>, trigger: &str, reason: &Value, now_ms: i64, mut outcomes: Vec<ResponseOutcome>) -> Vec<ResponseOutcome> {
+        for step in steps {
+            let outcome = match step {
+                Step::Process(action, pid, name) => ResponseOutcome::Process(act_on_process(&self.config, action, pid, Some(&name), trigger, self.dry_run)),
+                Step::Block(ip) => ResponseOutcome::Network(self.firewall.block_remote(ip, None, None, trigger, now_ms)),
+                Step::Isolate => ResponseOutcome::Network(self.firewall.isolate(trigger, now_ms)),
+                Step::Quarantine(path, sha256) => {
+                    ResponseOutcome::File(quarantine_file(&self.config, &self.data_dir, &path, sha256.as_deref(), trigger, now_ms, self.dry_run))
+                }
+                Step::Notify => {
+                    let taken = outcomes
+                        .iter()
+                        .filter(|o| o.status() == CommandStatus::Done && !matches!(o, ResponseOutcome::Approval(_)))
+                        .map(ResponseOutcome::describe)
+                        .collect();
+                    ResponseOutcome::Notice(self.notify(result, taken, trigger))
+                }
+            };
+            outcomes.push(outcome);
+        }
+        for outcome in outcomes.iter().filter(|o| !matches!(o, ResponseOutcome::Approval(_))) {
+            self.keep("policy", reason.clone(), outcome.before(), outcome, now_ms);
+        }
+        outcomes
+    }
+
+    fn notify(&self, result: &RiskResult, taken: Vec<String>, trigger: &str) -> NoticeOutcome {
+        let mut outcome = NoticeOutcome {
+            action: "notify",
+            taken,
+            trigger: trigger.to_string(),
+            status: CommandStatus::Done,
+            error: None,
+            dry_run: self.dry_run,
+        };
+        if let Some(reason) = disabled(&self.config, ResponseAction::Notify) {
+            outcome.status = CommandStatus::Refused;
+            outcome.error = Some(reason);
+        } else if self.dry_run {
+            tracing::info!(event_id = %result.event_id, taken = ?outcome.taken, "dry run: would have notified the user");
+        } else if let Some(notifier) = &self.notifier {
+            match notifier.response_notice(result, &outcome.taken, Instant::now()) {
+                Some(notice) => notifier.send(notice),
+                None => {
+                    outcome.status = CommandStatus::Refused;
+                    outcome.error = Some("a notification was shown less than notify.min_interval_secs ago".to_string());
+                }
+            }
+        }
+        outcome
+    }
+
+    /// Keep `outcome`, taken by `actor` (`policy` or `server`) because of `reason` on a target
+    /// found in `before`, in the response trail when it was taken; returns the record's id
+    pub fn keep(&self, actor: &str, reason: Value, before: Value, outcome: &ResponseOutcome, now_ms: i64) -> Option<String> {
+        let store = self.store.as_ref().filter(|_| outcome.status() == CommandStatus::Done && !outcome.dry_run())?;
+        let record = ResponseRecord::new(actor, outcome.trigger(), reason, before, outcome, now_ms);
+        match store.record_response(&record) {
+            Ok(()) => Some(record.id),
+            Err(e) => {
+                tracing::warn!(action = outcome.action(), error = %e, "failed to keep the response action");
+                None
+            }
+        }
+    }
+
+    /// Undo the response action kept as `id`, for `actor` and
+    /// `trigger`; the undo is kept on its record, unless it only ran as a dry run. Only the
+    /// server releases an isolation this way; at the machine that takes the operator credential
+    /// ([`super::isolation::release_locally`])
+    pub fn undo(&self, id: &str, actor: UndoActor, trigger: &str, now_ms: i64) -> Result<ResponseRecord, BoxError> {
+        let store = self.store.as_ref().ok_or("no response trail")?;
+        let mut record = store.response_record(id)?.ok_or_else(|| format!("no response action {}", id))?;
+        if !record.reversible() {
+            return Err(format!("{} can't be undone", record.action).into());
+        }
+        if record.is_undone() {
+            return Err(format!("response action {} was undone already", id).into());
+        }
+        if record.action == "isolate" && actor != UndoActor::Server {
+            return Err("an isolation is released by the server, or with release-isolation and the operator credential".into());
+        }
+        let field = |name: &str| record.outcome.get(name).and_then(Value::as_str).unwrap_or_default().to_string();
+        let outcome = match record.action.as_str() {
+            "suspend_process" => {
+                let pid = record.outcome.get("pid").and_then(Value::as_u64).and_then(|pid| u32::try_from(pid).ok()).ok_or("no pid recorded")?;
+                ResponseOutcome::Process(resume_process(&self.config, pid, &field("name"), trigger, self.dry_run))
+            }
+            "block_network" => ResponseOutcome::Network(self.firewall.unblock(&field("id"), trigger)),
+            "isolate" => ResponseOutcome::Network(self.firewall.release(trigger)),
+            _ => ResponseOutcome::File(quarantine::restore(&self.config, &self.data_dir, &field("id"), trigger, self.dry_run)),
+        };
+        let undo = Undo {
+            ts: now_ms,
+            actor: actor.as_str().to_string(),
+            trigger: trigger.to_string(),
+            status: outcome.status(),
+            outcome: serde_json::to_value(&outcome).unwrap_or_default(),
+        };
+        audit::record("response_undone", json!({ "id": id, "action": record.action, "undo": undo, "dry_run": outcome.dry_run() }));
+        record.undone = Some(undo);
+        if !outcome.dry_run() {
+            store.record_response(&record)?;
+        }
+        Ok(record)
+    }
+}
@@ -0,0 +1,305 @@
+//! File quarantine (`quarantine`): a file the response policy implicates, or the executable of
+//! an implicated process, is moved out of reach into `data_dir` ([`QUARANTINE_DIR`]): renamed
+//! (copied and removed across file systems) to `<id>` with its permissions cleared, beside
+//! `<id>.json` recording where it came from, its SHA-256 and size, and what asked for it. Files
+//! matching `response.quarantine.protected_paths`, the agent's executable and `data_dir`,
+//! anything but a regular file (symlinks aren't followed), files over
+//! `response.quarantine.max_bytes`, and a file whose content changed since it was seen are
+//! refused. A quarantined file can be put back where it was with its permissions ([`restore`]),
+//! unless another file took its place. Every quarantine and restore, made, refused, or failed, is
+//! logged and audited (`file_quarantined`, `file_restored`).
+
+use super::{disabled, ResponseAction};
+use crate::commands::CommandStatus;
+use crate::config::ResponseConfig;
+use crate::logging::audit;
+use crate::risk::rules::wildcard_match;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Quarantined files, under `data_dir`
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// Record of a quarantined file, kept beside it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinedFile {
+    pub id: String,
+    pub original_path: String,
+    pub sha256: String,
+    pub size: u64,
+    /// Permission bits it had (Unix)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// What asked for it: `risk:<event id>`
+    pub trigger: String,
+    /// When it was quarantined (ms)
+    pub quarantined_at: i64,
+}
+
+/// Outcome of a quarantine or restore
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantineOutcome {
+    /// `quarantine` or `restore`
+    pub action: &'static str,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    pub trigger: String,
+    pub status: CommandStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Only logged (`dry_run`)
+    pub dry_run: bool,
+}
+
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut h = Sha256::new();
+    std::io::copy(&mut file, &mut h)?;
+    Ok(format!("{:x}", h.finalize()))
+}
+
+/// Why `path` must not be quarantined, if it mustn't
+fn refusal(config: &ResponseConfig, data_dir: &Path, path: &Path) -> Option<String> {
+    if !path.is_absolute() {
+        return Some(format!("{} is not an absolute path", path.display()));
+    }
+    let text = path.to_string_lossy();
+    if config.quarantine.protected_paths.iter().any(|p| wildcard_match(p, &text)) {
+        return Some(format!("{} is protected (response.quarantine.protected_paths)", text));
+    }
+    let agent = std::env::current_exe().ok();
+    let data_dir = data_dir.canonicalize().unwrap_or_else(|_| data_dir.to_path_buf());
+    let resolved = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if agent.is_some_and(|agent| agent == resolved) || resolved.starts_with(&data_dir) {
+        return Some(format!("{} belongs to the agent", text));
+    }
+    None
+}
+
+#[cfg(unix)]
+fn mode(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode(_: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+fn private_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Move `path` into `dir` as `id`, with its permissions cleared; an error leaves it in place
+fn stash(path: &Path, dir: &Path, id: &str) -> std::io::Result<()> {
+    let target = dir.join(id);
+    if std::fs::rename(path, &target).is_err() {
+        // Another file system: copied, then removed; a file that can't be removed stays in place
+        std::fs::copy(path, &target)?;
+        if let Err(e) = std::fs::remove_file(path) {
+            let _ = std::fs::remove_file(&target);
+            return Err(e);
+        }
+    }
+    #[cfg(unix)]
+    let cleared = {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o000))
+    };
+    #[cfg(not(unix))]
+    let cleared = std::fs::metadata(&target).and_then(|m| {
+        let mut permissions = m.permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&target, permissions)
+    });
+    // Already out of place, inside the private quarantine directory
+    if let Err(e) = cleared {
+        tracing::warn!(id, error = %e, "quarantined file keeps its permissions");
+    }
+    Ok(())
+}
+
+/// Quarantine `path` into `data_dir`, expected to hash to `expected_sha256` when given, for
+/// `trigger`; the outcome is logged and audited
+pub fn quarantine_file(
+    config: &ResponseConfig,
+    data_dir: &Path,
+    path: &str,
+    expected_sha256: Option<&str>,
+    trigger: &str,
+    now_ms: i64,
+    dry_run: bool,
+) -> QuarantineOutcome {
+    let dry_run = dry_run || config.dry_run;
+    let mut outcome = QuarantineOutcome {
+        action: "quarantine",
+        path: path.to_string(),
+        id: None,
+        sha256: None,
+        size: None,
+        trigger: trigger.to_string(),
+        status: CommandStatus::Done,
+        error: None,
+        dry_run,
+    };
+    let file = Path::new(path);
+    let checked = match disabled(config, ResponseAction::Quarantine).or_else(|| refusal(config, data_dir, file)) {
+        Some(reason) => Err((CommandStatus::Refused, reason)),
+        None => match std::fs::symlink_metadata(file) {
+            Err(e) => Err((CommandStatus::Failed, format!("{}: {}", path, e))),
+            Ok(m) if !m.is_file() => Err((CommandStatus::Refused, format!("{} is not a regular file", path))),
+            Ok(m) if m.len() > config.quarantine.max_bytes => {
+                Err((CommandStatus::Refused, format!("{} is over response.quarantine.max_bytes", path)))
+            }
+            Ok(m) => hash_file(file).map(|hash| (hash, m)).map_err(|e| (CommandStatus::Failed, format!("{}: {}", path, e))),
+        },
+    };
+    let checked = checked.and_then(|(hash, metadata)| match expected_sha256 {
+        Some(expected) if !expected.eq_ignore_ascii_case(&hash) => Err((CommandStatus::Refused, format!("{} changed since it was seen", path))),
+        _ => Ok((hash, metadata)),
+    });
+    match checked {
+        Err((status, error)) => {
+            outcome.status = status;
+            outcome.error = Some(error);
+        }
+        Ok((sha256, metadata)) => {
+            let id = uuid::Uuid::new_v4().simple().to_string()[..12].to_string();
+            outcome.id = Some(id.clone());
+            outcome.sha256 = Some(sha256.clone());
+            outcome.size = Some(metadata.len());
+            if !dry_run {
+                let dir = data_dir.join(QUARANTINE_DIR);
+                let record = QuarantinedFile {
+                    id: id.clone(),
+                    original_path: path.to_string(),
+                    sha256,
+                    size: metadata.len(),
+                    mode: mode(&metadata),
+                    trigger: trigger.to_string(),
+                    quarantined_at: now_ms,
+                };
+                // Recorded first, so no file is moved without a record of where it came from
+                let record_path = dir.join(format!("{}.json", id));
+                let mut moved = private_dir(&dir).and_then(|_| std::fs::write(&record_path, serde_json::to_vec_pretty(&record)?));
+                if moved.is_ok() {
+                    moved = stash(file, &dir, &id);
+                    if moved.is_err() {
+                        let _ = std::fs::remove_file(&record_path);
+                    }
+                }
+                if let Err(e) = moved {
+                    outcome.status = CommandStatus::Failed;
+                    outcome.error = Some(e.to_string());
+                }
+            }
+        }
+    }
+    match (outcome.status, dry_run) {
+        (CommandStatus::Done, true) => tracing::info!(path, id = ?outcome.id, trigger, "dry run: would have quarantined the file"),
+        (CommandStatus::Done, false) => tracing::warn!(path, id = ?outcome.id, trigger, "file quarantined"),
+        _ => tracing::warn!(path, trigger, error = ?outcome.error, "file not quarantined"),
+    }
+    audit::record("file_quarantined", serde_json::to_value(&outcome).unwrap_or_default());
+    outcome
+}
+
+/// Record of the file quarantined as `id` in `data_dir`
+pub fn load(data_dir: &Path, id: &str) -> Option<QuarantinedFile> {
+    // Ids are ours: hex only, so a crafted one can't name a path outside the directory
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes = std::fs::read(data_dir.join(QUARANTINE_DIR).join(format!("{}.json", id))).ok()?;
+    serde_json::from_slice(&bytes).map_err(|e| tracing::warn!(id, error = %e, "quarantine record unreadable")).ok()
+}
+
+/// Move `from` to `to` and give it `mode` (Unix) or make it writable again
+fn put_back(from: &Path, to: &Path, mode: Option<u32>) -> std::io::Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(to, std::fs::Permissions::from_mode(mode.unwrap_or(0o600)))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = mode;
+        let mut permissions = std::fs::metadata(to)?.permissions();
+        permissions.set_readonly(false);
+        std::fs::set_permissions(to, permissions)
+    }
+}
+
+/// Put the file quarantined as `id` in `data_dir` back where it was, for `trigger`; refused when
+/// another file is there now. The outcome is logged and audited
+pub fn restore(config: &ResponseConfig, data_dir: &Path, id: &str, trigger: &str, dry_run: bool) -> QuarantineOutcome {
+    let dry_run = dry_run || config.dry_run;
+    let record = load(data_dir, id);
+    let mut outcome = QuarantineOutcome {
+        action: "restore",
+        path: record.as_ref().map(|r| r.original_path.clone()).unwrap_or_default(),
+        id: Some(id.to_string()),
+        sha256: record.as_ref().map(|r| r.sha256.clone()),
+        size: record.as_ref().map(|r| r.size),
+        trigger: trigger.to_string(),
+        status: CommandStatus::Done,
+        error: None,
+        dry_run,
+    };
+    let dir = data_dir.join(QUARANTINE_DIR);
+    let rejected = match record {
+        None => Some((CommandStatus::Failed, format!("no quarantined file {}", id))),
+        Some(ref record) if std::fs::symlink_metadata(&record.original_path).is_ok() => {
+            Some((CommandStatus::Refused, format!("{} exists; not overwritten", record.original_path)))
+        }
+        Some(_) => None,
+    };
+    if let Some((status, error)) = rejected {
+        outcome.status = status;
+        outcome.error = Some(error);
+    } else if let (Some(record), false) = (record, dry_run) {
+        let file = dir.join(id);
+        #[cfg(unix)]
+        {
+            // Readable again to check it, as an unprivileged agent can't read it otherwise
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o600));
+        }
+        let restored = match hash_file(&file) {
+            Err(e) => Err(e.to_string()),
+            Ok(hash) if hash != record.sha256 => Err(format!("quarantined file {} doesn't match its record", id)),
+            Ok(_) => put_back(&file, Path::new(&record.original_path), record.mode)
+                .and_then(|_| std::fs::remove_file(dir.join(format!("{}.json", id))))
+                .map_err(|e| e.to_string()),
+        };
+        if let Err(e) = restored {
+            outcome.status = CommandStatus::Failed;
+            outcome.error = Some(e);
+        }
+    }
+    let path = outcome.path.as_str();
+    match (outcome.status, dry_run) {
+        (CommandStatus::Done, true) => tracing::info!(path, id, trigger, "dry run: would have restored the file"),
+        (CommandStatus::Done, false) => tracing::warn!(path, id, trigger, "quarantined file restored"),
+        _ => tracing::warn!(path, id, trigger, error = ?outcome.error, "quarantined file not restored"),
+    }
+    audit::record("file_restored", serde_json::to_value(&outcome).unwrap_or_default());
+    outcome
+}
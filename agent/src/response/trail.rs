@@ -0,0 +1,95 @@
+//! Response trail: every response action taken (not refused, failed, or only logged in a dry
+//! run) is kept in the store ([`crate::SecureStore::record_response`]) with who asked for it,
+//! what it was taken against, why, and the state of the target before, so a containment mistake
+//! can be traced and undone ([`super::ResponseEngine::undo`]): a suspended process is resumed, a
+//! network block lifted, the isolation released, a quarantined file put back. A killed process
+//! can't be brought back, and only the server undoes an isolation: at the machine it is
+//! released with the operator credential (`release-isolation`). The undo is kept on the action's record and audited
+//! (`response_undone`), besides the audit entry of the undoing action itself.
+
+use super::ResponseOutcome;
+use crate::commands::CommandStatus;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Actions that can be undone
+pub const REVERSIBLE: &[&str] = &["suspend_process", "block_network", "isolate", "quarantine"];
+
+/// Response action taken, as kept in the store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseRecord {
+    /// Id to undo it by
+    pub id: String,
+    /// When it was taken (ms)
+    pub ts: i64,
+    pub action: String,
+    /// Who asked for it: `policy` or `server`
+    pub actor: String,
+    /// What asked for it: `risk:<event id>` or `command:<id>`
+    pub trigger: String,
+    /// Why: the policy rules matched and the result's level, score, and reasons, or the
+    /// command's arguments
+    pub reason: Value,
+    /// State of the target before the action
+    pub before: Value,
+    /// Outcome of the action, with its target
+    pub outcome: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undone: Option<Undo>,
+}
+
+/// Who asks for an undo: the server through an uplink command, or the operator at the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoActor {
+    Server,
+    Operator,
+}
+
+impl UndoActor {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UndoActor::Server => "server",
+            UndoActor::Operator => "operator",
+        }
+    }
+}
+
+/// Undo of a response action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Undo {
+    /// When it was undone (ms)
+    pub ts: i64,
+    /// Who asked for it: `server` or `operator`
+    pub actor: String,
+    /// What asked for it: `command:<id>` or `operator`
+    pub trigger: String,
+    pub status: CommandStatus,
+    /// Outcome of the undoing action
+    pub outcome: Value,
+}
+
+impl ResponseRecord {
+    /// Record of `outcome`, taken by `actor` because of `reason` on a target found in `before`
+    pub fn new(actor: &str, trigger: &str, reason: Value, before: Value, outcome: &ResponseOutcome, now_ms: i64) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().simple().to_string()[..12].to_string(),
+            ts: now_ms,
+            action: outcome.action().to_string(),
+            actor: actor.to_string(),
+            trigger: trigger.to_string(),
+            reason,
+            before,
+            outcome: serde_json::to_value(outcome).unwrap_or_default(),
+            undone: None,
+        }
+    }
+
+    pub fn reversible(&self) -> bool {
+        REVERSIBLE.contains(&self.action.as_str())
+    }
+
+    /// Whether it was undone already
+    pub fn is_undone(&self) -> bool {
+        self.undone.as_ref().is_some_and(|u| u.status == CommandStatus::Done)
+    }
+}
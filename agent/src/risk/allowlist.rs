@@ -0,0 +1,197 @@
+//! Allowlist (`risk.allowlist`): events from known-good executables, publishers, paths, or remote
+//! networks are taken out of the window before features, rules, and entity risk see them, and the
+//! result records why, so noisy internal tooling doesn't raise alerts.
+
+use super::rules::wildcard_match;
+use crate::collectors::{Event, EventKind};
+use crate::config::AllowlistConfig;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Events dropped from a window for one reason
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Suppression {
+    /// Matching allowlist entry, e.g. `path:/opt/backup/*` or `cidr:10.0.0.0/8`
+    pub reason: String,
+    pub events: usize,
+}
+
+/// Parsed `address/prefix` (a bare address is a single host)
+#[derive(Debug, Clone)]
+pub(super) struct Cidr {
+    pub(super) text: String,
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    pub(super) fn parse(text: &str) -> Option<Self> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u32>().ok()?)),
+            None => (text.parse::<IpAddr>().ok()?, None),
+        };
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        (prefix <= bits).then(|| Self {
+            text: text.to_string(),
+            network: addr,
+            prefix,
+        })
+    }
+
+    pub(super) fn contains(&self, addr: IpAddr) -> bool {
+        let (net, ip, bits) = match (self.network, addr) {
+            (IpAddr::V4(n), IpAddr::V4(a)) => (u32::from(n) as u128, u32::from(a) as u128, 32),
+            (IpAddr::V6(n), IpAddr::V6(a)) => (u128::from(n), u128::from(a), 128),
+            (IpAddr::V6(n), IpAddr::V4(a)) => (u128::from(n), u128::from(a.to_ipv6_mapped()), 128),
+            (IpAddr::V4(_), IpAddr::V6(a)) => match a.to_ipv4_mapped() {
+                Some(a) => return self.contains(IpAddr::V4(a)),
+                None => return false,
+            },
+        };
+        let host_bits = bits - self.prefix;
+        host_bits >= 128 || (net >> host_bits) == (ip >> host_bits)
+    }
+}
+
+/// Address part of `ip`, `ip:port`, or `[ipv6]:port`
+pub(super) fn parse_addr(text: &str) -> Option<IpAddr> {
+    if let Ok(addr) = text.parse() {
+        return Some(addr);
+    }
+    text.parse::<std::net::SocketAddr>().ok().map(|s| s.ip())
+}
+
+/// Cached executable hash, valid while mtime and size are unchanged
+struct HashedExe {
+    modified: Option<SystemTime>,
+    size: u64,
+    sha256: String,
+}
+
+/// SHA-256 of executables by path, so unchanged binaries are hashed once
+#[derive(Default)]
+pub(super) struct ExeHasher {
+    cache: Mutex<HashMap<String, HashedExe>>,
+}
+
+impl ExeHasher {
+    /// Lowercase hex SHA-256 of the file at `exe`, `None` if it can't be read
+    pub(super) fn sha256(&self, exe: &str) -> Option<String> {
+        let meta = std::fs::metadata(exe).ok()?;
+        let (modified, size) = (meta.modified().ok(), meta.len());
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(cached) = cache.get(exe) {
+            if cached.modified == modified && cached.size == size {
+                return Some(cached.sha256.clone());
+            }
+        }
+        let data = std::fs::read(Path::new(exe)).ok()?;
+        let sha256 = format!("{:x}", Sha256::digest(&data));
+        cache.insert(exe.to_string(), HashedExe { modified, size, sha256: sha256.clone() });
+        Some(sha256)
+    }
+}
+
+pub struct Allowlist {
+    exe_hashes: Vec<String>,
+    publishers: Vec<String>,
+    paths: Vec<String>,
+    cidrs: Vec<Cidr>,
+    hasher: ExeHasher,
+}
+
+impl Allowlist {
+    pub fn new(config: &AllowlistConfig) -> Self {
+        let cidrs = config
+            .remote_cidrs
+            .iter()
+            .filter_map(|c| {
+                let parsed = Cidr::parse(c);
+                if parsed.is_none() {
+                    tracing::warn!(cidr = %c, "ignoring invalid allowlist CIDR");
+                }
+                parsed
+            })
+            .collect();
+        Self {
+            exe_hashes: config.exe_hashes.iter().map(|h| h.to_ascii_lowercase()).collect(),
+            publishers: config.publishers.clone(),
+            paths: config.paths.clone(),
+            cidrs,
+            hasher: ExeHasher::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exe_hashes.is_empty() && self.publishers.is_empty() && self.paths.is_empty() && self.cidrs.is_empty()
+    }
+
+    fn path_reason(&self, path: &str) -> Option<String> {
+        self.paths
+            .iter()
+            .find(|p| wildcard_match(p, path))
+            .map(|p| format!("path:{}", p))
+    }
+
+    /// Allowlist entry matching `event`, if any
+    pub fn reason(&self, event: &Event) -> Option<String> {
+        match &event.kind {
+            EventKind::Process(p) => {
+                if let Some(publisher) = p.publisher.as_deref() {
+                    if let Some(allowed) = self.publishers.iter().find(|a| a.eq_ignore_ascii_case(publisher)) {
+                        return Some(format!("publisher:{}", allowed));
+                    }
+                }
+                let exe = p.exe.as_deref()?;
+                if let Some(reason) = self.path_reason(exe) {
+                    return Some(reason);
+                }
+                if self.exe_hashes.is_empty() {
+                    return None;
+                }
+                let hash = self.hasher.sha256(exe)?;
+                self.exe_hashes.contains(&hash).then(|| format!("exe_hash:{}", hash))
+            }
+            EventKind::FileIntegrity(f) => {
+                if self.exe_hashes.contains(&f.hash_sha256.to_ascii_lowercase()) {
+                    return Some(format!("exe_hash:{}", f.hash_sha256));
+                }
+                self.path_reason(&f.path)
+            }
+            EventKind::Network(n) => {
+                let addr = parse_addr(n.remote_addr.as_deref()?)?;
+                self.cidrs
+                    .iter()
+                    .find(|c| c.contains(addr))
+                    .map(|c| format!("cidr:{}", c.text))
+            }
+            EventKind::Privilege(_) => None,
+        }
+    }
+
+    /// Split `events` into those to score and per-reason counts of those suppressed
+    pub fn filter(&self, events: Vec<Event>) -> (Vec<Event>, Vec<Suppression>) {
+        if self.is_empty() {
+            return (events, Vec::new());
+        }
+        let mut kept = Vec::with_capacity(events.len());
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for event in events {
+            match self.reason(&event) {
+                Some(reason) => *counts.entry(reason).or_default() += 1,
+                None => kept.push(event),
+            }
+        }
+        let suppressed = counts
+            .into_iter()
+            .map(|(reason, events)| Suppression { reason, events })
+            .collect();
+        (kept, suppressed)
+    }
+}
@@ -0,0 +1,179 @@
+//! Cross-kind correlation (`risk.correlation`): patterns of steps across event kinds (new unsigned
+//! process, connection to a new address, privilege attempt, sensitive write, rule hit) that are all
+//! seen within a time window, in any order. A match boosts the score and is named on the result.
+
+use super::rules::{wildcard_match, RuleHit};
+use crate::collectors::{Event, EventKind, FileIntegrityChange};
+use crate::config::{AttackTag, CorrelationConfig, CorrelationPattern, CorrelationStep};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Executables / remote addresses remembered as "seen"; the set restarts beyond this
+const MAX_SEEN: usize = 50_000;
+
+/// Correlation pattern that matched
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CorrelationHit {
+    pub pattern: String,
+    pub boost: f32,
+    /// Event behind each step, in step order
+    pub event_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attack: Vec<AttackTag>,
+}
+
+/// Patterns enabled by `risk.correlation.builtin`
+pub fn builtin_patterns() -> Vec<CorrelationPattern> {
+    vec![CorrelationPattern {
+        id: "unsigned-exec-egress-privesc".into(),
+        description: Some("New unsigned process, connection to a new address, and a privilege attempt".into()),
+        boost: 0.7,
+        window_secs: 300,
+        steps: vec![
+            CorrelationStep::NewProcess { unsigned_only: true },
+            CorrelationStep::NewRemote,
+            CorrelationStep::PrivilegeAttempt { failed_only: false },
+        ],
+        attack: vec![AttackTag::new("T1071", "TA0011"), AttackTag::new("T1548", "TA0004")],
+    }]
+}
+
+#[derive(Default)]
+struct State {
+    /// False until the first window has seeded the seen sets (everything is new at startup); that
+    /// window records no steps
+    seeded: bool,
+    executables: HashSet<String>,
+    remotes: HashSet<String>,
+    /// Per pattern, per step: (ts ms, event id) of the latest occurrence
+    steps: Vec<Vec<Option<(i64, String)>>>,
+}
+
+fn remember(set: &mut HashSet<String>, value: &str) -> bool {
+    if set.len() >= MAX_SEEN {
+        set.clear();
+    }
+    set.insert(value.to_string())
+}
+
+pub struct Correlator {
+    patterns: Vec<CorrelationPattern>,
+    state: Mutex<State>,
+}
+
+impl Correlator {
+    pub fn new(config: &CorrelationConfig) -> Self {
+        let mut patterns = if config.builtin { builtin_patterns() } else { Vec::new() };
+        patterns.extend(config.patterns.iter().cloned());
+        patterns.retain(|p| !p.steps.is_empty());
+        for pattern in &mut patterns {
+            pattern.boost = pattern.boost.clamp(0.0, 1.0);
+        }
+        let steps = patterns.iter().map(|p| vec![None; p.steps.len()]).collect();
+        Self {
+            patterns,
+            state: Mutex::new(State { steps, ..State::default() }),
+        }
+    }
+
+    /// Executables and remote addresses seen so far, sorted
+    pub fn known(&self) -> (Vec<String>, Vec<String>) {
+        let state = self.state.lock().unwrap();
+        let mut executables: Vec<String> = state.executables.iter().cloned().collect();
+        let mut remotes: Vec<String> = state.remotes.iter().cloned().collect();
+        executables.sort();
+        remotes.sort();
+        (executables, remotes)
+    }
+
+    /// Restore what was seen before a restart; when anything is restored, the next window is
+    /// checked for first sightings instead of seeding.
+    pub fn restore(&self, executables: Vec<String>, remotes: Vec<String>) {
+        let mut state = self.state.lock().unwrap();
+        if executables.is_empty() && remotes.is_empty() {
+            return;
+        }
+        state.seeded = true;
+        for exe in executables.into_iter().take(MAX_SEEN) {
+            state.executables.insert(exe);
+        }
+        for remote in remotes.into_iter().take(MAX_SEEN) {
+            state.remotes.insert(remote);
+        }
+    }
+
+    /// Record the steps in `events` and `hits` at `ts`, and return the patterns now complete.
+    /// A matched pattern starts over, so it fires once per occurrence rather than every window.
+    pub fn evaluate(&self, events: &[Event], hits: &[RuleHit], ts: i64) -> Vec<CorrelationHit> {
+        let mut state = self.state.lock().unwrap();
+        let seeding = !state.seeded;
+        state.seeded = true;
+
+        // Which events are first sightings
+        let mut new_process = Vec::new();
+        let mut new_remote = Vec::new();
+        for e in events {
+            match &e.kind {
+                EventKind::Process(p) => {
+                    let exe = p.exe.as_deref().unwrap_or(&p.name);
+                    if remember(&mut state.executables, exe) {
+                        new_process.push((e, p.publisher.is_none()));
+                    }
+                }
+                EventKind::Network(n) => {
+                    if let Some(remote) = n.remote_addr.as_deref() {
+                        if remember(&mut state.remotes, remote) {
+                            new_remote.push(e);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if seeding {
+            return Vec::new();
+        }
+
+        let mut out = Vec::new();
+        for (pattern, seen) in self.patterns.iter().zip(state.steps.iter_mut()) {
+            for (step, slot) in pattern.steps.iter().zip(seen.iter_mut()) {
+                let matched: Option<String> = match step {
+                    CorrelationStep::NewProcess { unsigned_only } => new_process
+                        .iter()
+                        .find(|(_, unsigned)| *unsigned || !unsigned_only)
+                        .map(|(e, _)| e.id.clone()),
+                    CorrelationStep::NewRemote => new_remote.first().map(|e| e.id.clone()),
+                    CorrelationStep::PrivilegeAttempt { failed_only } => events
+                        .iter()
+                        .find(|e| matches!(&e.kind, EventKind::Privilege(p) if !p.success || !failed_only))
+                        .map(|e| e.id.clone()),
+                    CorrelationStep::FileWrite { patterns } => events
+                        .iter()
+                        .find(|e| match &e.kind {
+                            EventKind::FileIntegrity(f) => {
+                                !matches!(f.event, FileIntegrityChange::Scanned)
+                                    && patterns.iter().any(|p| wildcard_match(p, &f.path))
+                            }
+                            _ => false,
+                        })
+                        .map(|e| e.id.clone()),
+                    CorrelationStep::Rule { id } => hits.iter().find(|h| &h.rule == id).map(|h| h.event_id.clone()),
+                };
+                if let Some(event_id) = matched {
+                    *slot = Some((ts, event_id));
+                }
+            }
+            let horizon = ts.saturating_sub(pattern.window_secs.saturating_mul(1000) as i64);
+            if seen.iter().all(|s| s.as_ref().is_some_and(|(at, _)| *at >= horizon)) {
+                out.push(CorrelationHit {
+                    pattern: pattern.id.clone(),
+                    boost: pattern.boost,
+                    event_ids: seen.iter_mut().filter_map(|s| s.take().map(|(_, id)| id)).collect(),
+                    attack: pattern.attack.clone(),
+                });
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,47 @@
+//! Asset criticality (`risk.criticality`): a per-host weight, raised further for windows that
+//! involve the host's critical paths or services, so the same activity ranks higher on a domain
+//! controller than on a kiosk.
+
+use super::rules::wildcard_match;
+use crate::collectors::{Event, EventKind};
+use crate::config::CriticalityConfig;
+
+/// Weights are floored here so threshold division stays finite
+const MIN_WEIGHT: f32 = 0.01;
+
+pub struct Criticality {
+    config: CriticalityConfig,
+}
+
+impl Criticality {
+    pub fn new(config: CriticalityConfig) -> Self {
+        Self { config }
+    }
+
+    /// `event` involves a critical path or service
+    fn critical(&self, event: &Event) -> bool {
+        let path_match = |path: &str| self.config.critical_paths.iter().any(|p| wildcard_match(p, path));
+        match &event.kind {
+            EventKind::Process(p) => {
+                self.config.critical_services.iter().any(|s| wildcard_match(s, &p.name))
+                    || p.exe.as_deref().is_some_and(path_match)
+            }
+            EventKind::FileIntegrity(f) => path_match(&f.path),
+            EventKind::Network(_) | EventKind::Privilege(_) => false,
+        }
+    }
+
+    /// Weight of a window whose subject is `subject` and whose hits came from `hit_events`: the
+    /// asset weight, times `critical_weight` when one of those events is critical
+    pub fn weight(&self, subject: &str, hit_events: &[&str], events: &[Event]) -> f32 {
+        let mut weight = self.config.asset_weight;
+        let critical = events
+            .iter()
+            .filter(|e| e.id == subject || hit_events.contains(&e.id.as_str()))
+            .any(|e| self.critical(e));
+        if critical {
+            weight *= self.config.critical_weight;
+        }
+        weight.max(MIN_WEIGHT)
+    }
+}
@@ -0,0 +1,94 @@
+//! Alert deduplication (`risk.dedup`): results with the same subject entity, rules, correlation
+//! patterns, matched indicators, and ATT&CK techniques share a fingerprint, and repeats within the
+//! window of the first occurrence are counted against it so the store keeps one row and uplink
+//! posts rarely.
+
+use super::RiskResult;
+use crate::config::AlertDedupConfig;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Fingerprints tracked at most; the counts restart beyond this
+const MAX_TRACKED: usize = 10_000;
+
+/// Equivalence class of a result and how often it occurred
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// Hex digest over subject entity, rules, correlation patterns, indicators, and techniques
+    pub id: String,
+    /// First occurrence in the current window (ms)
+    pub first_seen: i64,
+    /// Occurrences since `first_seen`, this one included
+    pub occurrences: u32,
+}
+
+impl Fingerprint {
+    /// Not the first occurrence in its window
+    pub fn is_repeat(&self) -> bool {
+        self.occurrences > 1
+    }
+}
+
+/// Digest of what makes `result` the same alert, or `None` when nothing but the score flagged it
+fn digest(result: &RiskResult, entity: &str) -> Option<String> {
+    let mut rules: Vec<&str> = result.rule_hits.iter().map(|h| h.rule.as_str()).collect();
+    let mut patterns: Vec<&str> = result.correlations.iter().map(|c| c.pattern.as_str()).collect();
+    let mut indicators: Vec<&str> = result.ioc_hits.iter().map(|h| h.indicator.as_str()).collect();
+    if rules.is_empty() && patterns.is_empty() && indicators.is_empty() && result.attack.is_empty() {
+        return None;
+    }
+    rules.sort_unstable();
+    rules.dedup();
+    patterns.sort_unstable();
+    indicators.sort_unstable();
+    indicators.dedup();
+    let mut hasher = Sha256::new();
+    let parts = [entity.to_string(), rules.join(","), patterns.join(","), indicators.join(","), result.techniques().join(",")];
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0u8]);
+    }
+    let digest = hasher.finalize();
+    Some(digest[..8].iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub struct Deduplicator {
+    config: AlertDedupConfig,
+    /// (first_seen, occurrences) by fingerprint id
+    series: Mutex<HashMap<String, (i64, u32)>>,
+}
+
+impl Deduplicator {
+    pub fn new(config: AlertDedupConfig) -> Self {
+        Self {
+            config,
+            series: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fingerprint `result` (attributed to `entity`) at `ts` and count the occurrence
+    pub fn observe(&self, result: &RiskResult, entity: &str, ts: i64) -> Option<Fingerprint> {
+        if !self.config.enabled {
+            return None;
+        }
+        let id = digest(result, entity)?;
+        let window_ms = (self.config.window_secs as i64).saturating_mul(1000);
+        let mut series = self.series.lock().unwrap();
+        if series.len() >= MAX_TRACKED && !series.contains_key(&id) {
+            series.clear();
+        }
+        let (first_seen, occurrences) = series.entry(id.clone()).or_insert((ts, 0));
+        if ts < *first_seen || ts - *first_seen >= window_ms {
+            *first_seen = ts;
+            *occurrences = 0;
+        }
+        *occurrences += 1;
+        Some(Fingerprint {
+            id,
+            first_seen: *first_seen,
+            occurrences: *occurrences,
+        })
+    }
+}
@@ -1,9 +1,25 @@
 //! Combines anomaly score from model with configurable thresholds; produces risk level.
 
-use crate::config::RiskConfig;
+use super::allowlist::{Allowlist, Suppression};
+use super::correlation::{CorrelationHit, Correlator};
+use super::criticality::Criticality;
+use super::dedup::{Deduplicator, Fingerprint};
+use super::entity::{self, EntityRisk, EntityTracker};
+use super::fusion::{self, SourceScores};
+use super::hysteresis::Hysteresis;
+use super::ioc::{IocHit, IocMatcher};
+use super::killchain::{KillChain, KillChainHit};
+use super::learning::LearnedState;
+use super::rules::{self, RuleHit, RuleSet};
+use super::smoothing::Smoother;
+use crate::collectors::{DeepScan, Event, EventKind};
+use crate::config::{AttackTag, CriticalityMode, RiskConfig, ScoringProfile};
+use crate::model::ClassPrediction;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, schemars::JsonSchema, clap::ValueEnum)]
 #[serde(rename_all = "snake_case")]
 pub enum RiskLevel {
     Low,
@@ -12,10 +28,22 @@ pub enum RiskLevel {
 }
 
 impl RiskLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RiskLevel::Low => "low",
+            RiskLevel::Medium => "medium",
+            RiskLevel::High => "high",
+        }
+    }
+
     pub fn from_score(score: f32, config: &RiskConfig) -> Self {
-        if score >= config.high_threshold {
+        Self::from_thresholds(score, config.medium_threshold, config.high_threshold)
+    }
+
+    pub fn from_thresholds(score: f32, medium: f32, high: f32) -> Self {
+        if score >= high {
             RiskLevel::High
-        } else if score >= config.medium_threshold {
+        } else if score >= medium {
             RiskLevel::Medium
         } else {
             RiskLevel::Low
@@ -23,32 +51,463 @@ pub fn from_score(score: f32, config: &RiskConfig) -> Self {
     }
 }
 
-/// Risk result for a single event
+/// Risk result for a single event (or the window ending at it)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RiskResult {
     pub event_id: String,
     pub score: f32,
     pub level: RiskLevel,
     pub ts: i64,
+    /// Window the score covers (ms); both equal `ts` when unknown
+    #[serde(default)]
+    pub window_start: i64,
+    #[serde(default)]
+    pub window_end: i64,
+    /// Events that contributed to this result
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub event_ids: Vec<String>,
+    /// Top threat class when the model outputs class probabilities
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_class: Option<ClassPrediction>,
+    /// Heuristic rules that matched; `score` is the model score fused with their weights
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rule_hits: Vec<RuleHit>,
+    /// Cross-kind correlation patterns completed in this window (boosts fused like rule weights)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub correlations: Vec<CorrelationHit>,
+    /// Indicators of compromise matched by the window's events (`risk.ioc`; the IOC source score)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ioc_hits: Vec<IocHit>,
+    /// Entities whose kill-chain progression advanced in this window (boosts fused like rule weights)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kill_chain: Vec<KillChainHit>,
+    /// Scoring profile applied (`kind:<kind>` or `source:<source>` of the subject event)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Weight from `risk.criticality` (host, critical paths and services) applied to the score or
+    /// thresholds, when not 1.0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub criticality: Option<f32>,
+    /// Equivalence class for deduplication (`risk.dedup`): set when rules, correlations, or
+    /// techniques flagged the window; a repeat updates the stored result of the first occurrence
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+    /// Model score of this window alone, when `risk.smoothing` replaced it with the entity's average
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unsmoothed_score: Option<f32>,
+    /// Model score before fusion (set when another source contributed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_score: Option<f32>,
+    /// Score of each source before weighting (`risk.fusion`; set when another source contributed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sources: Option<SourceScores>,
+    /// MITRE ATT&CK techniques indicated by the rule hits and the top class, sorted and deduplicated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attack: Vec<AttackTag>,
+    /// Accumulated risk of the entities this window is attributed to (`risk.entity`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entity_risk: Vec<EntityRisk>,
+    /// Level was raised because an entity's accumulated risk crossed `risk.entity` bounds or a
+    /// kill-chain progression reached `risk.kill_chain.high_stages`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub escalated: bool,
+    /// Events left out of this window by the allowlist, counted per matching entry
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suppressed: Vec<Suppression>,
+    /// Level of this window alone, when hysteresis or the learning period reports a different `level`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_level: Option<RiskLevel>,
+    /// The reported level rose to medium or high in this window (outside the alert cooldown)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub alert: bool,
+    /// Scored during `risk.learning_period_days`: `level` is held at low (the computed level is in
+    /// `raw_level`) and no alert is raised
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub learning: bool,
+}
+
+impl RiskResult {
+    /// Technique ids in `attack`
+    pub fn techniques(&self) -> Vec<&str> {
+        self.attack.iter().map(|t| t.technique.as_str()).collect()
+    }
+
+    /// Distinct tactic ids in `attack`
+    pub fn tactics(&self) -> Vec<&str> {
+        let mut tactics: Vec<&str> = self.attack.iter().map(|t| t.tactic.as_str()).collect();
+        tactics.sort_unstable();
+        tactics.dedup();
+        tactics
+    }
+
+    /// What raised the score: rules, correlations, indicators, the model's class, kill-chain
+    /// progress, and entity escalation; `model score` when nothing else did
+    pub fn reasons(&self) -> Vec<String> {
+        let mut reasons: Vec<String> = self.rule_hits.iter().map(|h| h.rule.clone()).collect();
+        reasons.extend(self.correlations.iter().map(|c| c.pattern.clone()));
+        reasons.extend(self.ioc_hits.iter().map(|h| format!("ioc {}", h.indicator)));
+        if let Some(ref class) = self.top_class {
+            reasons.push(format!("class {}", class.label));
+        }
+        if !self.kill_chain.is_empty() {
+            reasons.push("kill chain".to_string());
+        }
+        if self.escalated {
+            reasons.push("entity risk".to_string());
+        }
+        if reasons.is_empty() {
+            reasons.push("model score".to_string());
+        }
+        reasons
+    }
+}
+
+/// Empirical quantile (nearest-rank) of `scores`; `q` in [0, 1]
+fn quantile(sorted: &[f32], q: f32) -> f32 {
+    let rank = (q.clamp(0.0, 1.0) * (sorted.len() - 1) as f32).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
 }
 
 pub struct RiskEngine {
     config: RiskConfig,
+    /// (medium, high) derived from score history when auto-tuning is enabled
+    tuned: RwLock<Option<(f32, f32)>>,
+    rules: RuleSet,
+    entities: EntityTracker,
+    allowlist: Allowlist,
+    hysteresis: Hysteresis,
+    correlator: Correlator,
+    kill_chain: KillChain,
+    smoother: Smoother,
+    dedup: Deduplicator,
+    criticality: Criticality,
+    ioc: IocMatcher,
+    /// Start of the learning period (ms), set by the first window with events
+    learning_started: Mutex<Option<i64>>,
 }
 
 impl RiskEngine {
     pub fn new(config: RiskConfig) -> Self {
-        Self { config }
+        Self {
+            rules: RuleSet::new(&config.rules),
+            entities: EntityTracker::new(config.entity.clone()),
+            allowlist: Allowlist::new(&config.allowlist),
+            hysteresis: Hysteresis::new(config.hysteresis.clone()),
+            correlator: Correlator::new(&config.correlation),
+            kill_chain: KillChain::new(config.kill_chain.clone()),
+            smoother: Smoother::new(config.smoothing.clone()),
+            dedup: Deduplicator::new(config.dedup.clone()),
+            criticality: Criticality::new(config.criticality.clone()),
+            ioc: IocMatcher::new(config.ioc.clone()),
+            config,
+            tuned: RwLock::new(None),
+            learning_started: Mutex::new(None),
+        }
+    }
+
+    /// Effective (medium, high) thresholds: tuned if available, otherwise static config.
+    pub fn thresholds(&self) -> (f32, f32) {
+        self.tuned
+            .read()
+            .ok()
+            .and_then(|t| *t)
+            .unwrap_or((self.config.medium_threshold, self.config.high_threshold))
+    }
+
+    /// Derive thresholds from historical scores (quantiles from `risk.auto_tune`, at least its
+    /// `min_threshold`). Returns the tuned (medium, high) pair, or `None` if tuning is disabled,
+    /// history is too short, or its scores have no spread (e.g. all 0 without a model).
+    pub fn tune(&self, history: &[f32]) -> Option<(f32, f32)> {
+        let cfg = &self.config.auto_tune;
+        if !cfg.enabled || history.len() < cfg.min_samples.max(1) {
+            return None;
+        }
+        let mut sorted: Vec<f32> = history.iter().copied().filter(|s| s.is_finite()).collect();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        if sorted[sorted.len() - 1] - sorted[0] <= f32::EPSILON {
+            return None;
+        }
+        let floor = cfg.min_threshold.clamp(0.0, 1.0);
+        let medium = quantile(&sorted, cfg.medium_quantile).clamp(floor, 1.0);
+        let high = quantile(&sorted, cfg.high_quantile).clamp(floor, 1.0).max(medium);
+        if let Ok(mut t) = self.tuned.write() {
+            *t = Some((medium, high));
+        }
+        tracing::info!(medium, high, samples = sorted.len(), "risk thresholds tuned");
+        Some((medium, high))
     }
 
     pub fn score(&self, event_id: String, raw_score: f32, ts: i64) -> RiskResult {
-        let level = RiskLevel::from_score(raw_score, &self.config);
+        let (medium, high) = self.thresholds();
+        let level = RiskLevel::from_thresholds(raw_score, medium, high);
         RiskResult {
             event_id,
             score: raw_score,
             level,
             ts,
+            window_start: ts,
+            window_end: ts,
+            event_ids: Vec::new(),
+            top_class: None,
+            rule_hits: Vec::new(),
+            correlations: Vec::new(),
+            ioc_hits: Vec::new(),
+            kill_chain: Vec::new(),
+            profile: None,
+            criticality: None,
+            fingerprint: None,
+            unsmoothed_score: None,
+            model_score: None,
+            sources: None,
+            attack: Vec::new(),
+            entity_risk: Vec::new(),
+            escalated: false,
+            suppressed: Vec::new(),
+            raw_level: None,
+            alert: false,
+            learning: false,
+        }
+    }
+
+    /// Profile for windows about `subject`: `risk.source_profiles` by its source, else
+    /// `risk.kind_profiles` by its kind
+    fn profile(&self, subject: Option<&Event>) -> Option<(String, &ScoringProfile)> {
+        let subject = subject?;
+        if let Some(p) = self.config.source_profiles.get(&subject.source) {
+            return Some((format!("source:{}", subject.source), p));
+        }
+        let kind = subject.kind.name();
+        self.config.kind_profiles.get(kind).map(|p| (format!("kind:{}", kind), p))
+    }
+
+    /// True while `risk.learning_period_days` has not elapsed at `ts` (also before the first window)
+    pub fn is_learning(&self, ts: i64) -> bool {
+        let days = self.config.learning_period_days;
+        if days == 0 {
+            return false;
         }
+        match *self.learning_started.lock().unwrap() {
+            Some(start) => ts < start.saturating_add(days as i64 * 86_400_000),
+            None => true,
+        }
+    }
+
+    /// Restore learning state saved by [`save_learned`](Self::save_learned) (if present), so a
+    /// restart keeps the learning period's start and what correlation has already seen.
+    pub fn load_learned(&self, path: &Path) {
+        let state = LearnedState::load(path);
+        if let Some(start) = state.started_at {
+            *self.learning_started.lock().unwrap() = Some(start);
+        }
+        self.correlator.restore(state.executables, state.remotes);
+    }
+
+    /// Persist learning state
+    pub fn save_learned(&self, path: &Path) -> std::io::Result<()> {
+        let (executables, remotes) = self.correlator.known();
+        LearnedState {
+            started_at: *self.learning_started.lock().unwrap(),
+            executables,
+            remotes,
+        }
+        .save(path)
+    }
+
+    /// Drop allowlisted events (`risk.allowlist`) before a window is scored; returns the events to
+    /// score and what was suppressed (set it on the result as `suppressed`).
+    pub fn suppress(&self, events: Vec<Event>) -> (Vec<Event>, Vec<Suppression>) {
+        self.allowlist.filter(events)
+    }
+
+    /// Score a window of `events`: evaluate the rules, correlation patterns, indicators of
+    /// compromise (`risk.ioc`), and kill-chain stages over them; smooth `model_score` per entity (`risk.smoothing`) and fuse it with their hits and
+    /// the entities' prior risk (`risk.fusion`), scaled and thresholded by the subject event's
+    /// profile (`risk.source_profiles` / `risk.kind_profiles`) and weighted by asset criticality
+    /// (`risk.criticality`); tag the result with the ATT&CK techniques of the hits and of `class`
+    /// (`risk.class_attack`); add the score to the window's entities, escalating the level if one
+    /// has accumulated enough; fingerprint it for deduplication (`risk.dedup`); and finally
+    /// debounce the level and decide whether to alert (`risk.hysteresis`). During the learning
+    /// period the level is held at low instead and there is no alert.
+    pub fn score_events(
+        &self,
+        event_id: String,
+        model_score: f32,
+        class: Option<ClassPrediction>,
+        ts: i64,
+        events: &[Event],
+    ) -> RiskResult {
+        if !events.is_empty() {
+            self.learning_started.lock().unwrap().get_or_insert(ts);
+        }
+        let raw_model_score = model_score;
+        let subject = entity::subject_entity(&event_id, events);
+        let model_score = self.smoother.observe(&subject, model_score);
+        let hits = self.rules.evaluate(events);
+        let correlations = self.correlator.evaluate(events, &hits, ts);
+        let ioc_hits = self.ioc.evaluate(events);
+        let class_attack = class.as_ref().and_then(|c| self.config.class_attack.get(&c.label));
+        let tagged: Vec<(&str, &[AttackTag])> = hits
+            .iter()
+            .map(|h| (h.event_id.as_str(), h.attack.as_slice()))
+            .chain(
+                correlations
+                    .iter()
+                    .flat_map(|c| c.event_ids.iter().map(move |id| (id.as_str(), c.attack.as_slice()))),
+            )
+            .chain(class_attack.map(|tags| (event_id.as_str(), tags.as_slice())))
+            .collect();
+        let kill_chain = self.kill_chain.observe(&tagged, events, ts);
+        let hit_events: Vec<&str> = hits
+            .iter()
+            .map(|h| h.event_id.as_str())
+            .chain(correlations.iter().flat_map(|c| c.event_ids.iter().map(String::as_str)))
+            .chain(ioc_hits.iter().map(|h| h.event_id.as_str()))
+            .collect();
+        let weight = self.criticality.weight(&event_id, &hit_events, events);
+        let entities = if self.entities.enabled() && !events.is_empty() {
+            entity::window_entities(&event_id, &hit_events, events)
+        } else {
+            Vec::new()
+        };
+        let prior = entities.iter().map(|e| self.entities.cumulative(e, ts)).fold(0.0, f32::max);
+        let profile = self.profile(events.iter().find(|e| e.id == event_id));
+        let sensitivity = profile.as_ref().map_or(1.0, |(_, p)| p.sensitivity.max(0.0));
+        let sources = SourceScores {
+            model: (model_score * sensitivity).clamp(0.0, 1.0),
+            rules: rules::fuse(
+                0.0,
+                hits.iter()
+                    .map(|h| h.weight)
+                    .chain(correlations.iter().map(|c| c.boost))
+                    .chain(kill_chain.first().map(|k| k.boost)),
+            ),
+            ioc: if ioc_hits.is_empty() { 0.0 } else { self.config.ioc.match_score.clamp(0.0, 1.0) },
+            entity: self.entities.relative(prior),
+        };
+        let mut result = self.score(event_id, fusion::combine(&sources, &self.config.fusion), ts);
+        if model_score != raw_model_score {
+            result.unsmoothed_score = Some(raw_model_score);
+        }
+        let (mut medium, mut high) = self.thresholds();
+        if let Some((name, p)) = profile {
+            (medium, high) = (p.medium_threshold.unwrap_or(medium), p.high_threshold.unwrap_or(high));
+            result.profile = Some(name);
+        }
+        if weight != 1.0 {
+            match self.config.criticality.apply {
+                CriticalityMode::Score => result.score = (result.score * weight).clamp(0.0, 1.0),
+                CriticalityMode::Thresholds => (medium, high) = (medium / weight, high / weight),
+            }
+            result.criticality = Some(weight);
+        }
+        result.level = RiskLevel::from_thresholds(result.score, medium, high);
+        if sources.beyond_model(&self.config.fusion.weights) {
+            result.model_score = Some(model_score);
+            result.sources = Some(sources);
+        }
+        let mut attack: Vec<AttackTag> = hits
+            .iter()
+            .flat_map(|h| h.attack.iter())
+            .chain(correlations.iter().flat_map(|c| c.attack.iter()))
+            .cloned()
+            .collect();
+        if let Some(tags) = class_attack {
+            attack.extend(tags.iter().cloned());
+        }
+        attack.sort();
+        attack.dedup();
+        result.attack = attack;
+        result.rule_hits = hits;
+        result.correlations = correlations;
+        result.ioc_hits = ioc_hits;
+        result.top_class = class;
+        if self.kill_chain.is_high(&kill_chain) && result.level < RiskLevel::High {
+            result.level = RiskLevel::High;
+            result.escalated = true;
+        }
+        result.kill_chain = kill_chain;
+        if !entities.is_empty() {
+            result.entity_risk = self.entities.observe(&entities, result.score, ts);
+            let peak = result.entity_risk.iter().map(|e| e.cumulative).fold(0.0, f32::max);
+            let level = self.entities.level(peak);
+            if level > result.level {
+                result.level = level;
+                result.escalated = true;
+            }
+        }
+        result.fingerprint = self.dedup.observe(&result, &subject, ts);
+        if self.is_learning(ts) {
+            // Hysteresis doesn't see these windows, so the first level after learning can alert
+            if result.level != RiskLevel::Low {
+                result.raw_level = Some(result.level);
+                result.level = RiskLevel::Low;
+            }
+            result.learning = true;
+            return result;
+        }
+        let debounced = self.hysteresis.observe(result.level, ts);
+        if debounced.level != result.level {
+            result.raw_level = Some(result.level);
+            result.level = debounced.level;
+        }
+        result.alert = debounced.alert;
+        result
+    }
+
+    /// Deep scan to request from the collectors when `result` (the window itself, before hysteresis
+    /// and the learning period) reaches `risk.deep_scan.min_level`: the executables' and files'
+    /// directories and the processes behind the subject and the hits, until `cooldown_secs` from
+    /// now.
+    pub fn deep_scan(&self, result: &RiskResult, events: &[Event]) -> Option<DeepScan> {
+        let cfg = &self.config.deep_scan;
+        let level = result.raw_level.map_or(result.level, |raw| raw.max(result.level));
+        if !cfg.enabled || level < cfg.min_level {
+            return None;
+        }
+        let implicated: Vec<&str> = std::iter::once(result.event_id.as_str())
+            .chain(result.rule_hits.iter().map(|h| h.event_id.as_str()))
+            .chain(result.correlations.iter().flat_map(|c| c.event_ids.iter().map(String::as_str)))
+            .chain(result.ioc_hits.iter().map(|h| h.event_id.as_str()))
+            .collect();
+        let mut scan = DeepScan {
+            until: result.ts.saturating_add(cfg.cooldown_secs.saturating_mul(1000) as i64),
+            interval_secs: cfg.interval_secs.max(1),
+            max_files: cfg.max_files,
+            ..DeepScan::default()
+        };
+        let parent = |path: &str| std::path::Path::new(path).parent().map(std::path::Path::to_path_buf);
+        for event in events.iter().filter(|e| implicated.contains(&e.id.as_str())) {
+            let (pid, dir) = match &event.kind {
+                EventKind::Process(p) => (Some(p.pid), p.exe.as_deref().and_then(parent)),
+                EventKind::Network(n) => (n.pid, None),
+                EventKind::Privilege(p) => (Some(p.pid), None),
+                EventKind::FileIntegrity(f) => (None, parent(&f.path)),
+            };
+            if let Some(pid) = pid.filter(|p| !scan.pids.contains(p)) {
+                scan.pids.push(pid);
+            }
+            if let Some(dir) = dir.filter(|d| !d.as_os_str().is_empty() && !scan.paths.contains(d)) {
+                scan.paths.push(dir);
+            }
+        }
+        Some(scan)
+    }
+
+    /// Accumulated risk of an entity (`device`, `process:<name>`, `user:<uid>`) at `ts`
+    pub fn entity_risk(&self, entity: &str, ts: i64) -> f32 {
+        self.entities.cumulative(entity, ts)
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    /// Indicator matcher, to reload files or set the uplink's indicators
+    pub fn ioc(&self) -> &IocMatcher {
+        &self.ioc
     }
 
     pub fn config(&self) -> &RiskConfig {
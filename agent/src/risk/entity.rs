@@ -0,0 +1,129 @@
+//! Per-entity cumulative risk: each process, user, and the device keeps an exponentially decaying
+//! sum of the window scores attributed to it, so repeated medium anomalies from the same entity
+//! escalate instead of every window being judged in isolation.
+
+use super::engine::RiskLevel;
+use crate::collectors::{Event, EventKind};
+use crate::config::EntityRiskConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Key of the device entity (every window is attributed to it)
+pub const DEVICE_ENTITY: &str = "device";
+
+/// Accumulated risk of one entity after a window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityRisk {
+    /// `device`, `process:<name>`, or `user:<uid>`
+    pub entity: String,
+    pub cumulative: f32,
+}
+
+/// Entities an event is about
+fn event_entities(event: &Event, out: &mut Vec<String>) {
+    match &event.kind {
+        EventKind::Process(p) => {
+            out.push(format!("process:{}", p.name));
+            if let Some(uid) = p.uid {
+                out.push(format!("user:{}", uid));
+            }
+        }
+        EventKind::Privilege(p) => out.push(format!("user:{}", p.from_uid)),
+        EventKind::Network(_) | EventKind::FileIntegrity(_) => {}
+    }
+}
+
+/// Main entity of the event `subject` (its process, else its user), or the device
+pub fn subject_entity(subject: &str, events: &[Event]) -> String {
+    let mut out = Vec::new();
+    if let Some(event) = events.iter().find(|e| e.id == subject) {
+        event_entities(event, &mut out);
+    }
+    out.into_iter().next().unwrap_or_else(|| DEVICE_ENTITY.to_string())
+}
+
+/// Entities a window's score is attributed to: the device, plus the subject event and the events
+/// behind rule and correlation hits (not every event in the window, which would escalate
+/// everything together).
+pub fn window_entities(subject: &str, hit_events: &[&str], events: &[Event]) -> Vec<String> {
+    let mut out = vec![DEVICE_ENTITY.to_string()];
+    for event in events {
+        if event.id == subject || hit_events.contains(&event.id.as_str()) {
+            event_entities(event, &mut out);
+        }
+    }
+    out.sort();
+    out.dedup();
+    out
+}
+
+pub struct EntityTracker {
+    config: EntityRiskConfig,
+    /// entity → (accumulated risk, ts of last update in ms)
+    scores: Mutex<HashMap<String, (f64, i64)>>,
+}
+
+impl EntityTracker {
+    pub fn new(config: EntityRiskConfig) -> Self {
+        Self {
+            config,
+            scores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn decayed(&self, (value, at): (f64, i64), ts: i64) -> f64 {
+        let half_life_ms = self.config.half_life_secs.max(1) as f64 * 1000.0;
+        let elapsed = (ts - at).max(0) as f64;
+        value * 0.5f64.powf(elapsed / half_life_ms)
+    }
+
+    /// Add `score` (if at least `min_score`) to each entity at `ts` and return their accumulated risk.
+    pub fn observe(&self, entities: &[String], score: f32, ts: i64) -> Vec<EntityRisk> {
+        let contribution = if score >= self.config.min_score { score as f64 } else { 0.0 };
+        let mut scores = self.scores.lock().unwrap();
+        let out = entities
+            .iter()
+            .map(|entity| {
+                let current = scores.get(entity).map_or(0.0, |s| self.decayed(*s, ts));
+                let value = current + contribution;
+                if value > 0.0 {
+                    scores.insert(entity.clone(), (value, ts));
+                }
+                EntityRisk {
+                    entity: entity.clone(),
+                    cumulative: value as f32,
+                }
+            })
+            .collect();
+        if scores.len() > self.config.max_entities {
+            let mut ranked: Vec<(String, f64)> = scores.iter().map(|(k, s)| (k.clone(), self.decayed(*s, ts))).collect();
+            ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+            let excess = scores.len() - self.config.max_entities;
+            for (entity, _) in ranked.into_iter().take(excess) {
+                scores.remove(&entity);
+            }
+        }
+        out
+    }
+
+    /// Accumulated risk of `entity` at `ts`
+    pub fn cumulative(&self, entity: &str, ts: i64) -> f32 {
+        let scores = self.scores.lock().unwrap();
+        scores.get(entity).map_or(0.0, |s| self.decayed(*s, ts) as f32)
+    }
+
+    /// Level implied by an accumulated risk
+    pub fn level(&self, cumulative: f32) -> RiskLevel {
+        RiskLevel::from_thresholds(cumulative, self.config.medium_cumulative, self.config.high_cumulative)
+    }
+
+    /// Accumulated risk as a fraction of `high_cumulative` (0.0–1.0), the entity fusion source
+    pub fn relative(&self, cumulative: f32) -> f32 {
+        (cumulative / self.config.high_cumulative.max(f32::EPSILON)).clamp(0.0, 1.0)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+}
@@ -0,0 +1,125 @@
+//! Analyst feedback: a verdict (false or true positive) on a stored risk result, kept with the
+//! result and its implicated events so it can be uplinked for retraining and mined for allowlist
+//! entries (`risk.allowlist`) that would have suppressed recurring false positives.
+
+use super::RiskResult;
+use crate::collectors::{Event, EventKind};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    FalsePositive,
+    TruePositive,
+}
+
+impl Verdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::FalsePositive => "false_positive",
+            Verdict::TruePositive => "true_positive",
+        }
+    }
+}
+
+impl std::str::FromStr for Verdict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fp" | "false_positive" | "false-positive" => Ok(Verdict::FalsePositive),
+            "tp" | "true_positive" | "true-positive" => Ok(Verdict::TruePositive),
+            other => Err(format!("unknown verdict {} (expected fp or tp)", other)),
+        }
+    }
+}
+
+/// Verdict on one risk result; a later verdict on the same result replaces it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Feedback {
+    /// Subject event of the result
+    pub event_id: String,
+    pub verdict: Verdict,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// When the verdict was given (ms)
+    pub ts: i64,
+    /// The result as it was stored
+    pub result: RiskResult,
+    /// Subject, rule-hit, and IOC-hit events of the result still in the store when the verdict was given
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+/// Allowlist entry that would have suppressed events implicated in false positives
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SuppressionCandidate {
+    /// `risk.allowlist` field: `publishers`, `paths`, or `remote_cidrs`
+    pub field: String,
+    pub value: String,
+    /// False-positive results implicating the value
+    pub false_positives: usize,
+}
+
+/// Ids of the events a result blames: its subject plus the events behind rule and IOC hits
+pub fn implicated_event_ids(result: &RiskResult) -> Vec<String> {
+    let mut ids = vec![result.event_id.clone()];
+    let hits = result.rule_hits.iter().map(|h| &h.event_id).chain(result.ioc_hits.iter().map(|h| &h.event_id));
+    for id in hits {
+        if !ids.contains(id) {
+            ids.push(id.clone());
+        }
+    }
+    ids
+}
+
+/// (allowlist field, value) that would match `event`
+fn allowlist_entry(event: &Event) -> Option<(&'static str, String)> {
+    match &event.kind {
+        EventKind::Process(p) => match (&p.publisher, &p.exe) {
+            (Some(publisher), _) => Some(("publishers", publisher.clone())),
+            (None, Some(exe)) => Some(("paths", exe.clone())),
+            (None, None) => None,
+        },
+        EventKind::FileIntegrity(f) => Some(("paths", f.path.clone())),
+        EventKind::Network(n) => {
+            let remote = n.remote_addr.as_deref()?;
+            let addr = remote
+                .parse::<std::net::IpAddr>()
+                .or_else(|_| remote.parse::<std::net::SocketAddr>().map(|s| s.ip()))
+                .ok()?;
+            Some(("remote_cidrs", addr.to_string()))
+        }
+        EventKind::Privilege(_) => None,
+    }
+}
+
+/// Entries implicated in at least `min_count` false positives and in no true positive, most
+/// frequent first
+pub fn suppression_candidates(feedback: &[Feedback], min_count: usize) -> Vec<SuppressionCandidate> {
+    let mut false_positives: BTreeMap<(&'static str, String), usize> = BTreeMap::new();
+    let mut confirmed: BTreeSet<(&'static str, String)> = BTreeSet::new();
+    for fb in feedback {
+        let entries: BTreeSet<_> = fb.events.iter().filter_map(allowlist_entry).collect();
+        for entry in entries {
+            match fb.verdict {
+                Verdict::FalsePositive => *false_positives.entry(entry).or_default() += 1,
+                Verdict::TruePositive => {
+                    confirmed.insert(entry);
+                }
+            }
+        }
+    }
+    let mut out: Vec<SuppressionCandidate> = false_positives
+        .into_iter()
+        .filter(|(entry, n)| *n >= min_count.max(1) && !confirmed.contains(entry))
+        .map(|((field, value), n)| SuppressionCandidate {
+            field: field.to_string(),
+            value,
+            false_positives: n,
+        })
+        .collect();
+    out.sort_by_key(|c| std::cmp::Reverse(c.false_positives));
+    out
+}
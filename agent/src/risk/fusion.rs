@@ -0,0 +1,55 @@
+//! Score fusion (`risk.fusion`): the result score is a weighted combination of the model score, the
+//! rule and correlation score, IOC matches, and the prior accumulated risk of the window's entities.
+
+use super::rules;
+use crate::config::{FusionConfig, FusionMethod, FusionWeights};
+use serde::{Deserialize, Serialize};
+
+/// Score of each source for one window, before weighting (each 0.0–1.0)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceScores {
+    pub model: f32,
+    /// Rule hit weights, correlation boosts, and the kill-chain boost combined by noisy-OR
+    pub rules: f32,
+    pub ioc: f32,
+    /// Peak prior accumulated risk of the window's entities, relative to `risk.entity.high_cumulative`
+    pub entity: f32,
+}
+
+impl SourceScores {
+    /// A source besides the model has a signal and a weight
+    pub fn beyond_model(&self, weights: &FusionWeights) -> bool {
+        [(weights.rules, self.rules), (weights.ioc, self.ioc), (weights.entity, self.entity)]
+            .iter()
+            .any(|(w, s)| *w > 0.0 && *s > 0.0)
+    }
+}
+
+/// Combine `sources` with the configured method and weights
+pub fn combine(sources: &SourceScores, config: &FusionConfig) -> f32 {
+    let w = &config.weights;
+    let weighted = [
+        (w.model, sources.model),
+        (w.rules, sources.rules),
+        (w.ioc, sources.ioc),
+        (w.entity, sources.entity),
+    ]
+    .map(|(weight, score)| (weight.clamp(0.0, 1.0), score.clamp(0.0, 1.0)));
+    match config.method {
+        FusionMethod::NoisyOr => {
+            let parts: Vec<f32> = weighted.iter().map(|(w, s)| w * s).filter(|p| *p > 0.0).collect();
+            match parts.as_slice() {
+                // Exactly the source's score (1 - (1 - x) can round)
+                [only] => *only,
+                _ => rules::fuse(0.0, parts),
+            }
+        }
+        FusionMethod::WeightedMean => {
+            let total: f32 = weighted.iter().map(|(w, _)| w).sum();
+            if total <= 0.0 {
+                return 0.0;
+            }
+            (weighted.iter().map(|(w, s)| w * s).sum::<f32>() / total).clamp(0.0, 1.0)
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Level hysteresis (`risk.hysteresis`): the reported level only rises after several consecutive
+//! windows above it and only falls after several below it, and alerts are rate-limited by a
+//! cooldown, so one noisy window doesn't flap results between high and low.
+
+use super::engine::RiskLevel;
+use crate::config::HysteresisConfig;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+struct State {
+    /// Reported level
+    level: RiskLevel,
+    /// Consecutive windows above / below `level`
+    above: u32,
+    below: u32,
+    /// Lowest raw level of the current run above (the level a rise settles at)
+    above_floor: RiskLevel,
+    /// Highest raw level of the current run below
+    below_ceiling: RiskLevel,
+    /// Time and level of the last alert
+    last_alert: Option<(i64, RiskLevel)>,
+}
+
+/// Outcome for one window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Debounced {
+    pub level: RiskLevel,
+    /// The level rose to medium or high and is not held back by the cooldown
+    pub alert: bool,
+}
+
+pub struct Hysteresis {
+    config: HysteresisConfig,
+    state: Mutex<State>,
+}
+
+impl Hysteresis {
+    pub fn new(config: HysteresisConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State {
+                level: RiskLevel::Low,
+                above: 0,
+                below: 0,
+                above_floor: RiskLevel::High,
+                below_ceiling: RiskLevel::Low,
+                last_alert: None,
+            }),
+        }
+    }
+
+    /// Feed one window's raw level at `ts` (ms)
+    pub fn observe(&self, raw: RiskLevel, ts: i64) -> Debounced {
+        let mut s = self.state.lock().unwrap();
+        let before = s.level;
+        if raw > s.level {
+            s.below = 0;
+            s.above_floor = if s.above == 0 { raw } else { s.above_floor.min(raw) };
+            s.above += 1;
+            if s.above >= self.config.raise_windows.max(1) {
+                s.level = s.above_floor;
+                s.above = 0;
+            }
+        } else if raw < s.level {
+            s.above = 0;
+            s.below_ceiling = if s.below == 0 { raw } else { s.below_ceiling.max(raw) };
+            s.below += 1;
+            if s.below >= self.config.clear_windows.max(1) {
+                s.level = s.below_ceiling;
+                s.below = 0;
+            }
+        } else {
+            s.above = 0;
+            s.below = 0;
+        }
+
+        let level = s.level;
+        let mut alert = level > before && level > RiskLevel::Low;
+        if alert {
+            let cooldown_ms = self.config.cooldown_secs.saturating_mul(1000) as i64;
+            if let Some((at, alerted)) = s.last_alert {
+                alert = level > alerted || ts.saturating_sub(at) >= cooldown_ms;
+            }
+        }
+        if alert {
+            s.last_alert = Some((ts, level));
+        }
+        Debounced { level, alert }
+    }
+}
@@ -0,0 +1,187 @@
+//! Indicator-of-compromise matching (`risk.ioc`): known-bad SHA-256 hashes, IP addresses and
+//! networks, and domains, loaded from local files and the uplink, are checked against process
+//! executables, file integrity hashes, and the remote ends of connections. A match is a
+//! deterministic signal fused as the IOC source score.
+
+use super::allowlist::{parse_addr, Cidr, ExeHasher};
+use crate::collectors::{Event, EventKind};
+use crate::config::IocConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::RwLock;
+
+/// Indicator matched by an event of the window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IocHit {
+    /// `sha256:<hex>`, `ip:<address or network>`, or `domain:<name>`
+    pub indicator: String,
+    pub event_id: String,
+}
+
+/// Parsed indicators
+#[derive(Default)]
+pub struct IndicatorSet {
+    hashes: HashSet<String>,
+    networks: Vec<Cidr>,
+    domains: HashSet<String>,
+}
+
+impl IndicatorSet {
+    /// Classify each indicator: 64 hex digits are a SHA-256, an address or `address/prefix` a
+    /// network, anything else a domain. Blank lines and `#` comments are skipped.
+    pub fn parse<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut set = Self::default();
+        for line in lines {
+            let value = line.split('#').next().unwrap_or_default().trim();
+            if value.is_empty() {
+                continue;
+            }
+            if value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit()) {
+                set.hashes.insert(value.to_ascii_lowercase());
+            } else if let Some(cidr) = Cidr::parse(value) {
+                set.networks.push(cidr);
+            } else {
+                set.domains.insert(value.trim_end_matches('.').to_ascii_lowercase());
+            }
+        }
+        set
+    }
+
+    /// Indicators in all files of `paths` (unreadable files are skipped with a warning)
+    pub fn load(paths: &[impl AsRef<Path>]) -> Self {
+        let mut text = String::new();
+        for path in paths {
+            match std::fs::read_to_string(path) {
+                Ok(t) => {
+                    text.push_str(&t);
+                    text.push('\n');
+                }
+                Err(e) => tracing::warn!(path = %path.as_ref().display(), error = %e, "cannot read indicator file"),
+            }
+        }
+        Self::parse(text.lines())
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len() + self.networks.len() + self.domains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn hash(&self, sha256: &str) -> Option<String> {
+        let sha256 = sha256.to_ascii_lowercase();
+        self.hashes.contains(&sha256).then(|| format!("sha256:{}", sha256))
+    }
+
+    fn addr(&self, remote: &str) -> Option<String> {
+        let addr = parse_addr(remote)?;
+        self.networks
+            .iter()
+            .find(|c| c.contains(addr))
+            .map(|c| format!("ip:{}", c.text))
+    }
+
+    /// `host` or the nearest parent domain listed
+    fn domain(&self, host: &str) -> Option<String> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let mut name = host.as_str();
+        loop {
+            if self.domains.contains(name) {
+                return Some(format!("domain:{}", name));
+            }
+            name = name.split_once('.')?.1;
+        }
+    }
+}
+
+pub struct IocMatcher {
+    config: IocConfig,
+    /// From `risk.ioc.files`
+    local: RwLock<IndicatorSet>,
+    /// From the uplink
+    remote: RwLock<IndicatorSet>,
+    hasher: ExeHasher,
+}
+
+impl IocMatcher {
+    pub fn new(config: IocConfig) -> Self {
+        let local = if config.enabled { IndicatorSet::load(&config.files) } else { IndicatorSet::default() };
+        Self {
+            config,
+            local: RwLock::new(local),
+            remote: RwLock::new(IndicatorSet::default()),
+            hasher: ExeHasher::default(),
+        }
+    }
+
+    /// Re-read `risk.ioc.files`; returns the number of indicators loaded from them
+    pub fn reload_files(&self) -> usize {
+        let set = IndicatorSet::load(&self.config.files);
+        let n = set.len();
+        *self.local.write().unwrap() = set;
+        n
+    }
+
+    /// Replace the uplink indicators; returns how many parsed
+    pub fn set_remote(&self, indicators: &[String]) -> usize {
+        let set = IndicatorSet::parse(indicators.iter().map(String::as_str));
+        let n = set.len();
+        *self.remote.write().unwrap() = set;
+        n
+    }
+
+    /// Indicators loaded (files and uplink)
+    pub fn len(&self) -> usize {
+        self.local.read().unwrap().len() + self.remote.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// First indicator in either set matching `event`
+    fn matches(&self, event: &Event, sets: &[&IndicatorSet]) -> Option<String> {
+        let first = |f: &dyn Fn(&IndicatorSet) -> Option<String>| sets.iter().find_map(|s| f(s));
+        match &event.kind {
+            EventKind::Process(p) => {
+                let exe = p.exe.as_deref().filter(|_| self.config.hash_executables)?;
+                if sets.iter().all(|s| s.hashes.is_empty()) {
+                    return None;
+                }
+                let sha256 = self.hasher.sha256(exe)?;
+                first(&|s| s.hash(&sha256))
+            }
+            EventKind::FileIntegrity(f) => first(&|s| s.hash(&f.hash_sha256)),
+            EventKind::Network(n) => n
+                .remote_addr
+                .as_deref()
+                .and_then(|a| first(&|s| s.addr(a)))
+                .or_else(|| n.remote_host.as_deref().and_then(|h| first(&|s| s.domain(h)))),
+            EventKind::Privilege(_) => None,
+        }
+    }
+
+    /// Indicator matches of the window's events, in event order
+    pub fn evaluate(&self, events: &[Event]) -> Vec<IocHit> {
+        if !self.config.enabled {
+            return Vec::new();
+        }
+        let (local, remote) = (self.local.read().unwrap(), self.remote.read().unwrap());
+        if local.is_empty() && remote.is_empty() {
+            return Vec::new();
+        }
+        let sets = [&*local, &*remote];
+        events
+            .iter()
+            .filter_map(|e| {
+                self.matches(e, &sets).map(|indicator| IocHit {
+                    indicator,
+                    event_id: e.id.clone(),
+                })
+            })
+            .collect()
+    }
+}
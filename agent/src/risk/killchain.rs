@@ -0,0 +1,102 @@
+//! Kill-chain sequencing (`risk.kill_chain`): each entity's progression through ordered attack
+//! stages (by default execution → persistence → privilege escalation → exfiltration), recognized
+//! from the ATT&CK tactics of the hits attributed to it. Reaching a later stage after an earlier
+//! one within the window boosts the score and can raise the level outright, escalating faster
+//! than scoring each window on its own.
+
+use super::entity;
+use crate::collectors::Event;
+use crate::config::{AttackTag, KillChainConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Entities tracked at most; expired progressions are dropped first
+const MAX_TRACKED: usize = 10_000;
+
+/// Progression that advanced in this window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KillChainHit {
+    pub entity: String,
+    /// Stages reached so far, in order
+    pub stages: Vec<String>,
+    pub boost: f32,
+}
+
+pub struct KillChain {
+    config: KillChainConfig,
+    /// entity → (stage index, ts ms) of each stage reached, in order
+    progress: Mutex<HashMap<String, Vec<(usize, i64)>>>,
+}
+
+impl KillChain {
+    pub fn new(config: KillChainConfig) -> Self {
+        Self {
+            config,
+            progress: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn stage_of(&self, tactic: &str) -> Option<usize> {
+        self.config
+            .stages
+            .iter()
+            .position(|s| s.tactics.iter().any(|t| t.eq_ignore_ascii_case(tactic)))
+    }
+
+    /// Advance the progressions of the entities behind `tagged` (event id and its ATT&CK tags) at
+    /// `ts`. An entity advances at most one stage per window (to the furthest one seen beyond its
+    /// current stage), so a single hit tagged with several tactics doesn't make a chain by itself.
+    /// Returns the progressions of two or more stages that advanced.
+    pub fn observe(&self, tagged: &[(&str, &[AttackTag])], events: &[Event], ts: i64) -> Vec<KillChainHit> {
+        if !self.config.enabled || self.config.stages.is_empty() {
+            return Vec::new();
+        }
+        // Furthest stage seen per entity in this window
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for (event_id, tags) in tagged {
+            let Some(stage) = tags.iter().filter_map(|t| self.stage_of(&t.tactic)).max() else {
+                continue;
+            };
+            for entity in entity::window_entities(event_id, &[], events) {
+                let furthest = seen.entry(entity).or_insert(stage);
+                *furthest = (*furthest).max(stage);
+            }
+        }
+        if seen.is_empty() {
+            return Vec::new();
+        }
+
+        let horizon = ts.saturating_sub(self.config.window_secs.saturating_mul(1000) as i64);
+        let mut progress = self.progress.lock().unwrap();
+        let mut out = Vec::new();
+        for (entity, stage) in seen {
+            let chain = progress.entry(entity.clone()).or_default();
+            chain.retain(|(_, at)| *at >= horizon);
+            if chain.last().is_some_and(|(last, _)| stage <= *last) {
+                continue;
+            }
+            chain.push((stage, ts));
+            if chain.len() >= 2 {
+                out.push(KillChainHit {
+                    entity,
+                    stages: chain.iter().map(|(s, _)| self.config.stages[*s].name.clone()).collect(),
+                    boost: (self.config.stage_boost * (chain.len() - 1) as f32).clamp(0.0, 1.0),
+                });
+            }
+        }
+        if progress.len() > MAX_TRACKED {
+            progress.retain(|_, chain| chain.last().is_some_and(|(_, at)| *at >= horizon));
+            if progress.len() > MAX_TRACKED {
+                progress.clear();
+            }
+        }
+        out.sort_by(|a, b| b.stages.len().cmp(&a.stages.len()).then_with(|| a.entity.cmp(&b.entity)));
+        out
+    }
+
+    /// A progression in `hits` reached `high_stages`
+    pub fn is_high(&self, hits: &[KillChainHit]) -> bool {
+        self.config.high_stages > 0 && hits.iter().any(|h| h.stages.len() >= self.config.high_stages)
+    }
+}
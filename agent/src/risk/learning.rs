@@ -0,0 +1,36 @@
+//! Learning period (`risk.learning_period_days`): for the first days after install, windows are
+//! collected, modeled, and scored as usual but reported as informational (level held at low, no
+//! alert), so a fresh install isn't flooded with first-seen anomalies while baselines settle. What
+//! was learned is persisted so a restart neither restarts the period nor forgets what was seen.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Persisted learning state of the risk engine
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LearnedState {
+    /// Timestamp (ms) of the first scored window; the learning period runs from here
+    #[serde(default)]
+    pub started_at: Option<i64>,
+    /// Executables seen so far (correlation first sightings)
+    #[serde(default)]
+    pub executables: Vec<String>,
+    /// Remote addresses seen so far
+    #[serde(default)]
+    pub remotes: Vec<String>,
+}
+
+impl LearnedState {
+    /// Read state saved at `path`; empty if missing or unreadable
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let data = serde_json::to_string(self)?;
+        std::fs::write(path, data)
+    }
+}
@@ -1,5 +1,31 @@
-//! Risk scoring engine: model score + thresholds → risk level per event.
+//! Risk scoring engine: model score fused with heuristic rule hits + thresholds → risk level per event.
 
+pub mod allowlist;
+pub mod correlation;
+mod criticality;
+mod dedup;
 mod engine;
+pub mod entity;
+pub mod feedback;
+pub mod fusion;
+mod hysteresis;
+pub mod ioc;
+pub mod killchain;
+pub mod learning;
+pub mod rules;
+mod smoothing;
+pub mod trend;
 
-pub use engine::RiskEngine;
+pub use allowlist::Suppression;
+pub use correlation::CorrelationHit;
+pub use dedup::Fingerprint;
+pub use engine::{RiskEngine, RiskLevel, RiskResult};
+pub use entity::EntityRisk;
+pub use feedback::{Feedback, SuppressionCandidate, Verdict};
+pub use fusion::SourceScores;
+pub use ioc::{IocHit, IocMatcher};
+pub use killchain::KillChainHit;
+pub use learning::LearnedState;
+pub use rules::{RuleHit, RuleSet};
+pub use trend::{RiskTrend, TrendPoint, TrendQuery};
+pub use crate::config::AttackTag;
@@ -0,0 +1,188 @@
+//! Heuristic rules: declarative conditions over collected events (`risk.rules`) whose weighted hits
+//! are fused with the model score, so known-bad behavior raises risk even when the model misses it.
+
+use crate::collectors::{Event, EventKind, FileIntegrityChange};
+use crate::config::{AttackTag, RuleCondition, RuleConfig, RulesConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Rule that matched in a scoring window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleHit {
+    pub rule: String,
+    pub weight: f32,
+    /// First event that matched (for bursts, the event completing it)
+    pub event_id: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attack: Vec<AttackTag>,
+}
+
+fn patterns(list: &[&str]) -> Vec<String> {
+    list.iter().map(|p| p.to_string()).collect()
+}
+
+/// Rules enabled by `risk.rules.builtin`
+pub fn builtin_rules() -> Vec<RuleConfig> {
+    vec![
+        RuleConfig {
+            id: "offensive-tool".into(),
+            description: Some("Known credential dumping, tunneling, or mining tool".into()),
+            weight: 0.6,
+            condition: RuleCondition::ProcessName {
+                patterns: patterns(&["mimikatz*", "lazagne*", "xmrig*", "ncat", "ncat.exe", "socat", "psexec*"]),
+            },
+            attack: vec![AttackTag::new("T1003", "TA0006")],
+        },
+        RuleConfig {
+            id: "exec-from-temp".into(),
+            description: Some("Executable started from a world-writable or temp directory".into()),
+            weight: 0.4,
+            condition: RuleCondition::ProcessPath {
+                patterns: patterns(&[
+                    "/tmp/*",
+                    "/var/tmp/*",
+                    "/dev/shm/*",
+                    "/private/tmp/*",
+                    "/private/var/tmp/*",
+                    "*\\AppData\\Local\\Temp\\*",
+                    "C:\\Windows\\Temp\\*",
+                ]),
+            },
+            attack: vec![AttackTag::new("T1204", "TA0002")],
+        },
+        RuleConfig {
+            id: "sensitive-file-write".into(),
+            description: Some("Account, sudo, or SSH authorization file changed".into()),
+            weight: 0.6,
+            condition: RuleCondition::FileWrite {
+                patterns: patterns(&["/etc/passwd", "/etc/shadow", "/etc/sudoers", "/etc/sudoers.d/*", "*/.ssh/authorized_keys"]),
+            },
+            attack: vec![AttackTag::new("T1098", "TA0003"), AttackTag::new("T1548.003", "TA0004")],
+        },
+        RuleConfig {
+            id: "privilege-failure-burst".into(),
+            description: Some("Repeated failed privilege changes".into()),
+            weight: 0.5,
+            condition: RuleCondition::PrivilegeFailures { count: 5, window_secs: 60 },
+            attack: vec![AttackTag::new("T1548", "TA0004"), AttackTag::new("T1110", "TA0006")],
+        },
+    ]
+}
+
+/// Case-insensitive wildcard match (`*` any run, `?` one character)
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let t: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // Position after the last `*` and the text index it is currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi + 1, ti));
+            pi += 1;
+        } else if let Some((after, matched)) = star {
+            pi = after;
+            ti = matched + 1;
+            star = Some((after, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+fn any_match(patterns: &[String], text: &str) -> bool {
+    patterns.iter().any(|p| wildcard_match(p, text))
+}
+
+/// Compiled rule set; burst conditions keep their recent timestamps across windows.
+pub struct RuleSet {
+    rules: Vec<RuleConfig>,
+    /// Failure timestamps (ms) per rule, for `PrivilegeFailures` rules
+    bursts: Vec<Mutex<VecDeque<i64>>>,
+}
+
+impl RuleSet {
+    pub fn new(config: &RulesConfig) -> Self {
+        let mut rules = if config.builtin { builtin_rules() } else { Vec::new() };
+        rules.extend(config.rules.iter().cloned());
+        for rule in &mut rules {
+            rule.weight = rule.weight.clamp(0.0, 1.0);
+        }
+        let bursts = rules.iter().map(|_| Mutex::new(VecDeque::new())).collect();
+        Self { rules, bursts }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rules matching `events` (one hit per rule), in rule order
+    pub fn evaluate(&self, events: &[Event]) -> Vec<RuleHit> {
+        let mut hits = Vec::new();
+        for (rule, burst) in self.rules.iter().zip(&self.bursts) {
+            let matched = match &rule.condition {
+                RuleCondition::ProcessName { patterns } => events.iter().find(|e| match &e.kind {
+                    EventKind::Process(p) => any_match(patterns, &p.name),
+                    _ => false,
+                }),
+                RuleCondition::ProcessPath { patterns } => events.iter().find(|e| match &e.kind {
+                    EventKind::Process(p) => p.exe.as_deref().is_some_and(|exe| any_match(patterns, exe)),
+                    _ => false,
+                }),
+                RuleCondition::FileWrite { patterns } => events.iter().find(|e| match &e.kind {
+                    EventKind::FileIntegrity(f) => {
+                        !matches!(f.event, FileIntegrityChange::Scanned) && any_match(patterns, &f.path)
+                    }
+                    _ => false,
+                }),
+                RuleCondition::PrivilegeFailures { count, window_secs } => {
+                    let mut recent = burst.lock().unwrap();
+                    let mut completing = None;
+                    for e in events {
+                        if !matches!(&e.kind, EventKind::Privilege(p) if !p.success) {
+                            continue;
+                        }
+                        let ts = e.ts.timestamp_millis();
+                        recent.push_back(ts);
+                        let horizon = ts.saturating_sub(*window_secs as i64 * 1000);
+                        while recent.front().is_some_and(|&t| t < horizon) {
+                            recent.pop_front();
+                        }
+                        if completing.is_none() && recent.len() >= (*count).max(1) {
+                            completing = Some(e);
+                        }
+                    }
+                    completing
+                }
+            };
+            if let Some(e) = matched {
+                hits.push(RuleHit {
+                    rule: rule.id.clone(),
+                    weight: rule.weight,
+                    event_id: e.id.clone(),
+                    attack: rule.attack.clone(),
+                });
+            }
+        }
+        hits
+    }
+}
+
+/// Fuse hit weights (rules, correlation boosts) into a model score: each hit independently raises
+/// the chance the window is malicious (noisy-OR), so one strong hit lifts a low model score and
+/// several weak ones add up.
+pub fn fuse(model_score: f32, weights: impl IntoIterator<Item = f32>) -> f32 {
+    let benign = weights
+        .into_iter()
+        .fold(1.0 - model_score.clamp(0.0, 1.0), |acc, w| acc * (1.0 - w.clamp(0.0, 1.0)));
+    (1.0 - benign).clamp(0.0, 1.0)
+}
@@ -0,0 +1,41 @@
+//! Score smoothing (`risk.smoothing`): an exponentially weighted moving average of model scores
+//! per entity, so a single noisy window on a device with few events doesn't cross a threshold.
+
+use crate::config::SmoothingConfig;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Entities tracked at most; the averages restart beyond this
+const MAX_TRACKED: usize = 10_000;
+
+pub struct Smoother {
+    config: SmoothingConfig,
+    averages: Mutex<HashMap<String, f32>>,
+}
+
+impl Smoother {
+    pub fn new(config: SmoothingConfig) -> Self {
+        Self {
+            config,
+            averages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fold `score` into the entity's average and return it (`score` itself when disabled or for
+    /// the entity's first window)
+    pub fn observe(&self, entity: &str, score: f32) -> f32 {
+        if !self.config.enabled {
+            return score;
+        }
+        let alpha = self.config.alpha.clamp(0.0, 1.0);
+        let mut averages = self.averages.lock().unwrap();
+        if averages.len() >= MAX_TRACKED && !averages.contains_key(entity) {
+            averages.clear();
+        }
+        let average = averages
+            .entry(entity.to_string())
+            .and_modify(|a| *a = alpha * score + (1.0 - alpha) * *a)
+            .or_insert(score);
+        *average
+    }
+}
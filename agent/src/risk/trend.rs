@@ -0,0 +1,127 @@
+//! Risk history: the score time series of the device (window scores) or of one entity (its
+//! accumulated risk), bucketed with rolling aggregates, so the CLI and uplink can show risk over
+//! the last day rather than only the latest window.
+
+use serde::{Deserialize, Serialize};
+
+/// Buckets per trend at most; wider ranges get wider buckets
+const MAX_POINTS: i64 = 10_000;
+
+/// Which series to aggregate and how
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrendQuery {
+    /// `None` for the device's window scores, else an entity (`process:<name>`, `user:<uid>`,
+    /// `device`) whose accumulated risk is recorded on results
+    pub entity: Option<String>,
+    /// Range [since, until) in ms
+    pub since: i64,
+    pub until: i64,
+    pub bucket_secs: u64,
+    /// Buckets (including the current one) averaged into `rolling_mean`
+    pub rolling_buckets: usize,
+}
+
+impl TrendQuery {
+    /// Device scores over [since, until) in hourly buckets with a 6-hour rolling mean
+    pub fn new(since: i64, until: i64) -> Self {
+        Self {
+            entity: None,
+            since,
+            until,
+            bucket_secs: 3600,
+            rolling_buckets: 6,
+        }
+    }
+}
+
+/// One bucket of a trend; the aggregates are `None` when the bucket has no samples
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrendPoint {
+    /// Bucket start (ms)
+    pub ts: i64,
+    pub samples: usize,
+    pub mean: Option<f32>,
+    pub max: Option<f32>,
+    /// Mean of the samples in this and the preceding `rolling_buckets - 1` buckets
+    pub rolling_mean: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RiskTrend {
+    /// `device` for window scores, else the entity
+    pub entity: String,
+    pub since: i64,
+    pub until: i64,
+    pub bucket_secs: u64,
+    pub points: Vec<TrendPoint>,
+    pub samples: usize,
+    pub mean: Option<f32>,
+    pub max: Option<f32>,
+    /// Most recent sample
+    pub latest: Option<f32>,
+    /// Mean of the last bucket with samples minus that of the first (positive = rising)
+    pub change: Option<f32>,
+}
+
+impl RiskTrend {
+    /// Aggregate `(ts ms, score)` samples for `query`; samples outside the range are ignored
+    pub fn from_samples(query: &TrendQuery, samples: &[(i64, f32)]) -> Self {
+        let until = query.until.max(query.since);
+        let span = until - query.since;
+        let mut bucket_ms = (query.bucket_secs.max(1) as i64).saturating_mul(1000);
+        if span / bucket_ms >= MAX_POINTS {
+            bucket_ms = span / MAX_POINTS + 1;
+        }
+        let buckets = ((span + bucket_ms - 1) / bucket_ms) as usize;
+        // (count, sum, max) per bucket
+        let mut acc: Vec<(usize, f64, f32)> = vec![(0, 0.0, f32::MIN); buckets];
+        let mut latest: Option<(i64, f32)> = None;
+        for &(ts, score) in samples {
+            if ts < query.since || ts >= until || !score.is_finite() {
+                continue;
+            }
+            let b = &mut acc[((ts - query.since) / bucket_ms) as usize];
+            b.0 += 1;
+            b.1 += score as f64;
+            b.2 = b.2.max(score);
+            match latest {
+                Some((at, _)) if ts < at => {}
+                _ => latest = Some((ts, score)),
+            }
+        }
+
+        let window = query.rolling_buckets.max(1);
+        let points: Vec<TrendPoint> = (0..buckets)
+            .map(|i| {
+                let (count, sum, max) = acc[i];
+                let (r_count, r_sum) = acc[(i + 1).saturating_sub(window)..=i]
+                    .iter()
+                    .fold((0, 0.0), |(c, s), b| (c + b.0, s + b.1));
+                TrendPoint {
+                    ts: query.since + i as i64 * bucket_ms,
+                    samples: count,
+                    mean: (count > 0).then(|| (sum / count as f64) as f32),
+                    max: (count > 0).then_some(max),
+                    rolling_mean: (r_count > 0).then(|| (r_sum / r_count as f64) as f32),
+                }
+            })
+            .collect();
+
+        let total: usize = acc.iter().map(|b| b.0).sum();
+        let sum: f64 = acc.iter().map(|b| b.1).sum();
+        let first = points.iter().find_map(|p| p.mean);
+        let last = points.iter().rev().find_map(|p| p.mean);
+        Self {
+            entity: query.entity.clone().unwrap_or_else(|| super::entity::DEVICE_ENTITY.to_string()),
+            since: query.since,
+            until,
+            bucket_secs: (bucket_ms / 1000) as u64,
+            samples: total,
+            mean: (total > 0).then(|| (sum / total as f64) as f32),
+            max: points.iter().filter_map(|p| p.max).reduce(f32::max),
+            latest: latest.map(|(_, s)| s),
+            change: first.zip(last).map(|(f, l)| l - f),
+            points,
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! Daemon runtime (tokio). Collection, analysis, and periodic jobs are separate tasks: the
+//! collector task pushes one [`Batch`] per pass into a bounded channel, the analysis task takes
+//! batches from it (features, inference, scoring, outputs) on the blocking pool, and store writes
+//! and uplink reports go on to their write-behind queues. Every task stops when its
+//! [`CancellationToken`] is cancelled: Ctrl+C / SIGTERM cancel the agent's token, and a child
+//! token ends one run (e.g. when a server policy is applied) without stopping the agent.
+//! Collection and periodic jobs run on a [`Schedule`]: a fixed grid of their interval with
+//! random jitter (`scheduler.jitter`), so slow runs don't make later ones slip and a fleet
+//! started together doesn't reach the server together.
+
+use crate::collectors::{CollectorPipeline, Event};
+use crate::risk::RiskResult;
+use crate::watchdog::{Recovery, Watchdog};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Notify};
+
+pub use tokio_util::sync::CancellationToken;
+
+/// Events of one collection pass
+pub struct Batch {
+    /// Pass number, from 1
+    pub cycle: u64,
+    pub events: Vec<Event>,
+    /// When collection started (Unix ns, and monotonic)
+    pub start_unix_nanos: u64,
+    pub started: Instant,
+    /// Time collection took
+    pub collection: Duration,
+}
+
+impl Batch {
+    /// Collect a batch from `collectors` (blocking)
+    pub fn collect(collectors: &CollectorPipeline, cycle: u64) -> Self {
+        let start_unix_nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        let started = Instant::now();
+        let events = collectors.collect_snapshot();
+        Self {
+            cycle,
+            events,
+            start_unix_nanos,
+            started,
+            collection: started.elapsed(),
+        }
+    }
+}
+
+/// What the daemon has done, for its status: events analyzed per collector, the latest risk
+/// result, and the agent's own resource usage
+pub struct Activity {
+    started: i64,
+    /// Per collector: events in total and in the last batch
+    events: Mutex<BTreeMap<&'static str, (u64, u64)>>,
+    latest: Mutex<Option<Value>>,
+    system: Mutex<sysinfo::System>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self {
+            started: chrono::Utc::now().timestamp_millis(),
+            events: Mutex::default(),
+            latest: Mutex::default(),
+            system: Mutex::new(sysinfo::System::new()),
+        }
+    }
+}
+
+impl Activity {
+    /// Count a batch's `events` and keep its `result`
+    pub fn record(&self, events: &[Event], result: &RiskResult) {
+        let mut counts = self.events.lock().unwrap();
+        for (_, last) in counts.values_mut() {
+            *last = 0;
+        }
+        for event in events {
+            let (total, last) = counts.entry(event.kind.name()).or_default();
+            *total += 1;
+            *last += 1;
+        }
+        *self.latest.lock().unwrap() = Some(json!({
+            "event_id": result.event_id,
+            "level": result.level,
+            "score": result.score,
+            "ts": result.ts,
+        }));
+    }
+
+    /// `started` (ms), `events` (`{collector: {total, last}}`), `risk` (latest result), and
+    /// `process` (CPU use since the previous call, in percent of one core, and resident memory)
+    pub fn snapshot(&self) -> Value {
+        let events: BTreeMap<_, _> =
+            self.events.lock().unwrap().iter().map(|(kind, (total, last))| (*kind, json!({ "total": total, "last": last }))).collect();
+        json!({
+            "started": self.started,
+            "events": events,
+            "risk": *self.latest.lock().unwrap(),
+            "process": process_usage(&self.system),
+        })
+    }
+}
+
+/// This process's `{cpu_percent, memory_bytes}`: CPU use since the previous call with `system`,
+/// in percent of one core, and resident memory
+pub(crate) fn process_usage(system: &Mutex<sysinfo::System>) -> Option<Value> {
+    let pid = sysinfo::get_current_pid().ok()?;
+    let mut system = system.lock().unwrap();
+    if !system.refresh_process(pid) {
+        return None;
+    }
+    system.process(pid).map(|p| json!({ "cpu_percent": p.cpu_usage(), "memory_bytes": p.memory() }))
+}
+
+/// Shortest period of a schedule
+const MIN_PERIOD: Duration = Duration::from_secs(1);
+/// Largest jitter, as a share of the period
+pub const MAX_JITTER: f64 = 0.5;
+
+/// Run times of a periodic task: on a grid of `period` from the first run, so the time a run
+/// takes doesn't push later runs back, each run delayed by a random share of the period up to
+/// `jitter`. Grid points that passed while a run overran are skipped, not run back to back.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    period: Duration,
+    jitter: f64,
+    /// Grid point of the next run
+    slot: Instant,
+    /// Next run: the grid point and its jitter
+    next: Instant,
+}
+
+impl Schedule {
+    /// First run `delay` after `now` (plus jitter), then every `period` (at least a second);
+    /// `jitter` is capped at [`MAX_JITTER`]
+    pub fn new(now: Instant, delay: Duration, period: Duration, jitter: f64) -> Self {
+        let mut schedule = Self {
+            period: period.max(MIN_PERIOD),
+            jitter: jitter.clamp(0.0, MAX_JITTER),
+            slot: now + delay,
+            next: now,
+        };
+        schedule.next = schedule.slot + schedule.offset();
+        schedule
+    }
+
+    fn offset(&self) -> Duration {
+        self.period.mul_f64(rand::random::<f64>() * self.jitter)
+    }
+
+    /// When the next run is due
+    pub fn next(&self) -> Instant {
+        self.next
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Use `period` from the run after the next on
+    pub fn set_period(&mut self, period: Duration) {
+        self.period = period.max(MIN_PERIOD);
+    }
+
+    /// Schedule the run after the one that was due, at `now`; returns the grid points skipped
+    /// because the run ended after them
+    pub fn advance(&mut self, now: Instant) -> u64 {
+        self.slot += self.period;
+        let behind = now.saturating_duration_since(self.slot);
+        let skipped = (behind.as_nanos() / self.period.as_nanos()) as u64;
+        if skipped > 0 {
+            self.slot += Duration::from_nanos((self.period.as_nanos() * skipped as u128) as u64);
+        }
+        self.next = self.slot + self.offset();
+        skipped
+    }
+
+    /// Run now, and lay the grid from `now`
+    pub fn restart(&mut self, now: Instant) {
+        self.slot = now;
+        self.next = now;
+    }
+}
+
+/// Multi-threaded runtime for the daemon
+pub fn build() -> std::io::Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("dadm-runtime")
+        .build()
+}
+
+/// Cancel `token` on Ctrl+C, or SIGTERM on Unix
+pub async fn cancel_on_signal(token: CancellationToken) {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+        _ = token.cancelled() => return,
+    }
+    token.cancel();
+}
+
+/// Collector task: a batch every `interval_secs` (changeable while running), or the deep scan's
+/// shorter interval while one is active, with `jitter`, and one at once when `scan` is notified,
+/// until `token` is cancelled. Waits for room in `batches`,
+/// so a slow analysis delays collection instead of queuing batches without bound. Dropping the
+/// sender on return lets the analysis task drain what is queued and end. A collection stuck past
+/// the `watchdog`'s limit is abandoned, or ends the run when the watchdog says so.
+pub async fn collect(
+    collectors: Arc<CollectorPipeline>,
+    interval_secs: Arc<AtomicU64>,
+    jitter: f64,
+    batches: mpsc::Sender<Batch>,
+    scan: Arc<Notify>,
+    watchdog: Arc<Watchdog>,
+    token: CancellationToken,
+) {
+    let mut cycle = 0;
+    // Set when the last abandoned collection finished
+    let mut abandoned: Option<Arc<AtomicBool>> = None;
+    let period = |collectors: &CollectorPipeline| {
+        let normal = interval_secs.load(Ordering::Relaxed);
+        Duration::from_secs(collectors.interval_secs(normal, chrono::Utc::now().timestamp_millis()))
+    };
+    let mut schedule = Schedule::new(Instant::now(), Duration::ZERO, period(&collectors), jitter);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(schedule.next().into()) => {}
+            _ = scan.notified() => {
+                tracing::info!("scan requested");
+                schedule.restart(Instant::now());
+            }
+            _ = token.cancelled() => break,
+        }
+        cycle += 1;
+        let pipeline = Arc::clone(&collectors);
+        let done = Arc::new(AtomicBool::new(false));
+        let finished = Arc::clone(&done);
+        let mut collection = tokio::task::spawn_blocking(move || {
+            let batch = Batch::collect(&pipeline, cycle);
+            finished.store(true, Ordering::Relaxed);
+            batch
+        });
+        let collected = match watchdog.stall_limit() {
+            Some(limit) => tokio::select! {
+                collected = &mut collection => Some(collected),
+                _ = tokio::time::sleep(limit) => None,
+            },
+            None => Some(collection.await),
+        };
+        match collected {
+            Some(Ok(batch)) => tokio::select! {
+                sent = batches.send(batch) => if sent.is_err() { break },
+                _ = token.cancelled() => break,
+            },
+            Some(Err(e)) => tracing::warn!(cycle, error = %e, "collection failed"),
+            None => {
+                let earlier_stuck = abandoned.as_ref().is_some_and(|done| !done.load(Ordering::Relaxed));
+                if watchdog.stalled("collector", cycle, earlier_stuck, Instant::now()) != Recovery::Abandoned {
+                    token.cancel();
+                    break;
+                }
+                abandoned = Some(done);
+            }
+        }
+        schedule.set_period(period(&collectors));
+        let skipped = schedule.advance(Instant::now());
+        if skipped > 0 {
+            tracing::warn!(cycle, skipped, "collection fell behind its interval; skipped cycles");
+        }
+    }
+}
+
+/// Run the blocking `job` on the blocking pool after `delay`, then every `period` (at least a
+/// second), each run with `jitter` (see [`Schedule`]), until `token` is cancelled. A run in
+/// progress is finished.
+pub async fn every<F>(delay: Duration, period: Duration, jitter: f64, token: CancellationToken, job: F)
+where
+    F: FnMut() + Send + 'static,
+{
+    let mut job = job;
+    let mut schedule = Schedule::new(Instant::now(), delay, period, jitter);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(schedule.next().into()) => {}
+            _ = token.cancelled() => return,
+        }
+        job = match tokio::task::spawn_blocking(move || {
+            job();
+            job
+        })
+        .await
+        {
+            Ok(job) => job,
+            // The job panicked: it is gone
+            Err(e) => {
+                tracing::warn!(error = %e, "periodic job failed; not run again");
+                return;
+            }
+        };
+        schedule.advance(Instant::now());
+    }
+}
@@ -0,0 +1,145 @@
+//! Per-cycle pipeline statistics: events per collector, events taken out by the allowlist,
+//! results folded into an earlier occurrence by alert deduplication (`risk.dedup`), and the time
+//! each stage took. Every cycle's statistics are logged at debug level and aggregated until the
+//! next heartbeat, which reports them, so performance regressions on a fleet show up server-side.
+
+use crate::collectors::Event;
+use crate::risk::RiskResult;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Stages of a cycle, in order (see [`crate::telemetry::CycleTrace`])
+pub const STAGES: &[&str] = &["collection", "features", "inference", "scoring", "storage", "uplink"];
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Statistics of one cycle
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CycleStats {
+    pub cycle: u64,
+    /// Events per collector
+    pub events: BTreeMap<&'static str, u64>,
+    /// Events taken out by the allowlist
+    pub suppressed: u64,
+    /// Results that repeated an earlier one within `risk.dedup`'s window
+    pub deduplicated: u64,
+    /// Time per stage that ran (ms)
+    pub stages_ms: BTreeMap<&'static str, f64>,
+    pub total_ms: f64,
+}
+
+impl CycleStats {
+    /// Statistics of `cycle`, which collected `events` and scored them into `result`; `stages`
+    /// is the time each stage took
+    pub fn new(cycle: u64, events: &[Event], result: &RiskResult, stages: &BTreeMap<String, Duration>, total: Duration) -> Self {
+        let mut counts = BTreeMap::new();
+        for event in events {
+            *counts.entry(event.kind.name()).or_default() += 1;
+        }
+        Self {
+            cycle,
+            events: counts,
+            suppressed: result.suppressed.iter().map(|s| s.events as u64).sum(),
+            deduplicated: result.fingerprint.as_ref().is_some_and(|fp| fp.is_repeat()) as u64,
+            stages_ms: STAGES.iter().filter_map(|stage| Some((*stage, ms(*stages.get(*stage)?)))).collect(),
+            total_ms: ms(total),
+        }
+    }
+}
+
+/// Mean and maximum of a stage's time
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct StageTiming {
+    pub cycles: u64,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+}
+
+impl StageTiming {
+    fn add(&mut self, ms: f64) {
+        self.merge(&StageTiming { cycles: 1, mean_ms: ms, max_ms: ms });
+    }
+
+    fn merge(&mut self, other: &StageTiming) {
+        let cycles = self.cycles + other.cycles;
+        if cycles > 0 {
+            self.mean_ms = (self.mean_ms * self.cycles as f64 + other.mean_ms * other.cycles as f64) / cycles as f64;
+        }
+        self.cycles = cycles;
+        self.max_ms = self.max_ms.max(other.max_ms);
+    }
+}
+
+/// Statistics of the cycles since the last report
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatsSummary {
+    pub cycles: u64,
+    pub events: BTreeMap<&'static str, u64>,
+    pub suppressed: u64,
+    pub deduplicated: u64,
+    /// Per stage that ran, and `total` for whole cycles
+    pub stages: BTreeMap<&'static str, StageTiming>,
+}
+
+impl StatsSummary {
+    pub fn add(&mut self, stats: &CycleStats) {
+        self.cycles += 1;
+        for (collector, count) in &stats.events {
+            *self.events.entry(collector).or_default() += count;
+        }
+        self.suppressed += stats.suppressed;
+        self.deduplicated += stats.deduplicated;
+        for (stage, ms) in &stats.stages_ms {
+            self.stages.entry(stage).or_default().add(*ms);
+        }
+        self.stages.entry("total").or_default().add(stats.total_ms);
+    }
+
+    pub fn merge(&mut self, other: &StatsSummary) {
+        self.cycles += other.cycles;
+        for (collector, count) in &other.events {
+            *self.events.entry(collector).or_default() += count;
+        }
+        self.suppressed += other.suppressed;
+        self.deduplicated += other.deduplicated;
+        for (stage, timing) in &other.stages {
+            self.stages.entry(stage).or_default().merge(timing);
+        }
+    }
+}
+
+/// Cycle statistics aggregated between heartbeats
+#[derive(Default)]
+pub struct PipelineStats {
+    pending: Mutex<StatsSummary>,
+}
+
+impl PipelineStats {
+    /// Log `stats` at debug level and add them to the next report
+    pub fn record(&self, stats: &CycleStats) {
+        tracing::debug!(
+            cycle = stats.cycle,
+            events = ?stats.events,
+            suppressed = stats.suppressed,
+            deduplicated = stats.deduplicated,
+            stages_ms = ?stats.stages_ms,
+            total_ms = stats.total_ms,
+            "cycle stats"
+        );
+        self.pending.lock().unwrap().add(stats);
+    }
+
+    /// Statistics since the last call, for a heartbeat
+    pub fn take(&self) -> StatsSummary {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Put back statistics a heartbeat failed to report
+    pub fn restore(&self, summary: StatsSummary) {
+        self.pending.lock().unwrap().merge(&summary);
+    }
+}
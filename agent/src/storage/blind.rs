@@ -0,0 +1,96 @@
+//! Blind index: keyed HMAC tags of selected event fields (process name, remote address, path, ...)
+//! kept in `event_index`, so equality lookups don't decrypt every payload while the values themselves
+//! are never stored in clear. The HMAC key is random per store and kept in `meta`, encrypted under
+//! the column key (re-encrypted on key rotation).
+
+use super::encrypted::{encrypt, hex, meta_get, meta_set, Key, SecureStore, KEY_LEN};
+use super::keystore::random_secret;
+use ring::hmac;
+use rusqlite::Connection;
+use zeroize::Zeroizing;
+
+const INDEX_KEY_META: &str = "blind_index_key";
+
+/// Persist the index key encrypted under column key `version`
+pub(super) fn store_index_key(
+    conn: &Connection,
+    version: u32,
+    key: &[u8; KEY_LEN],
+    index_key: &[u8; KEY_LEN],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let enc = encrypt(key, index_key).map_err(|e| format!("{:?}", e))?;
+    meta_set(conn, INDEX_KEY_META, &format!("{}:{}", version, enc))?;
+    Ok(())
+}
+
+/// Scalar value as index text (strings as-is, numbers and booleans in JSON form)
+fn index_text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Values of `fields` in an event payload; the collector-specific `kind` object is searched first,
+/// then the top level.
+fn field_values(payload: &str, fields: &[String]) -> Vec<(String, String)> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(payload) else {
+        return Vec::new();
+    };
+    fields
+        .iter()
+        .filter_map(|field| {
+            let v = value
+                .get("kind")
+                .and_then(|k| k.get(field))
+                .or_else(|| value.get(field))
+                .and_then(index_text)?;
+            Some((field.clone(), v))
+        })
+        .collect()
+}
+
+impl SecureStore {
+    /// Load the index key from `meta`; with `create`, generate one if the store has none yet.
+    pub(super) fn load_index_key(&self, conn: &Connection, create: bool) -> Result<Option<Key>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(stored) = meta_get(conn, INDEX_KEY_META)? {
+            let (version, enc) = stored.split_once(':').ok_or("malformed blind index key")?;
+            let plain = self.decrypt_row(version.parse()?, enc)?;
+            let key: [u8; KEY_LEN] = plain.as_slice().try_into().map_err(|_| "malformed blind index key")?;
+            return Ok(Some(Zeroizing::new(key)));
+        }
+        if !create {
+            return Ok(None);
+        }
+        let secret = random_secret();
+        let key: [u8; KEY_LEN] = secret[..KEY_LEN].try_into()?;
+        let key = Zeroizing::new(key);
+        let (version, column_key) = self.current_key();
+        store_index_key(conn, version, &column_key, &key)?;
+        Ok(Some(key))
+    }
+
+    /// Index tag for `field` = `value`; `None` if this store has no blind index.
+    pub fn blind_term(&self, field: &str, value: &str) -> Option<String> {
+        let key = self.index_key.as_ref()?;
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_slice());
+        let mut ctx = hmac::Context::with_key(&key);
+        ctx.update(field.as_bytes());
+        ctx.update(&[0]);
+        ctx.update(value.as_bytes());
+        Some(hex(ctx.sign().as_ref()))
+    }
+
+    /// Tags for the configured fields present in `payload`
+    pub(super) fn index_terms(&self, payload: &str) -> Vec<String> {
+        if self.index_fields.is_empty() {
+            return Vec::new();
+        }
+        field_values(payload, &self.index_fields)
+            .into_iter()
+            .filter_map(|(field, value)| self.blind_term(&field, &value))
+            .collect()
+    }
+}
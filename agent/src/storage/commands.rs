@@ -0,0 +1,44 @@
+//! Audit trail of server commands (`crate::commands`): one row per command id, with the action
+//! and status in the clear and the arguments and result encrypted. The id doubles as the record
+//! that a command was handled, so a redelivered command is not run again. Kept regardless of
+//! retention, like the policy audit.
+
+use super::encrypted::{encrypt, SecureStore};
+use crate::commands::CommandResult;
+use rusqlite::{params, OptionalExtension};
+
+impl SecureStore {
+    /// Record the outcome of a command
+    pub fn record_command(&self, result: &CommandResult) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (version, key) = self.current_key();
+        let enc = encrypt(&key, &serde_json::to_vec(result)?).map_err(|e| format!("{:?}", e))?;
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO command_audit (ts, command_id, action, status, detail_enc, key_version) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![result.ts, result.command_id, result.action, result.status.as_str(), enc, version],
+        )?;
+        Ok(())
+    }
+
+    /// Whether command `command_id` was handled already
+    pub fn command_handled(&self, command_id: &str) -> Result<bool, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached("SELECT 1 FROM command_audit WHERE command_id = ?1")?;
+        Ok(stmt.query_row(params![command_id], |_| Ok(())).optional()?.is_some())
+    }
+
+    /// Commands handled at or after `since`, oldest first
+    pub fn command_audit(&self, since: i64) -> Result<Vec<CommandResult>, Box<dyn std::error::Error + Send + Sync>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare_cached("SELECT detail_enc, key_version FROM command_audit WHERE ts >= ?1 ORDER BY ts, id")?;
+        let mut rows = stmt.query(params![since])?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let enc: String = row.get(0)?;
+            let plain = self.decrypt_row(row.get(1)?, &enc)?;
+            out.push(serde_json::from_slice(&plain)?);
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,121 @@
+//! Payload deduplication: polling collectors re-emit identical payloads that differ only in their
+//! per-event `id` and `ts`. Those envelope fields stay in the event row; the rest of the payload is
+//! stored once in `payloads`, keyed by an HMAC-SHA256 of its plaintext (keyed with the store's
+//! blind index key, so equal hashes don't reveal guessable contents), and shared by every repeat.
+//! Unreferenced payloads are deleted when events are pruned.
+
+use super::encrypted::{encrypt, Key, SecureStore};
+use ring::hmac;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{Map, Value};
+use zeroize::Zeroizing;
+
+/// Top-level payload fields that differ on every event and are kept per row
+const ENVELOPE_FIELDS: &[&str] = &["id", "ts"];
+
+/// Payload split into its per-event envelope and shareable content
+pub(super) struct SplitPayload {
+    pub envelope: Zeroizing<String>,
+    pub content: Zeroizing<String>,
+    /// Content hash (payload row key)
+    pub hash: String,
+    /// Payload as read back (envelope and content joined); the hash chain covers this form
+    pub canonical: Zeroizing<String>,
+}
+
+fn object(bytes: &[u8]) -> Result<Map<String, Value>, Box<dyn std::error::Error + Send + Sync>> {
+    match serde_json::from_slice(bytes)? {
+        Value::Object(map) => Ok(map),
+        _ => Err("stored payload is not a JSON object".into()),
+    }
+}
+
+/// Rebuild the full payload from a row's envelope and the shared content
+pub(super) fn join(envelope: &[u8], content: &[u8]) -> Result<Zeroizing<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut payload = object(content)?;
+    payload.extend(object(envelope)?);
+    Ok(Zeroizing::new(serde_json::to_vec(&Value::Object(payload))?))
+}
+
+fn content_hash(index_key: &Key, content: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, index_key.as_slice());
+    let mut ctx = hmac::Context::with_key(&key);
+    ctx.update(b"payload\0");
+    ctx.update(content.as_bytes());
+    super::encrypted::hex(ctx.sign().as_ref())
+}
+
+impl SecureStore {
+    /// Split `payload` for deduplicated storage; `None` when dedup is off or the payload is not a
+    /// JSON object (it is then stored whole in the event row).
+    pub(super) fn split_payload(&self, payload: &str) -> Option<SplitPayload> {
+        let index_key = self.index_key.as_ref().filter(|_| self.dedup_payloads)?;
+        let Ok(Value::Object(mut content)) = serde_json::from_str::<Value>(payload) else {
+            return None;
+        };
+        let mut envelope = Map::new();
+        for field in ENVELOPE_FIELDS {
+            if let Some(v) = content.remove(*field) {
+                envelope.insert(field.to_string(), v);
+            }
+        }
+        let envelope = Zeroizing::new(Value::Object(envelope).to_string());
+        let content = Zeroizing::new(Value::Object(content).to_string());
+        let canonical = join(envelope.as_bytes(), content.as_bytes()).ok()?;
+        Some(SplitPayload {
+            hash: content_hash(index_key, &content),
+            canonical: Zeroizing::new(String::from_utf8(canonical.to_vec()).ok()?),
+            envelope,
+            content,
+        })
+    }
+
+    /// Store shared content unless a row with its hash already exists
+    pub(super) fn put_payload(
+        &self,
+        conn: &Connection,
+        split: &SplitPayload,
+        ts: i64,
+        version: u32,
+        key: &Key,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let exists = conn
+            .prepare_cached("SELECT 1 FROM payloads WHERE hash = ?1")?
+            .exists(params![split.hash])?;
+        if !exists {
+            let enc = encrypt(key, split.content.as_bytes()).map_err(|e| format!("{:?}", e))?;
+            conn.prepare_cached("INSERT INTO payloads (hash, ts, payload_enc, key_version) VALUES (?1, ?2, ?3, ?4)")?
+                .execute(params![split.hash, ts, enc, version])?;
+        }
+        Ok(())
+    }
+
+    /// Decrypt an event row's payload, joining shared content when the row references it
+    pub(super) fn event_payload(
+        &self,
+        conn: &Connection,
+        version: u32,
+        enc: &str,
+        hash: Option<&str>,
+    ) -> Result<Zeroizing<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+        let plain = self.decrypt_row(version, enc)?;
+        let Some(hash) = hash else {
+            return Ok(plain);
+        };
+        let (content_enc, content_version): (String, u32) = conn
+            .prepare_cached("SELECT payload_enc, key_version FROM payloads WHERE hash = ?1")?
+            .query_row(params![hash], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?
+            .ok_or("shared payload missing")?;
+        let content = self.decrypt_row(content_version, &content_enc)?;
+        join(&plain, &content)
+    }
+}
+
+/// Delete shared payloads no event references any more
+pub(super) fn collect_payloads(conn: &Connection) -> Result<usize, rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM payloads WHERE NOT EXISTS (SELECT 1 FROM events WHERE events.payload_hash = payloads.hash)",
+        [],
+    )
+}
@@ -1,28 +1,64 @@
 //! SQLite-backed store with AES-GCM encryption of sensitive columns and optional full-DB encryption.
 //! Key derived from device-bound secret (in production: Secure Enclave / Keystore / DPAPI).
+//! Full-DB mode (SQLCipher, `sqlcipher` feature) also encrypts ts, kind, and risk_score pages.
 
 use aes_gcm::{
     aead::{Aead, KeyInit},
     Aes256Gcm,
 };
+use super::shard::ShardSet;
+use super::{blind, dedup, schema};
+use crate::config::{StorageConfig, StorageEncryption};
+use crate::features::FeatureVector;
+use crate::risk::{RiskResult, RiskTrend, TrendQuery};
 use rand::RngCore;
-use rusqlite::{Connection, params};
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, ToSql};
+use serde::Serialize;
+use std::collections::HashMap;
 use std::path::Path;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, RwLock};
+use zeroize::Zeroizing;
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 
 const NONCE_LEN: usize = 12;
-const KEY_LEN: usize = 32;
+pub(super) const KEY_LEN: usize = 32;
 
-fn derive_key(seed: &[u8]) -> [u8; KEY_LEN] {
+/// Column key, scrubbed from memory on drop
+pub(super) type Key = Zeroizing<[u8; KEY_LEN]>;
+
+fn derive_key(seed: &[u8]) -> Key {
     use ring::digest;
-    let mut out = [0u8; KEY_LEN];
+    let mut out = Zeroizing::new([0u8; KEY_LEN]);
     let h = digest::digest(&digest::SHA256, seed);
     out[..h.as_ref().len().min(KEY_LEN)].copy_from_slice(h.as_ref());
     out
 }
 
-fn encrypt(key: &[u8; KEY_LEN], plaintext: &[u8]) -> Result<String, aes_gcm::Error> {
+/// SQLCipher page key, domain-separated from the column key.
+#[cfg(feature = "sqlcipher")]
+fn derive_page_key(seed: &[u8]) -> Key {
+    let mut input = Zeroizing::new(b"dadm-sqlcipher:".to_vec());
+    input.extend_from_slice(seed);
+    derive_key(&input)
+}
+
+/// Key the connection with SQLCipher and verify the key opens the database.
+#[cfg(feature = "sqlcipher")]
+fn apply_page_key(conn: &Connection, secret: &[u8]) -> Result<(), rusqlite::Error> {
+    let hex = Zeroizing::new(hex(derive_page_key(secret).as_ref()));
+    conn.execute_batch(&Zeroizing::new(format!("PRAGMA key = \"x'{}'\";", *hex)))?;
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |r| r.get::<_, i64>(0))?;
+    Ok(())
+}
+
+/// Re-key SQLCipher pages to the page key derived from `secret`.
+#[cfg(feature = "sqlcipher")]
+fn rekey_pages(conn: &Connection, secret: &[u8]) -> Result<(), rusqlite::Error> {
+    let hex = Zeroizing::new(hex(derive_page_key(secret).as_ref()));
+    conn.execute_batch(&Zeroizing::new(format!("PRAGMA rekey = \"x'{}'\";", *hex)))
+}
+
+pub(super) fn encrypt(key: &[u8; KEY_LEN], plaintext: &[u8]) -> Result<String, aes_gcm::Error> {
     let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| aes_gcm::Error)?;
     let mut nonce = [0u8; NONCE_LEN];
     rand::thread_rng().fill_bytes(&mut nonce);
@@ -32,46 +68,630 @@
     Ok(BASE64.encode(&out))
 }
 
-fn decrypt(key: &[u8; KEY_LEN], encoded: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+/// Decrypted plaintext is scrubbed when dropped; copy out only what the caller returns.
+fn decrypt(key: &[u8; KEY_LEN], encoded: &str) -> Result<Zeroizing<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
     let raw = BASE64.decode(encoded)?;
     if raw.len() < NONCE_LEN {
         return Err("payload too short".into());
     }
     let (nonce, ct) = raw.split_at(NONCE_LEN);
     let cipher = Aes256Gcm::new_from_slice(key).map_err(|e| format!("{:?}", e))?;
-    Ok(cipher.decrypt(nonce.into(), ct)?)
+    Ok(Zeroizing::new(cipher.decrypt(nonce.into(), ct)?))
+}
+
+/// Plaintext encrypted per key version into meta (`key_check.<version>`) to verify secrets on open.
+const KEY_CHECK: &[u8] = b"dadm-key-check";
+/// Rows re-encrypted per transaction during key rotation
+const ROTATION_BATCH: usize = 500;
+/// Oldest rows (across events, risk results, features) dropped per step when over `max_db_bytes`
+const RETENTION_SIZE_STEP: i64 = 500;
+/// Prepared statements kept per connection (query filters produce a handful of SQL shapes)
+const STATEMENT_CACHE_CAPACITY: usize = 32;
+/// Oldest rows evicted per step in ring-buffer mode
+const RING_EVICT_STEP: i64 = 64;
+/// (table, encrypted column) pairs covered by key versions and rotation
+pub(super) const ENCRYPTED_COLUMNS: &[(&str, &str)] = &[
+    ("events", "payload_enc"),
+    ("risk_results", "detail_enc"),
+    ("features", "values_enc"),
+    ("outbox", "body_enc"),
+    ("payloads", "payload_enc"),
+    ("feedback", "detail_enc"),
+    ("policy_audit", "changes_enc"),
+    ("command_audit", "detail_enc"),
+    ("response_actions", "detail_enc"),
+];
+
+pub(super) fn meta_get(conn: &Connection, k: &str) -> Result<Option<String>, rusqlite::Error> {
+    let mut stmt = conn.prepare_cached("SELECT v FROM meta WHERE k = ?1")?;
+    let mut rows = stmt.query(params![k])?;
+    match rows.next()? {
+        Some(row) => row.get(0),
+        None => Ok(None),
+    }
+}
+
+pub(super) fn meta_set(conn: &Connection, k: &str, v: &str) -> Result<(), rusqlite::Error> {
+    conn.prepare_cached("INSERT OR REPLACE INTO meta (k, v) VALUES (?1, ?2)")?.execute(params![k, v])?;
+    Ok(())
+}
+
+fn meta_delete(conn: &Connection, k: &str) -> Result<(), rusqlite::Error> {
+    conn.execute("DELETE FROM meta WHERE k = ?1", params![k])?;
+    Ok(())
+}
+
+/// Whether `key` is the key for `version`: checked against `key_check.<version>`, or for stores
+/// without a check value, against a sample row. With `record`, stores the check value when it was missing.
+fn key_matches(conn: &Connection, version: u32, key: &[u8; KEY_LEN], record: bool) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let check_key = format!("key_check.{}", version);
+    if let Some(check) = meta_get(conn, &check_key)? {
+        return Ok(decrypt(key, &check).map(|p| p.as_slice() == KEY_CHECK).unwrap_or(false));
+    }
+    let sample: Option<String> = conn
+        .query_row(
+            "SELECT payload_enc FROM events WHERE key_version = ?1 LIMIT 1",
+            params![version],
+            |row| row.get(0),
+        )
+        .ok();
+    if let Some(enc) = sample {
+        if decrypt(key, &enc).is_err() {
+            return Ok(false);
+        }
+    }
+    if record {
+        let check = encrypt(key, KEY_CHECK).map_err(|e| format!("{:?}", e))?;
+        meta_set(conn, &check_key, &check)?;
+    }
+    Ok(true)
+}
+
+pub(super) fn meta_version(conn: &Connection, k: &str) -> Result<Option<u32>, rusqlite::Error> {
+    Ok(meta_get(conn, k)?.and_then(|v| v.parse().ok()))
+}
+
+/// `prev` hash of the first link in the event chain
+const CHAIN_GENESIS: &str = "genesis";
+
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hash of one event link over the previous link and the row's plaintext fields
+/// (not ciphertext, so links survive key rotation).
+fn chain_link(prev: &str, seq: i64, id: &str, ts: i64, kind: &str, payload: &[u8], risk_score: Option<f32>) -> String {
+    use ring::digest;
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(prev.as_bytes());
+    ctx.update(&seq.to_le_bytes());
+    ctx.update(&ts.to_le_bytes());
+    for field in [id.as_bytes(), kind.as_bytes(), payload] {
+        ctx.update(&(field.len() as u64).to_le_bytes());
+        ctx.update(field);
+    }
+    match risk_score {
+        Some(r) => {
+            ctx.update(&[1]);
+            ctx.update(&r.to_bits().to_le_bytes());
+        }
+        None => ctx.update(&[0]),
+    }
+    hex(ctx.finish().as_ref())
+}
+
+/// MAC over the chain anchor and a signed head, keyed from the column key (domain-separated).
+fn chain_mac(key: &[u8; KEY_LEN], anchor: &str, head: &str) -> String {
+    use ring::hmac;
+    let mut input = Zeroizing::new(b"dadm-chain:".to_vec());
+    input.extend_from_slice(key);
+    let mac_key = hmac::Key::new(hmac::HMAC_SHA256, derive_key(&input).as_ref());
+    hex(hmac::sign(&mac_key, format!("{}|{}", anchor, head).as_bytes()).as_ref())
+}
+
+/// Parse a `seq:hash` link reference from meta
+fn meta_link(conn: &Connection, k: &str) -> Result<(i64, String), rusqlite::Error> {
+    Ok(meta_get(conn, k)?
+        .and_then(|v| {
+            let (seq, hash) = v.split_once(':')?;
+            Some((seq.parse().ok()?, hash.to_string()))
+        })
+        .unwrap_or_else(|| (0, CHAIN_GENESIS.to_string())))
+}
+
+/// Signed chain checkpoint stored in meta as `key_version:seq:hash:mac`
+struct ChainCheckpoint {
+    key_version: u32,
+    seq: i64,
+    hash: String,
+    mac: String,
+}
+
+impl ChainCheckpoint {
+    fn load(conn: &Connection) -> Result<Option<Self>, rusqlite::Error> {
+        Ok(meta_get(conn, "chain_checkpoint")?.and_then(|v| {
+            let mut parts = v.splitn(4, ':');
+            Some(Self {
+                key_version: parts.next()?.parse().ok()?,
+                seq: parts.next()?.parse().ok()?,
+                hash: parts.next()?.to_string(),
+                mac: parts.next()?.to_string(),
+            })
+        }))
+    }
+
+    fn sign(conn: &Connection, version: u32, key: &[u8; KEY_LEN], seq: i64, hash: &str) -> Result<(), rusqlite::Error> {
+        let (anchor_seq, anchor_hash) = meta_link(conn, "chain_anchor")?;
+        let mac = chain_mac(key, &format!("{}:{}", anchor_seq, anchor_hash), &format!("{}:{}", seq, hash));
+        meta_set(conn, "chain_checkpoint", &format!("{}:{}:{}:{}", version, seq, hash, mac))
+    }
+
+    fn is_valid(&self, conn: &Connection, key: &[u8; KEY_LEN]) -> Result<bool, rusqlite::Error> {
+        let (anchor_seq, anchor_hash) = meta_link(conn, "chain_anchor")?;
+        let mac = chain_mac(key, &format!("{}:{}", anchor_seq, anchor_hash), &format!("{}:{}", self.seq, self.hash));
+        Ok(mac == self.mac)
+    }
+}
+
+/// Apply the configured encryption mode to a fresh connection; returns whether pages are encrypted.
+fn apply_encryption(conn: &Connection, secret: &[u8], config: &StorageConfig) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    match config.encryption {
+        StorageEncryption::Column => Ok(false),
+        #[cfg(feature = "sqlcipher")]
+        StorageEncryption::Full => match apply_page_key(conn, secret) {
+            // SQLCipher can't tell a wrong key from a file that isn't a database
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::NotADatabase => Err(WrongKey.into()),
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(true),
+        },
+        #[cfg(not(feature = "sqlcipher"))]
+        StorageEncryption::Full => {
+            let _ = (conn, secret);
+            Err("storage.encryption is full, but the agent was built without the sqlcipher feature".into())
+        }
+    }
+}
+
+/// Read-write connection with encryption applied and the schema migrated to the latest version
+fn open_conn(path: &Path, secret: &[u8], config: &StorageConfig) -> Result<(Connection, bool), Box<dyn std::error::Error + Send + Sync>> {
+    let conn = Connection::open(path)?;
+    let full_encryption = apply_encryption(&conn, secret, config)?;
+    // Lets retention hand freed pages back to the filesystem; only takes effect on a new file.
+    conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL;")?;
+    schema::migrate(&conn)?;
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    Ok((conn, full_encryption))
+}
+
+/// Immutable read-only connection; the store must already be at the latest schema version
+fn open_conn_readonly(path: &Path, secret: &[u8], config: &StorageConfig) -> Result<(Connection, bool), Box<dyn std::error::Error + Send + Sync>> {
+    if !path.exists() {
+        return Err(format!("{} does not exist", path.display()).into());
+    }
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let conn = Connection::open_with_flags(immutable_uri(path), flags)?;
+    let full_encryption = apply_encryption(&conn, secret, config)?;
+    conn.execute_batch("PRAGMA query_only = 1;")?;
+    let version = meta_version(&conn, "schema_version")?.unwrap_or(0);
+    if version != schema::latest_schema_version() {
+        return Err(format!(
+            "store schema version {} differs from supported {}; open a copy read-write to migrate it",
+            version,
+            schema::latest_schema_version()
+        )
+        .into());
+    }
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    Ok((conn, full_encryption))
+}
+
+/// SQLite URI opening `path` immutable (no locks, journal, or WAL)
+fn immutable_uri(path: &Path) -> String {
+    let mut uri = String::from("file:");
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '\\' => uri.push('/'),
+            '%' | '?' | '#' => uri.push_str(&format!("%{:02X}", c as u32)),
+            c => uri.push(c),
+        }
+    }
+    uri.push_str("?immutable=1");
+    uri
+}
+
+/// Opening a store with a secret that is not its key; other open errors (I/O, locks, corruption)
+/// are returned as they are
+#[derive(Debug)]
+pub struct WrongKey;
+
+impl std::fmt::Display for WrongKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("storage key does not match this store")
+    }
+}
+
+impl std::error::Error for WrongKey {}
+
+/// Key ring for `secret`: the store's current key, or the target of a rotation whose rows are
+/// all re-encrypted. Fails if `secret` matches neither.
+fn load_keys(conn: &Connection, secret: &[u8], record: bool) -> Result<KeyRing, Box<dyn std::error::Error + Send + Sync>> {
+    let key = derive_key(secret);
+    let version = meta_version(conn, "key_version")?.unwrap_or(1);
+    if key_matches(conn, version, &key, record)? {
+        return Ok(KeyRing::single(version, key));
+    }
+    // Rotation finished re-encrypting but the caller still holds the new secret only.
+    match meta_version(conn, "rotation_target")? {
+        Some(target) if key_matches(conn, target, &key, record)? => {
+            let mut remaining: i64 = 0;
+            for (table, _) in ENCRYPTED_COLUMNS {
+                remaining += conn.query_row(
+                    &format!("SELECT count(*) FROM {} WHERE key_version != ?1", table),
+                    params![target],
+                    |row| row.get::<_, i64>(0),
+                )?;
+            }
+            if remaining > 0 {
+                return Err("key rotation incomplete; open with the previous secret to resume".into());
+            }
+            Ok(KeyRing::single(target, key))
+        }
+        _ => Err(WrongKey.into()),
+    }
+}
+
+/// Column keys by version. New rows are written with `current`.
+struct KeyRing {
+    current: u32,
+    keys: HashMap<u32, Key>,
+}
+
+impl KeyRing {
+    fn single(version: u32, key: Key) -> Self {
+        Self {
+            current: version,
+            keys: HashMap::from([(version, key)]),
+        }
+    }
+}
+
+/// Filter for [`SecureStore::query`]; unset fields don't constrain. Results are ordered by ts.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    /// Inclusive lower bound on ts (ms)
+    pub since: Option<i64>,
+    /// Exclusive upper bound on ts (ms)
+    pub until: Option<i64>,
+    pub kind: Option<String>,
+    /// Only events with risk_score >= this value
+    pub min_risk: Option<f32>,
+    /// Only events whose blind-indexed field equals this value: (field, value). Matches events
+    /// stored while the field was listed in `storage.blind_index_fields`.
+    pub field_equals: Option<(String, String)>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+/// Event row with decrypted payload
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredEvent {
+    pub id: String,
+    pub ts: i64,
+    pub kind: String,
+    pub payload: String,
+    pub risk_score: Option<f32>,
+}
+
+/// Outcome of [`SecureStore::rotate_key`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationProgress {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// Rows re-encrypted by this call
+    pub rows_reencrypted: u64,
+}
+
+/// Outcome of one retention pass
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionReport {
+    pub pruned_by_age: u64,
+    pub pruned_by_size: u64,
+    /// Database size after the pass
+    pub db_bytes: u64,
+}
+
+/// Result of walking the event hash chain
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainReport {
+    pub rows_verified: u64,
+    /// Last link removed by retention; verification starts after it
+    pub anchor_seq: i64,
+    pub head_seq: i64,
+    /// Latest signed checkpoint, if any
+    pub checkpoint_seq: Option<i64>,
+    /// Rows stored before the chain existed (not covered)
+    pub unchained_rows: u64,
+    /// Links whose rows were moved to quarantine by a scrub (linked, contents not re-verified)
+    pub quarantined_links: u64,
+    pub issues: Vec<String>,
+}
+
+impl ChainReport {
+    pub fn is_intact(&self) -> bool {
+        self.issues.is_empty()
+    }
 }
 
 pub struct SecureStore {
-    conn: Mutex<Connection>,
-    key: [u8; KEY_LEN],
+    pub(super) conn: Mutex<Connection>,
+    /// Shared with day shards, which are rotated together with the main file
+    keys: Arc<RwLock<KeyRing>>,
+    full_encryption: bool,
+    chain_checkpoint_interval: u64,
+    /// Live-data budget in ring-buffer mode (0 = off)
+    pub(super) ring_buffer_bytes: u64,
+    read_only: bool,
+    /// Blind index HMAC key, present once the store has an index (see `storage::blind`)
+    pub(super) index_key: Option<Key>,
+    /// Event fields tagged on insert (`storage.blind_index_fields`)
+    pub(super) index_fields: Vec<String>,
+    /// Share repeated payload content (`storage.dedup_payloads`, see `storage::dedup`)
+    pub(super) dedup_payloads: bool,
+    /// Per-day data files (`storage.shard_by_day`); events, risk results, and features live there
+    pub(super) shards: Option<ShardSet>,
 }
 
 impl SecureStore {
     /// Open or create DB at path. Key is derived from `secret` (in production: device-bound).
-    pub fn open(path: &Path, secret: &[u8]) -> Result<Self, rusqlite::Error> {
-        let conn = Connection::open(path)?;
-        conn.execute_batch(
-            r#"
-            CREATE TABLE IF NOT EXISTS events (
-                id TEXT PRIMARY KEY,
-                ts INTEGER NOT NULL,
-                kind TEXT NOT NULL,
-                payload_enc TEXT NOT NULL,
-                risk_score REAL
-            );
-            CREATE INDEX IF NOT EXISTS idx_events_ts ON events(ts);
-            CREATE TABLE IF NOT EXISTS meta (k TEXT PRIMARY KEY, v TEXT);
-            "#,
-        )?;
-        let key = derive_key(secret);
+    pub fn open(path: &Path, secret: &[u8]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Self::open_with(path, secret, &StorageConfig::default())
+    }
+
+    /// Open with an explicit encryption mode. `Full` requires the `sqlcipher` feature;
+    /// without it the store refuses to open.
+    /// Fails if `secret` is not the store's current key (or the target of an interrupted rotation).
+    pub fn open_with(path: &Path, secret: &[u8], config: &StorageConfig) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let (conn, full_encryption) = open_conn(path, secret, config)?;
+        let ring = load_keys(&conn, secret, true)?;
+        let mut store = Self {
+            conn: Mutex::new(conn),
+            keys: Arc::new(RwLock::new(ring)),
+            full_encryption,
+            chain_checkpoint_interval: config.chain_checkpoint_interval,
+            ring_buffer_bytes: config.ring_buffer_bytes,
+            read_only: false,
+            index_key: None,
+            index_fields: config.blind_index_fields.clone(),
+            dedup_payloads: config.dedup_payloads,
+            shards: None,
+        };
+        let create_index_key = !store.index_fields.is_empty() || store.dedup_payloads;
+        store.index_key = store.load_index_key(&store.conn.lock().unwrap(), create_index_key)?;
+        if config.shard_by_day {
+            store.shards = Some(ShardSet::open(path, secret, config, &store)?);
+        }
+        Ok(store)
+    }
+
+    /// Store held entirely in memory: nothing is written to disk and all data is gone when it is
+    /// dropped. For tests and deployments that want no at-rest telemetry.
+    pub fn open_in_memory(secret: &[u8]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Self::open_in_memory_with(secret, &StorageConfig::default())
+    }
+
+    /// In-memory store with explicit settings (see `open_with`). Page encryption and day shards
+    /// don't apply without a file and are ignored.
+    pub fn open_in_memory_with(secret: &[u8], config: &StorageConfig) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let conn = Connection::open_in_memory()?;
+        schema::migrate(&conn)?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        let ring = load_keys(&conn, secret, true)?;
+        let mut store = Self {
+            conn: Mutex::new(conn),
+            keys: Arc::new(RwLock::new(ring)),
+            full_encryption: false,
+            chain_checkpoint_interval: config.chain_checkpoint_interval,
+            ring_buffer_bytes: config.ring_buffer_bytes,
+            read_only: false,
+            index_key: None,
+            index_fields: config.blind_index_fields.clone(),
+            dedup_payloads: config.dedup_payloads,
+            shards: None,
+        };
+        let create_index_key = !store.index_fields.is_empty() || store.dedup_payloads;
+        store.index_key = store.load_index_key(&store.conn.lock().unwrap(), create_index_key)?;
+        Ok(store)
+    }
+
+    /// Open a copied store for forensic inspection. The file is opened immutable and read-only:
+    /// no journal or WAL, no migrations, and any write (insert, prune, rotation) fails, so the
+    /// evidence is not altered. The store must already be at the current schema version.
+    pub fn open_readonly(path: &Path, secret: &[u8]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Self::open_readonly_with(path, secret, &StorageConfig::default())
+    }
+
+    /// Read-only open with an explicit encryption mode (see `open_with`).
+    pub fn open_readonly_with(path: &Path, secret: &[u8], config: &StorageConfig) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let (conn, full_encryption) = open_conn_readonly(path, secret, config)?;
+        let ring = load_keys(&conn, secret, false)?;
+        let mut store = Self {
+            conn: Mutex::new(conn),
+            keys: Arc::new(RwLock::new(ring)),
+            full_encryption,
+            chain_checkpoint_interval: 0,
+            ring_buffer_bytes: 0,
+            read_only: true,
+            index_key: None,
+            index_fields: Vec::new(),
+            dedup_payloads: false,
+            shards: None,
+        };
+        store.index_key = store.load_index_key(&store.conn.lock().unwrap(), false)?;
+        if config.shard_by_day {
+            store.shards = Some(ShardSet::open(path, secret, config, &store)?);
+        }
+        Ok(store)
+    }
+
+    /// Open one day shard of `root`: a data-only store sharing the root's key ring and blind index
+    /// key, opened read-only when the root is.
+    pub(super) fn open_shard(path: &Path, secret: &[u8], config: &StorageConfig, root: &SecureStore) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let (conn, full_encryption) = if root.read_only {
+            open_conn_readonly(path, secret, config)?
+        } else {
+            open_conn(path, secret, config)?
+        };
         Ok(Self {
             conn: Mutex::new(conn),
-            key,
+            keys: Arc::clone(&root.keys),
+            full_encryption,
+            chain_checkpoint_interval: root.chain_checkpoint_interval,
+            ring_buffer_bytes: 0,
+            read_only: root.read_only,
+            index_key: root.index_key.clone(),
+            index_fields: root.index_fields.clone(),
+            dedup_payloads: root.dedup_payloads,
+            shards: None,
+        })
+    }
+
+    /// True when opened with `open_readonly`
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// True when the whole database file is page-encrypted (SQLCipher)
+    pub fn is_fully_encrypted(&self) -> bool {
+        self.full_encryption
+    }
+
+    /// Version of the key new rows are written with
+    pub fn key_version(&self) -> u32 {
+        self.keys.read().unwrap().current
+    }
+
+    pub(super) fn current_key(&self) -> (u32, Key) {
+        let ring = self.keys.read().unwrap();
+        (ring.current, ring.keys[&ring.current].clone())
+    }
+
+    pub(super) fn decrypt_row(&self, version: u32, enc: &str) -> Result<Zeroizing<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+        let key = self
+            .keys
+            .read()
+            .unwrap()
+            .keys
+            .get(&version)
+            .cloned()
+            .ok_or_else(|| format!("no key for key version {}", version))?;
+        decrypt(&key, enc)
+    }
+
+    /// Re-encrypt all rows under a key derived from `new_secret`, in batches of
+    /// [`ROTATION_BATCH`] rows per transaction so the store stays usable meanwhile. Progress is
+    /// tracked in the meta table: after a crash, reopen with the old secret and call again with
+    /// the same `new_secret` to resume. New rows are written with the new key as soon as the
+    /// rotation starts. In full-DB mode the SQLCipher page key is rekeyed at the end.
+    pub fn rotate_key(&self, new_secret: &[u8]) -> Result<RotationProgress, Box<dyn std::error::Error + Send + Sync>> {
+        let new_key = derive_key(new_secret);
+        let (from, to) = {
+            let conn = self.conn.lock().unwrap();
+            let from = self.key_version();
+            match meta_version(&conn, "rotation_target")? {
+                Some(target) if target != from => {
+                    if !key_matches(&conn, target, &new_key, true)? {
+                        return Err("new secret does not match the in-progress rotation".into());
+                    }
+                    (from, target)
+                }
+                // Opened with the target key after all rows were re-encrypted: just finalize.
+                Some(target) => (target, target),
+                None => {
+                    if self.current_key().1 == new_key {
+                        return Ok(RotationProgress {
+                            from_version: from,
+                            to_version: from,
+                            rows_reencrypted: 0,
+                        });
+                    }
+                    let to = from + 1;
+                    let check = encrypt(&new_key, KEY_CHECK).map_err(|e| format!("{:?}", e))?;
+                    meta_set(&conn, &format!("key_check.{}", to), &check)?;
+                    meta_set(&conn, "rotation_target", &to.to_string())?;
+                    (from, to)
+                }
+            }
+        };
+        {
+            let mut ring = self.keys.write().unwrap();
+            ring.keys.insert(to, new_key.clone());
+            ring.current = to;
+        }
+        tracing::info!(from, to, "storage key rotation started");
+
+        let mut rotated: u64 = 0;
+        let shards = self.shards.as_ref().map(ShardSet::all).unwrap_or_default();
+        for store in std::iter::once(self).chain(shards.iter().map(|(_, shard)| shard.as_ref())) {
+            for (table, column) in ENCRYPTED_COLUMNS {
+                loop {
+                    let n = store.rotate_batch(table, column, to, &new_key)?;
+                    if n == 0 {
+                        break;
+                    }
+                    rotated += n as u64;
+                }
+            }
+        }
+        for (_, shard) in &shards {
+            let conn = shard.conn.lock().unwrap();
+            #[cfg(feature = "sqlcipher")]
+            if shard.full_encryption {
+                rekey_pages(&conn, new_secret)?;
+            }
+            shard.resign_checkpoint(&conn, to, &new_key)?;
+            meta_delete(&conn, "rotation_rows")?;
+        }
+        if let Some(ref shards) = self.shards {
+            shards.set_secret(new_secret);
+        }
+
+        let conn = self.conn.lock().unwrap();
+        #[cfg(feature = "sqlcipher")]
+        if self.full_encryption {
+            rekey_pages(&conn, new_secret)?;
+        }
+        self.resign_checkpoint(&conn, to, &new_key)?;
+        if let Some(ref index_key) = self.index_key {
+            blind::store_index_key(&conn, to, &new_key, index_key)?;
+        }
+        self.rotate_secrets(&conn, to, &new_key)?;
+        meta_set(&conn, "key_version", &to.to_string())?;
+        meta_delete(&conn, "rotation_target")?;
+        meta_delete(&conn, "rotation_rows")?;
+        if from != to {
+            meta_delete(&conn, &format!("key_check.{}", from))?;
+        }
+        self.keys.write().unwrap().keys.retain(|v, _| *v == to);
+        tracing::info!(from, to, rows = rotated, "storage key rotation complete");
+        Ok(RotationProgress {
+            from_version: from,
+            to_version: to,
+            rows_reencrypted: rotated,
         })
     }
 
-    /// Insert event (payload stored encrypted)
+    /// Carry a valid chain checkpoint over to key version `to`; an invalid one stays invalid.
+    fn resign_checkpoint(&self, conn: &Connection, to: u32, new_key: &[u8; KEY_LEN]) -> Result<(), rusqlite::Error> {
+        if let Some(cp) = ChainCheckpoint::load(conn)? {
+            let old_key = self.keys.read().unwrap().keys.get(&cp.key_version).cloned();
+            if cp.key_version != to && old_key.map(|k| cp.is_valid(conn, &k)).transpose()?.unwrap_or(false) {
+                ChainCheckpoint::sign(conn, to, new_key, cp.seq, &cp.hash)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Insert event (payload stored encrypted) and append it to the hash chain
     pub fn insert_event(
         &self,
         id: &str,
@@ -80,33 +700,645 @@ pub fn insert_event(
         payload_json: &str,
         risk_score: Option<f32>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let enc = encrypt(&self.key, payload_json.as_bytes())?;
-        self.conn.lock().unwrap().execute(
-            "INSERT OR REPLACE INTO events (id, ts, kind, payload_enc, risk_score) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![id, ts, kind, enc, risk_score],
-        )?;
+        if let Some(ref shards) = self.shards {
+            return self.shard_insert_event(shards, id, ts, kind, payload_json, risk_score);
+        }
+        let (version, key) = self.current_key();
+        let split = self.split_payload(payload_json);
+        let (enc, payload) = match split {
+            Some(ref split) => (encrypt(&key, split.envelope.as_bytes()).map_err(|e| format!("{:?}", e))?, split.canonical.as_str()),
+            None => (encrypt(&key, payload_json.as_bytes()).map_err(|e| format!("{:?}", e))?, payload_json),
+        };
+        let terms = self.index_terms(payload_json);
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        let (head_seq, head_hash) = meta_link(&tx, "chain_head")?;
+        let seq = head_seq + 1;
+        let hash = chain_link(&head_hash, seq, id, ts, kind, payload.as_bytes(), risk_score);
+        // Events are append-only: re-inserting an existing id is a no-op so no chain link is lost.
+        let inserted = tx
+            .prepare_cached(
+                "INSERT OR IGNORE INTO events (id, ts, kind, payload_enc, risk_score, key_version, chain_seq, chain_hash, payload_hash) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?
+            .execute(params![id, ts, kind, enc, risk_score, version, seq, hash, split.as_ref().map(|s| &s.hash)])?;
+        if inserted == 0 {
+            return Ok(());
+        }
+        if let Some(ref split) = split {
+            self.put_payload(&tx, split, ts, version, &key)?;
+        }
+        for term in &terms {
+            tx.prepare_cached("INSERT OR IGNORE INTO event_index (term, event_id) VALUES (?1, ?2)")?
+                .execute(params![term, id])?;
+        }
+        meta_set(&tx, "chain_head", &format!("{}:{}", seq, hash))?;
+        if (seq as u64).checked_rem(self.chain_checkpoint_interval) == Some(0) {
+            ChainCheckpoint::sign(&tx, version, &key, seq, &hash)?;
+        }
+        tx.commit()?;
+        drop(conn);
+        self.enforce_ring_buffer()?;
         Ok(())
     }
 
+    /// Sign the current chain head now (e.g. on shutdown) rather than waiting for the next interval.
+    pub fn checkpoint_chain(&self) -> Result<(), rusqlite::Error> {
+        if let Some(ref shards) = self.shards {
+            self.shard_checkpoint_chain(shards)?;
+        }
+        let (version, key) = self.current_key();
+        let conn = self.conn.lock().unwrap();
+        let (seq, hash) = meta_link(&conn, "chain_head")?;
+        if seq > 0 {
+            ChainCheckpoint::sign(&conn, version, &key, seq, &hash)?;
+        }
+        Ok(())
+    }
+
+    /// Fold a write-ahead log into the database file, for this file and every day shard, so the
+    /// next open has nothing to replay; a no-op for files not in WAL mode
+    pub fn checkpoint_wal(&self) -> Result<(), rusqlite::Error> {
+        if let Some(ref shards) = self.shards {
+            for (_, shard) in shards.all() {
+                shard.checkpoint_wal()?;
+            }
+        }
+        self.conn.lock().unwrap().query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+    }
+
+    /// Walk the event hash chain from the retention anchor to the head, recomputing every link and
+    /// checking the signed checkpoint. Detects altered rows, deleted rows, and a rewritten chain up to
+    /// the last checkpoint. In shard mode every shard's chain is walked and the counts are combined.
+    pub fn verify_chain(&self) -> Result<ChainReport, Box<dyn std::error::Error + Send + Sync>> {
+        match self.shards {
+            Some(ref shards) => self.shard_verify_chain(shards),
+            None => self.verify_chain_local(),
+        }
+    }
+
+    /// [`Self::verify_chain`] over this file only
+    pub(super) fn verify_chain_local(&self) -> Result<ChainReport, Box<dyn std::error::Error + Send + Sync>> {
+        let conn = self.conn.lock().unwrap();
+        let (anchor_seq, anchor_hash) = meta_link(&conn, "chain_anchor")?;
+        let (head_seq, head_hash) = meta_link(&conn, "chain_head")?;
+        let checkpoint = ChainCheckpoint::load(&conn)?;
+        let mut report = ChainReport {
+            rows_verified: 0,
+            anchor_seq,
+            head_seq,
+            checkpoint_seq: checkpoint.as_ref().map(|c| c.seq),
+            unchained_rows: conn.query_row("SELECT count(*) FROM events WHERE chain_seq IS NULL", [], |row| {
+                row.get::<_, i64>(0)
+            })? as u64,
+            quarantined_links: 0,
+            issues: Vec::new(),
+        };
+
+        let mut checkpoint_hash: Option<String> = None;
+        let (mut prev_seq, mut prev_hash) = (anchor_seq, anchor_hash);
+        let mut stmt = conn.prepare_cached(
+            "SELECT id, ts, kind, payload_enc, risk_score, key_version, chain_seq, chain_hash, 0, payload_hash \
+             FROM events WHERE chain_seq > ?1 \
+             UNION ALL SELECT row_key, ts, '', '', NULL, 0, chain_seq, chain_hash, 1, NULL \
+             FROM quarantine WHERE source_table = 'events' AND chain_seq > ?1 ORDER BY 7",
+        )?;
+        let mut rows = stmt.query(params![anchor_seq])?;
+        while let Some(row) = rows.next()? {
+            let id: String = row.get(0)?;
+            let seq: i64 = row.get(6)?;
+            let stored: String = row.get(7)?;
+            if seq != prev_seq + 1 {
+                report.issues.push(format!("links {}..{} missing (rows deleted)", prev_seq + 1, seq - 1));
+            }
+            let enc: String = row.get(3)?;
+            if row.get::<_, i64>(8)? == 1 {
+                report.quarantined_links += 1;
+            } else {
+                match self.event_payload(&conn, row.get(5)?, &enc, row.get::<_, Option<String>>(9)?.as_deref()) {
+                    Ok(payload) => {
+                        let expected = chain_link(&prev_hash, seq, &id, row.get(1)?, &row.get::<_, String>(2)?, &payload, row.get(4)?);
+                        if expected != stored {
+                            report.issues.push(format!("link {} (event {}) does not match its contents or predecessor", seq, id));
+                        }
+                    }
+                    Err(e) => report.issues.push(format!("link {} (event {}) cannot be decrypted: {}", seq, id, e)),
+                }
+            }
+            if checkpoint.as_ref().is_some_and(|c| c.seq == seq) {
+                checkpoint_hash = Some(stored.clone());
+            }
+            prev_seq = seq;
+            prev_hash = stored;
+            report.rows_verified += 1;
+        }
+
+        if prev_seq < head_seq {
+            report.issues.push(format!("links {}..{} missing at the head (rows deleted)", prev_seq + 1, head_seq));
+        } else if prev_seq > head_seq || prev_hash != head_hash {
+            report.issues.push(format!("chain head {} does not match the last link {}", head_seq, prev_seq));
+        }
+
+        if let Some(cp) = checkpoint {
+            match self.keys.read().unwrap().keys.get(&cp.key_version) {
+                Some(key) if cp.is_valid(&conn, key)? => {}
+                Some(_) => report.issues.push(format!("checkpoint {} signature is invalid", cp.seq)),
+                None => report.issues.push(format!("checkpoint {} signed with unknown key version {}", cp.seq, cp.key_version)),
+            }
+            if cp.seq > head_seq {
+                report.issues.push(format!("chain truncated before signed checkpoint {}", cp.seq));
+            } else if cp.seq > anchor_seq && checkpoint_hash.as_deref() != Some(cp.hash.as_str()) {
+                report.issues.push(format!("link {} differs from the signed checkpoint", cp.seq));
+            }
+        }
+        Ok(report)
+    }
+
     /// Read event by id (decrypt payload)
     pub fn get_event(&self, id: &str) -> Result<Option<(i64, String, Option<f32>)>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ref shards) = self.shards {
+            for (_, shard) in shards.all().into_iter().rev() {
+                if let Some(event) = shard.get_event(id)? {
+                    return Ok(Some(event));
+                }
+            }
+            return Ok(None);
+        }
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT ts, payload_enc, risk_score FROM events WHERE id = ?1")?;
+        let mut stmt = conn.prepare_cached("SELECT ts, payload_enc, risk_score, key_version, payload_hash FROM events WHERE id = ?1")?;
         let mut rows = stmt.query(params![id])?;
         if let Some(row) = rows.next()? {
             let ts: i64 = row.get(0)?;
             let enc: String = row.get(1)?;
             let score: Option<f32> = row.get(2)?;
-            let plain = decrypt(&self.key, &enc)?;
-            let payload = String::from_utf8(plain).unwrap_or_default();
+            let plain = self.event_payload(&conn, row.get(3)?, &enc, row.get::<_, Option<String>>(4)?.as_deref())?;
+            let payload = std::str::from_utf8(&plain).map(str::to_owned).unwrap_or_default();
             return Ok(Some((ts, payload, score)));
         }
         Ok(None)
     }
 
-    /// Retention: delete events older than given timestamp
+    /// Re-encrypt up to [`ROTATION_BATCH`] rows of `table` not yet on key version `to`, in one
+    /// transaction. Returns the number of rows re-encrypted.
+    fn rotate_batch(&self, table: &str, column: &str, to: u32, new_key: &[u8; KEY_LEN]) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let batch: Vec<(i64, String, u32)> = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT rowid, {}, key_version FROM {} WHERE key_version != ?1 LIMIT ?2",
+                column, table
+            ))?;
+            let rows = stmt.query_map(params![to, ROTATION_BATCH as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?;
+            rows.collect::<Result<_, _>>()?
+        };
+        for (rowid, enc, version) in &batch {
+            let plain = self.decrypt_row(*version, enc)?;
+            let re = encrypt(new_key, &plain).map_err(|e| format!("{:?}", e))?;
+            tx.execute(
+                &format!("UPDATE {} SET {} = ?1, key_version = ?2 WHERE rowid = ?3", table, column),
+                params![re, to, rowid],
+            )?;
+        }
+        let done = meta_version(&tx, "rotation_rows")?.unwrap_or(0) as u64 + batch.len() as u64;
+        meta_set(&tx, "rotation_rows", &done.to_string())?;
+        tx.commit()?;
+        Ok(batch.len())
+    }
+
+    /// Persist a risk result (full result encrypted; ts, score, level, window in clear for queries).
+    /// A repeat of a fingerprinted alert (`risk.dedup`) updates the row of its series instead: the
+    /// detail becomes the latest occurrence, the clear score the series' highest, and the window
+    /// spans all occurrences.
+    pub fn insert_risk_result(&self, result: &RiskResult) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ref shards) = self.shards {
+            return self.shard_insert_risk_result(shards, result);
+        }
+        let (version, key) = self.current_key();
+        let detail = serde_json::to_vec(result)?;
+        let enc = encrypt(&key, &detail).map_err(|e| format!("{:?}", e))?;
+        let fingerprint = result.fingerprint.as_ref();
+        let conn = self.conn.lock().unwrap();
+        if let Some(fp) = fingerprint.filter(|fp| fp.is_repeat()) {
+            let updated = conn.execute(
+                "UPDATE risk_results SET ts = ?1, score = max(score, ?2), level = ?3, window_end = ?4, detail_enc = ?5, \
+                 key_version = ?6, event_id = ?7 \
+                 WHERE id = (SELECT id FROM risk_results WHERE fingerprint = ?8 AND ts >= ?9 ORDER BY id DESC LIMIT 1)",
+                params![result.ts, result.score, result.level.as_str(), result.window_end, enc, version, result.event_id, fp.id, fp.first_seen],
+            )?;
+            if updated > 0 {
+                return Ok(());
+            }
+        }
+        conn.execute(
+            "INSERT INTO risk_results (ts, score, level, window_start, window_end, detail_enc, key_version, event_id, fingerprint) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                result.ts,
+                result.score,
+                result.level.as_str(),
+                result.window_start,
+                result.window_end,
+                enc,
+                version,
+                result.event_id,
+                fingerprint.map(|fp| fp.id.as_str())
+            ],
+        )?;
+        drop(conn);
+        self.enforce_ring_buffer()?;
+        Ok(())
+    }
+
+    /// Risk results with ts in [since, until), oldest first, at most `limit`
+    pub fn risk_results(&self, since: i64, until: Option<i64>, limit: Option<usize>) -> Result<Vec<RiskResult>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ref shards) = self.shards {
+            return self.shard_risk_results(shards, since, until, limit);
+        }
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "SELECT detail_enc, key_version FROM risk_results WHERE ts >= ?1 AND ts < ?2 ORDER BY ts LIMIT ?3",
+        )?;
+        let limit = limit.map(|l| l as i64).unwrap_or(-1);
+        let mut rows = stmt.query(params![since, until.unwrap_or(i64::MAX), limit])?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let enc: String = row.get(0)?;
+            let plain = self.decrypt_row(row.get(1)?, &enc)?;
+            out.push(serde_json::from_slice(&plain)?);
+        }
+        Ok(out)
+    }
+
+    /// Newest stored risk result whose subject is `event_id` (results stored before schema 11 are
+    /// not addressable)
+    pub fn risk_result(&self, event_id: &str) -> Result<Option<RiskResult>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ref shards) = self.shards {
+            for (_, shard) in shards.all().into_iter().rev() {
+                if let Some(result) = shard.risk_result(event_id)? {
+                    return Ok(Some(result));
+                }
+            }
+            return Ok(None);
+        }
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "SELECT detail_enc, key_version FROM risk_results WHERE event_id = ?1 ORDER BY ts DESC LIMIT 1",
+        )?;
+        let mut rows = stmt.query(params![event_id])?;
+        if let Some(row) = rows.next()? {
+            let enc: String = row.get(0)?;
+            let plain = self.decrypt_row(row.get(1)?, &enc)?;
+            return Ok(Some(serde_json::from_slice(&plain)?));
+        }
+        Ok(None)
+    }
+
+    /// (ts, score) of risk results with ts in [since, until), oldest first: the device's score history
+    pub fn risk_series(&self, since: i64, until: i64) -> Result<Vec<(i64, f32)>, rusqlite::Error> {
+        if let Some(ref shards) = self.shards {
+            return self.shard_risk_series(shards, since, until);
+        }
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare_cached("SELECT ts, score FROM risk_results WHERE ts >= ?1 AND ts < ?2 ORDER BY ts")?;
+        let rows = stmt.query_map(params![since, until], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f32>(1)?)))?;
+        rows.collect()
+    }
+
+    /// (ts, accumulated risk) of `entity` from the risk results in [since, until) that recorded it
+    pub fn entity_risk_series(
+        &self,
+        entity: &str,
+        since: i64,
+        until: i64,
+    ) -> Result<Vec<(i64, f32)>, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self
+            .risk_results(since, Some(until), None)?
+            .into_iter()
+            .filter_map(|r| {
+                let ts = r.ts;
+                r.entity_risk.into_iter().find(|e| e.entity == entity).map(|e| (ts, e.cumulative))
+            })
+            .collect())
+    }
+
+    /// Bucketed risk history for `query` (device scores, or an entity's accumulated risk)
+    pub fn risk_trend(&self, query: &TrendQuery) -> Result<RiskTrend, Box<dyn std::error::Error + Send + Sync>> {
+        let samples = match query.entity {
+            Some(ref entity) => self.entity_risk_series(entity, query.since, query.until)?,
+            None => self.risk_series(query.since, query.until)?,
+        };
+        Ok(RiskTrend::from_samples(query, &samples))
+    }
+
+    /// Persist a feature vector (values encrypted) so the window can be re-scored or exported later
+    pub fn insert_features(&self, fv: &FeatureVector) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ref shards) = self.shards {
+            return self.shard_insert_features(shards, fv);
+        }
+        let (version, key) = self.current_key();
+        let values = serde_json::to_vec(&fv.values)?;
+        let enc = encrypt(&key, &values).map_err(|e| format!("{:?}", e))?;
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO features (ts, event_id, dim, values_enc, key_version) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![fv.ts, fv.event_id, fv.dim as i64, enc, version],
+        )?;
+        self.enforce_ring_buffer()?;
+        Ok(())
+    }
+
+    /// Feature vectors with ts in [since, until), oldest first, at most `limit`
+    pub fn features(&self, since: i64, until: Option<i64>, limit: Option<usize>) -> Result<Vec<FeatureVector>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ref shards) = self.shards {
+            return self.shard_features(shards, since, until, limit);
+        }
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "SELECT ts, event_id, dim, values_enc, key_version FROM features WHERE ts >= ?1 AND ts < ?2 ORDER BY ts LIMIT ?3",
+        )?;
+        let limit = limit.map(|l| l as i64).unwrap_or(-1);
+        let mut rows = stmt.query(params![since, until.unwrap_or(i64::MAX), limit])?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let enc: String = row.get(3)?;
+            let plain = self.decrypt_row(row.get(4)?, &enc)?;
+            out.push(FeatureVector {
+                ts: row.get(0)?,
+                event_id: row.get(1)?,
+                dim: row.get::<_, i64>(2)? as usize,
+                values: serde_json::from_slice(&plain)?,
+            });
+        }
+        Ok(out)
+    }
+
+    /// WHERE clause (after `WHERE 1 = 1`) and arguments for the constraints of `filter`
+    fn filter_sql(&self, filter: &EventFilter) -> Result<(String, Vec<Box<dyn ToSql>>), Box<dyn std::error::Error + Send + Sync>> {
+        let mut sql = String::new();
+        let mut args: Vec<Box<dyn ToSql>> = Vec::new();
+        if let Some(since) = filter.since {
+            args.push(Box::new(since));
+            sql.push_str(&format!(" AND ts >= ?{}", args.len()));
+        }
+        if let Some(until) = filter.until {
+            args.push(Box::new(until));
+            sql.push_str(&format!(" AND ts < ?{}", args.len()));
+        }
+        if let Some(ref kind) = filter.kind {
+            args.push(Box::new(kind.clone()));
+            sql.push_str(&format!(" AND kind = ?{}", args.len()));
+        }
+        if let Some(min_risk) = filter.min_risk {
+            args.push(Box::new(min_risk));
+            sql.push_str(&format!(" AND risk_score >= ?{}", args.len()));
+        }
+        if let Some((ref field, ref value)) = filter.field_equals {
+            let term = self.blind_term(field, value).ok_or("store has no blind index")?;
+            args.push(Box::new(term));
+            sql.push_str(&format!(" AND id IN (SELECT event_id FROM event_index WHERE term = ?{})", args.len()));
+        }
+        Ok((sql, args))
+    }
+
+    /// Events matching `filter`'s constraints (limit / offset ignored), without decrypting
+    pub(super) fn count_events(&self, filter: &EventFilter) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let (clause, args) = self.filter_sql(filter)?;
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(&format!("SELECT count(*) FROM events WHERE 1 = 1{}", clause))?;
+        let n: i64 = stmt.query_row(params_from_iter(args.iter()), |row| row.get(0))?;
+        Ok(n as usize)
+    }
+
+    /// Rows in the events table
+    pub(super) fn event_count(&self) -> Result<u64, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT count(*) FROM events", [], |row| row.get::<_, i64>(0))
+            .map(|n| n as u64)
+    }
+
+    /// Query events by time range, kind, minimum risk, and blind-indexed field, with limit/offset;
+    /// payloads decrypted.
+    pub fn query(&self, filter: &EventFilter) -> Result<Vec<StoredEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ref shards) = self.shards {
+            return self.shard_query(shards, filter);
+        }
+        let (clause, mut args) = self.filter_sql(filter)?;
+        let mut sql = format!(
+            "SELECT id, ts, kind, payload_enc, risk_score, key_version, payload_hash FROM events WHERE 1 = 1{}",
+            clause
+        );
+        args.push(Box::new(filter.limit.map(|l| l as i64).unwrap_or(-1)));
+        sql.push_str(&format!(" ORDER BY ts LIMIT ?{}", args.len()));
+        args.push(Box::new(filter.offset as i64));
+        sql.push_str(&format!(" OFFSET ?{}", args.len()));
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let mut rows = stmt.query(params_from_iter(args.iter()))?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let enc: String = row.get(3)?;
+            let plain = self.event_payload(&conn, row.get(5)?, &enc, row.get::<_, Option<String>>(6)?.as_deref())?;
+            out.push(StoredEvent {
+                id: row.get(0)?,
+                ts: row.get(1)?,
+                kind: row.get(2)?,
+                payload: std::str::from_utf8(&plain).map(str::to_owned).unwrap_or_default(),
+                risk_score: row.get(4)?,
+            });
+        }
+        Ok(out)
+    }
+
+    /// Events at or after `ts` in timestamp order: (id, ts, decrypted payload)
+    pub fn events_since(&self, ts: i64) -> Result<Vec<(String, i64, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let filter = EventFilter {
+            since: Some(ts),
+            ..EventFilter::default()
+        };
+        Ok(self
+            .query(&filter)?
+            .into_iter()
+            .map(|e| (e.id, e.ts, e.payload))
+            .collect())
+    }
+
+    /// Risk scores recorded at or after `ts` (score distribution for threshold tuning)
+    pub fn risk_scores_since(&self, ts: i64) -> Result<Vec<f32>, rusqlite::Error> {
+        if let Some(ref shards) = self.shards {
+            return self.shard_risk_scores_since(shards, ts);
+        }
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached("SELECT score FROM risk_results WHERE ts >= ?1")?;
+        let rows = stmt.query_map(params![ts], |row| row.get::<_, f32>(0))?;
+        rows.collect()
+    }
+
+    /// Applied schema version (see `storage::schema`)
+    pub fn schema_version(&self) -> Result<u32, rusqlite::Error> {
+        Ok(meta_version(&self.conn.lock().unwrap(), "schema_version")?.unwrap_or(0))
+    }
+
+    /// Database size in bytes (page_count * page_size), summed over shards in shard mode
+    pub fn db_size(&self) -> Result<u64, rusqlite::Error> {
+        match self.shards {
+            Some(ref shards) => self.shard_db_size(shards),
+            None => self.db_size_local(),
+        }
+    }
+
+    pub(super) fn db_size_local(&self) -> Result<u64, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let pages: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        Ok((pages * page_size) as u64)
+    }
+
+    /// Return free pages to the filesystem. Stores created before incremental auto-vacuum
+    /// are converted with one full VACUUM.
+    fn reclaim(&self) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mode: i64 = conn.query_row("PRAGMA auto_vacuum", [], |row| row.get(0))?;
+        if mode == 2 {
+            // Frees one page per step, so drain the statement
+            let mut stmt = conn.prepare("PRAGMA incremental_vacuum")?;
+            let mut rows = stmt.query([])?;
+            while rows.next()?.is_some() {}
+            Ok(())
+        } else {
+            conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")
+        }
+    }
+
+    /// Drop the `count` oldest rows by ts across events, risk results, feature vectors, and the outbox.
+    /// Returns events removed and whether the store is now empty.
+    fn evict_oldest(&self, count: i64) -> Result<(u64, bool), rusqlite::Error> {
+        let cutoff: Option<i64> = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT ts FROM (SELECT ts FROM events UNION ALL SELECT ts FROM risk_results \
+                 UNION ALL SELECT ts FROM features UNION ALL SELECT ts FROM outbox) ORDER BY ts LIMIT 1 OFFSET ?1",
+                params![count.max(1) - 1],
+                |row| row.get(0),
+            )
+            .ok();
+        // Fewer rows than requested: drop them all
+        let cutoff = cutoff.map(|ts| ts.saturating_add(1)).unwrap_or(i64::MAX);
+        Ok((self.prune_local(cutoff)?, cutoff == i64::MAX))
+    }
+
+    /// Bytes in pages holding data (excludes the freelist, which new rows reuse)
+    pub(super) fn used_bytes(&self) -> Result<u64, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let pages: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let free: i64 = conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))?;
+        let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        Ok(((pages - free) * page_size) as u64)
+    }
+
+    /// Ring-buffer mode: evict the oldest rows until live data fits `ring_buffer_bytes`. Freed
+    /// pages are reused by later inserts, so the file stays at roughly the budget.
+    pub(super) fn enforce_ring_buffer(&self) -> Result<u64, rusqlite::Error> {
+        let mut evicted = 0;
+        if self.ring_buffer_bytes == 0 {
+            return Ok(evicted);
+        }
+        if let Some(ref shards) = self.shards {
+            return self.shard_ring_buffer(shards);
+        }
+        while self.used_bytes()? > self.ring_buffer_bytes {
+            let (pruned, exhausted) = self.evict_oldest(RING_EVICT_STEP)?;
+            evicted += pruned;
+            if exhausted {
+                break;
+            }
+        }
+        Ok(evicted)
+    }
+
+    /// Enforce `max_age_days` and `max_db_bytes` (0 disables either): prune by age, then drop the
+    /// oldest rows in steps until the database fits, vacuuming after each step. In shard mode
+    /// expired and excess shards are deleted as whole files.
+    pub fn enforce_retention(&self, max_age_days: u64, max_db_bytes: u64, now_ms: i64) -> Result<RetentionReport, rusqlite::Error> {
+        match self.shards {
+            Some(ref shards) => self.shard_retention(shards, max_age_days, max_db_bytes, now_ms),
+            None => self.enforce_retention_local(max_age_days, max_db_bytes, now_ms),
+        }
+    }
+
+    pub(super) fn enforce_retention_local(&self, max_age_days: u64, max_db_bytes: u64, now_ms: i64) -> Result<RetentionReport, rusqlite::Error> {
+        let mut report = RetentionReport::default();
+        if max_age_days > 0 {
+            let cutoff = now_ms.saturating_sub((max_age_days as i64).saturating_mul(86_400_000));
+            report.pruned_by_age = self.prune_local(cutoff)?;
+        }
+        self.reclaim()?;
+        if max_db_bytes > 0 {
+            while self.db_size()? > max_db_bytes {
+                let (pruned, exhausted) = self.evict_oldest(RETENTION_SIZE_STEP)?;
+                report.pruned_by_size += pruned;
+                self.reclaim()?;
+                if exhausted {
+                    break;
+                }
+            }
+        }
+        // Refresh planner statistics for the new table sizes
+        self.conn.lock().unwrap().execute_batch("PRAGMA optimize;")?;
+        report.db_bytes = self.db_size_local()?;
+        Ok(report)
+    }
+
+    /// Retention: delete events (and risk results, feature vectors, quarantined rows, unsent
+    /// outbox entries, analyst feedback) older than given timestamp.
+    /// The newest pruned chain link becomes the verification anchor. Shards older than `ts` are deleted.
     pub fn prune_before(&self, ts: i64) -> Result<u64, rusqlite::Error> {
-        let n = self.conn.lock().unwrap().execute("DELETE FROM events WHERE ts < ?1", params![ts])?;
-        Ok(n as u64)
+        let sharded = match self.shards {
+            Some(ref shards) => self.shard_prune_before(shards, ts)?,
+            None => 0,
+        };
+        Ok(sharded + self.prune_local(ts)?)
     }
-}
+
+    fn prune_local(&self, ts: i64) -> Result<u64, rusqlite::Error> {
+        let (version, key) = self.current_key();
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        // The newest pruned link becomes the chain anchor; verification starts after it.
+        let last_pruned: Option<(i64, String)> = tx
+            .query_row(
+                "SELECT chain_seq, chain_hash FROM events WHERE ts < ?1 AND chain_seq IS NOT NULL \
+                 UNION ALL SELECT chain_seq, chain_hash FROM quarantine WHERE ts < ?1 AND chain_seq IS NOT NULL \
+                 ORDER BY 1 DESC LIMIT 1",
+                params![ts],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .ok();
+        tx.execute(
+            "DELETE FROM event_index WHERE event_id IN (SELECT id FROM events WHERE ts < ?1)",
+            params![ts],
+        )?;
+        let n = tx.execute("DELETE FROM events WHERE ts < ?1", params![ts])?;
+        dedup::collect_payloads(&tx)?;
+        tx.execute("DELETE FROM risk_results WHERE ts < ?1", params![ts])?;
+        tx.execute("DELETE FROM features WHERE ts < ?1", params![ts])?;
+        tx.execute("DELETE FROM quarantine WHERE ts < ?1", params![ts])?;
+        tx.execute("DELETE FROM outbox WHERE ts < ?1", params![ts])?;
+        tx.execute("DELETE FROM feedback WHERE ts < ?1", params![ts])?;
+        if let Some((seq, hash)) = last_pruned {
+            if seq > meta_link(&tx, "chain_anchor")?.0 {
+                meta_set(&tx, "chain_anchor", &format!("{}:{}", seq, hash))?;
+                // Re-sign so the moved anchor is covered by the checkpoint MAC
+                if self.chain_checkpoint_interval > 0 || ChainCheckpoint::load(&tx)?.is_some() {
+                    let (head_seq, head_hash) = meta_link(&tx, "chain_head")?;
+                    ChainCheckpoint::sign(&tx, version, &key, head_seq, &head_hash)?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(n as u64)
+    }}
@@ -0,0 +1,181 @@
+//! Export decrypted events for SIEM ingestion and offline analysis.
+//! Streams in batches so large ranges don't have to fit in memory. Parquet requires the `parquet` feature.
+
+use super::encrypted::{EventFilter, SecureStore, StoredEvent};
+use std::io::Write;
+
+/// Rows fetched (and decrypted) per batch
+const EXPORT_BATCH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Jsonl,
+    Csv,
+    Parquet,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            "parquet" => Ok(Self::Parquet),
+            other => Err(format!("unknown export format {}", other)),
+        }
+    }
+}
+
+/// Quote a CSV field when it contains a delimiter, quote, or newline (RFC 4180)
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn write_jsonl<W: Write>(writer: &mut W, events: &[StoredEvent], redact: bool) -> std::io::Result<()> {
+    for e in events {
+        let payload = if redact {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_str(&e.payload).unwrap_or_else(|_| serde_json::Value::String(e.payload.clone()))
+        };
+        let line = serde_json::json!({
+            "id": e.id,
+            "ts": e.ts,
+            "kind": e.kind,
+            "risk_score": e.risk_score,
+            "payload": payload,
+        });
+        serde_json::to_writer(&mut *writer, &line)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn write_csv<W: Write>(writer: &mut W, events: &[StoredEvent], redact: bool) -> std::io::Result<()> {
+    for e in events {
+        let risk = e.risk_score.map(|r| r.to_string()).unwrap_or_default();
+        let payload = if redact { "" } else { e.payload.as_str() };
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            csv_field(&e.id),
+            e.ts,
+            csv_field(&e.kind),
+            risk,
+            csv_field(payload)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_out {
+    use super::StoredEvent;
+    use arrow_array::{ArrayRef, Float32Array, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    pub struct ParquetSink<W: Write + Send> {
+        schema: Arc<Schema>,
+        writer: ArrowWriter<W>,
+    }
+
+    impl<W: Write + Send> ParquetSink<W> {
+        pub fn new(writer: W) -> Result<Self, parquet::errors::ParquetError> {
+            let schema = Arc::new(Schema::new(vec![
+                Field::new("id", DataType::Utf8, false),
+                Field::new("ts", DataType::Int64, false),
+                Field::new("kind", DataType::Utf8, false),
+                Field::new("risk_score", DataType::Float32, true),
+                Field::new("payload", DataType::Utf8, true),
+            ]));
+            let writer = ArrowWriter::try_new(writer, schema.clone(), None)?;
+            Ok(Self { schema, writer })
+        }
+
+        pub fn write(&mut self, events: &[StoredEvent], redact: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from_iter_values(events.iter().map(|e| e.id.as_str()))),
+                Arc::new(Int64Array::from_iter_values(events.iter().map(|e| e.ts))),
+                Arc::new(StringArray::from_iter_values(events.iter().map(|e| e.kind.as_str()))),
+                Arc::new(Float32Array::from_iter(events.iter().map(|e| e.risk_score))),
+                Arc::new(StringArray::from_iter(
+                    events.iter().map(|e| (!redact).then_some(e.payload.as_str())),
+                )),
+            ];
+            self.writer.write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+            Ok(())
+        }
+
+        pub fn finish(self) -> Result<(), parquet::errors::ParquetError> {
+            self.writer.close().map(|_| ())
+        }
+    }
+}
+
+impl SecureStore {
+    /// Run `f` over successive batches of events matching `filter`; returns rows visited.
+    fn for_each_batch(
+        &self,
+        filter: &EventFilter,
+        mut f: impl FnMut(&[StoredEvent]) -> Result<(), Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let mut total = 0usize;
+        let mut page = filter.clone();
+        loop {
+            let want = match filter.limit {
+                Some(limit) if total >= limit => break,
+                Some(limit) => (limit - total).min(EXPORT_BATCH),
+                None => EXPORT_BATCH,
+            };
+            page.limit = Some(want);
+            let events = self.query(&page)?;
+            if events.is_empty() {
+                break;
+            }
+            f(&events)?;
+            total += events.len();
+            page.offset += events.len();
+            if events.len() < want {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Stream decrypted events matching `filter` to `writer` in the given format; returns rows written.
+    /// With `redact`, payloads are omitted and only id / ts / kind / risk_score are exported.
+    pub fn export<W: Write + Send>(
+        &self,
+        filter: &EventFilter,
+        format: ExportFormat,
+        redact: bool,
+        mut writer: W,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let total = match format {
+            ExportFormat::Jsonl => self.for_each_batch(filter, |events| Ok(write_jsonl(&mut writer, events, redact)?))?,
+            ExportFormat::Csv => {
+                writer.write_all(b"id,ts,kind,risk_score,payload\n")?;
+                self.for_each_batch(filter, |events| Ok(write_csv(&mut writer, events, redact)?))?
+            }
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => {
+                let mut sink = parquet_out::ParquetSink::new(&mut writer)?;
+                let total = self.for_each_batch(filter, |events| sink.write(events, redact))?;
+                sink.finish()?;
+                total
+            }
+            #[cfg(not(feature = "parquet"))]
+            ExportFormat::Parquet => return Err("parquet export requires the `parquet` feature".into()),
+        };
+        writer.flush()?;
+        Ok(total)
+    }
+}
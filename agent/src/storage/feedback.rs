@@ -0,0 +1,69 @@
+//! Analyst verdicts on risk results (`risk::feedback`): one row per result, keyed by its subject
+//! event, with the result and implicated events encrypted alongside. Kept in the main file when
+//! the store is sharded; retention prunes verdicts by the time they were given.
+
+use super::encrypted::{encrypt, SecureStore};
+use crate::risk::feedback::{implicated_event_ids, suppression_candidates};
+use crate::risk::{Feedback, SuppressionCandidate, Verdict};
+use rusqlite::params;
+
+impl SecureStore {
+    /// Record a verdict on the risk result for `event_id`, replacing any earlier one. Fails if no
+    /// stored result has that subject event.
+    pub fn record_feedback(
+        &self,
+        event_id: &str,
+        verdict: Verdict,
+        note: Option<String>,
+        now_ms: i64,
+    ) -> Result<Feedback, Box<dyn std::error::Error + Send + Sync>> {
+        let result = self
+            .risk_result(event_id)?
+            .ok_or_else(|| format!("no risk result for event {}", event_id))?;
+        let mut events = Vec::new();
+        for id in implicated_event_ids(&result) {
+            if let Some((_, payload, _)) = self.get_event(&id)? {
+                if let Ok(event) = serde_json::from_str(&payload) {
+                    events.push(event);
+                }
+            }
+        }
+        let feedback = Feedback {
+            event_id: event_id.to_string(),
+            verdict,
+            note,
+            ts: now_ms,
+            result,
+            events,
+        };
+        let (version, key) = self.current_key();
+        let detail = serde_json::to_vec(&feedback)?;
+        let enc = encrypt(&key, &detail).map_err(|e| format!("{:?}", e))?;
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO feedback (event_id, ts, verdict, detail_enc, key_version) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![event_id, now_ms, verdict.as_str(), enc, version],
+        )?;
+        Ok(feedback)
+    }
+
+    /// Verdicts given at or after `since`, oldest first
+    pub fn feedback(&self, since: i64) -> Result<Vec<Feedback>, Box<dyn std::error::Error + Send + Sync>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare_cached("SELECT detail_enc, key_version FROM feedback WHERE ts >= ?1 ORDER BY ts")?;
+        let mut rows = stmt.query(params![since])?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let enc: String = row.get(0)?;
+            let plain = self.decrypt_row(row.get(1)?, &enc)?;
+            out.push(serde_json::from_slice(&plain)?);
+        }
+        Ok(out)
+    }
+
+    /// Allowlist entries implicated in at least `min_count` false positives (and no true positive)
+    /// across all stored verdicts
+    pub fn suppression_candidates(&self, min_count: usize) -> Result<Vec<SuppressionCandidate>, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(suppression_candidates(&self.feedback(0)?, min_count))
+    }
+}
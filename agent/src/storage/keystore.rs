@@ -0,0 +1,504 @@
+//! Storage key providers: bind the store secret to the device via the platform keystore.
+//! Windows: DPAPI-protected key file. macOS: login/system Keychain. Linux: kernel keyring
+//! (`keyctl`) when the key was provisioned there, e.g. at boot from a TPM-sealed blob, else the
+//! key file; the secret is never in both. Android: unsupported (the Android Keystore needs the
+//! app's JVM); `key_source: file` must be chosen explicitly. File-based fallback elsewhere.
+//! The same providers hold named secrets referenced from the config ([`secret_provider`]).
+
+use crate::config::{KeySource, StorageConfig};
+use rand::RngCore;
+use std::io;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// Length of generated storage secrets (bytes)
+const SECRET_LEN: usize = 32;
+/// Keychain service / keyring description for the storage secret
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+const KEY_NAME: &str = "dadm:storage";
+
+/// Secret bytes, scrubbed from memory on drop
+pub type Secret = Zeroizing<Vec<u8>>;
+
+/// Which secret a provider operation refers to. `Next` holds a staged secret during key
+/// rotation so an interrupted rotation can resume with both keys available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySlot {
+    Current,
+    Next,
+}
+
+/// Source of the device-bound secret the store key is derived from.
+pub trait KeyProvider: Send + Sync {
+    /// Short name for logs (e.g. "dpapi", "keychain", "keyctl", "file")
+    fn name(&self) -> &'static str;
+
+    /// Load the secret in `slot`, if one exists.
+    fn load(&self, slot: KeySlot) -> io::Result<Option<Secret>>;
+
+    /// Persist `secret` in `slot`, replacing any previous value.
+    fn store(&self, slot: KeySlot, secret: &[u8]) -> io::Result<()>;
+
+    /// Delete the secret in `slot` (no-op if absent).
+    fn remove(&self, slot: KeySlot) -> io::Result<()>;
+
+    /// Load the current secret, creating and persisting a new random one on first use.
+    fn load_or_create(&self) -> io::Result<Secret> {
+        if let Some(secret) = self.load(KeySlot::Current)? {
+            return Ok(secret);
+        }
+        let secret = random_secret();
+        self.store(KeySlot::Current, &secret)?;
+        Ok(secret)
+    }
+}
+
+/// Fresh random secret (e.g. for staging a key rotation)
+pub fn random_secret() -> Secret {
+    let mut secret = Zeroizing::new(vec![0u8; SECRET_LEN]);
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// Key file for `slot`: `<path>` for the current secret, `<path>.next` for a staged one.
+fn slot_path(path: &Path, slot: KeySlot) -> PathBuf {
+    match slot {
+        KeySlot::Current => path.to_path_buf(),
+        KeySlot::Next => {
+            let mut p = path.as_os_str().to_owned();
+            p.push(".next");
+            PathBuf::from(p)
+        }
+    }
+}
+
+fn read_optional(path: &Path) -> io::Result<Option<Secret>> {
+    match std::fs::read(path) {
+        Ok(data) if data.is_empty() => Err(io::Error::new(io::ErrorKind::InvalidData, "empty key file")),
+        Ok(data) => Ok(Some(Zeroizing::new(data))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn remove_optional(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Write `data` to `path`, readable by the agent user only.
+pub(crate) fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut f = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        f.write_all(data)
+    }
+    #[cfg(not(unix))]
+    {
+        std::fs::write(path, data)
+    }
+}
+
+/// Plain key file in the data directory (fallback; protected by file permissions only).
+pub struct FileKeyProvider {
+    path: PathBuf,
+}
+
+impl FileKeyProvider {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn load(&self, slot: KeySlot) -> io::Result<Option<Secret>> {
+        read_optional(&slot_path(&self.path, slot))
+    }
+
+    fn store(&self, slot: KeySlot, secret: &[u8]) -> io::Result<()> {
+        write_private(&slot_path(&self.path, slot), secret)
+    }
+
+    fn remove(&self, slot: KeySlot) -> io::Result<()> {
+        remove_optional(&slot_path(&self.path, slot))
+    }
+}
+
+/// Windows DPAPI: the key file holds a blob only this machine (LocalMachine scope) can unprotect.
+#[cfg(windows)]
+pub struct DpapiKeyProvider {
+    path: PathBuf,
+}
+
+#[cfg(windows)]
+impl DpapiKeyProvider {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn transform(data: &[u8], protect: bool) -> io::Result<Secret> {
+        use windows::Win32::Foundation::{LocalFree, HLOCAL};
+        use windows::Win32::Security::Cryptography::{
+            CryptProtectData, CryptUnprotectData, CRYPTPROTECT_LOCAL_MACHINE, CRYPTPROTECT_UI_FORBIDDEN,
+            CRYPT_INTEGER_BLOB,
+        };
+        let input = CRYPT_INTEGER_BLOB {
+            cbData: data.len() as u32,
+            pbData: data.as_ptr() as *mut u8,
+        };
+        let mut output = CRYPT_INTEGER_BLOB::default();
+        let flags = CRYPTPROTECT_UI_FORBIDDEN | CRYPTPROTECT_LOCAL_MACHINE;
+        // SAFETY: input points to a live slice; output is allocated by DPAPI and freed below.
+        unsafe {
+            if protect {
+                CryptProtectData(&input, None, None, None, None, flags, &mut output)
+            } else {
+                CryptUnprotectData(&input, None, None, None, None, flags, &mut output)
+            }
+            .map_err(io::Error::other)?;
+            let out = Zeroizing::new(std::slice::from_raw_parts(output.pbData, output.cbData as usize).to_vec());
+            let _ = LocalFree(HLOCAL(output.pbData as _));
+            Ok(out)
+        }
+    }
+}
+
+#[cfg(windows)]
+impl KeyProvider for DpapiKeyProvider {
+    fn name(&self) -> &'static str {
+        "dpapi"
+    }
+
+    fn load(&self, slot: KeySlot) -> io::Result<Option<Secret>> {
+        match read_optional(&slot_path(&self.path, slot))? {
+            Some(blob) => Self::transform(&blob, false).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&self, slot: KeySlot, secret: &[u8]) -> io::Result<()> {
+        write_private(&slot_path(&self.path, slot), &Self::transform(secret, true)?)
+    }
+
+    fn remove(&self, slot: KeySlot) -> io::Result<()> {
+        remove_optional(&slot_path(&self.path, slot))
+    }
+}
+
+/// macOS Keychain generic password (service `dadm:storage`, account `store` / `store.next`).
+#[cfg(target_os = "macos")]
+pub struct KeychainKeyProvider {
+    account: String,
+}
+
+#[cfg(target_os = "macos")]
+impl KeychainKeyProvider {
+    pub fn new(account: impl Into<String>) -> Self {
+        Self {
+            account: account.into(),
+        }
+    }
+
+    fn account(&self, slot: KeySlot) -> String {
+        match slot {
+            KeySlot::Current => self.account.clone(),
+            KeySlot::Next => format!("{}.next", self.account),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl KeyProvider for KeychainKeyProvider {
+    fn name(&self) -> &'static str {
+        "keychain"
+    }
+
+    fn load(&self, slot: KeySlot) -> io::Result<Option<Secret>> {
+        use security_framework::passwords::get_generic_password;
+        Ok(get_generic_password(KEY_NAME, &self.account(slot)).ok().map(Zeroizing::new))
+    }
+
+    fn store(&self, slot: KeySlot, secret: &[u8]) -> io::Result<()> {
+        use security_framework::passwords::set_generic_password;
+        set_generic_password(KEY_NAME, &self.account(slot), secret)
+            .map_err(io::Error::other)
+    }
+
+    fn remove(&self, slot: KeySlot) -> io::Result<()> {
+        use security_framework::passwords::delete_generic_password;
+        let _ = delete_generic_password(KEY_NAME, &self.account(slot));
+        Ok(())
+    }
+}
+
+/// Linux kernel keyring: the `user` key `dadm:storage`, provisioned before the agent starts (e.g.
+/// at boot with `tpm2_unseal ... | keyctl padd user dadm:storage @u`). While it is there and no
+/// key file exists, secrets are read from and written to the keyring only, and a written one
+/// must be sealed again for the next boot. Without it the key file is used, and nothing is put
+/// in the keyring. Once the keyring was used, a marker (`<key file>.keyring`) records it, and a
+/// boot where the key is missing fails instead of starting over with a new key file.
+#[cfg(target_os = "linux")]
+pub struct KeyctlKeyProvider {
+    /// Key description of the current slot
+    name: String,
+    fallback: FileKeyProvider,
+}
+
+#[cfg(target_os = "linux")]
+impl KeyctlKeyProvider {
+    const KEYCTL_READ: libc::c_long = 11;
+    const KEYCTL_INVALIDATE: libc::c_long = 21;
+    const KEY_SPEC_USER_KEYRING: libc::c_long = -4;
+
+    pub fn new(fallback_path: PathBuf) -> Self {
+        Self::named(KEY_NAME, fallback_path)
+    }
+
+    /// Provider of the `user` key `name` instead of `dadm:storage`
+    pub fn named(name: impl Into<String>, fallback_path: PathBuf) -> Self {
+        Self {
+            name: name.into(),
+            fallback: FileKeyProvider::new(fallback_path),
+        }
+    }
+
+    fn description(&self, slot: KeySlot) -> std::ffi::CString {
+        let desc = match slot {
+            KeySlot::Current => self.name.clone(),
+            KeySlot::Next => format!("{}:next", self.name),
+        };
+        std::ffi::CString::new(desc.replace('\0', "")).expect("NUL removed")
+    }
+
+    fn find_key(&self, slot: KeySlot) -> Option<libc::c_long> {
+        let ty = std::ffi::CString::new("user").expect("static key type");
+        let desc = self.description(slot);
+        // SAFETY: pointers reference live NUL-terminated strings.
+        let serial = unsafe {
+            libc::syscall(libc::SYS_request_key, ty.as_ptr(), desc.as_ptr(), std::ptr::null::<libc::c_char>(), 0)
+        };
+        (serial >= 0).then_some(serial)
+    }
+
+    fn read_key(&self, slot: KeySlot) -> Option<Secret> {
+        let serial = self.find_key(slot)?;
+        let mut buf = Zeroizing::new(vec![0u8; 4096]);
+        // SAFETY: buf is a live buffer of the given length.
+        let n = unsafe { libc::syscall(libc::SYS_keyctl, Self::KEYCTL_READ, serial, buf.as_mut_ptr(), buf.len()) };
+        if n <= 0 {
+            return None;
+        }
+        let cap = buf.len();
+        buf.truncate((n as usize).min(cap));
+        Some(buf)
+    }
+
+    fn add_key(&self, slot: KeySlot, secret: &[u8]) -> io::Result<()> {
+        let ty = std::ffi::CString::new("user").expect("static key type");
+        let desc = self.description(slot);
+        // SAFETY: pointers reference live NUL-terminated strings / the secret slice.
+        let serial = unsafe {
+            libc::syscall(
+                libc::SYS_add_key,
+                ty.as_ptr(),
+                desc.as_ptr(),
+                secret.as_ptr(),
+                secret.len(),
+                Self::KEY_SPEC_USER_KEYRING,
+            )
+        };
+        if serial < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Records that the secrets are in the keyring
+    fn marker(&self) -> PathBuf {
+        let mut p = self.fallback.path.as_os_str().to_owned();
+        p.push(".keyring");
+        PathBuf::from(p)
+    }
+
+    /// Whether the secrets are in the keyring: the current one was provisioned there (now, or
+    /// on an earlier run), and there is no key file
+    fn provisioned(&self) -> bool {
+        !slot_path(&self.fallback.path, KeySlot::Current).exists()
+            && (self.marker().exists() || self.find_key(KeySlot::Current).is_some())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl KeyProvider for KeyctlKeyProvider {
+    fn name(&self) -> &'static str {
+        "keyctl"
+    }
+
+    fn load(&self, slot: KeySlot) -> io::Result<Option<Secret>> {
+        if !self.provisioned() {
+            return self.fallback.load(slot);
+        }
+        let secret = self.read_key(slot);
+        match slot {
+            KeySlot::Current if secret.is_none() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{:?} was provisioned in the kernel keyring but isn't there; provision it again", self.description(slot)),
+                ));
+            }
+            KeySlot::Current if !self.marker().exists() => {
+                if let Err(e) = write_private(&self.marker(), self.name.as_bytes()) {
+                    tracing::warn!(error = %e, "couldn't record that the secret is in the kernel keyring");
+                }
+            }
+            _ => {}
+        }
+        Ok(secret)
+    }
+
+    fn store(&self, slot: KeySlot, secret: &[u8]) -> io::Result<()> {
+        if !self.provisioned() {
+            return self.fallback.store(slot, secret);
+        }
+        self.add_key(slot, secret)?;
+        tracing::warn!(key = ?self.description(slot), "secret written to the kernel keyring only; seal it again for the next boot");
+        Ok(())
+    }
+
+    fn remove(&self, slot: KeySlot) -> io::Result<()> {
+        if let Some(serial) = self.find_key(slot) {
+            // SAFETY: plain integer arguments.
+            unsafe {
+                libc::syscall(libc::SYS_keyctl, Self::KEYCTL_INVALIDATE, serial);
+            }
+        }
+        if slot == KeySlot::Current {
+            remove_optional(&self.marker())?;
+        }
+        self.fallback.remove(slot)
+    }
+}
+
+/// Android Keystore: not supported, as its keys are only reachable through the app's JVM. Every
+/// operation fails rather than quietly using a plain key file; `key_source: file` opts into one.
+#[cfg(target_os = "android")]
+pub struct AndroidKeyProvider;
+
+#[cfg(target_os = "android")]
+impl AndroidKeyProvider {
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the Android Keystore isn't supported; set storage.key_source to file to keep the key in the app's data directory",
+        )
+    }
+}
+
+#[cfg(target_os = "android")]
+impl KeyProvider for AndroidKeyProvider {
+    fn name(&self) -> &'static str {
+        "android-keystore"
+    }
+
+    fn load(&self, _slot: KeySlot) -> io::Result<Option<Secret>> {
+        Err(Self::unsupported())
+    }
+
+    fn store(&self, _slot: KeySlot, _secret: &[u8]) -> io::Result<()> {
+        Err(Self::unsupported())
+    }
+
+    fn remove(&self, _slot: KeySlot) -> io::Result<()> {
+        Err(Self::unsupported())
+    }
+}
+
+/// Select the provider for `config.key_source`. `Auto` uses the platform keystore where
+/// one is implemented, otherwise the key file in `data_dir`.
+pub fn key_provider(config: &StorageConfig, data_dir: &Path) -> Box<dyn KeyProvider> {
+    let key_file = config
+        .key_file
+        .clone()
+        .unwrap_or_else(|| data_dir.join("storage.key"));
+    match config.key_source {
+        KeySource::File => Box::new(FileKeyProvider::new(key_file)),
+        KeySource::Auto => platform_provider(key_file),
+    }
+}
+
+#[cfg(windows)]
+fn platform_provider(key_file: PathBuf) -> Box<dyn KeyProvider> {
+    Box::new(DpapiKeyProvider::new(key_file))
+}
+
+#[cfg(target_os = "macos")]
+fn platform_provider(_key_file: PathBuf) -> Box<dyn KeyProvider> {
+    Box::new(KeychainKeyProvider::new("store"))
+}
+
+#[cfg(target_os = "linux")]
+fn platform_provider(key_file: PathBuf) -> Box<dyn KeyProvider> {
+    Box::new(KeyctlKeyProvider::new(key_file))
+}
+
+#[cfg(target_os = "android")]
+fn platform_provider(_key_file: PathBuf) -> Box<dyn KeyProvider> {
+    Box::new(AndroidKeyProvider)
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux", target_os = "android")))]
+fn platform_provider(key_file: PathBuf) -> Box<dyn KeyProvider> {
+    Box::new(FileKeyProvider::new(key_file))
+}
+
+/// Provider of the config secret `name` (see [`crate::config::secrets`]), in the current slot:
+/// the platform keystore under `Auto` (Keychain account `secret.<name>`, keyring key
+/// `dadm:secret:<name>`, DPAPI blob), else the file `data_dir/secrets/<name>`.
+pub fn secret_provider(config: &StorageConfig, data_dir: &Path, name: &str) -> Box<dyn KeyProvider> {
+    let file = data_dir.join("secrets").join(name);
+    match config.key_source {
+        KeySource::File => Box::new(FileKeyProvider::new(file)),
+        KeySource::Auto => platform_secret_provider(name, file),
+    }
+}
+
+#[cfg(windows)]
+fn platform_secret_provider(_name: &str, file: PathBuf) -> Box<dyn KeyProvider> {
+    Box::new(DpapiKeyProvider::new(file))
+}
+
+#[cfg(target_os = "macos")]
+fn platform_secret_provider(name: &str, _file: PathBuf) -> Box<dyn KeyProvider> {
+    Box::new(KeychainKeyProvider::new(format!("secret.{}", name)))
+}
+
+#[cfg(target_os = "linux")]
+fn platform_secret_provider(name: &str, file: PathBuf) -> Box<dyn KeyProvider> {
+    Box::new(KeyctlKeyProvider::named(format!("dadm:secret:{}", name), file))
+}
+
+#[cfg(target_os = "android")]
+fn platform_secret_provider(_name: &str, _file: PathBuf) -> Box<dyn KeyProvider> {
+    Box::new(AndroidKeyProvider)
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux", target_os = "android")))]
+fn platform_secret_provider(_name: &str, file: PathBuf) -> Box<dyn KeyProvider> {
+    Box::new(FileKeyProvider::new(file))
+}
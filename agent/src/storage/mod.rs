@@ -1,5 +1,30 @@
-//! Encrypted local storage for events, features, and risk results.
+//! Encrypted local storage for events, features, risk results, analyst feedback, the uplink
+//! outbox, small secrets, the server policy and command audit, and the response trail, with a
+//! write-behind queue.
 
+mod blind;
+mod commands;
+mod dedup;
 mod encrypted;
+mod export;
+mod feedback;
+mod keystore;
+mod outbox;
+mod policy;
+mod queue;
+mod response;
+mod schema;
+mod scrub;
+mod secrets;
+mod shard;
 
-pub use encrypted::SecureStore;
+pub use encrypted::{ChainReport, EventFilter, RetentionReport, RotationProgress, SecureStore, StoredEvent, WrongKey};
+pub use export::ExportFormat;
+pub use keystore::{key_provider, random_secret, secret_provider, FileKeyProvider, KeyProvider, KeySlot, Secret};
+pub(crate) use keystore::write_private;
+#[cfg(target_os = "linux")]
+pub use keystore::KeyctlKeyProvider;
+pub use outbox::OutboxEntry;
+pub use queue::WriteQueue;
+pub use schema::latest_schema_version;
+pub use scrub::{CorruptRow, ScrubReport};
@@ -0,0 +1,112 @@
+//! Persistent uplink outbox: payloads waiting to be sent are stored (body encrypted) in FIFO order
+//! with attempt counts, so reports survive restarts and long offline periods. Entries are removed
+//! once the server accepts them; retention prunes entries that never could be delivered.
+//! A payload identical to one still queued is not queued again, and failed entries carry the time
+//! of their next attempt so the sender can back off.
+
+use super::encrypted::{encrypt, hex, Key, SecureStore};
+use ring::hmac;
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+
+/// Queued uplink request with decrypted body
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboxEntry {
+    pub id: i64,
+    /// When the entry was queued (ms)
+    pub ts: i64,
+    /// API path the body is POSTed to
+    pub path: String,
+    /// JSON request body
+    pub body: String,
+    /// Failed delivery attempts so far
+    pub attempts: u32,
+    /// Not to be retried before this time (ms); `None` until an attempt failed
+    pub next_attempt: Option<i64>,
+}
+
+/// Keyed digest of a queued request, so identical payloads are found without decrypting bodies
+fn body_hash(key: &Key, path: &str, body: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_slice());
+    let mut ctx = hmac::Context::with_key(&key);
+    ctx.update(b"outbox\0");
+    ctx.update(path.as_bytes());
+    ctx.update(b"\0");
+    ctx.update(body.as_bytes());
+    hex(ctx.sign().as_ref())
+}
+
+impl SecureStore {
+    /// Queue a JSON `body` for POST to `path`; returns the entry id. When the same body is
+    /// already queued for `path` (under the current key), that entry's id is returned instead.
+    pub fn outbox_push(&self, path: &str, body: &str, now_ms: i64) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+        let (version, key) = self.current_key();
+        let hash = body_hash(&key, path, body);
+        let id = {
+            let conn = self.conn.lock().unwrap();
+            let queued: Option<i64> = conn
+                .query_row("SELECT id FROM outbox WHERE body_hash = ?1 LIMIT 1", params![hash], |row| row.get(0))
+                .optional()?;
+            if let Some(id) = queued {
+                return Ok(id);
+            }
+            let enc = encrypt(&key, body.as_bytes()).map_err(|e| format!("{:?}", e))?;
+            conn.execute(
+                "INSERT INTO outbox (ts, path, body_enc, key_version, body_hash) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![now_ms, path, enc, version, hash],
+            )?;
+            conn.last_insert_rowid()
+        };
+        self.enforce_ring_buffer()?;
+        Ok(id)
+    }
+
+    /// Oldest `limit` queued entries, in the order they were pushed
+    pub fn outbox_pending(&self, limit: usize) -> Result<Vec<OutboxEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "SELECT id, ts, path, body_enc, key_version, attempts, next_attempt FROM outbox ORDER BY id LIMIT ?1",
+        )?;
+        let mut rows = stmt.query(params![limit as i64])?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let enc: String = row.get(3)?;
+            let plain = self.decrypt_row(row.get(4)?, &enc)?;
+            out.push(OutboxEntry {
+                id: row.get(0)?,
+                ts: row.get(1)?,
+                path: row.get(2)?,
+                body: std::str::from_utf8(&plain)?.to_owned(),
+                attempts: row.get(5)?,
+                next_attempt: row.get(6)?,
+            });
+        }
+        Ok(out)
+    }
+
+    /// Remove a delivered (or permanently rejected) entry
+    pub fn outbox_ack(&self, id: i64) -> Result<(), rusqlite::Error> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM outbox WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Record a failed delivery attempt; the entry stays queued and is not retried before
+    /// `next_attempt_ms`
+    pub fn outbox_fail(&self, id: i64, error: &str, now_ms: i64, next_attempt_ms: i64) -> Result<(), rusqlite::Error> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE outbox SET attempts = attempts + 1, last_attempt = ?2, last_error = ?3, next_attempt = ?4 WHERE id = ?1",
+            params![id, now_ms, error, next_attempt_ms],
+        )?;
+        Ok(())
+    }
+
+    /// Entries waiting to be sent
+    pub fn outbox_len(&self) -> Result<u64, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT count(*) FROM outbox", [], |row| row.get::<_, i64>(0))
+            .map(|n| n as u64)
+    }
+}
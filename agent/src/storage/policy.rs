@@ -0,0 +1,60 @@
+//! Server policy (`crate::policy`): the last applied signed policy is kept in `meta` as
+//! `policy.current` (as served, so it is verified again when loaded), and every application is
+//! recorded in `policy_audit` with the settings it changed (encrypted). Audit rows are kept
+//! regardless of retention.
+
+use super::encrypted::{encrypt, meta_get, meta_set, SecureStore};
+use crate::policy::{PolicyAuditEntry, PolicyChange, SignedPolicy};
+use rusqlite::params;
+
+const CURRENT_POLICY: &str = "policy.current";
+
+impl SecureStore {
+    /// Keep `policy` (version `version`) as the current policy and record the changes applying it
+    /// made, in one transaction
+    pub fn record_policy(
+        &self,
+        policy: &SignedPolicy,
+        version: u64,
+        changes: &[PolicyChange],
+        now_ms: i64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (key_version, key) = self.current_key();
+        let enc = encrypt(&key, &serde_json::to_vec(changes)?).map_err(|e| format!("{:?}", e))?;
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO policy_audit (ts, version, changes_enc, key_version) VALUES (?1, ?2, ?3, ?4)",
+            params![now_ms, version as i64, enc, key_version],
+        )?;
+        meta_set(&tx, CURRENT_POLICY, &serde_json::to_string(policy)?)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Last applied policy, if any
+    pub fn current_policy(&self) -> Result<Option<SignedPolicy>, Box<dyn std::error::Error + Send + Sync>> {
+        match meta_get(&self.conn.lock().unwrap(), CURRENT_POLICY)? {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Applied policies, oldest first
+    pub fn policy_audit(&self) -> Result<Vec<PolicyAuditEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached("SELECT ts, version, changes_enc, key_version FROM policy_audit ORDER BY id")?;
+        let mut rows = stmt.query([])?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let enc: String = row.get(2)?;
+            let plain = self.decrypt_row(row.get(3)?, &enc)?;
+            out.push(PolicyAuditEntry {
+                ts: row.get(0)?,
+                version: row.get::<_, i64>(1)? as u64,
+                changes: serde_json::from_slice(&plain)?,
+            });
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,160 @@
+//! Write-behind queue: persistence runs on a dedicated writer thread fed by a bounded channel,
+//! so a slow disk never delays collection and inference. When the queue is full, new writes are
+//! dropped (and counted) rather than blocking the pipeline. Pending writes are flushed on close.
+
+use super::encrypted::SecureStore;
+use crate::features::FeatureVector;
+use crate::risk::RiskResult;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use zeroize::Zeroizing;
+
+enum WriteOp {
+    Event {
+        id: String,
+        ts: i64,
+        kind: String,
+        payload: Zeroizing<String>,
+        risk_score: Option<f32>,
+    },
+    Features(FeatureVector),
+    RiskResult(Box<RiskResult>),
+    /// Acknowledged once every earlier write has been applied
+    Flush(SyncSender<()>),
+}
+
+pub struct WriteQueue {
+    store: Arc<SecureStore>,
+    tx: Option<SyncSender<WriteOp>>,
+    writer: Option<JoinHandle<()>>,
+    dropped: AtomicU64,
+}
+
+fn apply(store: &SecureStore, op: WriteOp) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match op {
+        WriteOp::Event {
+            id,
+            ts,
+            kind,
+            payload,
+            risk_score,
+        } => store.insert_event(&id, ts, &kind, &payload, risk_score),
+        WriteOp::Features(fv) => store.insert_features(&fv),
+        WriteOp::RiskResult(result) => store.insert_risk_result(&result),
+        WriteOp::Flush(ack) => {
+            let _ = ack.send(());
+            Ok(())
+        }
+    }
+}
+
+fn run_writer(store: Arc<SecureStore>, rx: Receiver<WriteOp>) {
+    for op in rx {
+        if let Err(e) = apply(&store, op) {
+            tracing::warn!(error = %e, "write-behind store write failed");
+        }
+    }
+}
+
+impl WriteQueue {
+    /// Start the writer thread with room for `capacity` pending writes.
+    /// `capacity` 0 writes synchronously on the caller's thread instead.
+    pub fn new(store: Arc<SecureStore>, capacity: usize) -> Self {
+        let dropped = AtomicU64::new(0);
+        if capacity == 0 {
+            return Self {
+                store,
+                tx: None,
+                writer: None,
+                dropped,
+            };
+        }
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let writer_store = Arc::clone(&store);
+        let writer = std::thread::Builder::new()
+            .name("dadm-store-writer".to_string())
+            .spawn(move || run_writer(writer_store, rx))
+            .expect("spawn store writer");
+        Self {
+            store,
+            tx: Some(tx),
+            writer: Some(writer),
+            dropped,
+        }
+    }
+
+    /// Writes dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn submit(&self, op: WriteOp) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(ref tx) = self.tx else {
+            return apply(&self.store, op);
+        };
+        match tx.try_send(op) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                let n = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                // Log the 1st, 2nd, 4th, 8th, ... drop to avoid flooding the log under sustained backpressure
+                if n.is_power_of_two() {
+                    tracing::warn!(dropped = n, "store write queue full; dropping writes");
+                }
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err("store writer thread has stopped".into()),
+        }
+    }
+
+    pub fn insert_event(
+        &self,
+        id: &str,
+        ts: i64,
+        kind: &str,
+        payload: Zeroizing<String>,
+        risk_score: Option<f32>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.submit(WriteOp::Event {
+            id: id.to_string(),
+            ts,
+            kind: kind.to_string(),
+            payload,
+            risk_score,
+        })
+    }
+
+    pub fn insert_features(&self, fv: &FeatureVector) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.submit(WriteOp::Features(fv.clone()))
+    }
+
+    pub fn insert_risk_result(&self, result: &RiskResult) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.submit(WriteOp::RiskResult(Box::new(result.clone())))
+    }
+
+    /// Block until every write queued so far has been applied.
+    pub fn flush(&self) {
+        let Some(ref tx) = self.tx else {
+            return;
+        };
+        let (ack_tx, ack_rx) = mpsc::sync_channel(1);
+        if tx.send(WriteOp::Flush(ack_tx)).is_ok() {
+            let _ = ack_rx.recv();
+        }
+    }
+
+    /// Apply all pending writes and stop the writer thread.
+    pub fn close(&mut self) {
+        self.tx.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl Drop for WriteQueue {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
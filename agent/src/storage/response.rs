@@ -0,0 +1,47 @@
+//! Response trail (`crate::response::trail`): one row per response action taken, keyed by its
+//! record id, with the action and when it was undone in the clear and the record encrypted. An
+//! undo rewrites the row. Kept regardless of retention, like the command audit.
+
+use super::encrypted::{encrypt, SecureStore};
+use crate::response::ResponseRecord;
+use rusqlite::{params, OptionalExtension};
+
+impl SecureStore {
+    /// Keep `record`, replacing an earlier version of it
+    pub fn record_response(&self, record: &ResponseRecord) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (version, key) = self.current_key();
+        let enc = encrypt(&key, &serde_json::to_vec(record)?).map_err(|e| format!("{:?}", e))?;
+        let undone_at = record.undone.as_ref().filter(|_| record.is_undone()).map(|u| u.ts);
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO response_actions (ts, action_id, action, undone_at, detail_enc, key_version) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![record.ts, record.id, record.action, undone_at, enc, version],
+        )?;
+        Ok(())
+    }
+
+    /// Response action `id`
+    pub fn response_record(&self, id: &str) -> Result<Option<ResponseRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached("SELECT detail_enc, key_version FROM response_actions WHERE action_id = ?1")?;
+        let Some((enc, version)) = stmt.query_row(params![id], |row| Ok((row.get::<_, String>(0)?, row.get(1)?))).optional()? else {
+            return Ok(None);
+        };
+        Ok(Some(serde_json::from_slice(&self.decrypt_row(version, &enc)?)?))
+    }
+
+    /// Response actions taken at or after `since`, oldest first
+    pub fn response_audit(&self, since: i64) -> Result<Vec<ResponseRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare_cached("SELECT detail_enc, key_version FROM response_actions WHERE ts >= ?1 ORDER BY ts, id")?;
+        let mut rows = stmt.query(params![since])?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next()? {
+            let enc: String = row.get(0)?;
+            let plain = self.decrypt_row(row.get(1)?, &enc)?;
+            out.push(serde_json::from_slice(&plain)?);
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,309 @@
+//! Versioned schema migrations for `SecureStore`. The applied version is kept in `meta`
+//! (`schema_version`); pending steps run in order at open, each in its own transaction.
+//! Steps are idempotent so stores created before versioning migrate cleanly.
+
+use super::encrypted::{meta_set, meta_version};
+use rusqlite::Connection;
+
+/// One schema step
+struct Migration {
+    version: u32,
+    description: &'static str,
+    apply: fn(&Connection) -> Result<(), rusqlite::Error>,
+}
+
+/// Ordered by version; append new steps, never edit released ones.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "events table",
+        apply: |conn| {
+            conn.execute_batch(
+                r#"
+                CREATE TABLE IF NOT EXISTS events (
+                    id TEXT PRIMARY KEY,
+                    ts INTEGER NOT NULL,
+                    kind TEXT NOT NULL,
+                    payload_enc TEXT NOT NULL,
+                    risk_score REAL
+                );
+                CREATE INDEX IF NOT EXISTS idx_events_ts ON events(ts);
+                "#,
+            )
+        },
+    },
+    Migration {
+        version: 2,
+        description: "per-row key versions",
+        apply: |conn| ensure_column(conn, "events", "key_version", "INTEGER NOT NULL DEFAULT 1"),
+    },
+    Migration {
+        version: 3,
+        description: "risk_results table",
+        apply: |conn| {
+            conn.execute_batch(
+                r#"
+                CREATE TABLE IF NOT EXISTS risk_results (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    ts INTEGER NOT NULL,
+                    score REAL NOT NULL,
+                    level TEXT NOT NULL,
+                    window_start INTEGER NOT NULL,
+                    window_end INTEGER NOT NULL,
+                    detail_enc TEXT NOT NULL,
+                    key_version INTEGER NOT NULL DEFAULT 1
+                );
+                CREATE INDEX IF NOT EXISTS idx_risk_results_ts ON risk_results(ts);
+                "#,
+            )
+        },
+    },
+    Migration {
+        version: 4,
+        description: "features table",
+        apply: |conn| {
+            conn.execute_batch(
+                r#"
+                CREATE TABLE IF NOT EXISTS features (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    ts INTEGER NOT NULL,
+                    event_id TEXT NOT NULL,
+                    dim INTEGER NOT NULL,
+                    values_enc TEXT NOT NULL,
+                    key_version INTEGER NOT NULL DEFAULT 1
+                );
+                CREATE INDEX IF NOT EXISTS idx_features_ts ON features(ts);
+                "#,
+            )
+        },
+    },
+    Migration {
+        version: 5,
+        description: "event hash chain",
+        apply: |conn| {
+            ensure_column(conn, "events", "chain_seq", "INTEGER")?;
+            ensure_column(conn, "events", "chain_hash", "TEXT")?;
+            conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_events_chain_seq ON events(chain_seq);")
+        },
+    },
+    Migration {
+        version: 6,
+        description: "kind / risk_score query indexes",
+        apply: |conn| {
+            // kind first so `kind = ? AND ts range` is a single index range scan
+            conn.execute_batch(
+                r#"
+                CREATE INDEX IF NOT EXISTS idx_events_kind_ts ON events(kind, ts);
+                CREATE INDEX IF NOT EXISTS idx_events_risk_score ON events(risk_score);
+                ANALYZE;
+                "#,
+            )
+        },
+    },
+    Migration {
+        version: 7,
+        description: "quarantine table",
+        apply: |conn| {
+            conn.execute_batch(
+                r#"
+                CREATE TABLE IF NOT EXISTS quarantine (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    source_table TEXT NOT NULL,
+                    row_key TEXT NOT NULL,
+                    ts INTEGER NOT NULL,
+                    chain_seq INTEGER,
+                    chain_hash TEXT,
+                    raw TEXT NOT NULL,
+                    reason TEXT NOT NULL,
+                    quarantined_at INTEGER NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS idx_quarantine_chain_seq ON quarantine(chain_seq);
+                "#,
+            )
+        },
+    },
+    Migration {
+        version: 8,
+        description: "uplink outbox",
+        apply: |conn| {
+            conn.execute_batch(
+                r#"
+                CREATE TABLE IF NOT EXISTS outbox (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    ts INTEGER NOT NULL,
+                    path TEXT NOT NULL,
+                    body_enc TEXT NOT NULL,
+                    key_version INTEGER NOT NULL DEFAULT 1,
+                    attempts INTEGER NOT NULL DEFAULT 0,
+                    last_attempt INTEGER,
+                    last_error TEXT
+                );
+                "#,
+            )
+        },
+    },
+    Migration {
+        version: 9,
+        description: "blind index",
+        apply: |conn| {
+            conn.execute_batch(
+                r#"
+                CREATE TABLE IF NOT EXISTS event_index (
+                    term TEXT NOT NULL,
+                    event_id TEXT NOT NULL,
+                    PRIMARY KEY (term, event_id)
+                ) WITHOUT ROWID;
+                CREATE INDEX IF NOT EXISTS idx_event_index_event_id ON event_index(event_id);
+                "#,
+            )
+        },
+    },
+    Migration {
+        version: 10,
+        description: "deduplicated payloads",
+        apply: |conn| {
+            conn.execute_batch(
+                r#"
+                CREATE TABLE IF NOT EXISTS payloads (
+                    hash TEXT PRIMARY KEY,
+                    ts INTEGER NOT NULL,
+                    payload_enc TEXT NOT NULL,
+                    key_version INTEGER NOT NULL DEFAULT 1
+                );
+                "#,
+            )?;
+            ensure_column(conn, "events", "payload_hash", "TEXT")?;
+            conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_events_payload_hash ON events(payload_hash);")
+        },
+    },
+    Migration {
+        version: 11,
+        description: "analyst feedback",
+        apply: |conn| {
+            ensure_column(conn, "risk_results", "event_id", "TEXT")?;
+            conn.execute_batch(
+                r#"
+                CREATE INDEX IF NOT EXISTS idx_risk_results_event_id ON risk_results(event_id);
+                CREATE TABLE IF NOT EXISTS feedback (
+                    event_id TEXT PRIMARY KEY,
+                    ts INTEGER NOT NULL,
+                    verdict TEXT NOT NULL,
+                    detail_enc TEXT NOT NULL,
+                    key_version INTEGER NOT NULL DEFAULT 1
+                );
+                CREATE INDEX IF NOT EXISTS idx_feedback_ts ON feedback(ts);
+                "#,
+            )
+        },
+    },
+    Migration {
+        version: 12,
+        description: "risk result fingerprints",
+        apply: |conn| {
+            ensure_column(conn, "risk_results", "fingerprint", "TEXT")?;
+            conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_risk_results_fingerprint ON risk_results(fingerprint);")
+        },
+    },
+    Migration {
+        version: 13,
+        description: "outbox backoff and payload hashes",
+        apply: |conn| {
+            ensure_column(conn, "outbox", "next_attempt", "INTEGER")?;
+            ensure_column(conn, "outbox", "body_hash", "TEXT")?;
+            conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_outbox_body_hash ON outbox(body_hash);")
+        },
+    },
+    Migration {
+        version: 14,
+        description: "server policy audit",
+        apply: |conn| {
+            conn.execute_batch(
+                r#"
+                CREATE TABLE IF NOT EXISTS policy_audit (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    ts INTEGER NOT NULL,
+                    version INTEGER NOT NULL,
+                    changes_enc TEXT NOT NULL,
+                    key_version INTEGER NOT NULL DEFAULT 1
+                );
+                "#,
+            )
+        },
+    },
+    Migration {
+        version: 15,
+        description: "server command audit",
+        apply: |conn| {
+            conn.execute_batch(
+                r#"
+                CREATE TABLE IF NOT EXISTS command_audit (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    ts INTEGER NOT NULL,
+                    command_id TEXT NOT NULL UNIQUE,
+                    action TEXT NOT NULL,
+                    status TEXT NOT NULL,
+                    detail_enc TEXT NOT NULL,
+                    key_version INTEGER NOT NULL DEFAULT 1
+                );
+                CREATE INDEX IF NOT EXISTS idx_command_audit_ts ON command_audit(ts);
+                "#,
+            )
+        },
+    },
+    Migration {
+        version: 16,
+        description: "response trail",
+        apply: |conn| {
+            conn.execute_batch(
+                r#"
+                CREATE TABLE IF NOT EXISTS response_actions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    ts INTEGER NOT NULL,
+                    action_id TEXT NOT NULL UNIQUE,
+                    action TEXT NOT NULL,
+                    undone_at INTEGER,
+                    detail_enc TEXT NOT NULL,
+                    key_version INTEGER NOT NULL DEFAULT 1
+                );
+                CREATE INDEX IF NOT EXISTS idx_response_actions_ts ON response_actions(ts);
+                "#,
+            )
+        },
+    },
+];
+
+/// Newest schema version this build knows
+pub fn latest_schema_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Add a column to an existing table if a pre-upgrade schema lacks it.
+fn ensure_column(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<(), rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|c| c.ok())
+        .any(|c| c == column);
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))?;
+    }
+    Ok(())
+}
+
+/// Bring the store up to `latest_schema_version()`; refuses stores written by a newer agent.
+pub(super) fn migrate(conn: &Connection) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+    conn.execute_batch("CREATE TABLE IF NOT EXISTS meta (k TEXT PRIMARY KEY, v TEXT);")?;
+    let current = meta_version(conn, "schema_version")?.unwrap_or(0);
+    let latest = latest_schema_version();
+    if current > latest {
+        return Err(format!("store schema version {} is newer than supported ({})", current, latest).into());
+    }
+    for m in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn.unchecked_transaction()?;
+        (m.apply)(&tx)?;
+        meta_set(&tx, "schema_version", &m.version.to_string())?;
+        tx.commit()?;
+        tracing::info!(version = m.version, migration = m.description, "applied storage migration");
+    }
+    Ok(latest)
+}
@@ -0,0 +1,197 @@
+//! Integrity scrub: run SQLite's structural check, decrypt and parse every encrypted row, and walk
+//! the event hash chain. Corrupted rows can be moved to the `quarantine` table so normal reads and
+//! exports stop failing on them; quarantined events keep their chain link so the chain still verifies.
+
+use super::encrypted::{ChainReport, SecureStore, ENCRYPTED_COLUMNS};
+use crate::commands::CommandResult;
+use crate::policy::PolicyChange;
+use crate::response::ResponseRecord;
+use crate::risk::{Feedback, RiskResult};
+use rusqlite::{params, types::ValueRef, Connection};
+use serde::Serialize;
+
+/// Rows decrypted per batch (the store lock is released between batches)
+const SCRUB_BATCH: i64 = 500;
+
+/// Row that failed decryption or parsing
+#[derive(Debug, Clone, Serialize)]
+pub struct CorruptRow {
+    pub table: String,
+    /// Event id, or rowid for other tables
+    pub row_key: String,
+    pub ts: i64,
+    pub error: String,
+}
+
+/// Outcome of [`SecureStore::scrub`]
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrubReport {
+    /// `PRAGMA quick_check` findings (empty when the file is structurally sound)
+    pub integrity: Vec<String>,
+    pub rows_checked: u64,
+    pub corrupt: Vec<CorruptRow>,
+    /// Corrupt rows moved to the quarantine table by this scrub
+    pub quarantined: u64,
+    pub chain: ChainReport,
+}
+
+impl ScrubReport {
+    pub fn is_clean(&self) -> bool {
+        self.integrity.is_empty() && self.corrupt.is_empty() && self.chain.is_intact()
+    }
+}
+
+/// Check that decrypted plaintext has the shape its table stores
+fn check_plaintext(table: &str, plain: &[u8]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match table {
+        "events" => {
+            std::str::from_utf8(plain)?;
+        }
+        "risk_results" => {
+            serde_json::from_slice::<RiskResult>(plain)?;
+        }
+        "features" => {
+            serde_json::from_slice::<Vec<f32>>(plain)?;
+        }
+        "feedback" => {
+            serde_json::from_slice::<Feedback>(plain)?;
+        }
+        "policy_audit" => {
+            serde_json::from_slice::<Vec<PolicyChange>>(plain)?;
+        }
+        "command_audit" => {
+            serde_json::from_slice::<CommandResult>(plain)?;
+        }
+        "response_actions" => {
+            serde_json::from_slice::<ResponseRecord>(plain)?;
+        }
+        "outbox" | "payloads" => {
+            serde_json::from_slice::<serde_json::Value>(plain)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Whole row as a JSON object (ciphertext stays encrypted) for the quarantine table
+fn row_json(conn: &Connection, table: &str, rowid: i64) -> Result<String, rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {} WHERE rowid = ?1", table))?;
+    let names: Vec<String> = stmt.column_names().into_iter().map(str::to_owned).collect();
+    stmt.query_row(params![rowid], |row| {
+        let mut obj = serde_json::Map::new();
+        for (i, name) in names.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => serde_json::Value::Null,
+                ValueRef::Integer(n) => n.into(),
+                ValueRef::Real(f) => f.into(),
+                ValueRef::Text(t) => String::from_utf8_lossy(t).into_owned().into(),
+                ValueRef::Blob(b) => b.iter().map(|byte| format!("{:02x}", byte)).collect::<String>().into(),
+            };
+            obj.insert(name.clone(), value);
+        }
+        Ok(serde_json::Value::Object(obj).to_string())
+    })
+}
+
+impl SecureStore {
+    /// Move one row to the quarantine table, keeping its chain link (events only).
+    fn quarantine_row(&self, table: &str, rowid: i64, row: &CorruptRow) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        let raw = row_json(&tx, table, rowid)?;
+        let link: (Option<i64>, Option<String>) = if table == "events" {
+            tx.query_row("SELECT chain_seq, chain_hash FROM events WHERE rowid = ?1", params![rowid], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })?
+        } else {
+            (None, None)
+        };
+        tx.execute(
+            "INSERT INTO quarantine (source_table, row_key, ts, chain_seq, chain_hash, raw, reason, quarantined_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![table, row.row_key, row.ts, link.0, link.1, raw, row.error, chrono::Utc::now().timestamp_millis()],
+        )?;
+        if table == "events" {
+            tx.execute("DELETE FROM event_index WHERE event_id = ?1", params![row.row_key])?;
+        }
+        tx.execute(&format!("DELETE FROM {} WHERE rowid = ?1", table), params![rowid])?;
+        tx.commit()
+    }
+
+    /// Scrub the store: structural check, decrypt and parse every encrypted row in batches of
+    /// [`SCRUB_BATCH`], then verify the event hash chain. With `quarantine`, rows that fail are
+    /// moved to the quarantine table (not allowed on a read-only store).
+    pub fn scrub(&self, quarantine: bool) -> Result<ScrubReport, Box<dyn std::error::Error + Send + Sync>> {
+        if quarantine && self.is_read_only() {
+            return Err("cannot quarantine rows in a store opened read-only".into());
+        }
+        match self.shards {
+            Some(ref shards) => self.shard_scrub(shards, quarantine),
+            None => self.scrub_local(quarantine),
+        }
+    }
+
+    /// [`Self::scrub`] over this file only
+    pub(super) fn scrub_local(&self, quarantine: bool) -> Result<ScrubReport, Box<dyn std::error::Error + Send + Sync>> {
+        let integrity: Vec<String> = {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn.prepare("PRAGMA quick_check")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            rows.filter(|r| !matches!(r.as_deref(), Ok("ok"))).collect::<Result<_, _>>()?
+        };
+
+        let mut rows_checked = 0u64;
+        let mut corrupt = Vec::new();
+        let mut quarantined = 0u64;
+        for (table, column) in ENCRYPTED_COLUMNS {
+            let key = if *table == "events" { "id" } else { "CAST(rowid AS TEXT)" };
+            let mut after = 0i64;
+            loop {
+                let batch: Vec<(i64, String, i64, String, u32)> = {
+                    let conn = self.conn.lock().unwrap();
+                    let mut stmt = conn.prepare_cached(&format!(
+                        "SELECT rowid, {}, ts, {}, key_version FROM {} WHERE rowid > ?1 ORDER BY rowid LIMIT ?2",
+                        key, column, table
+                    ))?;
+                    let rows = stmt.query_map(params![after, SCRUB_BATCH], |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+                    })?;
+                    rows.collect::<Result<_, _>>()?
+                };
+                let Some(last) = batch.last() else {
+                    break;
+                };
+                after = last.0;
+                for (rowid, row_key, ts, enc, version) in batch {
+                    rows_checked += 1;
+                    let result = self
+                        .decrypt_row(version, &enc)
+                        .and_then(|plain| check_plaintext(table, &plain));
+                    let Err(e) = result else {
+                        continue;
+                    };
+                    let row = CorruptRow {
+                        table: table.to_string(),
+                        row_key,
+                        ts,
+                        error: e.to_string(),
+                    };
+                    tracing::warn!(table = %row.table, row = %row.row_key, error = %row.error, "scrub: corrupt row");
+                    if quarantine {
+                        self.quarantine_row(table, rowid, &row)?;
+                        quarantined += 1;
+                    }
+                    corrupt.push(row);
+                }
+            }
+        }
+
+        Ok(ScrubReport {
+            integrity,
+            rows_checked,
+            corrupt,
+            quarantined,
+            chain: self.verify_chain_local()?,
+        })
+    }
+}
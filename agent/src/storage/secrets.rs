@@ -0,0 +1,57 @@
+//! Small named secrets (e.g. the uplink device token) kept in `meta` as `secret.<name>`, encrypted
+//! under the column key and re-encrypted on key rotation, like the blind index key.
+
+use super::encrypted::{encrypt, meta_get, meta_set, Key, SecureStore};
+use rusqlite::{params, Connection};
+use zeroize::Zeroizing;
+
+const SECRET_PREFIX: &str = "secret.";
+
+impl SecureStore {
+    /// Store (or replace) secret `name`
+    pub fn set_secret(&self, name: &str, value: &[u8]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (version, key) = self.current_key();
+        let enc = encrypt(&key, value).map_err(|e| format!("{:?}", e))?;
+        meta_set(&self.conn.lock().unwrap(), &format!("{}{}", SECRET_PREFIX, name), &format!("{}:{}", version, enc))?;
+        Ok(())
+    }
+
+    /// Secret `name`, if set
+    pub fn secret(&self, name: &str) -> Result<Option<Zeroizing<Vec<u8>>>, Box<dyn std::error::Error + Send + Sync>> {
+        let stored = meta_get(&self.conn.lock().unwrap(), &format!("{}{}", SECRET_PREFIX, name))?;
+        let Some(stored) = stored else {
+            return Ok(None);
+        };
+        let (version, enc) = stored.split_once(':').ok_or("malformed secret")?;
+        Ok(Some(self.decrypt_row(version.parse()?, enc)?))
+    }
+
+    pub fn delete_secret(&self, name: &str) -> Result<(), rusqlite::Error> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM meta WHERE k = ?1", params![format!("{}{}", SECRET_PREFIX, name)])?;
+        Ok(())
+    }
+
+    /// Re-encrypt every secret under key version `to` (during rotation, while the old key is
+    /// still in the ring)
+    pub(super) fn rotate_secrets(&self, conn: &Connection, to: u32, key: &Key) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let stored: Vec<(String, String)> = {
+            let mut stmt = conn.prepare("SELECT k, v FROM meta WHERE k LIKE 'secret.%'")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<Result<_, _>>()?
+        };
+        for (k, v) in stored {
+            let (version, enc) = v.split_once(':').ok_or("malformed secret")?;
+            let version: u32 = version.parse()?;
+            if version == to {
+                continue;
+            }
+            let plain = self.decrypt_row(version, enc)?;
+            let enc = encrypt(key, &plain).map_err(|e| format!("{:?}", e))?;
+            meta_set(conn, &k, &format!("{}:{}", to, enc))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+//! Per-day shards: with `storage.shard_by_day`, events, risk results, and feature vectors are written
+//! to one database file per UTC day (`store-YYYYMMDD.db` next to `store.db`), and `SecureStore`
+//! routes reads and writes by timestamp. Retention and size limits drop whole files instead of
+//! deleting rows and vacuuming, so pruning stays cheap on low-end hardware. The main file keeps
+//! store-wide state (keys, outbox, quarantine); each shard has its own hash chain.
+
+use super::encrypted::{ChainReport, EventFilter, RetentionReport, SecureStore, StoredEvent};
+use super::keystore::Secret;
+use super::scrub::ScrubReport;
+use crate::config::StorageConfig;
+use crate::features::FeatureVector;
+use crate::risk::RiskResult;
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use zeroize::Zeroizing;
+
+const DAY_MS: i64 = 86_400_000;
+
+fn day_of(ts: i64) -> i64 {
+    ts.div_euclid(DAY_MS)
+}
+
+fn epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).expect("valid date")
+}
+
+pub(super) struct ShardSet {
+    dir: PathBuf,
+    /// File stem of the main store (`store` for `store.db`)
+    stem: String,
+    /// Needed for the SQLCipher page key of new shards
+    secret: RwLock<Secret>,
+    config: StorageConfig,
+    shards: Mutex<BTreeMap<i64, Arc<SecureStore>>>,
+}
+
+impl ShardSet {
+    /// Open every existing shard of the store at `root_path`.
+    pub(super) fn open(
+        root_path: &Path,
+        secret: &[u8],
+        config: &StorageConfig,
+        root: &SecureStore,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let dir = match root_path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let stem = root_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "store".to_string());
+        let mut config = config.clone();
+        config.shard_by_day = false;
+        config.ring_buffer_bytes = 0;
+        let set = Self {
+            dir,
+            stem,
+            secret: RwLock::new(Zeroizing::new(secret.to_vec())),
+            config,
+            shards: Mutex::new(BTreeMap::new()),
+        };
+        let mut shards = BTreeMap::new();
+        for entry in std::fs::read_dir(&set.dir)? {
+            let entry = entry?;
+            if let Some(day) = set.parse_day(&entry.file_name().to_string_lossy()) {
+                let shard = SecureStore::open_shard(&entry.path(), secret, &set.config, root)?;
+                shards.insert(day, Arc::new(shard));
+            }
+        }
+        let unsharded: i64 = root
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT count(*) FROM events", [], |row| row.get(0))?;
+        if unsharded > 0 {
+            tracing::warn!(events = unsharded, "events stored before shard mode stay in the main file and are not queried");
+        }
+        *set.shards.lock().unwrap() = shards;
+        Ok(set)
+    }
+
+    fn file_name(&self, day: i64) -> String {
+        let date = epoch() + chrono::Duration::days(day);
+        format!("{}-{}.db", self.stem, date.format("%Y%m%d"))
+    }
+
+    fn parse_day(&self, name: &str) -> Option<i64> {
+        let date = name.strip_prefix(&self.stem)?.strip_prefix('-')?.strip_suffix(".db")?;
+        if date.len() != 8 {
+            return None;
+        }
+        let date = NaiveDate::parse_from_str(date, "%Y%m%d").ok()?;
+        Some(date.signed_duration_since(epoch()).num_days())
+    }
+
+    /// Shard holding `ts`, created on first write to that day
+    fn for_ts(&self, root: &SecureStore, ts: i64) -> Result<Arc<SecureStore>, Box<dyn std::error::Error + Send + Sync>> {
+        let day = day_of(ts);
+        let mut shards = self.shards.lock().unwrap();
+        if let Some(shard) = shards.get(&day) {
+            return Ok(Arc::clone(shard));
+        }
+        if root.is_read_only() {
+            return Err(format!("no shard {} in a store opened read-only", self.file_name(day)).into());
+        }
+        let path = self.dir.join(self.file_name(day));
+        let shard = Arc::new(SecureStore::open_shard(&path, &self.secret.read().unwrap(), &self.config, root)?);
+        shards.insert(day, Arc::clone(&shard));
+        Ok(shard)
+    }
+
+    /// All shards, oldest day first
+    pub(super) fn all(&self) -> Vec<(i64, Arc<SecureStore>)> {
+        self.shards
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(day, shard)| (*day, Arc::clone(shard)))
+            .collect()
+    }
+
+    /// Shards that can hold rows with ts in [since, until), oldest first
+    fn overlapping(&self, since: Option<i64>, until: Option<i64>) -> Vec<Arc<SecureStore>> {
+        let first = since.map(day_of).unwrap_or(i64::MIN);
+        let last = until.map(|u| day_of(u.saturating_sub(1))).unwrap_or(i64::MAX);
+        self.all()
+            .into_iter()
+            .filter(|(day, _)| *day >= first && *day <= last)
+            .map(|(_, shard)| shard)
+            .collect()
+    }
+
+    /// Close and delete a shard's files. Failures are logged; the file is retried on the next start.
+    fn remove(&self, day: i64) {
+        drop(self.shards.lock().unwrap().remove(&day));
+        let name = self.file_name(day);
+        for suffix in ["", "-wal", "-shm", "-journal"] {
+            let path = self.dir.join(format!("{}{}", name, suffix));
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!(path = %path.display(), error = %e, "failed to delete expired shard"),
+            }
+        }
+    }
+
+    pub(super) fn set_secret(&self, secret: &[u8]) {
+        *self.secret.write().unwrap() = Zeroizing::new(secret.to_vec());
+    }
+
+    fn label(&self, day: i64) -> String {
+        self.file_name(day)
+    }
+}
+
+/// Fold one shard's chain report into the combined report (counts summed, issues labelled).
+fn merge_chain(into: &mut ChainReport, label: &str, report: ChainReport) {
+    into.rows_verified += report.rows_verified;
+    into.unchained_rows += report.unchained_rows;
+    into.quarantined_links += report.quarantined_links;
+    into.anchor_seq = report.anchor_seq;
+    into.head_seq = report.head_seq;
+    into.checkpoint_seq = report.checkpoint_seq;
+    into.issues
+        .extend(report.issues.into_iter().map(|issue| format!("{}: {}", label, issue)));
+}
+
+impl SecureStore {
+    pub(super) fn shard_insert_event(
+        &self,
+        shards: &ShardSet,
+        id: &str,
+        ts: i64,
+        kind: &str,
+        payload_json: &str,
+        risk_score: Option<f32>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        shards.for_ts(self, ts)?.insert_event(id, ts, kind, payload_json, risk_score)?;
+        self.enforce_ring_buffer()?;
+        Ok(())
+    }
+
+    pub(super) fn shard_insert_risk_result(&self, shards: &ShardSet, result: &RiskResult) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        shards.for_ts(self, result.ts)?.insert_risk_result(result)?;
+        self.enforce_ring_buffer()?;
+        Ok(())
+    }
+
+    pub(super) fn shard_insert_features(&self, shards: &ShardSet, fv: &FeatureVector) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        shards.for_ts(self, fv.ts)?.insert_features(fv)?;
+        self.enforce_ring_buffer()?;
+        Ok(())
+    }
+
+    /// Query shards in day order; offset and limit apply across shards.
+    pub(super) fn shard_query(&self, shards: &ShardSet, filter: &EventFilter) -> Result<Vec<StoredEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut out = Vec::new();
+        let mut skip = filter.offset;
+        for shard in shards.overlapping(filter.since, filter.until) {
+            let want = match filter.limit {
+                Some(limit) if out.len() >= limit => break,
+                Some(limit) => Some(limit - out.len()),
+                None => None,
+            };
+            if skip > 0 {
+                let n = shard.count_events(filter)?;
+                if skip >= n {
+                    skip -= n;
+                    continue;
+                }
+            }
+            let page = EventFilter {
+                limit: want,
+                offset: skip,
+                ..filter.clone()
+            };
+            skip = 0;
+            out.extend(shard.query(&page)?);
+        }
+        Ok(out)
+    }
+
+    pub(super) fn shard_risk_results(
+        &self,
+        shards: &ShardSet,
+        since: i64,
+        until: Option<i64>,
+        limit: Option<usize>,
+    ) -> Result<Vec<RiskResult>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut out = Vec::new();
+        for shard in shards.overlapping(Some(since), until) {
+            let want = limit.map(|l| l.saturating_sub(out.len()));
+            if want == Some(0) {
+                break;
+            }
+            out.extend(shard.risk_results(since, until, want)?);
+        }
+        Ok(out)
+    }
+
+    pub(super) fn shard_features(
+        &self,
+        shards: &ShardSet,
+        since: i64,
+        until: Option<i64>,
+        limit: Option<usize>,
+    ) -> Result<Vec<FeatureVector>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut out = Vec::new();
+        for shard in shards.overlapping(Some(since), until) {
+            let want = limit.map(|l| l.saturating_sub(out.len()));
+            if want == Some(0) {
+                break;
+            }
+            out.extend(shard.features(since, until, want)?);
+        }
+        Ok(out)
+    }
+
+    pub(super) fn shard_risk_scores_since(&self, shards: &ShardSet, ts: i64) -> Result<Vec<f32>, rusqlite::Error> {
+        let mut out = Vec::new();
+        for shard in shards.overlapping(Some(ts), None) {
+            out.extend(shard.risk_scores_since(ts)?);
+        }
+        Ok(out)
+    }
+
+    pub(super) fn shard_risk_series(
+        &self,
+        shards: &ShardSet,
+        since: i64,
+        until: i64,
+    ) -> Result<Vec<(i64, f32)>, rusqlite::Error> {
+        let mut out = Vec::new();
+        for shard in shards.overlapping(Some(since), Some(until)) {
+            out.extend(shard.risk_series(since, until)?);
+        }
+        Ok(out)
+    }
+
+    pub(super) fn shard_verify_chain(&self, shards: &ShardSet) -> Result<ChainReport, Box<dyn std::error::Error + Send + Sync>> {
+        let mut report = self.verify_chain_local()?;
+        for (day, shard) in shards.all() {
+            merge_chain(&mut report, &shards.label(day), shard.verify_chain()?);
+        }
+        Ok(report)
+    }
+
+    pub(super) fn shard_checkpoint_chain(&self, shards: &ShardSet) -> Result<(), rusqlite::Error> {
+        for (_, shard) in shards.all() {
+            shard.checkpoint_chain()?;
+        }
+        Ok(())
+    }
+
+    /// Main file plus every shard
+    pub(super) fn shard_db_size(&self, shards: &ShardSet) -> Result<u64, rusqlite::Error> {
+        let mut total = self.db_size_local()?;
+        for (_, shard) in shards.all() {
+            total += shard.db_size()?;
+        }
+        Ok(total)
+    }
+
+    /// Drop shards entirely before `ts` and prune the one that straddles it; returns events removed.
+    pub(super) fn shard_prune_before(&self, shards: &ShardSet, ts: i64) -> Result<u64, rusqlite::Error> {
+        let mut pruned = 0;
+        for (day, shard) in shards.all() {
+            if (day + 1).saturating_mul(DAY_MS) <= ts {
+                pruned += shard.event_count()?;
+                drop(shard);
+                shards.remove(day);
+            } else if day.saturating_mul(DAY_MS) < ts {
+                pruned += shard.prune_before(ts)?;
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Age limit via [`Self::shard_prune_before`], then drop the oldest shards (never the newest)
+    /// until the total size fits.
+    pub(super) fn shard_retention(
+        &self,
+        shards: &ShardSet,
+        max_age_days: u64,
+        max_db_bytes: u64,
+        now_ms: i64,
+    ) -> Result<RetentionReport, rusqlite::Error> {
+        let mut report = self.enforce_retention_local(max_age_days, 0, now_ms)?;
+        if max_age_days > 0 {
+            let cutoff = now_ms.saturating_sub((max_age_days as i64).saturating_mul(DAY_MS));
+            report.pruned_by_age += self.shard_prune_before(shards, cutoff)?;
+        }
+        for (_, shard) in shards.all() {
+            shard.enforce_retention(0, 0, now_ms)?;
+        }
+        if max_db_bytes > 0 {
+            report.pruned_by_size += self.drop_oldest_shards(shards, max_db_bytes, |s| s.shard_db_size(shards))?;
+        }
+        report.db_bytes = self.shard_db_size(shards)?;
+        Ok(report)
+    }
+
+    /// Ring-buffer mode over shards: drop the oldest shards while live data exceeds the budget.
+    pub(super) fn shard_ring_buffer(&self, shards: &ShardSet) -> Result<u64, rusqlite::Error> {
+        self.drop_oldest_shards(shards, self.ring_buffer_bytes, |s| {
+            let mut used = s.used_bytes()?;
+            for (_, shard) in shards.all() {
+                used += shard.used_bytes()?;
+            }
+            Ok(used)
+        })
+    }
+
+    fn drop_oldest_shards(
+        &self,
+        shards: &ShardSet,
+        budget: u64,
+        size: impl Fn(&Self) -> Result<u64, rusqlite::Error>,
+    ) -> Result<u64, rusqlite::Error> {
+        let mut dropped = 0;
+        while size(self)? > budget {
+            let all = shards.all();
+            let [(day, shard), _, ..] = all.as_slice() else {
+                break;
+            };
+            dropped += shard.event_count()?;
+            let day = *day;
+            drop(all);
+            shards.remove(day);
+        }
+        Ok(dropped)
+    }
+
+    pub(super) fn shard_scrub(&self, shards: &ShardSet, quarantine: bool) -> Result<ScrubReport, Box<dyn std::error::Error + Send + Sync>> {
+        let mut report = self.scrub_local(quarantine)?;
+        for (day, shard) in shards.all() {
+            let label = shards.label(day);
+            let part = shard.scrub(quarantine)?;
+            report
+                .integrity
+                .extend(part.integrity.into_iter().map(|issue| format!("{}: {}", label, issue)));
+            report.rows_checked += part.rows_checked;
+            report.quarantined += part.quarantined;
+            report.corrupt.extend(part.corrupt.into_iter().map(|mut row| {
+                row.table = format!("{}:{}", label, row.table);
+                row
+            }));
+            merge_chain(&mut report.chain, &label, part.chain);
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,327 @@
+//! OpenTelemetry export (`telemetry`): each collection cycle is traced as a `cycle` span with one
+//! child span per stage (collection, features, inference, scoring, storage, uplink), and counters
+//! plus a stage duration histogram are kept. Spans and metrics are sent with OTLP/HTTP (JSON
+//! encoding) to `<endpoint>/v1/traces` and `<endpoint>/v1/metrics` every `export_interval_secs`.
+//! Metrics are cumulative; spans beyond `max_queued_spans` between exports are dropped and counted.
+
+use crate::config::TelemetryConfig;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// Upper bounds (ms) of the stage duration histogram buckets
+const DURATION_BOUNDS_MS: &[f64] = &[1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0];
+const SCOPE: &str = "dadm-agent";
+/// OTLP span kind internal; status codes ok / error
+const SPAN_KIND_INTERNAL: u8 = 1;
+const STATUS_OK: u8 = 1;
+const STATUS_ERROR: u8 = 2;
+
+fn now_nanos() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// OTLP attribute list
+fn attributes(attrs: &[(&str, String)]) -> Value {
+    Value::Array(attrs.iter().map(|(k, v)| json!({ "key": k, "value": { "stringValue": v } })).collect())
+}
+
+/// Metric identity: name and attributes
+type SeriesKey = (&'static str, Vec<(&'static str, String)>);
+
+#[derive(Default)]
+struct Histogram {
+    count: u64,
+    sum: f64,
+    buckets: Vec<u64>,
+}
+
+#[derive(Default)]
+struct State {
+    spans: Vec<Value>,
+    dropped_spans: u64,
+    counters: BTreeMap<SeriesKey, u64>,
+    histograms: BTreeMap<SeriesKey, Histogram>,
+}
+
+pub struct Telemetry {
+    config: TelemetryConfig,
+    endpoint: String,
+    client: reqwest::blocking::Client,
+    resource: Value,
+    /// Start of the cumulative metrics
+    start_nanos: u64,
+    state: Mutex<State>,
+}
+
+impl Telemetry {
+    /// `None` when telemetry is off or no endpoint is set (`telemetry.endpoint`, else
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT`). Starts the export thread, which ends with the last `Arc`.
+    pub fn new(config: &TelemetryConfig, device_id: &str) -> Option<Arc<Self>> {
+        if !config.enabled {
+            return None;
+        }
+        let endpoint = config
+            .endpoint
+            .clone()
+            .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok())
+            .filter(|e| !e.trim().is_empty())?;
+        let client = reqwest::blocking::Client::builder().timeout(Duration::from_secs(10)).build().ok()?;
+        let host = sysinfo::System::host_name().unwrap_or_default();
+        let telemetry = Arc::new(Self {
+            config: config.clone(),
+            endpoint: endpoint.trim().trim_end_matches('/').to_string(),
+            client,
+            resource: json!({
+                "attributes": attributes(&[
+                    ("service.name", config.service_name.clone()),
+                    ("service.version", env!("CARGO_PKG_VERSION").to_string()),
+                    ("service.instance.id", device_id.to_string()),
+                    ("host.name", host),
+                ])
+            }),
+            start_nanos: now_nanos(),
+            state: Mutex::new(State::default()),
+        });
+        let weak = Arc::downgrade(&telemetry);
+        let interval = Duration::from_secs(config.export_interval_secs.max(1));
+        std::thread::spawn(move || export_loop(weak, interval));
+        Some(telemetry)
+    }
+
+    /// Add `value` to the counter `name` with `attrs`
+    pub fn add(&self, name: &'static str, value: u64, attrs: &[(&'static str, &str)]) {
+        let key = (name, attrs.iter().map(|(k, v)| (*k, v.to_string())).collect());
+        let mut state = self.state.lock().unwrap();
+        *state.counters.entry(key).or_default() += value;
+    }
+
+    fn record_duration(&self, stage: &str, ms: f64) {
+        let key = ("dadm.stage.duration", vec![("stage", stage.to_string())]);
+        let mut state = self.state.lock().unwrap();
+        let histogram = state.histograms.entry(key).or_default();
+        if histogram.buckets.is_empty() {
+            histogram.buckets = vec![0; DURATION_BOUNDS_MS.len() + 1];
+        }
+        let bucket = DURATION_BOUNDS_MS.iter().position(|b| ms <= *b).unwrap_or(DURATION_BOUNDS_MS.len());
+        histogram.buckets[bucket] += 1;
+        histogram.count += 1;
+        histogram.sum += ms;
+    }
+
+    fn queue_span(&self, span: Value) {
+        let mut state = self.state.lock().unwrap();
+        if state.spans.len() >= self.config.max_queued_spans {
+            state.dropped_spans += 1;
+        } else {
+            state.spans.push(span);
+        }
+    }
+
+    fn post(&self, path: &str, body: &Value) -> Result<(), String> {
+        let mut req = self.client.post(format!("{}{}", self.endpoint, path)).json(body);
+        for (name, value) in &self.config.headers {
+            req = req.header(name.as_str(), value.as_str());
+        }
+        let resp = req.send().map_err(|e| e.to_string())?;
+        match resp.status().is_success() {
+            true => Ok(()),
+            false => Err(format!("{} answered {}", path, resp.status())),
+        }
+    }
+
+    /// Send the queued spans and the current metrics now
+    pub fn flush(&self) -> Result<(), String> {
+        let (spans, metrics) = {
+            let mut state = self.state.lock().unwrap();
+            let spans = std::mem::take(&mut state.spans);
+            (spans, self.metrics(&state))
+        };
+        let mut result = Ok(());
+        if !spans.is_empty() {
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": self.resource,
+                    "scopeSpans": [{ "scope": { "name": SCOPE }, "spans": spans }],
+                }]
+            });
+            // Spans that could not be sent are not queued again; the metrics still count the cycles
+            result = self.post("/v1/traces", &body);
+        }
+        let body = json!({
+            "resourceMetrics": [{
+                "resource": self.resource,
+                "scopeMetrics": [{ "scope": { "name": SCOPE }, "metrics": metrics }],
+            }]
+        });
+        result.and(self.post("/v1/metrics", &body))
+    }
+
+    /// OTLP metrics of `state`
+    fn metrics(&self, state: &State) -> Vec<Value> {
+        let start = self.start_nanos.to_string();
+        let now = now_nanos().to_string();
+        let mut sums: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+        for ((name, attrs), value) in &state.counters {
+            let attrs: Vec<(&str, String)> = attrs.iter().map(|(k, v)| (*k, v.clone())).collect();
+            sums.entry(name).or_default().push(json!({
+                "attributes": attributes(&attrs),
+                "startTimeUnixNano": start,
+                "timeUnixNano": now,
+                "asInt": value.to_string(),
+            }));
+        }
+        sums.entry("dadm.telemetry.dropped_spans").or_default().push(json!({
+            "startTimeUnixNano": start,
+            "timeUnixNano": now,
+            "asInt": state.dropped_spans.to_string(),
+        }));
+        let mut metrics: Vec<Value> = sums
+            .into_iter()
+            .map(|(name, points)| {
+                json!({
+                    "name": name,
+                    "unit": "1",
+                    "sum": { "dataPoints": points, "aggregationTemporality": 2, "isMonotonic": true },
+                })
+            })
+            .collect();
+        let points: Vec<Value> = state
+            .histograms
+            .iter()
+            .map(|((_, attrs), h)| {
+                let attrs: Vec<(&str, String)> = attrs.iter().map(|(k, v)| (*k, v.clone())).collect();
+                json!({
+                    "attributes": attributes(&attrs),
+                    "startTimeUnixNano": start,
+                    "timeUnixNano": now,
+                    "count": h.count.to_string(),
+                    "sum": h.sum,
+                    "bucketCounts": h.buckets.iter().map(u64::to_string).collect::<Vec<_>>(),
+                    "explicitBounds": DURATION_BOUNDS_MS,
+                })
+            })
+            .collect();
+        if !points.is_empty() {
+            metrics.push(json!({
+                "name": "dadm.stage.duration",
+                "unit": "ms",
+                "histogram": { "dataPoints": points, "aggregationTemporality": 2 },
+            }));
+        }
+        metrics
+    }
+}
+
+fn export_loop(telemetry: Weak<Telemetry>, interval: Duration) {
+    let mut last = Instant::now();
+    loop {
+        std::thread::sleep(Duration::from_secs(1).min(interval));
+        let Some(telemetry) = telemetry.upgrade() else {
+            return;
+        };
+        if last.elapsed() < interval {
+            continue;
+        }
+        last = Instant::now();
+        match telemetry.flush() {
+            Ok(()) => debug!("telemetry exported"),
+            Err(e) => warn!(error = %e, "telemetry export failed"),
+        }
+    }
+}
+
+/// Trace of one cycle; without telemetry, stages are only timed
+pub struct CycleTrace<'a> {
+    telemetry: Option<&'a Telemetry>,
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    start: u64,
+    started: Instant,
+    /// Time each stage took
+    stages: RefCell<BTreeMap<String, Duration>>,
+}
+
+impl<'a> CycleTrace<'a> {
+    pub fn start(telemetry: Option<&'a Telemetry>) -> Self {
+        Self::started_at(telemetry, now_nanos(), Instant::now())
+    }
+
+    /// Trace of a cycle that began at `start` (Unix ns) / `started`, e.g. on another task
+    pub fn started_at(telemetry: Option<&'a Telemetry>, start: u64, started: Instant) -> Self {
+        Self {
+            telemetry,
+            trace_id: rand::random(),
+            span_id: rand::random(),
+            start,
+            started,
+            stages: RefCell::default(),
+        }
+    }
+
+    /// Time each stage that ran took, by name
+    pub fn stages(&self) -> BTreeMap<String, Duration> {
+        self.stages.borrow().clone()
+    }
+
+    /// Time since the cycle began
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    fn span(&self, name: &str, span_id: [u8; 8], parent: Option<[u8; 8]>, start: u64, elapsed: Duration, ok: bool) -> Value {
+        let mut span = json!({
+            "traceId": hex(&self.trace_id),
+            "spanId": hex(&span_id),
+            "name": name,
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": start.to_string(),
+            "endTimeUnixNano": start.saturating_add(elapsed.as_nanos() as u64).to_string(),
+            "status": { "code": if ok { STATUS_OK } else { STATUS_ERROR } },
+        });
+        if let Some(parent) = parent {
+            span["parentSpanId"] = Value::String(hex(&parent));
+        }
+        span
+    }
+
+    /// Run stage `name` as a child span of the cycle
+    pub fn stage<T>(&self, name: &str, f: impl FnOnce() -> T) -> T {
+        let start = now_nanos();
+        let started = Instant::now();
+        let value = f();
+        self.stage_done(name, start, started.elapsed());
+        value
+    }
+
+    /// Record stage `name` that ran elsewhere from `start` (Unix ns) for `elapsed`
+    pub fn stage_done(&self, name: &str, start: u64, elapsed: Duration) {
+        self.stages.borrow_mut().insert(name.to_string(), elapsed);
+        if let Some(telemetry) = self.telemetry {
+            self.record_stage(telemetry, name, start, elapsed);
+        }
+    }
+
+    fn record_stage(&self, telemetry: &Telemetry, name: &str, start: u64, elapsed: Duration) {
+        telemetry.record_duration(name, elapsed.as_secs_f64() * 1000.0);
+        telemetry.queue_span(self.span(name, rand::random(), Some(self.span_id), start, elapsed, true));
+    }
+
+    /// Record the cycle span and count the cycle
+    pub fn finish(self, ok: bool) {
+        let Some(telemetry) = self.telemetry else {
+            return;
+        };
+        let elapsed = self.started.elapsed();
+        telemetry.record_duration("cycle", elapsed.as_secs_f64() * 1000.0);
+        telemetry.queue_span(self.span("cycle", self.span_id, None, self.start, elapsed, ok));
+        telemetry.add("dadm.cycles", 1, &[("outcome", if ok { "ok" } else { "error" })]);
+    }
+}
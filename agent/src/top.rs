@@ -0,0 +1,273 @@
+//! `dadm-agent top`: terminal dashboard of the running daemon for admins debugging one box. It
+//! polls the control interface ([`crate::control`]) and shows the events per collector and their
+//! rate, the latest risk level, recent alerts with what raised them, and the agent's own CPU and
+//! memory use.
+
+use crate::control::{self, Request};
+use crate::risk::RiskResult;
+use crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Alerts listed
+const ALERTS: usize = 20;
+/// Span the event rates are averaged over
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// What the dashboard shows, updated from the daemon's answers
+#[derive(Default)]
+pub struct Dashboard {
+    status: Value,
+    alerts: Vec<Value>,
+    /// Event totals per collector at each update within [`RATE_WINDOW`]
+    samples: VecDeque<(Instant, BTreeMap<String, u64>)>,
+    /// Failure of the last update
+    error: Option<String>,
+    /// Outcome of the last key action
+    notice: Option<String>,
+}
+
+impl Dashboard {
+    /// Take the daemon's `status` and `alerts` answers, received at `now`
+    pub fn update(&mut self, status: Result<Value, BoxError>, alerts: Result<Value, BoxError>, now: Instant) {
+        self.error = None;
+        match status {
+            Ok(status) => {
+                let totals = status["events"]
+                    .as_object()
+                    .map(|events| events.iter().map(|(kind, e)| (kind.clone(), e["total"].as_u64().unwrap_or(0))).collect())
+                    .unwrap_or_default();
+                // A restarted daemon counts from 0 again
+                if self.status["pid"] != status["pid"] {
+                    self.samples.clear();
+                }
+                self.samples.push_back((now, totals));
+                while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= RATE_WINDOW {
+                    self.samples.pop_front();
+                }
+                self.status = status;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+        match alerts {
+            Ok(Value::Array(alerts)) => self.alerts = alerts,
+            Ok(_) => {}
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Events per second of `collector` over the samples kept
+    pub fn rate(&self, collector: &str) -> Option<f64> {
+        let ((first_at, first), (last_at, last)) = (self.samples.front()?, self.samples.back()?);
+        let secs = last_at.duration_since(*first_at).as_secs_f64();
+        if secs <= 0.0 {
+            return None;
+        }
+        let delta = last.get(collector)?.saturating_sub(first.get(collector).copied().unwrap_or(0));
+        Some(delta as f64 / secs)
+    }
+
+    /// Render the dashboard into `frame`
+    pub fn draw(&self, frame: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Length(8), Constraint::Min(5), Constraint::Length(1)])
+            .split(frame.size());
+        frame.render_widget(Paragraph::new(self.header()), rows[0]);
+        let middle = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+            .split(rows[1]);
+        self.draw_collectors(frame, middle[0]);
+        self.draw_agent(frame, middle[1]);
+        self.draw_alerts(frame, rows[2]);
+        let footer = match (&self.error, &self.notice) {
+            (Some(e), _) => Line::from(Span::styled(format!("daemon not reachable: {}", e), Style::default().fg(Color::Red))),
+            (None, Some(notice)) => Line::from(notice.as_str()),
+            (None, None) => Line::from("q quit  s scan now  r refresh"),
+        };
+        frame.render_widget(Paragraph::new(footer).style(Style::default().add_modifier(Modifier::DIM)), rows[3]);
+    }
+
+    fn header(&self) -> Line<'static> {
+        let s = &self.status;
+        let mut spans = vec![Span::styled("DADM agent", Style::default().add_modifier(Modifier::BOLD))];
+        if let Some(pid) = s["pid"].as_u64() {
+            spans.push(Span::raw(format!("  pid {}  cycle {}", pid, s["cycle"].as_u64().unwrap_or(0))));
+        }
+        if let (Some(started), Some(ts)) = (s["started"].as_i64(), s["ts"].as_i64()) {
+            spans.push(Span::raw(format!("  up {}", duration((ts - started).max(0) / 1000))));
+        }
+        spans.push(Span::raw("  risk "));
+        let level = s["risk"]["level"].as_str().unwrap_or("-");
+        spans.push(Span::styled(level.to_uppercase(), level_style(level).add_modifier(Modifier::BOLD)));
+        if let Some(score) = s["risk"]["score"].as_f64() {
+            spans.push(Span::raw(format!(" ({:.2})", score)));
+        }
+        Line::from(spans)
+    }
+
+    fn draw_collectors(&self, frame: &mut Frame, area: Rect) {
+        let rows: Vec<Row> = self.status["events"]
+            .as_object()
+            .map(|events| {
+                events
+                    .iter()
+                    .map(|(kind, e)| {
+                        let rate = self.rate(kind).map_or("-".to_string(), |r| format!("{:.1}", r));
+                        Row::new(vec![
+                            kind.clone(),
+                            e["total"].as_u64().unwrap_or(0).to_string(),
+                            e["last"].as_u64().unwrap_or(0).to_string(),
+                            rate,
+                        ])
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let widths = [Constraint::Length(16), Constraint::Length(10), Constraint::Length(10), Constraint::Length(10)];
+        let table = Table::new(rows, widths)
+            .header(Row::new(vec!["collector", "total", "last cycle", "events/s"]).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(Block::default().borders(Borders::ALL).title(" Events "));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_agent(&self, frame: &mut Frame, area: Rect) {
+        let s = &self.status;
+        let process = &s["process"];
+        let mut lines = vec![
+            Line::from(match process["cpu_percent"].as_f64() {
+                Some(cpu) => format!("CPU     {:.1} %", cpu),
+                None => "CPU     -".to_string(),
+            }),
+            Line::from(match process["memory_bytes"].as_u64() {
+                Some(bytes) => format!("Memory  {:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+                None => "Memory  -".to_string(),
+            }),
+        ];
+        let uplink = match s["uplink"]["active"].as_str() {
+            _ if s["uplink"].is_null() => "off".to_string(),
+            Some(endpoint) => endpoint.to_string(),
+            None => "no endpoint reachable".to_string(),
+        };
+        lines.push(Line::from(format!("Uplink  {}", uplink)));
+        if let Some(backlog) = s["uplink"]["outbox_len"].as_u64() {
+            lines.push(Line::from(format!("Outbox  {}", backlog)));
+        }
+        let block = Block::default().borders(Borders::ALL).title(" Agent ");
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    fn draw_alerts(&self, frame: &mut Frame, area: Rect) {
+        let rows: Vec<Row> = self
+            .alerts
+            .iter()
+            .map(|alert| {
+                let level = alert["level"].as_str().unwrap_or("-");
+                let time = alert["ts"]
+                    .as_i64()
+                    .and_then(chrono::DateTime::from_timestamp_millis)
+                    .map(|t| t.with_timezone(&chrono::Local).format("%H:%M:%S").to_string())
+                    .unwrap_or_default();
+                Row::new(vec![
+                    Cell::from(time),
+                    Cell::from(level.to_string()).style(level_style(level)),
+                    Cell::from(format!("{:.2}", alert["score"].as_f64().unwrap_or(0.0))),
+                    Cell::from(alert_reasons(alert)),
+                ])
+            })
+            .collect();
+        let widths = [Constraint::Length(9), Constraint::Length(7), Constraint::Length(6), Constraint::Min(20)];
+        let table = Table::new(rows, widths)
+            .header(Row::new(vec!["time", "level", "score", "reasons"]).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(Block::default().borders(Borders::ALL).title(" Recent alerts (24 h) "));
+        frame.render_widget(table, area);
+    }
+}
+
+fn level_style(level: &str) -> Style {
+    match level {
+        "high" => Style::default().fg(Color::Red),
+        "medium" => Style::default().fg(Color::Yellow),
+        "low" => Style::default().fg(Color::Green),
+        _ => Style::default(),
+    }
+}
+
+/// `1d 02:03:04` / `02:03:04`
+fn duration(secs: i64) -> String {
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    let clock = format!("{:02}:{:02}:{:02}", rest / 3600, rest % 3600 / 60, rest % 60);
+    if days > 0 {
+        format!("{}d {}", days, clock)
+    } else {
+        clock
+    }
+}
+
+/// [`RiskResult::reasons`] of a stored risk result (JSON), comma-separated
+pub fn alert_reasons(result: &Value) -> String {
+    serde_json::from_value::<RiskResult>(result.clone()).map(|r| r.reasons().join(", ")).unwrap_or_default()
+}
+
+/// Run the dashboard in the terminal against the daemon at `endpoint`, refreshing every
+/// `refresh`, until `q`, Esc, or Ctrl+C
+pub fn run(endpoint: &Path, refresh: Duration) -> Result<(), BoxError> {
+    // Fails before the terminal is taken over when no daemon answers
+    control::call(endpoint, &Request::Status)
+        .map_err(|e| format!("no daemon answering at {}: {} (is it running with control.enabled?)", endpoint.display(), e))?;
+    enable_raw_mode()?;
+    crossterm::execute!(std::io::stdout(), EnterAlternateScreen)?;
+    let outcome = Terminal::new(CrosstermBackend::new(std::io::stdout()))
+        .map_err(BoxError::from)
+        .and_then(|mut terminal| event_loop(&mut terminal, endpoint, refresh));
+    disable_raw_mode()?;
+    crossterm::execute!(std::io::stdout(), LeaveAlternateScreen, crossterm::cursor::Show)?;
+    outcome
+}
+
+fn event_loop<B: Backend>(terminal: &mut Terminal<B>, endpoint: &Path, refresh: Duration) -> Result<(), BoxError> {
+    let mut dashboard = Dashboard::default();
+    let mut due = Instant::now();
+    loop {
+        if Instant::now() >= due {
+            let status = control::call(endpoint, &Request::Status);
+            let alerts = control::call(endpoint, &Request::Alerts { since: None, limit: Some(ALERTS) });
+            dashboard.update(status, alerts, Instant::now());
+            due = Instant::now() + refresh;
+        }
+        terminal.draw(|frame| dashboard.draw(frame))?;
+        if !event::poll(due.saturating_duration_since(Instant::now()))? {
+            continue;
+        }
+        let TermEvent::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Char('r') => due = Instant::now(),
+            KeyCode::Char('s') => {
+                dashboard.notice = Some(match control::call(endpoint, &Request::Scan) {
+                    Ok(_) => "scan requested".to_string(),
+                    Err(e) => format!("scan not requested: {}", e),
+                });
+            }
+            _ => {}
+        }
+    }
+}
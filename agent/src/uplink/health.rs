@@ -0,0 +1,227 @@
+//! Uplink health, per endpoint (`uplink.endpoint`): outcome counts and latencies per request
+//! path, and a circuit breaker. After `uplink.breaker_failures` consecutive failed requests (no
+//! answer, 5xx, 408, 429, or an authentication failure) an endpoint's breaker opens: requests go
+//! to the next endpoint, or fail at once without touching the network when every breaker is open
+//! (reports stay in the outbox). One probe request is let through every
+//! `uplink.breaker_probe_secs`; an answered probe closes the breaker again.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Error of a request refused by the open breaker
+pub(crate) const CIRCUIT_OPEN: &str = "uplink circuit open";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Requests go out
+    Closed,
+    /// Requests are refused until the next probe
+    Open,
+    /// A probe is in flight
+    HalfOpen,
+}
+
+/// Requests to one path of an endpoint
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PathStats {
+    pub path: String,
+    pub successes: u64,
+    pub failures: u64,
+    /// Mean latency of the requests sent (ms)
+    pub mean_latency_ms: u64,
+    pub max_latency_ms: u64,
+    pub last_latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// When the last request succeeded / failed (ms)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<i64>,
+    #[serde(skip)]
+    total_latency_ms: u64,
+}
+
+/// State of one endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointHealth {
+    pub url: String,
+    pub breaker: BreakerState,
+    pub consecutive_failures: u32,
+    /// When the breaker last opened (ms)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opened_at: Option<i64>,
+    /// When the open breaker lets the next probe through (ms)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_probe: Option<i64>,
+    /// Requests refused by the open breaker
+    pub short_circuited: u64,
+    pub paths: Vec<PathStats>,
+}
+
+/// Uplink state for status surfaces
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UplinkHealth {
+    /// Endpoint requests currently go to; `None` while every breaker is open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active: Option<String>,
+    /// In priority order
+    pub endpoints: Vec<EndpointHealth>,
+    /// Entries waiting in the outbox
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outbox_len: Option<u64>,
+    /// Reports dropped because the uplink queue was full
+    pub dropped_reports: u64,
+}
+
+/// How a request ended
+pub(crate) enum Outcome<'a> {
+    Ok,
+    /// The server answered and refused the request; says nothing about its health
+    Rejected(&'a str),
+    /// No usable answer
+    Failed(&'a str),
+}
+
+impl Outcome<'_> {
+    /// Outcome of an answer with `status`
+    pub(crate) fn of_status(status: reqwest::StatusCode, text: &str) -> Outcome<'_> {
+        if status.is_success() {
+            Outcome::Ok
+        } else if status.is_server_error()
+            || status == reqwest::StatusCode::UNAUTHORIZED
+            || status == reqwest::StatusCode::FORBIDDEN
+            || status == reqwest::StatusCode::REQUEST_TIMEOUT
+            || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        {
+            Outcome::Failed(text)
+        } else {
+            Outcome::Rejected(text)
+        }
+    }
+}
+
+struct State {
+    breaker: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<i64>,
+    next_probe: Option<i64>,
+    short_circuited: u64,
+    paths: BTreeMap<String, PathStats>,
+}
+
+pub(crate) struct Health {
+    url: String,
+    /// Consecutive failures that open the breaker; 0 never opens it
+    failures: u32,
+    probe_ms: i64,
+    state: Mutex<State>,
+}
+
+impl Health {
+    pub(crate) fn new(url: &str, failures: u32, probe_secs: u64) -> Self {
+        Self {
+            url: url.to_string(),
+            failures,
+            probe_ms: (probe_secs.max(1) as i64).saturating_mul(1000),
+            state: Mutex::new(State {
+                breaker: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                next_probe: None,
+                short_circuited: 0,
+                paths: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Whether a request may go out at `now_ms`; while the breaker is open, the first request
+    /// once the probe is due becomes the probe (a probe that never reports back is replaced after
+    /// another probe interval)
+    pub(crate) fn allow(&self, now_ms: i64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.breaker == BreakerState::Closed {
+            return true;
+        }
+        if state.next_probe.is_some_and(|t| now_ms >= t) {
+            state.breaker = BreakerState::HalfOpen;
+            state.next_probe = Some(now_ms.saturating_add(self.probe_ms));
+            return true;
+        }
+        state.short_circuited += 1;
+        false
+    }
+
+    /// Whether requests are refused at `now_ms` (open, and no probe due)
+    pub(crate) fn blocked(&self, now_ms: i64) -> bool {
+        let state = self.state.lock().unwrap();
+        state.breaker != BreakerState::Closed
+            && match state.next_probe {
+                Some(t) => now_ms < t,
+                None => true,
+            }
+    }
+
+    /// Record a request to `path` sent at `now_ms`
+    pub(crate) fn record(&self, path: &str, latency: Duration, outcome: Outcome, now_ms: i64) {
+        let mut state = self.state.lock().unwrap();
+        let latency_ms = latency.as_millis() as u64;
+        let stats = state.paths.entry(path.to_string()).or_insert_with(|| PathStats {
+            path: path.to_string(),
+            ..PathStats::default()
+        });
+        stats.last_latency_ms = latency_ms;
+        stats.max_latency_ms = stats.max_latency_ms.max(latency_ms);
+        stats.total_latency_ms = stats.total_latency_ms.saturating_add(latency_ms);
+        match outcome {
+            Outcome::Ok => {
+                stats.successes += 1;
+                stats.last_success = Some(now_ms);
+            }
+            Outcome::Rejected(error) | Outcome::Failed(error) => {
+                stats.failures += 1;
+                stats.last_failure = Some(now_ms);
+                stats.last_error = Some(error.to_string());
+            }
+        }
+        stats.mean_latency_ms = stats.total_latency_ms / (stats.successes + stats.failures);
+
+        if let Outcome::Failed(error) = outcome {
+            state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+            let trips = self.failures > 0 && state.consecutive_failures >= self.failures;
+            if trips && state.breaker != BreakerState::Open {
+                if state.breaker == BreakerState::Closed {
+                    warn!(url = %self.url, failures = state.consecutive_failures, error, "uplink circuit opened");
+                    state.opened_at = Some(now_ms);
+                }
+                state.breaker = BreakerState::Open;
+                state.next_probe = Some(now_ms.saturating_add(self.probe_ms));
+            }
+        } else {
+            if state.breaker != BreakerState::Closed {
+                info!(url = %self.url, path, "uplink circuit closed");
+            }
+            state.breaker = BreakerState::Closed;
+            state.consecutive_failures = 0;
+            state.next_probe = None;
+        }
+    }
+
+    /// Current state
+    pub(crate) fn snapshot(&self) -> EndpointHealth {
+        let state = self.state.lock().unwrap();
+        EndpointHealth {
+            url: self.url.clone(),
+            breaker: state.breaker,
+            consecutive_failures: state.consecutive_failures,
+            opened_at: state.opened_at,
+            next_probe: state.next_probe.filter(|_| state.breaker != BreakerState::Closed),
+            short_circuited: state.short_circuited,
+            paths: state.paths.values().cloned().collect(),
+        }
+    }
+}
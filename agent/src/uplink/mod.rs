@@ -0,0 +1,1411 @@
+//! Uplink client: report device, events, risk scores, and analyst feedback to graph/fusion API,
+//! and fetch indicators of compromise.
+//! With an outbox store attached, reports are queued in the store first and delivered in order,
+//! so nothing is lost while the endpoint is unreachable or the agent restarts. Failed deliveries
+//! are retried with jittered exponential backoff (`uplink.retry_base_secs` / `retry_max_secs`).
+//! With `uplink.client_cert_path` the device authenticates with a client certificate (mutual TLS);
+//! server certificates can be pinned by SPKI or certificate hash. Requests go through
+//! `uplink.proxy` or the system proxy settings.
+//! With `uplink.enrollment_code` it exchanges the code for a bearer token on first contact, keeps
+//! the token encrypted in the store, and refreshes it before it expires.
+//! With `uplink.streaming` events and risk scores go over a persistent WebSocket as they are made
+//! (falling back to the HTTP path and outbox while it is down), and the server can push messages,
+//! such as indicator updates, on the same connection.
+//! With `uplink.policy_keys` it fetches the signed server policy ([`crate::policy`]), and with
+//! `commands.enabled` pending server commands ([`crate::commands`]), whose results it reports.
+//! [`UplinkQueue`] runs reporting on a dedicated thread so a slow server never stalls collection.
+//! `uplink.endpoint` may list several endpoints in priority order. Request outcomes and latencies
+//! are tracked per endpoint, and a circuit breaker per endpoint moves requests off a failing one
+//! and back once it answers again ([`health`]); [`UplinkClient::health`] reports both.
+//! In a dry run (`dry_run`) nothing is posted or queued: each report is logged as what would
+//! have been sent. Fetches (policy, indicators, commands) still happen.
+
+use crate::collectors::{Event, EventKind, NetworkEvent};
+use crate::commands::{Command, CommandResult};
+use crate::config::{UplinkCompression, UplinkConfig};
+use crate::policy::SignedPolicy;
+use crate::redact::Redactor;
+use crate::risk::feedback::implicated_event_ids;
+use crate::risk::{Feedback, RiskLevel, RiskResult};
+use crate::storage::SecureStore;
+use chrono::Utc;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::io::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+pub mod health;
+mod stream;
+mod tls;
+
+pub use health::UplinkHealth;
+pub use stream::ServerMessage;
+
+/// Outbox entries read (and decrypted) per drain step
+const OUTBOX_DRAIN_BATCH: usize = 100;
+
+/// Bodies smaller than this are sent uncompressed (not worth the framing)
+const COMPRESS_MIN_BYTES: usize = 512;
+
+/// Exchanges an enrollment code for a device token
+const ENROLL_PATH: &str = "/api/v1/devices/enroll";
+/// Exchanges a valid device token for a fresh one
+const TOKEN_REFRESH_PATH: &str = "/api/v1/devices/token/refresh";
+/// Store secret holding the device token
+const TOKEN_SECRET: &str = "uplink_token";
+/// Wait after a failed enrollment or refresh before trying again (ms)
+const TOKEN_RETRY_MS: i64 = 60 * 1000;
+
+/// Batch ingest endpoint of the graph API (`{"events": [...]}`)
+const INGEST_BATCH_PATH: &str = "/api/v1/ingest/batch";
+
+/// Pending server commands for a device (`?device_id=`)
+const COMMANDS_PATH: &str = "/api/v1/devices/commands";
+/// Results of server commands
+const COMMAND_RESULT_PATH: &str = "/api/v1/devices/commands/result";
+
+/// How often the reporter thread reads server frames while no report is pending (streaming)
+const STREAM_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Model release downloads may take much longer than API requests
+const ARTIFACT_TIMEOUT: Duration = Duration::from_secs(600);
+/// Window of `uplink.bytes_per_hour`
+const BUDGET_WINDOW_MS: i64 = 3600 * 1000;
+
+/// Failed POST; `retryable` is false when the server rejected the payload itself (4xx other than
+/// 401 / 403 / 408 / 429)
+struct SendError {
+    message: String,
+    retryable: bool,
+}
+
+fn ts_iso(ms: i64) -> String {
+    let dt = Utc.timestamp_millis_opt(ms).single().unwrap_or_else(Utc::now);
+    dt.to_rfc3339()
+}
+
+/// Payloads for graph API (align with graph/schema and ingest endpoints).
+#[derive(Serialize)]
+struct DevicePayload {
+    node_id: String,
+    platform: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_seen: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen: Option<String>,
+    /// Daemon health sent with heartbeats
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct EventPayload {
+    event_id: String,
+    kind: String,
+    ts: String,
+    device_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_hash: Option<String>,
+    /// Part of a full-state checkpoint (`uplink.delta_reporting`): state of the device missing
+    /// from the checkpoint is gone
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    checkpoint: bool,
+}
+
+#[derive(Serialize)]
+struct RiskPayload {
+    id: String,
+    score: f32,
+    level: String,
+    ts: String,
+    window_start: String,
+    window_end: String,
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_class_probability: Option<f32>,
+    /// MITRE ATT&CK technique ids
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    techniques: Vec<String>,
+    /// MITRE ATT&CK tactic ids
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tactics: Vec<String>,
+    /// Matched indicators of compromise
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    indicators: Vec<String>,
+    /// Level rose in this window (debounced, outside the alert cooldown)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    alert: bool,
+    /// Scored during the learning period (informational only)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    learning: bool,
+    /// Dedup fingerprint (`risk.dedup`); repeats reuse the first occurrence's `id`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    occurrences: Option<u32>,
+}
+
+/// Frame sent on the stream connection (`uplink.streaming`)
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamFrame<'a> {
+    Events { events: &'a [EventPayload] },
+    RiskScore(&'a RiskPayload),
+}
+
+/// Analyst verdict on a reported risk score, for retraining
+#[derive(Serialize)]
+struct FeedbackPayload {
+    /// Id of the risk score it judges (as sent to `/api/v1/risk_scores`)
+    risk_id: String,
+    event_id: String,
+    verdict: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    ts: String,
+    source: String,
+    score: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model_score: Option<f32>,
+    level: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_class: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    rules: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    techniques: Vec<String>,
+    /// Subject and rule-hit events
+    event_ids: Vec<String>,
+}
+
+/// Response of `/api/v1/ingest/batch`; items the server could not ingest are listed, the rest
+/// were accepted
+#[derive(Deserialize)]
+struct BatchResponse {
+    #[serde(default)]
+    errors: Vec<BatchItemError>,
+}
+
+#[derive(Deserialize)]
+struct BatchItemError {
+    /// Section of the batch (`events`, `risk_scores`, `devices`)
+    #[serde(default)]
+    kind: String,
+    index: usize,
+    #[serde(default)]
+    id: Option<String>,
+    error: String,
+}
+
+/// `{"events": [...]}` request bodies holding `items` (serialized JSON objects) in order, each
+/// body at most `max_bytes` unless a single item is larger
+fn batch_bodies(items: &[String], max_bytes: usize) -> Vec<String> {
+    const OPEN: &str = r#"{"events":["#;
+    const CLOSE: &str = "]}";
+    let mut bodies = Vec::new();
+    let mut body = String::from(OPEN);
+    let mut count = 0;
+    for item in items {
+        if count > 0 && body.len() + 1 + item.len() + CLOSE.len() > max_bytes {
+            body.push_str(CLOSE);
+            bodies.push(std::mem::replace(&mut body, String::from(OPEN)));
+            count = 0;
+        }
+        if count > 0 {
+            body.push(',');
+        }
+        body.push_str(item);
+        count += 1;
+    }
+    if count > 0 {
+        body.push_str(CLOSE);
+        bodies.push(body);
+    }
+    bodies
+}
+
+/// `body` encoded as `encoding`
+fn compress(encoding: UplinkCompression, body: &[u8]) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        UplinkCompression::None => Ok(body.to_vec()),
+        UplinkCompression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        UplinkCompression::Zstd => zstd::encode_all(body, 0),
+    }
+}
+
+/// Encoding to fall back to after the server refused `rejected`, from the `Accept-Encoding` it
+/// sent with the 415 (preferring zstd); uncompressed when it listed nothing usable
+fn fallback_encoding(rejected: UplinkCompression, accept: Option<&str>) -> UplinkCompression {
+    let accepted: Vec<String> = accept
+        .unwrap_or_default()
+        .split(',')
+        .map(|t| t.split(';').next().unwrap_or_default().trim().to_ascii_lowercase())
+        .collect();
+    [UplinkCompression::Zstd, UplinkCompression::Gzip]
+        .into_iter()
+        .filter(|c| *c != rejected)
+        .find(|c| c.content_encoding().is_some_and(|name| accepted.iter().any(|a| a == name)))
+        .unwrap_or(UplinkCompression::None)
+}
+
+/// Route requests through the configured proxy, the proxy environment variables, or neither
+fn configure_proxy(
+    builder: reqwest::blocking::ClientBuilder,
+    config: &UplinkConfig,
+) -> Result<reqwest::blocking::ClientBuilder, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(ref url) = config.proxy else {
+        // reqwest reads HTTP_PROXY / HTTPS_PROXY / ALL_PROXY / NO_PROXY by default
+        return Ok(if config.system_proxy { builder } else { builder.no_proxy() });
+    };
+    // Credentials in the URL become Proxy-Authorization (basic)
+    let no_proxy = if config.no_proxy.is_empty() {
+        reqwest::NoProxy::from_env()
+    } else {
+        reqwest::NoProxy::from_string(&config.no_proxy.join(","))
+    };
+    let proxy = reqwest::Proxy::all(url.as_str()).map_err(|e| format!("uplink.proxy: {}", e))?.no_proxy(no_proxy);
+    let host = reqwest::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_owned)).unwrap_or_default();
+    info!(proxy = %host, "uplink requests go through proxy");
+    Ok(builder.proxy(proxy))
+}
+
+/// HTTP client for `config`, connecting to the `pinned` addresses for those host names
+fn build_client(
+    config: &UplinkConfig,
+    tls: Option<&Arc<rustls::ClientConfig>>,
+    pinned: &BTreeMap<String, Vec<IpAddr>>,
+) -> Result<reqwest::blocking::Client, Box<dyn std::error::Error + Send + Sync>> {
+    let mut builder = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(15))
+        .connect_timeout(Duration::from_secs(5));
+    if let Some(tls) = tls {
+        builder = builder.use_preconfigured_tls((**tls).clone());
+    }
+    for (host, addresses) in pinned {
+        // The port is taken from the URL
+        let addrs: Vec<SocketAddr> = addresses.iter().map(|ip| SocketAddr::new(*ip, 0)).collect();
+        builder = builder.resolve_to_addrs(host, &addrs);
+    }
+    Ok(configure_proxy(builder, config)?.build()?)
+}
+
+/// Device bearer token, persisted as JSON in the store
+#[derive(Serialize, Deserialize)]
+struct DeviceToken {
+    token: String,
+    /// Expiry (ms)
+    expires_at: i64,
+}
+
+/// Response of the enrollment and refresh endpoints
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: String,
+    /// Lifetime in seconds
+    expires_in: u64,
+}
+
+#[derive(Default)]
+struct TokenState {
+    token: Option<DeviceToken>,
+    /// No enrollment or refresh before this time (ms), after one failed
+    retry_at: i64,
+}
+
+/// Response of `/api/v1/indicators`
+#[derive(Deserialize)]
+struct IndicatorsResponse {
+    indicators: Vec<String>,
+}
+
+/// Response of [`COMMANDS_PATH`]
+#[derive(Deserialize)]
+struct CommandsResponse {
+    commands: Vec<Command>,
+}
+
+/// Outcome of a server command
+#[derive(Serialize)]
+struct CommandResultPayload<'a> {
+    device_id: &'a str,
+    #[serde(flatten)]
+    result: &'a CommandResult,
+}
+
+pub struct UplinkClient {
+    config: UplinkConfig,
+    client: RwLock<reqwest::blocking::Client>,
+    tls: Option<Arc<rustls::ClientConfig>>,
+    /// Host names `client` connects to fixed addresses for (see [`UplinkClient::pin_hosts`])
+    pinned: Mutex<BTreeMap<String, Vec<IpAddr>>>,
+    /// `uplink.endpoint`, in priority order
+    endpoints: Vec<Endpoint>,
+    /// Index of the endpoint the last request went to
+    active: std::sync::atomic::AtomicUsize,
+    device_id: String,
+    device_registered: std::sync::atomic::AtomicBool,
+    outbox: Option<Arc<SecureStore>>,
+    /// `uplink.compression`, or what the server accepted instead
+    encoding: Mutex<UplinkCompression>,
+    token: Mutex<TokenState>,
+    token_store: Option<Arc<SecureStore>>,
+    /// With `uplink.streaming`
+    stream: Option<stream::Stream>,
+    budget: Mutex<Budget>,
+    delta: Mutex<DeltaState>,
+    /// With `uplink.redaction.enabled`
+    redactor: Option<Redactor>,
+    /// Log reports instead of sending them (`dry_run`)
+    dry_run: bool,
+}
+
+struct Endpoint {
+    /// Base URL, without a trailing `/`
+    url: String,
+    health: health::Health,
+}
+
+/// Identity and content of a state item (`uplink.delta_reporting`)
+type StateKey = [u8; 32];
+
+/// State items the server has acknowledged since the last checkpoint
+#[derive(Default)]
+struct DeltaState {
+    acked: HashSet<StateKey>,
+    last_checkpoint: Option<i64>,
+}
+
+/// Key of the state `event` reports: its content without the event id and time (and, for
+/// connections, without the traffic counters, which change every cycle)
+fn state_key(event: &Event) -> StateKey {
+    let content = match &event.kind {
+        EventKind::Network(net) => serde_json::to_vec(&EventKind::Network(NetworkEvent {
+            bytes_sent: 0,
+            bytes_recv: 0,
+            ..net.clone()
+        })),
+        kind => serde_json::to_vec(kind),
+    };
+    Sha256::digest(content.unwrap_or_default()).into()
+}
+
+/// Bytes uploaded in the current `uplink.bytes_per_hour` window
+#[derive(Default)]
+struct Budget {
+    window_start: i64,
+    used: u64,
+}
+
+impl UplinkClient {
+    /// Device node id (e.g. did:local-device) sent to graph.
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn new(config: UplinkConfig) -> Option<Self> {
+        let endpoints: Vec<Endpoint> = config
+            .endpoint
+            .iter()
+            .map(|url| url.trim().trim_end_matches('/'))
+            .filter(|url| !url.is_empty())
+            .map(|url| Endpoint {
+                url: url.to_string(),
+                health: health::Health::new(url, config.breaker_failures, config.breaker_probe_secs),
+            })
+            .collect();
+        // The stream stays on the primary endpoint; HTTP requests fail over
+        let endpoint = endpoints.first()?.url.clone();
+        let device_id = config
+            .device_id
+            .clone()
+            .unwrap_or_else(|| "local-device".to_string());
+        let node_id = if device_id.starts_with("did:") {
+            device_id.clone()
+        } else {
+            format!("did:{}", device_id)
+        };
+        // A misconfigured identity or pin disables uplink rather than connecting without it
+        let tls = match tls::client_config(&config) {
+            Ok(tls) => tls.map(Arc::new),
+            Err(e) => {
+                warn!(error = %e, "uplink TLS configuration invalid; uplink disabled");
+                return None;
+            }
+        };
+        let client = match build_client(&config, tls.as_ref(), &BTreeMap::new()) {
+            Ok(client) => client,
+            Err(e) => {
+                warn!(error = %e, "uplink proxy configuration invalid; uplink disabled");
+                return None;
+            }
+        };
+        let stream = config
+            .streaming
+            .then(|| stream::Stream::new(&endpoint, &config.stream_path, &node_id, tls.clone()));
+        Some(Self {
+            client: RwLock::new(client),
+            tls,
+            pinned: Mutex::new(BTreeMap::new()),
+            endpoints,
+            active: std::sync::atomic::AtomicUsize::new(0),
+            device_id: node_id,
+            device_registered: std::sync::atomic::AtomicBool::new(false),
+            outbox: None,
+            encoding: Mutex::new(config.compression),
+            token: Mutex::new(TokenState::default()),
+            token_store: None,
+            stream,
+            budget: Mutex::new(Budget::default()),
+            delta: Mutex::new(DeltaState::default()),
+            redactor: Redactor::new(&config.redaction),
+            dry_run: false,
+            config,
+        })
+    }
+
+    /// Log reports as what would have been sent instead of sending or queueing them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Connect to these addresses for the uplink host names instead of resolving them, for as
+    /// long as the host is isolated (DNS answers may move outside what isolation lets through);
+    /// empty resolves them again.
+    pub fn pin_hosts(&self, pinned: &BTreeMap<String, Vec<IpAddr>>) {
+        let mut current = self.pinned.lock().expect("pins lock");
+        if *current == *pinned {
+            return;
+        }
+        match build_client(&self.config, self.tls.as_ref(), pinned) {
+            Ok(client) => {
+                *self.client.write().expect("client lock") = client;
+                *current = pinned.clone();
+                match pinned.is_empty() {
+                    true => info!("uplink host addresses no longer pinned"),
+                    false => info!(hosts = ?pinned.keys().collect::<Vec<_>>(), "uplink host addresses pinned"),
+                }
+            }
+            Err(e) => warn!(error = %e, "couldn't pin the uplink host addresses"),
+        }
+    }
+
+    /// HTTP client, with the current pins
+    fn http(&self) -> reqwest::blocking::Client {
+        self.client.read().expect("client lock").clone()
+    }
+
+    /// Queue reports in `store`'s outbox and deliver them from there (oldest first).
+    pub fn with_outbox(mut self, store: Arc<SecureStore>) -> Self {
+        self.outbox = Some(store);
+        self
+    }
+
+    /// Keep the device token in `store` (encrypted) and use the one saved by an earlier run.
+    pub fn with_token_store(mut self, store: Arc<SecureStore>) -> Self {
+        match store.secret(TOKEN_SECRET) {
+            Ok(Some(raw)) => match serde_json::from_slice::<DeviceToken>(&raw) {
+                Ok(token) => self.token.get_mut().unwrap().token = Some(token),
+                Err(e) => warn!(error = %e, "stored uplink token unreadable; enrolling again"),
+            },
+            Ok(None) => {}
+            Err(e) => warn!(error = %e, "cannot read stored uplink token"),
+        }
+        self.token_store = Some(store);
+        self
+    }
+
+    /// POST `body` to a token endpoint and parse the issued token
+    fn request_token(&self, path: &str, body: serde_json::Value, bearer: Option<&str>) -> Result<DeviceToken, String> {
+        let mut req = self.http().post(format!("{}{}", self.base_url(), path)).json(&body);
+        if let Some(bearer) = bearer {
+            req = req.bearer_auth(bearer);
+        }
+        let res = req.send().map_err(|e| e.to_string())?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(format!("{} {}", status, res.text().unwrap_or_default()));
+        }
+        let issued: TokenResponse = res.json().map_err(|e| e.to_string())?;
+        let lifetime_ms = (issued.expires_in as i64).saturating_mul(1000);
+        Ok(DeviceToken {
+            token: issued.token,
+            expires_at: Utc::now().timestamp_millis().saturating_add(lifetime_ms),
+        })
+    }
+
+    /// Bearer token for the next request: enrolls when there is none yet and refreshes one that
+    /// expires within `uplink.token_refresh_secs`. `None` without a token or enrollment code.
+    fn bearer_token(&self) -> Option<String> {
+        let mut state = self.token.lock().unwrap();
+        let now = Utc::now().timestamp_millis();
+        let refresh_ms = (self.config.token_refresh_secs as i64).saturating_mul(1000);
+        if now < state.retry_at {
+            return state.token.as_ref().map(|t| t.token.clone());
+        }
+        let issued = match state.token {
+            None => {
+                let code = self.config.enrollment_code.as_deref()?;
+                let body = serde_json::json!({ "device_id": self.device_id, "enrollment_code": code });
+                Some(("enrollment", self.request_token(ENROLL_PATH, body, None)))
+            }
+            Some(ref token) if token.expires_at - now <= refresh_ms => {
+                let body = serde_json::json!({ "device_id": self.device_id });
+                Some(("refresh", self.request_token(TOKEN_REFRESH_PATH, body, Some(&token.token))))
+            }
+            Some(_) => None,
+        };
+        match issued {
+            Some((action, Ok(token))) => {
+                if let Some(ref store) = self.token_store {
+                    let saved = serde_json::to_vec(&token).map_err(|e| e.into()).and_then(|raw| store.set_secret(TOKEN_SECRET, &raw));
+                    if let Err(e) = saved {
+                        warn!(error = %e, "cannot store uplink token");
+                    }
+                }
+                info!(device_id = %self.device_id, action, "uplink token issued");
+                state.token = Some(token);
+            }
+            Some((action, Err(e))) => {
+                warn!(action, error = %e, "uplink token request failed");
+                state.retry_at = now + TOKEN_RETRY_MS;
+            }
+            None => {}
+        }
+        state.token.as_ref().map(|t| t.token.clone())
+    }
+
+    /// After a 401, refresh the token before the next request
+    fn token_rejected(&self) {
+        if let Some(ref mut token) = self.token.lock().unwrap().token {
+            token.expires_at = 0;
+        }
+    }
+
+    /// Endpoint for the next request: the first whose breaker lets it through (a due probe of a
+    /// preferred endpoint included); `None` while every breaker is open
+    fn pick_endpoint(&self, now_ms: i64) -> Option<&Endpoint> {
+        let (index, endpoint) = self.endpoints.iter().enumerate().find(|(_, e)| e.health.allow(now_ms))?;
+        let previous = self.active.swap(index, Ordering::Relaxed);
+        if index > previous {
+            warn!(from = %self.endpoints[previous].url, to = %endpoint.url, "uplink failing over");
+        } else if index < previous {
+            info!(from = %self.endpoints[previous].url, to = %endpoint.url, "uplink back on preferred endpoint");
+        }
+        Some(endpoint)
+    }
+
+    /// Base URL of the first endpoint not refusing requests (the primary while all do), for
+    /// requests outside the breaker
+    fn base_url(&self) -> &str {
+        let now = Utc::now().timestamp_millis();
+        let endpoint = self.endpoints.iter().find(|e| !e.health.blocked(now)).unwrap_or(&self.endpoints[0]);
+        &endpoint.url
+    }
+
+    /// [`Self::send_request`] to the endpoint the breakers select, recording the outcome
+    fn send(&self, path: &str, body: String) -> Result<(), SendError> {
+        let now = Utc::now().timestamp_millis();
+        let Some(endpoint) = self.pick_endpoint(now) else {
+            return Err(SendError {
+                message: health::CIRCUIT_OPEN.to_string(),
+                retryable: true,
+            });
+        };
+        let start = Instant::now();
+        let result = self.send_request(&endpoint.url, path, body);
+        let outcome = match result {
+            Ok(()) => health::Outcome::Ok,
+            Err(ref e) if e.retryable => health::Outcome::Failed(&e.message),
+            Err(ref e) => health::Outcome::Rejected(&e.message),
+        };
+        endpoint.health.record(path, start.elapsed(), outcome, now);
+        result
+    }
+
+    /// GET built by `request` (from the endpoint's base URL) from the endpoint the breakers
+    /// select, recording the outcome under `path`
+    fn get(
+        &self,
+        path: &str,
+        request: impl FnOnce(&str) -> reqwest::blocking::RequestBuilder,
+    ) -> Result<reqwest::blocking::Response, String> {
+        let now = Utc::now().timestamp_millis();
+        let endpoint = self.pick_endpoint(now).ok_or(health::CIRCUIT_OPEN)?;
+        let start = Instant::now();
+        let res = request(&endpoint.url).send();
+        match res {
+            Ok(ref res) => {
+                let status = res.status().to_string();
+                let outcome = match res.status() {
+                    // No content for this device
+                    reqwest::StatusCode::NOT_FOUND => health::Outcome::Ok,
+                    code => health::Outcome::of_status(code, &status),
+                };
+                endpoint.health.record(path, start.elapsed(), outcome, now);
+            }
+            Err(ref e) => endpoint.health.record(path, start.elapsed(), health::Outcome::Failed(&e.to_string()), now),
+        }
+        res.map_err(|e| e.to_string())
+    }
+
+    /// Request outcomes, latencies, and circuit breaker state per endpoint, with the outbox backlog
+    pub fn health(&self) -> UplinkHealth {
+        let now = Utc::now().timestamp_millis();
+        UplinkHealth {
+            active: self.endpoints.iter().find(|e| !e.health.blocked(now)).map(|e| e.url.clone()),
+            endpoints: self.endpoints.iter().map(|e| e.health.snapshot()).collect(),
+            outbox_len: self.outbox.as_ref().and_then(|store| store.outbox_len().ok()),
+            dropped_reports: 0,
+        }
+    }
+
+    /// POST `body` to `path`, compressed with the negotiated encoding. A 415 answer to a
+    /// compressed body switches the client to an encoding the server accepts and sends again.
+    fn send_request(&self, base_url: &str, path: &str, body: String) -> Result<(), SendError> {
+        let url = format!("{}{}", base_url, path);
+        let bearer = self.bearer_token();
+        let mut refused = false;
+        let res = loop {
+            let sent;
+            let encoding = *self.encoding.lock().unwrap();
+            let encoding = if body.len() < COMPRESS_MIN_BYTES { UplinkCompression::None } else { encoding };
+            let mut req = self.http().post(&url).header(reqwest::header::CONTENT_TYPE, "application/json");
+            if let Some(ref bearer) = bearer {
+                req = req.bearer_auth(bearer);
+            }
+            req = match encoding.content_encoding() {
+                Some(name) => {
+                    let compressed = compress(encoding, body.as_bytes()).map_err(|e| SendError {
+                        message: e.to_string(),
+                        retryable: false,
+                    })?;
+                    sent = compressed.len();
+                    req.header(reqwest::header::CONTENT_ENCODING, name).body(compressed)
+                }
+                None => {
+                    sent = body.len();
+                    req.body(body.clone())
+                }
+            };
+            self.spend(sent);
+            let res = req.send().map_err(|e| SendError {
+                message: e.to_string(),
+                retryable: true,
+            })?;
+            if res.status() != reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE || encoding == UplinkCompression::None {
+                break res;
+            }
+            let accept = res.headers().get(reqwest::header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok());
+            // A second refusal ends negotiation, whatever the server claims to accept
+            let fallback = if refused { UplinkCompression::None } else { fallback_encoding(encoding, accept) };
+            refused = true;
+            info!(rejected = ?encoding, using = ?fallback, "uplink server refused request encoding");
+            *self.encoding.lock().unwrap() = fallback;
+        };
+        let status = res.status();
+        if !status.is_success() {
+            if status == reqwest::StatusCode::UNAUTHORIZED {
+                self.token_rejected();
+            }
+            let text = res.text().unwrap_or_default();
+            // Authentication failures are not the payload's fault: keep it for a later attempt
+            return Err(SendError {
+                message: format!("{} {}", status, text),
+                retryable: !status.is_client_error()
+                    || status == reqwest::StatusCode::UNAUTHORIZED
+                    || status == reqwest::StatusCode::FORBIDDEN
+                    || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                    || status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+            });
+        }
+        if path == INGEST_BATCH_PATH {
+            // The batch as a whole was accepted; items the server rejected are not retried
+            if let Ok(response) = res.json::<BatchResponse>() {
+                for e in &response.errors {
+                    warn!(kind = %e.kind, index = e.index, id = e.id.as_deref().unwrap_or_default(), error = %e.error, "uplink rejected batch item");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Count `bytes` against `uplink.bytes_per_hour`
+    fn spend(&self, bytes: usize) {
+        let now = Utc::now().timestamp_millis();
+        let mut budget = self.budget.lock().unwrap();
+        if now - budget.window_start >= BUDGET_WINDOW_MS {
+            *budget = Budget { window_start: now, used: 0 };
+        }
+        budget.used = budget.used.saturating_add(bytes as u64);
+    }
+
+    /// Whether this hour's `uplink.bytes_per_hour` is spent
+    fn over_budget(&self) -> bool {
+        if self.config.bytes_per_hour == 0 {
+            return false;
+        }
+        let budget = self.budget.lock().unwrap();
+        Utc::now().timestamp_millis() - budget.window_start < BUDGET_WINDOW_MS && budget.used >= self.config.bytes_per_hour
+    }
+
+    /// Events of a report that are sent: all of them for medium and high risk or an alert;
+    /// otherwise the implicated ones and, while within budget, a stable sample (by event id) of
+    /// `uplink.low_risk_sample_percent` of the rest
+    fn select_events<'a>(&self, events: &'a [Event], risk: &RiskResult) -> Vec<&'a Event> {
+        if risk.level > RiskLevel::Low || risk.alert {
+            return events.iter().collect();
+        }
+        let implicated = implicated_event_ids(risk);
+        let over_budget = self.over_budget();
+        let percent = u16::from(self.config.low_risk_sample_percent.min(100));
+        let selected: Vec<&Event> = events
+            .iter()
+            .filter(|ev| {
+                if implicated.contains(&ev.id) {
+                    return true;
+                }
+                let hash = Sha256::digest(ev.id.as_bytes());
+                !over_budget && u16::from_be_bytes([hash[0], hash[1]]) % 100 < percent
+            })
+            .collect();
+        if selected.len() < events.len() {
+            debug!(skipped = events.len() - selected.len(), over_budget, "uplink low-risk events sampled out");
+        }
+        selected
+    }
+
+    /// Whether the next report is a full-state checkpoint (`uplink.delta_reporting`): the first
+    /// one, and then every `uplink.checkpoint_secs`
+    fn checkpoint_due(&self, now_ms: i64) -> bool {
+        if !self.config.delta_reporting {
+            return false;
+        }
+        match self.delta.lock().unwrap().last_checkpoint {
+            Some(last) => now_ms - last >= (self.config.checkpoint_secs as i64).saturating_mul(1000),
+            None => true,
+        }
+    }
+
+    /// `events` (with state `keys`) the server has not acknowledged; all of them at a checkpoint
+    /// or without `uplink.delta_reporting`
+    fn unacknowledged<'a>(&self, events: &[&'a Event], keys: &[StateKey], checkpoint: bool) -> Vec<&'a Event> {
+        if !self.config.delta_reporting || checkpoint {
+            return events.to_vec();
+        }
+        let delta = self.delta.lock().unwrap();
+        let unacked: Vec<&Event> = events
+            .iter()
+            .zip(keys)
+            .filter(|(_, key)| !delta.acked.contains(*key))
+            .map(|(ev, _)| *ev)
+            .collect();
+        debug!(unchanged = events.len() - unacked.len(), sent = unacked.len(), "uplink delta");
+        unacked
+    }
+
+    /// Record the outcome of a report carrying state `keys`: once delivered, they are the
+    /// acknowledged state; otherwise only state that is still current stays acknowledged, so
+    /// what was not delivered goes again with the next report
+    fn acknowledge(&self, keys: Vec<StateKey>, checkpoint: bool, now_ms: i64, delivered: bool) {
+        if !self.config.delta_reporting {
+            return;
+        }
+        let mut delta = self.delta.lock().unwrap();
+        let current: HashSet<StateKey> = keys.into_iter().collect();
+        if delivered {
+            delta.acked = current;
+            if checkpoint {
+                delta.last_checkpoint = Some(now_ms);
+            }
+        } else {
+            delta.acked.retain(|key| current.contains(key));
+        }
+    }
+
+    /// When to retry an entry that has now failed `attempts` times: the base delay doubled per
+    /// earlier failure, capped, then drawn uniformly from its upper half so agents that went
+    /// offline together do not retry in lockstep
+    fn next_attempt(&self, attempts: u32, now_ms: i64) -> i64 {
+        let base = self.config.retry_base_secs.max(1).saturating_mul(1000);
+        let max = self.config.retry_max_secs.saturating_mul(1000).max(base);
+        let delay = base.saturating_mul(1u64 << attempts.saturating_sub(1).min(32)).min(max);
+        let delay = rand::thread_rng().gen_range(delay / 2..=delay);
+        now_ms.saturating_add(delay as i64)
+    }
+
+    fn post<T: Serialize + ?Sized>(&self, path: &str, body: &T) -> Result<(), String> {
+        let body = serde_json::to_string(body).map_err(|e| e.to_string())?;
+        if self.dry_run {
+            info!(path, bytes = body.len(), "dry run: would have posted to the server");
+            return Ok(());
+        }
+        self.send(path, self.redact(body)).map_err(|e| e.message)
+    }
+
+    /// `body` through the privacy filter (`uplink.redaction`); bodies are redacted where they
+    /// are made, so queued entries are stored redacted and never filtered twice
+    fn redact(&self, body: String) -> String {
+        match self.redactor {
+            Some(ref redactor) => redactor.body(&body),
+            None => body,
+        }
+    }
+
+    /// Deliver queued outbox entries in order until the outbox is empty, a send fails (the entry's
+    /// attempt count is bumped, its next attempt scheduled, and the rest wait behind it), or the
+    /// head entry is still backing off. Entries the server rejects outright are dropped. Returns
+    /// the number of entries delivered.
+    pub fn drain_outbox(&self) -> Result<usize, String> {
+        let Some(ref store) = self.outbox else {
+            return Ok(0);
+        };
+        // Entries queued by an earlier run wait for a run that sends
+        if self.dry_run {
+            return Ok(0);
+        }
+        let mut sent = 0;
+        loop {
+            let batch = store.outbox_pending(OUTBOX_DRAIN_BATCH).map_err(|e| e.to_string())?;
+            if batch.is_empty() {
+                break;
+            }
+            for entry in batch {
+                let now = Utc::now().timestamp_millis();
+                if let Some(retry_at) = entry.next_attempt.filter(|t| *t > now) {
+                    debug!(attempts = entry.attempts, retry_in_ms = retry_at - now, "uplink outbox backing off");
+                    if sent > 0 {
+                        info!(sent, "uplink outbox partially drained");
+                    }
+                    return Ok(sent);
+                }
+                if self.endpoints.iter().all(|e| e.health.blocked(now)) {
+                    // Not an attempt: the entry keeps its backoff for when the breaker closes
+                    return Err(health::CIRCUIT_OPEN.to_string());
+                }
+                match self.send(&entry.path, entry.body) {
+                    Ok(()) => sent += 1,
+                    Err(e) if !e.retryable => {
+                        warn!(path = %entry.path, attempts = entry.attempts, error = %e.message, "uplink rejected queued payload; dropping");
+                    }
+                    Err(e) => {
+                        let retry_at = self.next_attempt(entry.attempts + 1, now);
+                        store
+                            .outbox_fail(entry.id, &e.message, now, retry_at)
+                            .map_err(|e| e.to_string())?;
+                        if sent > 0 {
+                            info!(sent, "uplink outbox partially drained");
+                        }
+                        return Err(e.message);
+                    }
+                }
+                store.outbox_ack(entry.id).map_err(|e| e.to_string())?;
+            }
+        }
+        if sent > 0 {
+            info!(sent, "uplink outbox drained");
+        }
+        Ok(sent)
+    }
+
+    /// POST now, or queue when an outbox is attached
+    fn submit<T: Serialize + ?Sized>(&self, path: &str, body: &T) -> Result<(), String> {
+        let body = serde_json::to_string(body).map_err(|e| e.to_string())?;
+        self.submit_raw(path, body)
+    }
+
+    /// [`Self::submit`] with an already serialized body
+    fn submit_raw(&self, path: &str, body: String) -> Result<(), String> {
+        if self.dry_run {
+            info!(path, bytes = body.len(), "dry run: would have posted to the server");
+            return Ok(());
+        }
+        let body = self.redact(body);
+        let Some(ref store) = self.outbox else {
+            return self.send(path, body).map_err(|e| e.message);
+        };
+        store
+            .outbox_push(path, &body, Utc::now().timestamp_millis())
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// GET `url` with the device token, if there is one
+    fn authorized_get(&self, url: &str) -> reqwest::blocking::RequestBuilder {
+        let req = self.http().get(url);
+        match self.bearer_token() {
+            Some(bearer) => req.bearer_auth(bearer),
+            None => req,
+        }
+    }
+
+    /// Current indicators of compromise for this device (`risk.ioc.uplink`)
+    pub fn fetch_indicators(&self) -> Result<Vec<String>, String> {
+        let query = format!("/api/v1/indicators?device_id={}", self.device_id);
+        let res = self.get("/api/v1/indicators", |base| self.authorized_get(&format!("{}{}", base, query)))?;
+        let status = res.status();
+        if !status.is_success() {
+            if status == reqwest::StatusCode::UNAUTHORIZED {
+                self.token_rejected();
+            }
+            return Err(format!("{} {}", status, res.text().unwrap_or_default()));
+        }
+        let body: IndicatorsResponse = res.json().map_err(|e| e.to_string())?;
+        Ok(body.indicators)
+    }
+
+    /// Signed policy for this device (`uplink.policy_keys`); `None` when the server has none
+    pub fn fetch_policy(&self) -> Result<Option<SignedPolicy>, String> {
+        let query = format!("{}?device_id={}", self.config.policy_path, self.device_id);
+        let res = self.get(&self.config.policy_path, |base| self.authorized_get(&format!("{}{}", base, query)))?;
+        let status = res.status();
+        if status == reqwest::StatusCode::NO_CONTENT || status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            if status == reqwest::StatusCode::UNAUTHORIZED {
+                self.token_rejected();
+            }
+            return Err(format!("{} {}", status, res.text().unwrap_or_default()));
+        }
+        res.json().map(Some).map_err(|e| e.to_string())
+    }
+
+    /// Download a model release file (`url` relative to the endpoint, or absolute; the device
+    /// token is only sent to the endpoints)
+    pub fn fetch_artifact(&self, url: &str) -> Result<Vec<u8>, String> {
+        let absolute = url.starts_with("http://") || url.starts_with("https://");
+        let res = if absolute {
+            // Elsewhere (e.g. a download mirror): outside the endpoints' breakers, without the token
+            self.http().get(url).timeout(ARTIFACT_TIMEOUT).send().map_err(|e| e.to_string())?
+        } else {
+            self.get("artifact", |base| self.authorized_get(&format!("{}{}", base, url)).timeout(ARTIFACT_TIMEOUT))?
+        };
+        let status = res.status();
+        if !status.is_success() {
+            return Err(format!("{}: {}", url, status));
+        }
+        res.bytes().map(|b| b.to_vec()).map_err(|e| e.to_string())
+    }
+
+    /// Register device once (idempotent).
+    pub fn ensure_device(&self, platform: &str) {
+        if self
+            .device_registered
+            .load(std::sync::atomic::Ordering::Relaxed)
+        {
+            return;
+        }
+        let now = Utc::now().to_rfc3339();
+        let payload = DevicePayload {
+            node_id: self.device_id.clone(),
+            platform: platform.to_string(),
+            first_seen: Some(now.clone()),
+            last_seen: Some(now),
+            health: None,
+        };
+        if self.post("/api/v1/devices", &payload).is_ok() {
+            self.device_registered
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+            info!(device_id = %self.device_id, "uplink device registered");
+        } else {
+            warn!(device_id = %self.device_id, "uplink device registration failed");
+        }
+    }
+
+    /// Heartbeat: refresh the device's `last_seen` and send the daemon's `health` (cycle,
+    /// watchdog incidents)
+    pub fn heartbeat(&self, platform: &str, health: serde_json::Value) -> Result<(), String> {
+        let payload = DevicePayload {
+            node_id: self.device_id.clone(),
+            platform: platform.to_string(),
+            first_seen: None,
+            last_seen: Some(Utc::now().to_rfc3339()),
+            health: Some(health),
+        };
+        self.post("/api/v1/devices", &payload)
+    }
+
+    fn event_payload(&self, ev: &Event, checkpoint: bool) -> EventPayload {
+        let kind = match &ev.kind {
+            crate::collectors::EventKind::Process(_) => "process",
+            crate::collectors::EventKind::Network(_) => "network",
+            crate::collectors::EventKind::FileIntegrity(_) => "file_integrity",
+            crate::collectors::EventKind::Privilege(_) => "privilege",
+        };
+        EventPayload {
+            event_id: ev.id.clone(),
+            kind: kind.to_string(),
+            ts: ev.ts.to_rfc3339(),
+            device_id: self.device_id.clone(),
+            payload_hash: None,
+            checkpoint,
+        }
+    }
+
+    /// Risk score payload, or `None` for a repeated alert (`risk.dedup`) that is not due: repeats
+    /// update the score of their first occurrence and are only sent when they alert or their
+    /// occurrence count reaches a power of two
+    fn risk_payload(&self, risk: &RiskResult) -> Option<RiskPayload> {
+        let fingerprint = risk.fingerprint.as_ref();
+        let send = match fingerprint {
+            Some(fp) => !fp.is_repeat() || risk.alert || fp.occurrences.is_power_of_two(),
+            None => true,
+        };
+        if !send {
+            return None;
+        }
+        // Fall back to a 60 s window ending at the result when the engine didn't record one.
+        let (window_start_ms, window_end_ms) = if risk.window_start < risk.window_end {
+            (risk.window_start, risk.window_end)
+        } else {
+            (risk.ts - 60 * 1000, risk.ts)
+        };
+        Some(RiskPayload {
+            id: format!("risk_{}_{}", self.device_id, fingerprint.map_or(risk.ts, |fp| fp.first_seen)),
+            score: risk.score,
+            level: risk.level.as_str().to_string(),
+            ts: ts_iso(risk.ts),
+            window_start: ts_iso(window_start_ms),
+            window_end: ts_iso(window_end_ms),
+            source: self.device_id.clone(), // so graph can link HAS_RISK_IN to device
+            top_class: risk.top_class.as_ref().map(|c| c.label.clone()),
+            top_class_probability: risk.top_class.as_ref().map(|c| c.probability),
+            techniques: risk.techniques().into_iter().map(str::to_owned).collect(),
+            tactics: risk.tactics().into_iter().map(str::to_owned).collect(),
+            indicators: risk.ioc_hits.iter().map(|h| h.indicator.clone()).collect(),
+            alert: risk.alert,
+            learning: risk.learning,
+            fingerprint: fingerprint.map(|fp| fp.id.clone()),
+            occurrences: fingerprint.map(|fp| fp.occurrences),
+        })
+    }
+
+    /// Send events and the risk score as stream frames; `Err` when streaming is off or the
+    /// connection failed (nothing is known to have been delivered then)
+    fn stream_report(&self, events: &[&Event], risk: Option<&RiskPayload>, checkpoint: bool) -> Result<(), String> {
+        let stream = self.stream.as_ref().ok_or("streaming disabled")?;
+        let events: Vec<EventPayload> = events.iter().map(|ev| self.event_payload(ev, checkpoint)).collect();
+        let mut frames = Vec::new();
+        if !events.is_empty() {
+            frames.push(serde_json::to_string(&StreamFrame::Events { events: &events }).map_err(|e| e.to_string())?);
+        }
+        if let Some(risk) = risk {
+            frames.push(serde_json::to_string(&StreamFrame::RiskScore(risk)).map_err(|e| e.to_string())?);
+        }
+        let frames: Vec<String> = frames.into_iter().map(|frame| self.redact(frame)).collect();
+        stream.send(&frames, self.bearer_token().as_deref())?;
+        self.spend(frames.iter().map(String::len).sum());
+        Ok(())
+    }
+
+    /// Report events and one risk result to graph API. With `uplink.streaming` they are sent as
+    /// frames on the stream connection once the outbox is empty; otherwise (or when the stream is
+    /// down) events go in `{"events": [...]}` batches of at most `uplink.event_batch_bytes` (one
+    /// request per event when 0) and the risk score once. Low-risk reports carry only a sample of
+    /// their events (`uplink.low_risk_sample_percent`, `uplink.bytes_per_hour`).
+    /// With `uplink.delta_reporting`, events whose state the server already acknowledged are left
+    /// out between checkpoints.
+    /// With an outbox, everything is queued first and then the whole outbox is drained.
+    pub fn report(
+        &self,
+        platform: &str,
+        events: &[Event],
+        risk: &RiskResult,
+    ) -> Result<(), String> {
+        if self.dry_run {
+            info!(events = events.len(), score = risk.score, level = ?risk.level, "dry run: would have reported events and the risk result");
+            return Ok(());
+        }
+        let now = Utc::now().timestamp_millis();
+        let checkpoint = self.checkpoint_due(now);
+        if checkpoint {
+            // Refreshes the device's last_seen with each checkpoint
+            self.device_registered.store(false, std::sync::atomic::Ordering::Relaxed);
+        }
+        self.ensure_device(platform);
+        let risk_payload = self.risk_payload(risk);
+        let selected = self.select_events(events, risk);
+        let keys: Vec<StateKey> = match self.config.delta_reporting {
+            true => selected.iter().map(|ev| state_key(ev)).collect(),
+            false => Vec::new(),
+        };
+        let events = self.unacknowledged(&selected, &keys, checkpoint);
+
+        if self.stream.is_some() {
+            // Older queued reports go first so the server sees them in order
+            let backlog = match self.outbox {
+                Some(ref store) => self.drain_outbox().and_then(|_| store.outbox_len().map_err(|e| e.to_string())),
+                None => Ok(0),
+            };
+            if backlog == Ok(0) {
+                match self.stream_report(&events, risk_payload.as_ref(), checkpoint) {
+                    Ok(()) => {
+                        self.acknowledge(keys, checkpoint, now, true);
+                        info!(score = risk.score, level = ?risk.level, "uplink risk streamed");
+                        return Ok(());
+                    }
+                    Err(e) => debug!(error = %e, "uplink stream unavailable; posting"),
+                }
+            }
+        }
+
+        let mut items = Vec::new();
+        let mut delivered = true;
+        for ev in events {
+            let payload = self.event_payload(ev, checkpoint);
+            if self.config.event_batch_bytes == 0 {
+                if let Err(e) = self.submit("/api/v1/events", &payload) {
+                    warn!(event_id = %ev.id, error = %e, "uplink event failed");
+                    delivered = false;
+                }
+                continue;
+            }
+            match serde_json::to_string(&payload) {
+                Ok(item) => items.push(item),
+                Err(e) => warn!(event_id = %ev.id, error = %e, "uplink event failed"),
+            }
+        }
+        for body in batch_bodies(&items, self.config.event_batch_bytes) {
+            if let Err(e) = self.submit_raw(INGEST_BATCH_PATH, body) {
+                warn!(error = %e, "uplink event batch failed");
+                delivered = false;
+            }
+        }
+        let result = (|| {
+            if let Some(ref payload) = risk_payload {
+                self.submit("/api/v1/risk_scores", payload)?;
+            }
+            if self.outbox.is_some() {
+                self.drain_outbox()?;
+            }
+            Ok::<(), String>(())
+        })();
+        self.acknowledge(keys, checkpoint, now, delivered && result.is_ok());
+        result?;
+        info!(score = risk.score, level = ?risk.level, "uplink risk reported");
+        Ok(())
+    }
+
+    /// Read server frames that arrived on the stream since the last report (no-op without one)
+    pub fn poll_stream(&self) {
+        if let Some(ref stream) = self.stream {
+            stream.poll();
+        }
+    }
+
+    /// Server messages received on the stream since the last call
+    pub fn take_server_messages(&self) -> Vec<ServerMessage> {
+        self.stream.as_ref().map(|s| s.take_messages()).unwrap_or_default()
+    }
+
+    /// Close the stream connection, if open
+    pub fn close_stream(&self) {
+        if let Some(ref stream) = self.stream {
+            stream.close();
+        }
+    }
+
+    /// Report an analyst verdict. With an outbox it is queued, then the outbox is drained.
+    pub fn report_feedback(&self, feedback: &Feedback) -> Result<(), String> {
+        let risk = &feedback.result;
+        let payload = FeedbackPayload {
+            risk_id: format!("risk_{}_{}", self.device_id, risk.fingerprint.as_ref().map_or(risk.ts, |fp| fp.first_seen)),
+            event_id: feedback.event_id.clone(),
+            verdict: feedback.verdict.as_str().to_string(),
+            note: feedback.note.clone(),
+            ts: ts_iso(feedback.ts),
+            source: self.device_id.clone(),
+            score: risk.score,
+            model_score: risk.model_score,
+            level: risk.level.as_str().to_string(),
+            top_class: risk.top_class.as_ref().map(|c| c.label.clone()),
+            rules: risk.rule_hits.iter().map(|h| h.rule.clone()).collect(),
+            techniques: risk.techniques().into_iter().map(str::to_owned).collect(),
+            event_ids: implicated_event_ids(risk),
+        };
+        self.submit("/api/v1/risk_feedback", &payload)?;
+        if self.outbox.is_some() {
+            self.drain_outbox()?;
+        }
+        info!(event_id = %feedback.event_id, verdict = feedback.verdict.as_str(), "uplink feedback reported");
+        Ok(())
+    }
+
+    /// Pending server commands (`commands.enabled`)
+    pub fn fetch_commands(&self) -> Result<Vec<Command>, String> {
+        let query = format!("{}?device_id={}", COMMANDS_PATH, self.device_id);
+        let res = self.get(COMMANDS_PATH, |base| self.authorized_get(&format!("{}{}", base, query)))?;
+        let status = res.status();
+        if status == reqwest::StatusCode::NO_CONTENT {
+            return Ok(Vec::new());
+        }
+        if !status.is_success() {
+            if status == reqwest::StatusCode::UNAUTHORIZED {
+                self.token_rejected();
+            }
+            return Err(format!("{} {}", status, res.text().unwrap_or_default()));
+        }
+        let body: CommandsResponse = res.json().map_err(|e| e.to_string())?;
+        Ok(body.commands)
+    }
+
+    /// Report the outcome of a server command. With an outbox it is queued, then the outbox is
+    /// drained.
+    pub fn report_command_result(&self, result: &CommandResult) -> Result<(), String> {
+        let payload = CommandResultPayload { device_id: &self.device_id, result };
+        self.submit(COMMAND_RESULT_PATH, &payload)?;
+        if self.outbox.is_some() {
+            self.drain_outbox()?;
+        }
+        Ok(())
+    }
+}
+
+enum UplinkOp {
+    Report {
+        platform: String,
+        events: Vec<Event>,
+        risk: Box<RiskResult>,
+    },
+    /// Acknowledged once every earlier report has been handled
+    Flush(SyncSender<()>),
+}
+
+fn run_reporter(client: Arc<UplinkClient>, rx: Receiver<UplinkOp>) {
+    loop {
+        match rx.recv_timeout(STREAM_POLL_INTERVAL) {
+            Ok(UplinkOp::Report { platform, events, risk }) => {
+                if let Err(e) = client.report(&platform, &events, &risk) {
+                    warn!(error = %e, "uplink report failed");
+                }
+            }
+            Ok(UplinkOp::Flush(ack)) => {
+                let _ = ack.send(());
+            }
+            Err(RecvTimeoutError::Timeout) => client.poll_stream(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+/// Reports handed to a dedicated uplink thread through a bounded channel, like the store's
+/// write-behind queue: the collection loop never waits on the network, and when the queue is full
+/// new reports are dropped (and counted) rather than blocking it.
+pub struct UplinkQueue {
+    client: Arc<UplinkClient>,
+    tx: Option<SyncSender<UplinkOp>>,
+    reporter: Option<JoinHandle<()>>,
+    dropped: AtomicU64,
+}
+
+impl UplinkQueue {
+    /// Start the uplink thread with room for `capacity` pending reports.
+    /// `capacity` 0 reports synchronously on the caller's thread instead.
+    pub fn new(client: UplinkClient, capacity: usize) -> Self {
+        let client = Arc::new(client);
+        let dropped = AtomicU64::new(0);
+        if capacity == 0 {
+            return Self {
+                client,
+                tx: None,
+                reporter: None,
+                dropped,
+            };
+        }
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let reporter_client = Arc::clone(&client);
+        let reporter = std::thread::Builder::new()
+            .name("dadm-uplink".to_string())
+            .spawn(move || run_reporter(reporter_client, rx))
+            .expect("spawn uplink reporter");
+        Self {
+            client,
+            tx: Some(tx),
+            reporter: Some(reporter),
+            dropped,
+        }
+    }
+
+    /// The client, for requests the caller waits on (e.g. fetching indicators)
+    pub fn client(&self) -> &UplinkClient {
+        &self.client
+    }
+
+    /// Reports dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// [`UplinkClient::health`] with the reports dropped by the queue
+    pub fn health(&self) -> UplinkHealth {
+        let mut health = self.client.health();
+        health.dropped_reports = self.dropped();
+        health
+    }
+
+    /// Queue events and a risk result for [`UplinkClient::report`]
+    pub fn report(&self, platform: &str, events: &[Event], risk: &RiskResult) {
+        let Some(ref tx) = self.tx else {
+            if let Err(e) = self.client.report(platform, events, risk) {
+                warn!(error = %e, "uplink report failed");
+            }
+            return;
+        };
+        let op = UplinkOp::Report {
+            platform: platform.to_string(),
+            events: events.to_vec(),
+            risk: Box::new(risk.clone()),
+        };
+        match tx.try_send(op) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let n = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                // Log the 1st, 2nd, 4th, 8th, ... drop to avoid flooding the log while the server is slow
+                if n.is_power_of_two() {
+                    warn!(dropped = n, "uplink queue full; dropping reports");
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => warn!("uplink thread has stopped"),
+        }
+    }
+
+    /// Block until every report queued so far has been handled.
+    pub fn flush(&self) {
+        let Some(ref tx) = self.tx else {
+            return;
+        };
+        let (ack_tx, ack_rx) = mpsc::sync_channel(1);
+        if tx.send(UplinkOp::Flush(ack_tx)).is_ok() {
+            let _ = ack_rx.recv();
+        }
+    }
+
+    /// Handle all pending reports and stop the uplink thread.
+    pub fn close(&mut self) {
+        self.tx.take();
+        if let Some(reporter) = self.reporter.take() {
+            let _ = reporter.join();
+        }
+        self.client.close_stream();
+    }
+}
+
+impl Drop for UplinkQueue {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
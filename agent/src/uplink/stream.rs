@@ -0,0 +1,186 @@
+//! WebSocket streaming (`uplink.streaming`): reports are sent as JSON text frames over one
+//! persistent connection as soon as they are made, and the server's frames on the same connection
+//! are kept for the agent to act on. A connection that fails is dropped and reopened on a later
+//! report (at most every [`RECONNECT_MS`]); the caller falls back to HTTP meanwhile.
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Connector, Message, WebSocket};
+
+/// Wait after a failed connection before trying again (ms)
+const RECONNECT_MS: i64 = 30 * 1000;
+/// Connect and handshake timeout
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Write timeout once connected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long a poll waits for a server frame
+const POLL_TIMEOUT: Duration = Duration::from_millis(50);
+/// Server messages kept until taken; older ones are dropped beyond this
+const MAX_PENDING_MESSAGES: usize = 1024;
+
+/// Frame sent by the server, e.g. `{"type": "indicators", "indicators": [...]}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerMessage {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub body: serde_json::Map<String, serde_json::Value>,
+}
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+#[derive(Default)]
+struct State {
+    socket: Option<Socket>,
+    /// No connection attempt before this time (ms), after one failed
+    retry_at: i64,
+    messages: Vec<ServerMessage>,
+}
+
+pub(super) struct Stream {
+    url: String,
+    tls: Option<Arc<rustls::ClientConfig>>,
+    state: Mutex<State>,
+}
+
+impl Stream {
+    /// Stream to `path` of `base_url` (`http` becomes `ws`, `https` becomes `wss`), with the TLS
+    /// settings of the HTTP client (`None`: default roots)
+    pub(super) fn new(base_url: &str, path: &str, device_id: &str, tls: Option<Arc<rustls::ClientConfig>>) -> Self {
+        let url = match base_url.split_once("://") {
+            Some(("https", rest)) => format!("wss://{}", rest),
+            Some((_, rest)) => format!("ws://{}", rest),
+            None => format!("ws://{}", base_url),
+        };
+        Self {
+            url: format!("{}{}?device_id={}", url, path, device_id),
+            tls,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn connect(&self, bearer: Option<&str>) -> Result<Socket, String> {
+        let mut request = self.url.as_str().into_client_request().map_err(|e| e.to_string())?;
+        if let Some(bearer) = bearer {
+            let value = format!("Bearer {}", bearer).parse().map_err(|_| "invalid token")?;
+            request.headers_mut().insert(tungstenite::http::header::AUTHORIZATION, value);
+        }
+        let uri = request.uri();
+        let host = uri.host().ok_or("stream URL without host")?.trim_matches(|c| c == '[' || c == ']').to_string();
+        let tls = uri.scheme_str() == Some("wss");
+        let port = uri.port_u16().unwrap_or(if tls { 443 } else { 80 });
+        let addr = (host.as_str(), port)
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or("stream host did not resolve")?;
+        let tcp = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).map_err(|e| e.to_string())?;
+        tcp.set_read_timeout(Some(CONNECT_TIMEOUT)).map_err(|e| e.to_string())?;
+        tcp.set_write_timeout(Some(WRITE_TIMEOUT)).map_err(|e| e.to_string())?;
+        let connector = match (&self.tls, tls) {
+            (_, false) => Some(Connector::Plain),
+            (Some(config), true) => Some(Connector::Rustls(Arc::clone(config))),
+            (None, true) => None,
+        };
+        let (socket, _) = tungstenite::client_tls_with_config(request, tcp, None, connector).map_err(|e| e.to_string())?;
+        let tcp = match socket.get_ref() {
+            MaybeTlsStream::Plain(s) => s,
+            MaybeTlsStream::Rustls(s) => s.get_ref(),
+            _ => return Err("unsupported stream".into()),
+        };
+        tcp.set_read_timeout(Some(POLL_TIMEOUT)).map_err(|e| e.to_string())?;
+        Ok(socket)
+    }
+
+    /// Read the frames the server has sent so far; `Err` when the connection is gone
+    fn read_available(socket: &mut Socket, messages: &mut Vec<ServerMessage>) -> Result<(), String> {
+        loop {
+            match socket.read() {
+                Ok(Message::Text(text)) => match serde_json::from_str::<ServerMessage>(&text) {
+                    Ok(message) => {
+                        if messages.len() >= MAX_PENDING_MESSAGES {
+                            messages.remove(0);
+                        }
+                        messages.push(message);
+                    }
+                    Err(e) => debug!(error = %e, "uplink stream: unrecognized server frame"),
+                },
+                Ok(Message::Close(_)) => return Err("closed by server".into()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) =>
+                {
+                    return Ok(())
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    /// Send `frames` (JSON texts) in order, connecting first if needed
+    pub(super) fn send(&self, frames: &[String], bearer: Option<&str>) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let now = Utc::now().timestamp_millis();
+        if state.socket.is_none() {
+            if now < state.retry_at {
+                return Err("stream reconnect pending".into());
+            }
+            match self.connect(bearer) {
+                Ok(socket) => {
+                    info!("uplink stream connected");
+                    state.socket = Some(socket);
+                }
+                Err(e) => {
+                    state.retry_at = now + RECONNECT_MS;
+                    return Err(e);
+                }
+            }
+        }
+        let State { socket, messages, .. } = &mut *state;
+        let result = (|| {
+            let socket = socket.as_mut().expect("connected above");
+            for frame in frames {
+                socket.write(Message::Text(frame.clone())).map_err(|e| e.to_string())?;
+            }
+            socket.flush().map_err(|e| e.to_string())?;
+            Self::read_available(socket, messages)
+        })();
+        if let Err(ref e) = result {
+            warn!(error = %e, "uplink stream lost");
+            state.socket = None;
+        }
+        result
+    }
+
+    /// Pick up server frames and answer pings while no report is being sent
+    pub(super) fn poll(&self) {
+        let mut state = self.state.lock().unwrap();
+        let State { socket, messages, .. } = &mut *state;
+        let Some(socket) = socket.as_mut() else {
+            return;
+        };
+        let result = Self::read_available(socket, messages).and_then(|()| socket.flush().map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!(error = %e, "uplink stream lost");
+            state.socket = None;
+        }
+    }
+
+    /// Server messages received since the last call
+    pub(super) fn take_messages(&self) -> Vec<ServerMessage> {
+        std::mem::take(&mut self.state.lock().unwrap().messages)
+    }
+
+    pub(super) fn close(&self) {
+        if let Some(mut socket) = self.state.lock().unwrap().socket.take() {
+            let _ = socket.close(None);
+            let _ = socket.flush();
+        }
+    }
+}
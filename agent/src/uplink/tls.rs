@@ -0,0 +1,216 @@
+//! TLS settings of the uplink connection: extra CA certificates, the mTLS client identity (its
+//! key in a file, or held by the platform keystore as `uplink.client_key`), and server pins
+//! (`uplink.pinned_spki_sha256` / `pinned_cert_sha256`). When any is configured the rustls
+//! client config is built here and handed to reqwest as is.
+
+use crate::config::UplinkConfig;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use zeroize::Zeroizing;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// One DER element of `der`: (tag, contents, rest)
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n].iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+        (len, &rest[n..])
+    };
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+/// DER `SubjectPublicKeyInfo` of an X.509 certificate: the seventh field of `tbsCertificate`
+/// (sixth when the optional version is absent)
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_element(cert)?;
+    let (_, tbs, _) = der_element(certificate)?;
+    let mut rest = tbs;
+    let (tag, _, after) = der_element(rest)?;
+    if tag == 0xa0 {
+        rest = after;
+    }
+    // serialNumber, signature, issuer, validity, subject
+    for _ in 0..5 {
+        rest = der_element(rest)?.2;
+    }
+    let (_, _, after) = der_element(rest)?;
+    Some(&rest[..rest.len() - after.len()])
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// SHA-256 digests of accepted server public keys and certificates
+struct Pins {
+    spki: Vec<[u8; 32]>,
+    certs: Vec<[u8; 32]>,
+}
+
+impl Pins {
+    fn is_empty(&self) -> bool {
+        self.spki.is_empty() && self.certs.is_empty()
+    }
+
+    fn matches(&self, cert: &Certificate) -> bool {
+        self.certs.contains(&sha256(&cert.0)) || spki(&cert.0).is_some_and(|k| self.spki.contains(&sha256(k)))
+    }
+}
+
+/// Parse the configured pins: SPKI hashes in base64 (`openssl pkey -pubin -outform der |
+/// openssl dgst -sha256 -binary | base64`, optionally prefixed `sha256//` as in curl) and
+/// certificate fingerprints in hex (colons allowed)
+fn parse_pins(config: &UplinkConfig) -> Result<Pins, BoxError> {
+    let spki = config
+        .pinned_spki_sha256
+        .iter()
+        .map(|pin| {
+            let b64 = pin.trim().trim_start_matches("sha256//");
+            let bytes = BASE64.decode(b64).map_err(|e| format!("SPKI pin {}: {}", pin, e))?;
+            bytes.try_into().map_err(|_| format!("SPKI pin {}: not a SHA-256 digest", pin).into())
+        })
+        .collect::<Result<Vec<_>, BoxError>>()?;
+    let certs = config
+        .pinned_cert_sha256
+        .iter()
+        .map(|pin| {
+            let hex: String = pin.chars().filter(|c| *c != ':' && !c.is_whitespace()).collect();
+            if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(format!("certificate pin {}: not a SHA-256 fingerprint", pin).into());
+            }
+            let mut digest = [0u8; 32];
+            for (i, byte) in digest.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)?;
+            }
+            Ok(digest)
+        })
+        .collect::<Result<Vec<_>, BoxError>>()?;
+    Ok(Pins { spki, certs })
+}
+
+/// Usual chain and name verification, then (when pins are set) a pin check: some certificate of
+/// the presented chain must match an SPKI or certificate pin
+struct PinningVerifier {
+    inner: WebPkiVerifier,
+    pins: Pins,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self
+            .inner
+            .verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)?;
+        if self.pins.is_empty() || std::iter::once(end_entity).chain(intermediates).any(|c| self.pins.matches(c)) {
+            Ok(verified)
+        } else {
+            tracing::warn!("uplink server certificate matches no pin; connection refused");
+            Err(rustls::Error::General("server certificate does not match the configured pins".into()))
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Zeroizing<Vec<u8>>, BoxError> {
+    std::fs::read(path)
+        .map(Zeroizing::new)
+        .map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Certificates and the last private key in PEM files `paths`
+fn read_pem(paths: &[&Path]) -> Result<(Vec<Certificate>, Option<PrivateKey>), BoxError> {
+    let mut certs = Vec::new();
+    let mut key = None;
+    for path in paths {
+        let pem = read(path)?;
+        parse_pem(&pem, &mut certs, &mut key).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok((certs, key))
+}
+
+/// Add the certificates in `pem` to `certs`, and its last private key as `key`
+fn parse_pem(pem: &[u8], certs: &mut Vec<Certificate>, key: &mut Option<PrivateKey>) -> std::io::Result<()> {
+    for item in rustls_pemfile::read_all(&mut &pem[..])? {
+        match item {
+            rustls_pemfile::Item::X509Certificate(der) => certs.push(Certificate(der)),
+            rustls_pemfile::Item::PKCS8Key(der) | rustls_pemfile::Item::RSAKey(der) | rustls_pemfile::Item::ECKey(der) => {
+                *key = Some(PrivateKey(der))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// rustls config for `config`, or `None` when no TLS option is set (reqwest's defaults apply)
+pub(super) fn client_config(config: &UplinkConfig) -> Result<Option<ClientConfig>, BoxError> {
+    let pinned = !config.pinned_spki_sha256.is_empty() || !config.pinned_cert_sha256.is_empty();
+    let key_set = config.client_key_path.is_some() || config.client_key.is_some();
+    if config.client_cert_path.is_none() && !key_set && config.ca_cert_path.is_none() && !pinned {
+        return Ok(None);
+    }
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+    }));
+    if let Some(ref ca_path) = config.ca_cert_path {
+        let (certs, _) = read_pem(&[ca_path])?;
+        if certs.is_empty() {
+            return Err(format!("{}: no certificates", ca_path.display()).into());
+        }
+        for cert in &certs {
+            roots.add(cert).map_err(|e| format!("{}: {}", ca_path.display(), e))?;
+        }
+    }
+    let verifier = PinningVerifier {
+        inner: WebPkiVerifier::new(roots, None),
+        pins: parse_pins(config)?,
+    };
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+    let tls = match (&config.client_cert_path, &config.client_key_path) {
+        (Some(_), Some(_)) if config.client_key.is_some() => {
+            return Err("set uplink.client_key_path or uplink.client_key, not both".into());
+        }
+        (Some(cert_path), key_path) => {
+            let paths: Vec<&Path> = std::iter::once(cert_path.as_path()).chain(key_path.as_deref()).collect();
+            let (chain, mut key) = read_pem(&paths)?;
+            if let Some(ref pem) = config.client_key {
+                // Resolved from the keystore at startup
+                let (pem, mut held) = (Zeroizing::new(pem.as_bytes().to_vec()), None);
+                parse_pem(&pem, &mut Vec::new(), &mut held).map_err(|e| format!("uplink.client_key: {}", e))?;
+                key = Some(held.ok_or("uplink.client_key: no PEM private key")?);
+            }
+            let key = key.ok_or("client certificate: private key not found")?;
+            if chain.is_empty() {
+                return Err("client certificate: no certificate found".into());
+            }
+            builder
+                .with_client_auth_cert(chain, key)
+                .map_err(|e| format!("client certificate: {}", e))?
+        }
+        (None, _) if key_set => return Err("a client key is set without uplink.client_cert_path".into()),
+        (None, _) => builder.with_no_client_auth(),
+    };
+    Ok(Some(tls))
+}
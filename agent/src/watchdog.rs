@@ -0,0 +1,140 @@
+//! Watchdog of the daemon's cycles (`watchdog`): a collection or analysis running longer than
+//! `stall_secs` is stuck. A stuck collection is abandoned (its thread can't be stopped; it ends on
+//! its own) and the next one starts on schedule; when it is still stuck at the next stall, or an
+//! analysis is stuck (it may hold the pipeline's locks), the pipeline is rebuilt. After
+//! `max_restarts` rebuilds within an hour the agent exits with an error so its service manager
+//! starts it afresh. Each incident is logged, recorded in the audit log, and kept for the next
+//! heartbeat to the server (`uplink.heartbeat_secs`).
+
+use crate::config::WatchdogConfig;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Incidents kept for the heartbeat; older ones are dropped
+const MAX_PENDING: usize = 32;
+const RESTART_WINDOW: Duration = Duration::from_secs(3600);
+
+/// What was done about a stuck cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// The collection was left to finish on its own; collection goes on
+    Abandoned,
+    /// The pipeline is rebuilt
+    Restart,
+    /// Too many rebuilds: the agent exits
+    Exit,
+}
+
+impl Recovery {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Recovery::Abandoned => "collection_abandoned",
+            Recovery::Restart => "pipeline_restarted",
+            Recovery::Exit => "agent_exiting",
+        }
+    }
+}
+
+pub struct Watchdog {
+    config: WatchdogConfig,
+    /// Incidents not yet reported in a heartbeat
+    pending: Mutex<Vec<Value>>,
+    /// Pipeline rebuilds within the last hour
+    restarts: Mutex<VecDeque<Instant>>,
+    /// Recovery the current run ends with (0: none)
+    outcome: AtomicU8,
+    system: Mutex<sysinfo::System>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new(WatchdogConfig::default())
+    }
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Self {
+            config,
+            pending: Mutex::default(),
+            restarts: Mutex::default(),
+            outcome: AtomicU8::new(0),
+            system: Mutex::new(sysinfo::System::new()),
+        }
+    }
+
+    /// How long a collection or analysis may run; `None` when the watchdog is off
+    pub fn stall_limit(&self) -> Option<Duration> {
+        (self.config.enabled && self.config.stall_secs > 0).then(|| Duration::from_secs(self.config.stall_secs))
+    }
+
+    /// Handle a `subsystem` (`collector` or `analysis`) stuck in `cycle` at `now`;
+    /// `earlier_stuck`: an abandoned collection is still running. Returns the recovery, which the
+    /// caller carries out for [`Recovery::Abandoned`]; for the others it ends the run.
+    pub fn stalled(&self, subsystem: &str, cycle: u64, earlier_stuck: bool, now: Instant) -> Recovery {
+        let recovery = if subsystem == "collector" && !earlier_stuck {
+            Recovery::Abandoned
+        } else {
+            let mut restarts = self.restarts.lock().unwrap();
+            while restarts.front().is_some_and(|t| now.duration_since(*t) >= RESTART_WINDOW) {
+                restarts.pop_front();
+            }
+            if restarts.len() < self.config.max_restarts as usize {
+                restarts.push_back(now);
+                Recovery::Restart
+            } else {
+                Recovery::Exit
+            }
+        };
+        let process = crate::runtime::process_usage(&self.system);
+        let incident = json!({
+            "ts": chrono::Utc::now().timestamp_millis(),
+            "subsystem": subsystem,
+            "cycle": cycle,
+            "stuck_secs": self.config.stall_secs,
+            "earlier_collection_stuck": earlier_stuck,
+            "recovery": recovery.as_str(),
+            "process": process,
+        });
+        tracing::error!(subsystem, cycle, stuck_secs = self.config.stall_secs, recovery = recovery.as_str(), "watchdog: cycle stuck");
+        crate::logging::audit::record("watchdog_incident", incident.clone());
+        let mut pending = self.pending.lock().unwrap();
+        if pending.len() >= MAX_PENDING {
+            pending.remove(0);
+        }
+        pending.push(incident);
+        let code = match recovery {
+            Recovery::Abandoned => 0,
+            Recovery::Restart => 1,
+            Recovery::Exit => 2,
+        };
+        self.outcome.fetch_max(code, Ordering::Relaxed);
+        recovery
+    }
+
+    /// Recovery the run ended with, if the watchdog ended it; resets it for the next run
+    pub fn take_outcome(&self) -> Option<Recovery> {
+        match self.outcome.swap(0, Ordering::Relaxed) {
+            1 => Some(Recovery::Restart),
+            2 => Some(Recovery::Exit),
+            _ => None,
+        }
+    }
+
+    /// Incidents not yet reported, for a heartbeat
+    pub fn take_incidents(&self) -> Vec<Value> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Put back incidents a heartbeat failed to report
+    pub fn restore_incidents(&self, incidents: Vec<Value>) {
+        let mut pending = self.pending.lock().unwrap();
+        let newer = std::mem::replace(&mut *pending, incidents);
+        pending.extend(newer);
+        let excess = pending.len().saturating_sub(MAX_PENDING);
+        pending.drain(..excess);
+    }
+}
@@ -5,6 +5,7 @@ Endpoints: ingest, risk propagation, clustering, dashboard queries.
 
 from __future__ import annotations
 
+import gzip
 import os
 from datetime import datetime, timedelta
 from typing import Any, Dict, List
@@ -49,6 +50,32 @@ def get_store() -> Neo4jStore:
 
 # ---- Ingest ----
 
+try:
+    import zstandard
+except ImportError:  # optional; gzip is always accepted
+    zstandard = None
+
+_DECODERS = {"gzip": gzip.decompress}
+if zstandard is not None:
+    _DECODERS["zstd"] = lambda data: zstandard.ZstdDecompressor().decompressobj().decompress(data)
+
+
+@app.before_request
+def decode_request_body():
+    """Decompress gzip/zstd request bodies (Content-Encoding); 415 with Accept-Encoding otherwise."""
+    encoding = (request.headers.get("Content-Encoding") or "identity").strip().lower()
+    if encoding == "identity":
+        return None
+    decode = _DECODERS.get(encoding)
+    if decode is None:
+        resp = jsonify({"error": f"unsupported content encoding {encoding}"})
+        resp.headers["Accept-Encoding"] = ", ".join(_DECODERS)
+        return resp, 415
+    # get_json() reads the cached body, so replace it with the decoded one
+    request._cached_data = decode(request.get_data())
+    return None
+
+
 def _parse_optional_dt(v: Any) -> Any:
     if v is None:
         return None
@@ -108,32 +135,47 @@ def ingest_risk_score():
 
 @app.route("/api/v1/ingest/batch", methods=["POST"])
 def ingest_batch():
-    """Batch ingest devices, events, risk_scores. Body: { devices: [], events: [], risk_scores: [] }."""
+    """Batch ingest devices, events, risk_scores. Body: { devices: [], events: [], risk_scores: [] }.
+
+    Items are ingested independently: malformed ones are reported in `errors`
+    ({kind, index, id, error}) and the rest are still stored.
+    """
     body = request.get_json() or {}
     store = get_store()
     devices = body.get("devices", [])
     events = body.get("events", [])
     risk_scores = body.get("risk_scores", [])
-    for d in devices:
-        dev = Device(
+    errors = []
+    counts = {"devices": 0, "events": 0, "risk_scores": 0}
+
+    def ingest(kind, items, item_id, upsert):
+        for i, item in enumerate(items):
+            try:
+                upsert(item)
+                counts[kind] += 1
+            except (KeyError, TypeError, ValueError, AttributeError) as exc:
+                errors.append({"kind": kind, "index": i, "id": item_id(item), "error": repr(exc)})
+
+    def upsert_device(d):
+        store.upsert_device(Device(
             node_id=d.get("node_id") or device_node_id(d.get("id", "")),
             platform=d.get("platform", "unknown"),
             first_seen=datetime.fromisoformat(d["first_seen"].replace("Z", "+00:00")) if d.get("first_seen") else None,
             last_seen=datetime.fromisoformat(d["last_seen"].replace("Z", "+00:00")) if d.get("last_seen") else None,
             mesh_id=d.get("mesh_id"),
-        )
-        store.upsert_device(dev)
-    for e in events:
-        ev = Event(
+        ))
+
+    def upsert_event(e):
+        store.upsert_event(Event(
             event_id=e.get("event_id") or event_id(e.get("id", "")),
             kind=e.get("kind", "process"),
             ts=datetime.fromisoformat(e["ts"].replace("Z", "+00:00")),
             device_id=e["device_id"],
             payload_hash=e.get("payload_hash"),
-        )
-        store.upsert_event(ev)
-    for r in risk_scores:
-        rs = RiskScore(
+        ))
+
+    def upsert_risk_score(r):
+        store.upsert_risk_score(RiskScore(
             id=r.get("id", f"risk_{r.get('device_id', '')}_{r.get('ts', '')}"),
             score=float(r["score"]),
             level=r.get("level", "low"),
@@ -141,14 +183,15 @@ def ingest_batch():
             window_start=datetime.fromisoformat(r["window_start"].replace("Z", "+00:00")),
             window_end=datetime.fromisoformat(r["window_end"].replace("Z", "+00:00")),
             source=r.get("source", r.get("device_id", "")),
-        )
-        store.upsert_risk_score(rs)
-    return jsonify({
-        "status": "ok",
-        "devices": len(devices),
-        "events": len(events),
-        "risk_scores": len(risk_scores),
-    }), 201
+        ))
+
+    def item_id(key):
+        return lambda item: item.get(key) if isinstance(item, dict) else None
+
+    ingest("devices", devices, item_id("node_id"), upsert_device)
+    ingest("events", events, item_id("event_id"), upsert_event)
+    ingest("risk_scores", risk_scores, item_id("id"), upsert_risk_score)
+    return jsonify({"status": "ok" if not errors else "partial", **counts, "errors": errors}), 201
 
 
 @app.route("/api/v1/subgraph")